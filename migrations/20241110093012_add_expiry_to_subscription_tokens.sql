@@ -0,0 +1,15 @@
+-- Add migration script here
+
+BEGIN;
+    ALTER TABLE subscription_tokens
+    ADD COLUMN created_at timestamptz NOT NULL DEFAULT now(),
+    ADD COLUMN expires_at timestamptz NULL,
+    ADD COLUMN consumed_at timestamptz NULL;
+
+    -- Tokens issued before this migration get a fresh validity window
+    UPDATE subscription_tokens
+    SET expires_at = now() + interval '48 hours';
+
+    ALTER TABLE subscription_tokens
+    ALTER COLUMN expires_at SET NOT NULL;
+COMMIT;
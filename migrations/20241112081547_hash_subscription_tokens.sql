@@ -0,0 +1,10 @@
+-- Add migration script here
+
+-- Tokens are now stored as the hex-encoded SHA-256 of the value sent by email
+BEGIN;
+    UPDATE subscription_tokens
+    SET subscription_token = encode(sha256(convert_to(subscription_token, 'UTF8')), 'hex');
+
+    ALTER TABLE subscription_tokens
+    RENAME COLUMN subscription_token TO subscription_token_hash;
+COMMIT;
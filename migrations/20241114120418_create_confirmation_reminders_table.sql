@@ -0,0 +1,7 @@
+-- Add migration script here
+
+CREATE TABLE confirmation_reminders (
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id),
+    sent_at timestamptz NOT NULL,
+    PRIMARY KEY (subscriber_id, sent_at)
+);
@@ -0,0 +1,14 @@
+-- Add migration script here
+
+-- Deleting a subscriber takes their tokens and reminders along
+BEGIN;
+    ALTER TABLE subscription_tokens
+    DROP CONSTRAINT subscription_tokens_subscriber_id_fkey,
+    ADD CONSTRAINT subscription_tokens_subscriber_id_fkey
+        FOREIGN KEY (subscriber_id) REFERENCES subscriptions (id) ON DELETE CASCADE;
+
+    ALTER TABLE confirmation_reminders
+    DROP CONSTRAINT confirmation_reminders_subscriber_id_fkey,
+    ADD CONSTRAINT confirmation_reminders_subscriber_id_fkey
+        FOREIGN KEY (subscriber_id) REFERENCES subscriptions (id) ON DELETE CASCADE;
+COMMIT;
@@ -0,0 +1,42 @@
+-- Add migration script here
+
+-- Subscribers can now join several mailing lists, each confirmed separately.
+-- Everything that existed so far belongs to the default `newsletter` list.
+BEGIN;
+    CREATE TABLE lists (
+        list_id uuid NOT NULL,
+        PRIMARY KEY (list_id),
+        slug TEXT NOT NULL UNIQUE,
+        name TEXT NOT NULL,
+        created_at timestamptz NOT NULL DEFAULT now()
+    );
+
+    INSERT INTO lists (list_id, slug, name)
+    VALUES (gen_random_uuid(), 'newsletter', 'Newsletter');
+
+    CREATE TABLE list_memberships (
+        subscriber_id uuid NOT NULL
+            REFERENCES subscriptions (id) ON DELETE CASCADE,
+        list_id uuid NOT NULL
+            REFERENCES lists (list_id),
+        status TEXT NOT NULL,
+        created_at timestamptz NOT NULL DEFAULT now(),
+        PRIMARY KEY (subscriber_id, list_id)
+    );
+
+    INSERT INTO list_memberships (subscriber_id, list_id, status, created_at)
+    SELECT id, (SELECT list_id FROM lists WHERE slug = 'newsletter'), status, subscribed_at
+    FROM subscriptions;
+
+    ALTER TABLE subscription_tokens
+    ADD COLUMN list_id uuid REFERENCES lists (list_id);
+    UPDATE subscription_tokens
+    SET list_id = (SELECT list_id FROM lists WHERE slug = 'newsletter');
+    ALTER TABLE subscription_tokens ALTER COLUMN list_id SET NOT NULL;
+
+    ALTER TABLE newsletter_issues
+    ADD COLUMN list_id uuid REFERENCES lists (list_id);
+    UPDATE newsletter_issues
+    SET list_id = (SELECT list_id FROM lists WHERE slug = 'newsletter');
+    ALTER TABLE newsletter_issues ALTER COLUMN list_id SET NOT NULL;
+COMMIT;
@@ -0,0 +1,13 @@
+-- Add migration script here
+
+-- A subscriber's new address only replaces the old one once it is confirmed
+CREATE TABLE email_change_requests (
+    token_hash TEXT NOT NULL,
+    PRIMARY KEY (token_hash),
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    new_email TEXT NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    expires_at timestamptz NOT NULL,
+    consumed_at timestamptz
+);
@@ -0,0 +1,12 @@
+-- Add migration script here
+
+-- What was sent to whom, so a subscriber's delivery history can be exported
+CREATE TABLE issue_deliveries (
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id),
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    outcome TEXT NOT NULL,
+    attempted_at timestamptz NOT NULL,
+    PRIMARY KEY (newsletter_issue_id, subscriber_id)
+);
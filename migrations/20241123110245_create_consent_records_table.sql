@@ -0,0 +1,19 @@
+-- Add migration script here
+
+-- Evidence of consent, one row per sign-up or confirmation
+CREATE TABLE consent_records (
+    consent_record_id uuid NOT NULL,
+    PRIMARY KEY (consent_record_id),
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    list_id uuid NOT NULL
+        REFERENCES lists (list_id),
+    event TEXT NOT NULL,
+    recorded_at timestamptz NOT NULL,
+    ip_address TEXT,
+    user_agent TEXT,
+    form_version TEXT,
+    subscription_token_hash TEXT
+);
+
+CREATE INDEX consent_records_subscriber_id_idx ON consent_records (subscriber_id);
@@ -0,0 +1,129 @@
+-- Add migration script here
+
+-- Addresses are normalized the way `SubscriberEmail::parse` does it: trimmed,
+-- with the domain lowercased and converted to punycode label by label.
+-- Postgres cannot convert to punycode on its own, hence RFC 3492's encoding
+-- below; the functions only live as long as the migration's session.
+CREATE FUNCTION pg_temp.punycode(label TEXT) RETURNS TEXT AS $$
+DECLARE
+    code_points INT[] := ARRAY(SELECT ascii(c) FROM regexp_split_to_table(label, '') AS c);
+    output TEXT := '';
+    n INT := 128;
+    bias INT := 72;
+    delta BIGINT := 0;
+    n_basic INT;
+    h INT;
+    m INT;
+    q BIGINT;
+    k INT;
+    t INT;
+    digit INT;
+    code_point INT;
+BEGIN
+    FOREACH code_point IN ARRAY code_points LOOP
+        IF code_point < 128 THEN
+            output := output || chr(code_point);
+        END IF;
+    END LOOP;
+    n_basic := length(output);
+    h := n_basic;
+    IF n_basic > 0 THEN
+        output := output || '-';
+    END IF;
+
+    WHILE h < cardinality(code_points) LOOP
+        SELECT min(c) INTO m FROM unnest(code_points) AS c WHERE c >= n;
+        delta := delta + (m - n) * (h + 1);
+        n := m;
+        FOREACH code_point IN ARRAY code_points LOOP
+            IF code_point < n THEN
+                delta := delta + 1;
+            ELSIF code_point = n THEN
+                q := delta;
+                k := 36;
+                LOOP
+                    t := greatest(1, least(26, k - bias));
+                    EXIT WHEN q < t;
+                    digit := t + (q - t) % (36 - t);
+                    output := output || chr(CASE WHEN digit < 26 THEN 97 + digit ELSE 22 + digit END);
+                    q := (q - t) / (36 - t);
+                    k := k + 36;
+                END LOOP;
+                output := output || chr(CASE WHEN q < 26 THEN 97 + q ELSE 22 + q END::INT);
+
+                -- Adapt the bias.
+                delta := CASE WHEN h = n_basic THEN delta / 700 ELSE delta / 2 END;
+                delta := delta + delta / (h + 1);
+                k := 0;
+                WHILE delta > 455 LOOP
+                    delta := delta / 35;
+                    k := k + 36;
+                END LOOP;
+                bias := k + (36 * delta) / (delta + 38);
+
+                delta := 0;
+                h := h + 1;
+            END IF;
+        END LOOP;
+        delta := delta + 1;
+        n := n + 1;
+    END LOOP;
+
+    RETURN output;
+END
+$$ LANGUAGE plpgsql IMMUTABLE STRICT;
+
+CREATE FUNCTION pg_temp.normalize_email(email TEXT) RETURNS TEXT AS $$
+    SELECT CASE WHEN position('@' IN btrim(email)) = 0 THEN btrim(email) ELSE
+        substring(btrim(email) FROM '^(.*)@') || '@' || (
+            SELECT string_agg(
+                CASE WHEN label ~ '[^\x01-\x7f]' THEN 'xn--' || pg_temp.punycode(label) ELSE label END,
+                '.' ORDER BY position
+            )
+            FROM regexp_split_to_table(
+                normalize(lower(substring(btrim(email) FROM '@([^@]*)$')), NFKC),
+                '[.。]'
+            ) WITH ORDINALITY AS labels(label, position)
+        )
+    END
+$$ LANGUAGE sql IMMUTABLE STRICT;
+
+-- Addresses used to be stored verbatim, so case, whitespace or Unicode and
+-- punycode variants of the same address may already be on file as separate
+-- subscribers. Those have to be merged by hand: report every collision and
+-- refuse to go any further.
+DO $$
+DECLARE
+    collisions TEXT;
+BEGIN
+    SELECT string_agg(format('%s (%s)', canonical, addresses), '; ')
+    INTO collisions
+    FROM (
+        SELECT lower(pg_temp.normalize_email(email)) AS canonical, string_agg(email, ', ' ORDER BY subscribed_at) AS addresses
+        FROM subscriptions
+        GROUP BY lower(pg_temp.normalize_email(email))
+        HAVING COUNT(*) > 1
+    ) AS duplicates;
+
+    IF collisions IS NOT NULL THEN
+        RAISE EXCEPTION 'Subscribers share a canonical email address: %', collisions
+            USING HINT = 'Merge or delete the duplicate subscribers, then run the migration again.';
+    END IF;
+END
+$$;
+
+-- The address as it was typed is kept for display, surrounding whitespace
+-- aside.
+ALTER TABLE subscriptions ADD COLUMN email_canonical TEXT, ADD COLUMN email_display TEXT;
+
+UPDATE subscriptions SET email_display = btrim(email), email = pg_temp.normalize_email(email);
+UPDATE subscriptions SET email_canonical = lower(email);
+
+ALTER TABLE subscriptions
+    ALTER COLUMN email_canonical SET NOT NULL,
+    ALTER COLUMN email_display SET NOT NULL;
+
+CREATE UNIQUE INDEX subscriptions_email_canonical_key ON subscriptions (email_canonical);
+
+DROP FUNCTION pg_temp.normalize_email(TEXT);
+DROP FUNCTION pg_temp.punycode(TEXT);
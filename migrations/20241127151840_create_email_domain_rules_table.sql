@@ -0,0 +1,9 @@
+-- Add migration script here
+
+-- Domains admins allow or deny sign-ups from, overriding the automatic checks
+CREATE TABLE email_domain_rules (
+    domain TEXT NOT NULL,
+    PRIMARY KEY (domain),
+    rule TEXT NOT NULL CHECK (rule IN ('allow', 'deny')),
+    created_at timestamptz NOT NULL
+);
@@ -0,0 +1,17 @@
+-- Add migration script here
+
+-- Addresses we must never email again, whether or not they are subscribed
+CREATE TABLE suppressions (
+    email_canonical TEXT NOT NULL,
+    PRIMARY KEY (email_canonical),
+    email TEXT NOT NULL,
+    reason TEXT NOT NULL
+        CHECK (reason IN ('hard_bounce', 'complaint', 'manual', 'unsubscribe')),
+    created_at timestamptz NOT NULL
+);
+
+-- People who already unsubscribed stay that way.
+INSERT INTO suppressions (email_canonical, email, reason, created_at)
+SELECT email_canonical, email, 'unsubscribe', now()
+FROM subscriptions
+WHERE status = 'unsubscribed';
@@ -0,0 +1,3 @@
+-- Free-form details about each subscriber, filled into issues by merge tags.
+ALTER TABLE subscriptions
+ADD COLUMN attributes JSONB NOT NULL DEFAULT '{}'::jsonb;
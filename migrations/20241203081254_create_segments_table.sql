@@ -0,0 +1,16 @@
+-- Add migration script here
+
+-- Saved audiences within a list, written in the segment filter language.
+BEGIN;
+    CREATE TABLE segments (
+        segment_id uuid NOT NULL,
+        PRIMARY KEY (segment_id),
+        name TEXT NOT NULL UNIQUE,
+        expression TEXT NOT NULL,
+        created_at timestamptz NOT NULL DEFAULT now()
+    );
+
+    -- Issues sent to the whole list have no segment.
+    ALTER TABLE newsletter_issues
+    ADD COLUMN segment_id uuid REFERENCES segments (segment_id) ON DELETE SET NULL;
+COMMIT;
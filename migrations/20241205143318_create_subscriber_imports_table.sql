@@ -0,0 +1,15 @@
+-- Add migration script here
+
+-- The outcome of each bulk import of subscribers, kept for its report
+CREATE TABLE subscriber_imports (
+    import_id uuid NOT NULL,
+    PRIMARY KEY (import_id),
+    list_id uuid NOT NULL
+        REFERENCES lists (list_id),
+    confirmed BOOLEAN NOT NULL,
+    n_imported INTEGER NOT NULL,
+    n_rejected INTEGER NOT NULL,
+    -- The rejected rows and why, as a CSV file
+    report TEXT NOT NULL,
+    created_at timestamptz NOT NULL
+);
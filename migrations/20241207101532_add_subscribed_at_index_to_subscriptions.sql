@@ -0,0 +1,4 @@
+-- Add migration script here
+
+-- The order the admin browser and exports page through subscribers in.
+CREATE INDEX subscriptions_subscribed_at_id_idx ON subscriptions (subscribed_at, id);
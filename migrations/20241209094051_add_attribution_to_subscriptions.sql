@@ -0,0 +1,7 @@
+-- Where each subscriber came from, as reported when they first signed up.
+ALTER TABLE subscriptions
+    ADD COLUMN utm_source TEXT,
+    ADD COLUMN utm_medium TEXT,
+    ADD COLUMN utm_campaign TEXT,
+    ADD COLUMN referrer TEXT,
+    ADD COLUMN source TEXT;
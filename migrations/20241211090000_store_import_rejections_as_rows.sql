@@ -0,0 +1,18 @@
+-- The rejected rows of each import, one per row, so that erasing a
+-- subscriber can scrub their address and name from past reports.
+CREATE TABLE subscriber_import_rejections (
+    import_id uuid NOT NULL
+        REFERENCES subscriber_imports (import_id) ON DELETE CASCADE,
+    line INTEGER NOT NULL,
+    PRIMARY KEY (import_id, line),
+    email TEXT NOT NULL,
+    -- NULL when the row did not hold a valid address
+    email_canonical TEXT,
+    name TEXT NOT NULL,
+    reason TEXT NOT NULL
+);
+CREATE INDEX subscriber_import_rejections_email_canonical_idx
+    ON subscriber_import_rejections (email_canonical);
+
+-- Reports stored as a single CSV file cannot be scrubbed: they go.
+ALTER TABLE subscriber_imports DROP COLUMN report;
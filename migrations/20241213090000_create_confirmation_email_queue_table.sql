@@ -0,0 +1,10 @@
+-- Subscribers imported with double opt-in wait here for the worker to send
+-- them their confirmation link, rather than the import sending it inline.
+CREATE TABLE confirmation_email_queue (
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    list_id uuid NOT NULL
+        REFERENCES lists (list_id) ON DELETE CASCADE,
+    enqueued_at timestamptz NOT NULL,
+    PRIMARY KEY (subscriber_id, list_id)
+);
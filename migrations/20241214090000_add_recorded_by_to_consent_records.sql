@@ -0,0 +1,3 @@
+-- The admin who recorded consent on a subscriber's behalf, e.g. by
+-- confirming them by hand. NULL when the subscriber acted themselves.
+ALTER TABLE consent_records ADD COLUMN recorded_by uuid REFERENCES users (user_id);
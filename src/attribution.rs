@@ -0,0 +1,226 @@
+//! src/attribution.rs
+//!
+//! Which campaigns and pages bring in subscribers.
+
+use actix_web::http::header::REFERER;
+use actix_web::HttpRequest;
+use anyhow::Context;
+use sqlx::PgPool;
+
+use crate::startup::HmacSecret;
+use crate::subscribers::SubscriberFilter;
+
+/// Longer values are cut short rather than turned down: a sign-up must not
+/// fail over a campaign tag.
+const MAX_TAG_LENGTH: usize = 200;
+const MAX_REFERRER_LENGTH: usize = 2000;
+
+/// Where a sign-up came from, as reported by the form and the browser.
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+pub struct Attribution {
+    #[serde(default)]
+    pub utm_source: Option<String>,
+    #[serde(default)]
+    pub utm_medium: Option<String>,
+    #[serde(default)]
+    pub utm_campaign: Option<String>,
+    /// A tag of the site's choosing, such as the page embedding the form.
+    #[serde(default)]
+    pub source: Option<String>,
+    /// The page that linked to our form, as the home page captured it when
+    /// serving the form. Only trusted along with a valid signature.
+    #[serde(default)]
+    pub referrer: Option<String>,
+    #[serde(default)]
+    pub referrer_signature: Option<String>,
+}
+
+impl Attribution {
+    /// The attribution a sign-up sent, tidied up, along with the page it came
+    /// from: the one our form was reached from, failing that the page the
+    /// browser says the sign-up was sent from, our own pages aside.
+    pub fn for_sign_up(&self, request: &HttpRequest, secret: &HmacSecret) -> Self {
+        let captured = self
+            .referrer
+            .as_deref()
+            .zip(self.referrer_signature.as_deref())
+            .filter(|(referrer, signature)| secret.verify(&referrer_message(referrer), signature))
+            .map(|(referrer, _)| referrer.to_owned());
+
+        Self {
+            utm_source: clean(self.utm_source.as_deref(), MAX_TAG_LENGTH),
+            utm_medium: clean(self.utm_medium.as_deref(), MAX_TAG_LENGTH),
+            utm_campaign: clean(self.utm_campaign.as_deref(), MAX_TAG_LENGTH),
+            source: clean(self.source.as_deref(), MAX_TAG_LENGTH),
+            referrer: captured.or_else(|| external_referrer(request)),
+            referrer_signature: None,
+        }
+    }
+}
+
+/// The page a request was sent from, unless it is one of ours: a sign-up
+/// through our form would otherwise always be referred by the home page.
+pub fn external_referrer(request: &HttpRequest) -> Option<String> {
+    let referrer = request
+        .headers()
+        .get(REFERER)
+        .and_then(|value| value.to_str().ok())?;
+    let own_host = request.connection_info().host().to_owned();
+
+    clean(Some(referrer), MAX_REFERRER_LENGTH)
+        .filter(|referrer| !referrer_host(referrer).eq_ignore_ascii_case(&own_host))
+}
+
+/// The signature the home page sends along with the referrer it captured.
+pub fn sign_referrer(referrer: &str, secret: &HmacSecret) -> String {
+    secret.sign(&referrer_message(referrer))
+}
+
+fn referrer_message(referrer: &str) -> String {
+    format!("referrer.{}", referrer)
+}
+
+/// The host, port included, of an absolute URL.
+fn referrer_host(referrer: &str) -> &str {
+    let rest = referrer
+        .split_once("://")
+        .map_or(referrer, |(_, rest)| rest);
+    let end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    &rest[..end]
+}
+
+fn clean(value: Option<&str>, max_length: usize) -> Option<String> {
+    value
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(|value| value.chars().take(max_length).collect())
+}
+
+pub struct SourceCount {
+    pub source: String,
+    pub sign_ups: i64,
+}
+
+/// How many subscribers matching `filter` each source brought in, the
+/// biggest first.
+///
+/// A sign-up's source is its `source` tag, failing that its `utm_source`,
+/// failing that the host it was referred from. Sign-ups with none of them
+/// count as `(direct)`.
+#[tracing::instrument(name = "Count sign-ups by source", skip(pool))]
+pub async fn count_sign_ups_by_source(
+    pool: &PgPool,
+    filter: &SubscriberFilter,
+) -> Result<Vec<SourceCount>, anyhow::Error> {
+    sqlx::query_as!(
+        SourceCount,
+        r#"
+        SELECT
+            COALESCE(
+                source,
+                utm_source,
+                lower(substring(referrer FROM '^[A-Za-z][A-Za-z0-9+.-]*://([^/:?#]+)')),
+                '(direct)'
+            ) AS "source!",
+            COUNT(*) AS "sign_ups!"
+        FROM subscriptions
+        WHERE ($1::text IS NULL OR email ILIKE $1 OR name ILIKE $1)
+            AND ($2::text IS NULL OR status = $2)
+            AND ($3::timestamptz IS NULL OR subscribed_at >= $3)
+            AND ($4::timestamptz IS NULL OR subscribed_at < $4)
+        GROUP BY 1
+        ORDER BY 2 DESC, 1
+        "#,
+        filter.search_pattern(),
+        filter.status,
+        filter.subscribed_from,
+        filter.subscribed_before,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to count sign-ups by source.")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sign_referrer, Attribution};
+    use crate::startup::HmacSecret;
+    use actix_web::test::TestRequest;
+    use redact::Secret;
+
+    fn secret() -> HmacSecret {
+        HmacSecret(Secret::new("a-long-and-random-secret".into()))
+    }
+
+    #[test]
+    fn blank_values_are_dropped_and_long_ones_cut_short() {
+        let sent = Attribution {
+            utm_source: Some("  newsletter-swap ".into()),
+            utm_medium: Some("   ".into()),
+            utm_campaign: Some("x".repeat(500)),
+            source: None,
+            // Whatever the form claims without a signature, the header counts
+            referrer: Some("https://forged.example".into()),
+            referrer_signature: Some("forged".into()),
+        };
+        let request = TestRequest::default()
+            .insert_header(("Referer", "https://blog.example/post"))
+            .to_http_request();
+
+        let attribution = sent.for_sign_up(&request, &secret());
+
+        assert_eq!(attribution.utm_source.as_deref(), Some("newsletter-swap"));
+        assert_eq!(attribution.utm_medium, None);
+        assert_eq!(attribution.utm_campaign.unwrap().len(), 200);
+        assert_eq!(attribution.source, None);
+        assert_eq!(
+            attribution.referrer.as_deref(),
+            Some("https://blog.example/post")
+        );
+    }
+
+    #[test]
+    fn there_is_no_referrer_without_the_header() {
+        let request = TestRequest::default().to_http_request();
+
+        assert_eq!(
+            Attribution::default()
+                .for_sign_up(&request, &secret())
+                .referrer,
+            None
+        );
+    }
+
+    #[test]
+    fn a_referrer_signed_by_the_home_page_beats_the_header() {
+        let referrer = "https://blog.example/post";
+        let sent = Attribution {
+            referrer: Some(referrer.into()),
+            referrer_signature: Some(sign_referrer(referrer, &secret())),
+            ..Attribution::default()
+        };
+        let request = TestRequest::default()
+            .insert_header(("Host", "newsletter.example"))
+            .insert_header(("Referer", "https://newsletter.example/"))
+            .to_http_request();
+
+        let attribution = sent.for_sign_up(&request, &secret());
+
+        assert_eq!(attribution.referrer.as_deref(), Some(referrer));
+    }
+
+    #[test]
+    fn our_own_pages_are_not_referrers() {
+        let request = TestRequest::default()
+            .insert_header(("Host", "newsletter.example"))
+            .insert_header(("Referer", "https://Newsletter.example/?utm_source=x"))
+            .to_http_request();
+
+        assert_eq!(
+            Attribution::default()
+                .for_sign_up(&request, &secret())
+                .referrer,
+            None
+        );
+    }
+}
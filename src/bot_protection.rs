@@ -0,0 +1,257 @@
+//! src/bot_protection.rs
+//!
+//! Telling sign-ups made by people from those made by scripts, before a
+//! confirmation email goes out.
+
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+
+use crate::configuration::BotProtectionSettings;
+use crate::domain::FormToken;
+use crate::routes::FormData;
+use crate::startup::HmacSecret;
+
+#[derive(Debug, PartialEq)]
+pub enum BotCheck {
+    Passed,
+    /// The honeypot was filled in. The sign-up is dropped without a word,
+    /// so that the bot does not learn it was caught.
+    Caught,
+    /// Turned down, telling the sender why in case they are a person.
+    Rejected(String),
+}
+
+/// What the checks need, shared by the form and the endpoint it posts to.
+pub struct BotProtection {
+    settings: BotProtectionSettings,
+    secret: HmacSecret,
+}
+
+impl BotProtection {
+    pub fn new(settings: BotProtectionSettings, secret: HmacSecret) -> Self {
+        Self { settings, secret }
+    }
+
+    /// The token to serve with a copy of the form.
+    pub fn issue_form_token(&self, now: DateTime<Utc>) -> FormToken {
+        FormToken::generate(now, &self.secret)
+    }
+
+    pub fn proof_of_work_bits(&self) -> u32 {
+        self.settings.proof_of_work_bits
+    }
+
+    pub fn check_sign_up(&self, form: &FormData, now: DateTime<Utc>) -> BotCheck {
+        check_sign_up(form, &self.settings, &self.secret, now)
+    }
+}
+
+/// Run the checks `settings` turn on against a sign-up through the form.
+pub fn check_sign_up(
+    form: &FormData,
+    settings: &BotProtectionSettings,
+    secret: &HmacSecret,
+    now: DateTime<Utc>,
+) -> BotCheck {
+    if form
+        .website
+        .as_deref()
+        .is_some_and(|value| !value.is_empty())
+    {
+        return BotCheck::Caught;
+    }
+    if !settings.require_form_token {
+        return BotCheck::Passed;
+    }
+
+    let form_token = match form
+        .form_token
+        .as_deref()
+        .map(|token| FormToken::parse(token, secret))
+    {
+        Some(Ok(form_token)) => form_token,
+        Some(Err(e)) => return BotCheck::Rejected(e),
+        None => {
+            return BotCheck::Rejected(
+                "The form is invalid. Please reload the page and try again.".into(),
+            )
+        }
+    };
+
+    let elapsed = now - form_token.issued_at();
+    if elapsed < settings.min_fill_time() {
+        return BotCheck::Rejected(
+            "The form was sent too quickly. Please wait a moment and try again.".into(),
+        );
+    }
+    if elapsed > settings.form_token_ttl() {
+        return BotCheck::Rejected(
+            "The form has expired. Please reload the page and try again.".into(),
+        );
+    }
+
+    if settings.proof_of_work_bits > 0 {
+        let challenge = proof_of_work_challenge(&form_token.to_string(), &form.email);
+        let solved = form
+            .proof_of_work
+            .as_deref()
+            .is_some_and(|nonce| solves_challenge(&challenge, nonce, settings.proof_of_work_bits));
+        if !solved {
+            return BotCheck::Rejected(
+                "The form could not be verified. Please reload the page and try again.".into(),
+            );
+        }
+    }
+
+    BotCheck::Passed
+}
+
+/// What the form has to find a nonce for: a fresh token for every page
+/// load, tied to the address so one solution cannot sign up many.
+///
+/// The address is only trimmed and lowercased, as the form's script does.
+pub fn proof_of_work_challenge(form_token: &str, email: &str) -> String {
+    format!("{}:{}", form_token, email.trim().to_lowercase())
+}
+
+/// Whether the SHA-256 of `<challenge>:<nonce>` starts with `bits` zero bits.
+pub fn solves_challenge(challenge: &str, nonce: &str, bits: u32) -> bool {
+    let hash = Sha256::digest(format!("{}:{}", challenge, nonce).as_bytes());
+
+    let mut remaining = bits;
+    for byte in hash {
+        if remaining == 0 {
+            break;
+        }
+        if remaining >= 8 {
+            if byte != 0 {
+                return false;
+            }
+            remaining -= 8;
+        } else {
+            return byte.leading_zeros() >= remaining;
+        }
+    }
+
+    remaining == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use chrono::{TimeDelta, Utc};
+    use redact::Secret;
+
+    use super::{check_sign_up, proof_of_work_challenge, solves_challenge, BotCheck};
+    use crate::attribution::Attribution;
+    use crate::configuration::BotProtectionSettings;
+    use crate::domain::FormToken;
+    use crate::routes::FormData;
+    use crate::startup::HmacSecret;
+
+    fn secret() -> HmacSecret {
+        HmacSecret(Secret::new("super-secret".to_string()))
+    }
+
+    fn settings(proof_of_work_bits: u32) -> BotProtectionSettings {
+        BotProtectionSettings {
+            require_form_token: true,
+            min_fill_seconds: 3,
+            form_token_ttl_minutes: 120,
+            proof_of_work_bits,
+        }
+    }
+
+    fn form(form_token: Option<String>, proof_of_work: Option<String>) -> FormData {
+        FormData {
+            name: "Ursula".into(),
+            email: "Ursula@Domain.com ".into(),
+            list: None,
+            form_version: None,
+            website: None,
+            form_token,
+            proof_of_work,
+            attribution: Attribution::default(),
+            extra: HashMap::new(),
+        }
+    }
+
+    fn token(age: TimeDelta) -> Option<String> {
+        Some(FormToken::generate(Utc::now() - age, &secret()).to_string())
+    }
+
+    fn solve(challenge: &str, bits: u32) -> String {
+        (0u64..)
+            .map(|nonce| nonce.to_string())
+            .find(|nonce| solves_challenge(challenge, nonce, bits))
+            .unwrap()
+    }
+
+    fn check(form: &FormData, settings: &BotProtectionSettings) -> BotCheck {
+        check_sign_up(form, settings, &secret(), Utc::now())
+    }
+
+    #[test]
+    fn a_filled_in_honeypot_is_caught_even_without_tokens() {
+        let mut form = form(None, None);
+        form.website = Some("https://spam.example".into());
+        let settings = BotProtectionSettings {
+            require_form_token: false,
+            ..settings(0)
+        };
+
+        assert_eq!(check(&form, &settings), BotCheck::Caught);
+    }
+
+    #[test]
+    fn tokens_are_only_taken_between_the_fill_time_and_the_expiry() {
+        let test_cases = vec![
+            (None, false),
+            (Some("forged".to_string()), false),
+            (token(TimeDelta::seconds(1)), false),
+            (token(TimeDelta::seconds(10)), true),
+            (token(TimeDelta::minutes(119)), true),
+            (token(TimeDelta::minutes(121)), false),
+        ];
+
+        for (form_token, accepted) in test_cases {
+            let verdict = check(&form(form_token.clone(), None), &settings(0));
+            assert_eq!(verdict == BotCheck::Passed, accepted, "{:?}", form_token);
+        }
+    }
+
+    #[test]
+    fn the_proof_of_work_must_solve_the_challenge_for_the_address() {
+        let form_token = token(TimeDelta::seconds(10)).unwrap();
+        let challenge = proof_of_work_challenge(&form_token, "ursula@domain.com");
+        let nonce = solve(&challenge, 8);
+
+        assert_eq!(
+            check(
+                &form(Some(form_token.clone()), Some(nonce.clone())),
+                &settings(8)
+            ),
+            BotCheck::Passed
+        );
+        assert_ne!(
+            check(&form(Some(form_token.clone()), None), &settings(8)),
+            BotCheck::Passed
+        );
+        let mut other_address = form(Some(form_token), Some(nonce));
+        other_address.email = "le_guin@domain.com".into();
+        assert_ne!(check(&other_address, &settings(8)), BotCheck::Passed);
+    }
+
+    #[test]
+    fn zero_bits_are_always_solved() {
+        assert!(solves_challenge("challenge", "anything", 0));
+    }
+
+    #[test]
+    fn partial_bytes_are_counted_bit_by_bit() {
+        let nonce = solve("challenge", 12);
+        assert!(solves_challenge("challenge", &nonce, 12));
+        assert!(solves_challenge("challenge", &nonce, 9));
+    }
+}
@@ -0,0 +1,107 @@
+//! src/client_ip.rs
+
+use std::net::IpAddr;
+
+use actix_web::HttpRequest;
+
+/// Reverse proxies whose `X-Forwarded-For` entries we believe.
+#[derive(Clone, Debug, Default)]
+pub struct TrustedProxies(pub Vec<IpAddr>);
+
+impl TrustedProxies {
+    /// The address of the client behind `request`.
+    ///
+    /// `X-Forwarded-For` is read right to left, each trusted proxy vouching
+    /// for the hop before it: the first address no trusted proxy vouches for
+    /// is the client. Entries further left were written by the client itself
+    /// and are ignored, so they cannot be used to spoof an address.
+    pub fn client_ip(&self, request: &HttpRequest) -> Option<IpAddr> {
+        let mut client = request.peer_addr()?.ip();
+
+        let forwarded_for: Vec<&str> = request
+            .headers()
+            .get_all("X-Forwarded-For")
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect();
+
+        for hop in forwarded_for.into_iter().rev() {
+            if !self.0.contains(&client) {
+                break;
+            }
+
+            match hop.parse() {
+                Ok(ip) => client = ip,
+                Err(_) => break,
+            }
+        }
+
+        Some(client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TrustedProxies;
+    use actix_web::test::TestRequest;
+    use std::net::{IpAddr, SocketAddr};
+
+    const PROXY: &str = "10.0.0.1";
+
+    fn request(peer: &str, forwarded_for: Option<&str>) -> actix_web::HttpRequest {
+        let peer: IpAddr = peer.parse().unwrap();
+        let request = TestRequest::default().peer_addr(SocketAddr::new(peer, 4242));
+
+        match forwarded_for {
+            Some(value) => request.insert_header(("X-Forwarded-For", value)),
+            None => request,
+        }
+        .to_http_request()
+    }
+
+    fn trusted() -> TrustedProxies {
+        TrustedProxies(vec![PROXY.parse().unwrap()])
+    }
+
+    fn ip(value: &str) -> Option<IpAddr> {
+        Some(value.parse().unwrap())
+    }
+
+    #[test]
+    fn without_a_proxy_the_peer_is_the_client() {
+        let request = request("203.0.113.7", None);
+        assert_eq!(trusted().client_ip(&request), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn forwarded_for_is_ignored_when_the_peer_is_not_trusted() {
+        let request = request("203.0.113.7", Some("198.51.100.1"));
+        assert_eq!(trusted().client_ip(&request), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn a_trusted_proxy_vouches_for_the_last_hop() {
+        let request = request(PROXY, Some("198.51.100.1"));
+        assert_eq!(trusted().client_ip(&request), ip("198.51.100.1"));
+    }
+
+    #[test]
+    fn addresses_supplied_by_the_client_are_ignored() {
+        // The client sent `X-Forwarded-For: 192.0.2.66` to pose as someone else
+        let request = request(PROXY, Some("192.0.2.66, 198.51.100.1"));
+        assert_eq!(trusted().client_ip(&request), ip("198.51.100.1"));
+    }
+
+    #[test]
+    fn chains_of_trusted_proxies_are_followed() {
+        let request = request(PROXY, Some("198.51.100.1, 10.0.0.1"));
+        assert_eq!(trusted().client_ip(&request), ip("198.51.100.1"));
+    }
+
+    #[test]
+    fn a_malformed_hop_stops_at_the_last_trusted_address() {
+        let request = request(PROXY, Some("not-an-ip"));
+        assert_eq!(trusted().client_ip(&request), ip(PROXY));
+    }
+}
@@ -1,13 +1,24 @@
 //! src/configuration.rs
 
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
 use std::time::Duration;
 
+use chrono::TimeDelta;
 use config::ConfigError;
 use redact::Secret;
 use serde_aux::field_attributes::deserialize_number_from_string;
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
+use sqlx::PgPool;
 
-use crate::{domain::SubscriberEmail, email_client::EmailClient};
+use crate::email_validation::{
+    DisposableDomainCheck, DnsMxResolver, DomainRuleCheck, EmailCheck, EmailValidator, MxCheck,
+    MxResolver, TypoCheck,
+};
+use crate::{
+    domain::SubscriberEmail,
+    email_client::{EmailClient, PostmarkWebhookCredentials},
+};
 
 #[derive(serde::Deserialize, Clone)]
 pub struct Settings {
@@ -15,6 +26,8 @@ pub struct Settings {
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
     pub redis_uri: Secret<String>,
+    pub pending_subscribers: PendingSubscriberSettings,
+    pub email_validation: EmailValidationSettings,
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -25,6 +38,78 @@ pub struct ApplicationSettings {
     pub name: String,
     pub base_url: String,
     pub hmac_secret: Secret<String>,
+    /// Thank-you page on the marketing site, shown after a confirmation.
+    pub confirmation_redirect_url: Option<String>,
+    /// Load balancers allowed to report the client's address via
+    /// `X-Forwarded-For`.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+    pub rate_limits: RateLimitSettings,
+    pub bot_protection: BotProtectionSettings,
+}
+
+/// Checks on sign-ups through the subscription form, to keep bots out.
+#[derive(serde::Deserialize, Clone)]
+pub struct BotProtectionSettings {
+    /// Only take sign-ups carrying a token issued with the form.
+    pub require_form_token: bool,
+    /// Sign-ups sent sooner than this after the form was served are
+    /// turned down: people take a while to type.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub min_fill_seconds: i64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub form_token_ttl_minutes: i64,
+    /// How many leading zero bits the proof of work solved by the form
+    /// must have. 0 turns it off; it needs form tokens to be required.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub proof_of_work_bits: u32,
+}
+
+impl BotProtectionSettings {
+    pub fn min_fill_time(&self) -> TimeDelta {
+        TimeDelta::seconds(self.min_fill_seconds)
+    }
+
+    pub fn form_token_ttl(&self) -> TimeDelta {
+        TimeDelta::minutes(self.form_token_ttl_minutes)
+    }
+}
+
+/// How often the public endpoints may be called, per route.
+#[derive(serde::Deserialize, Clone)]
+pub struct RateLimitSettings {
+    /// Prepended to the Redis keys the counters are kept under.
+    pub key_prefix: String,
+    /// Signing up, through the form or the API. Each sign-up sends an email.
+    pub subscribe: RouteRateLimits,
+    pub confirm: RouteRateLimits,
+    pub login: RouteRateLimits,
+}
+
+/// The limits on one route. A route without any is not limited.
+#[derive(serde::Deserialize, Clone, Debug, Default)]
+pub struct RouteRateLimits {
+    /// Requests from one client address.
+    #[serde(default)]
+    pub per_ip: Option<WindowLimit>,
+    /// Requests about one email address, read from the body.
+    #[serde(default)]
+    pub per_email: Option<WindowLimit>,
+}
+
+/// At most `max_requests` in any `window_seconds`-long stretch of time.
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+pub struct WindowLimit {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_requests: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub window_seconds: u64,
+}
+
+impl WindowLimit {
+    pub fn window(&self) -> Duration {
+        Duration::from_secs(self.window_seconds)
+    }
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -44,6 +129,86 @@ pub struct EmailClientSettings {
     pub sender_email: String,
     pub authorization_token: Secret<String>,
     pub timeout_milliseconds: u64,
+    /// The user name Postmark sends along with `webhook_secret` when it
+    /// calls our webhook with basic auth.
+    pub webhook_username: String,
+    pub webhook_secret: Secret<String>,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct PendingSubscriberSettings {
+    /// How often the background worker looks for pending subscribers.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub poll_interval_seconds: u64,
+    /// How long to wait after the sign-up, or the previous reminder,
+    /// before reminding a subscriber to confirm.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub reminder_after_hours: i64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_reminders: i64,
+    /// How long an unconfirmed sign-up is kept before being deleted.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub retention_days: i64,
+}
+
+/// Checks run on an address at sign-up, beyond its syntax.
+#[derive(serde::Deserialize, Clone)]
+pub struct EmailValidationSettings {
+    /// Apply the domains admins allowed or denied.
+    pub apply_domain_rules: bool,
+    /// Reject the throwaway providers bundled with the application.
+    pub block_disposable_domains: bool,
+    /// Reject near misses of popular providers, suggesting the fix.
+    pub suggest_typo_fixes: bool,
+    /// Reject domains with nowhere to deliver mail to.
+    pub check_mx_records: bool,
+    pub nameserver: SocketAddr,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub mx_lookup_timeout_milliseconds: u64,
+}
+
+impl EmailValidationSettings {
+    pub fn mx_resolver(&self) -> DnsMxResolver {
+        DnsMxResolver::new(
+            self.nameserver,
+            Duration::from_millis(self.mx_lookup_timeout_milliseconds),
+        )
+    }
+
+    pub fn validator(&self, pool: PgPool, mx_resolver: Arc<dyn MxResolver>) -> EmailValidator {
+        let mut checks: Vec<Box<dyn EmailCheck>> = Vec::new();
+
+        // Admin rules go first, so an allowed domain skips the other checks.
+        if self.apply_domain_rules {
+            checks.push(Box::new(DomainRuleCheck::new(pool)));
+        }
+        if self.block_disposable_domains {
+            checks.push(Box::new(DisposableDomainCheck::default()));
+        }
+        if self.suggest_typo_fixes {
+            checks.push(Box::new(TypoCheck));
+        }
+        // The only check that goes over the network comes last.
+        if self.check_mx_records {
+            checks.push(Box::new(MxCheck::new(mx_resolver)));
+        }
+
+        EmailValidator::new(checks)
+    }
+}
+
+impl PendingSubscriberSettings {
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_secs(self.poll_interval_seconds)
+    }
+
+    pub fn reminder_after(&self) -> TimeDelta {
+        TimeDelta::hours(self.reminder_after_hours)
+    }
+
+    pub fn retention(&self) -> TimeDelta {
+        TimeDelta::days(self.retention_days)
+    }
 }
 
 impl EmailClientSettings {
@@ -55,6 +220,13 @@ impl EmailClientSettings {
         Duration::from_millis(self.timeout_milliseconds)
     }
 
+    pub fn webhook_credentials(&self) -> PostmarkWebhookCredentials {
+        PostmarkWebhookCredentials {
+            username: self.webhook_username.clone(),
+            secret: self.webhook_secret.clone(),
+        }
+    }
+
     pub fn client(self) -> EmailClient {
         let sender_email = self.sender().expect("Invalid sender email address.");
         let timeout = self.timeout();
@@ -0,0 +1,212 @@
+//! src/consent.rs
+
+use actix_web::http::header::USER_AGENT;
+use actix_web::HttpRequest;
+use chrono::{DateTime, Utc};
+use sqlx::{Executor, PgExecutor, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::client_ip::TrustedProxies;
+use crate::domain::SubscriptionToken;
+
+/// Version of the confirmation page, recorded alongside each confirmation.
+pub const CONFIRMATION_FORM_VERSION: &str = "confirmation-page-v1";
+
+/// Version of the preference center, recorded when it is used to join a list.
+pub const PREFERENCE_CENTER_FORM_VERSION: &str = "preference-center-v1";
+
+/// Version of the admin subscriber page, recorded when an admin confirms a
+/// subscriber by hand.
+pub const ADMIN_CONFIRMATION_FORM_VERSION: &str = "admin-subscriber-page-v1";
+
+/// Longer values sent by clients are cut short rather than turned down, like
+/// campaign tags: a sign-up must not fail over them, nor fill the table.
+const MAX_FORM_VERSION_LENGTH: usize = 100;
+const MAX_USER_AGENT_LENGTH: usize = 500;
+
+pub enum ConsentEvent {
+    SignUp,
+    Confirmation,
+    /// Consent given elsewhere, brought over by an admin's import.
+    Import,
+    /// Confirmation given outside of the confirmation page, e.g. by replying
+    /// to an email, and recorded by an admin.
+    AdminConfirmation,
+}
+
+impl AsRef<str> for ConsentEvent {
+    fn as_ref(&self) -> &str {
+        match self {
+            Self::SignUp => "sign_up",
+            Self::Confirmation => "confirmation",
+            Self::Import => "import",
+            Self::AdminConfirmation => "admin_confirmation",
+        }
+    }
+}
+
+/// Who gave consent, and through which form.
+#[derive(Debug, Default)]
+pub struct ConsentContext {
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub form_version: Option<String>,
+}
+
+impl ConsentContext {
+    /// The context of a request, `form_version` being whatever the client
+    /// says it filled in.
+    pub fn from_request(
+        request: &HttpRequest,
+        trusted_proxies: &TrustedProxies,
+        form_version: Option<String>,
+    ) -> Self {
+        Self {
+            ip_address: trusted_proxies.client_ip(request).map(|ip| ip.to_string()),
+            user_agent: request
+                .headers()
+                .get(USER_AGENT)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| clean(value, MAX_USER_AGENT_LENGTH)),
+            form_version: form_version.and_then(|value| clean(&value, MAX_FORM_VERSION_LENGTH)),
+        }
+    }
+}
+
+/// Trimmed, without control characters and at most `max_length` characters
+/// long; `None` if nothing is left.
+fn clean(value: &str, max_length: usize) -> Option<String> {
+    let value: String = value
+        .trim()
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(max_length)
+        .collect();
+    (!value.is_empty()).then_some(value)
+}
+
+#[tracing::instrument(
+    name = "Record consent",
+    skip(transaction, event, context, subscription_token)
+)]
+pub async fn record_consent(
+    transaction: &mut Transaction<'static, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+    event: ConsentEvent,
+    context: &ConsentContext,
+    subscription_token: &SubscriptionToken,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO consent_records (
+            consent_record_id,
+            subscriber_id,
+            list_id,
+            event,
+            recorded_at,
+            ip_address,
+            user_agent,
+            form_version,
+            subscription_token_hash
+        )
+        VALUES ($1, $2, $3, $4, now(), $5, $6, $7, $8)
+        "#,
+        Uuid::new_v4(),
+        subscriber_id,
+        list_id,
+        event.as_ref(),
+        context.ip_address,
+        context.user_agent,
+        context.form_version,
+        subscription_token.hash()
+    );
+
+    transaction.execute(query).await?;
+
+    Ok(())
+}
+
+/// Record that the admin `user_id` confirmed the subscriber's memberships of
+/// `list_ids` on their behalf.
+#[tracing::instrument(name = "Record an admin confirmation", skip(transaction))]
+pub async fn record_admin_confirmation(
+    transaction: &mut Transaction<'static, Postgres>,
+    subscriber_id: Uuid,
+    list_ids: &[Uuid],
+    user_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO consent_records (
+            consent_record_id,
+            subscriber_id,
+            list_id,
+            event,
+            recorded_at,
+            form_version,
+            recorded_by
+        )
+        SELECT gen_random_uuid(), $1, list_id, $3, now(), $4, $5
+        FROM UNNEST($2::uuid[]) AS rows(list_id)
+        "#,
+        subscriber_id,
+        list_ids,
+        ConsentEvent::AdminConfirmation.as_ref(),
+        ADMIN_CONFIRMATION_FORM_VERSION,
+        user_id
+    );
+
+    transaction.execute(query).await?;
+
+    Ok(())
+}
+
+/// One entry of a subscriber's consent trail.
+///
+/// The token hash is shown to admins but left out of data exports, which
+/// never carry credentials. So is the admin who recorded the entry: the event
+/// already says it was done on the subscriber's behalf.
+#[derive(serde::Serialize)]
+pub struct ConsentRecord {
+    pub list: String,
+    pub event: String,
+    pub recorded_at: DateTime<Utc>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub form_version: Option<String>,
+    #[serde(skip)]
+    pub subscription_token_hash: Option<String>,
+    #[serde(skip)]
+    pub recorded_by: Option<String>,
+}
+
+/// A subscriber's consent records, oldest first.
+#[tracing::instrument(name = "Get consent trail", skip(executor))]
+pub async fn get_consent_trail(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+) -> Result<Vec<ConsentRecord>, sqlx::Error> {
+    sqlx::query_as!(
+        ConsentRecord,
+        r#"
+        SELECT
+            lists.slug AS list,
+            consent_records.event,
+            consent_records.recorded_at,
+            consent_records.ip_address,
+            consent_records.user_agent,
+            consent_records.form_version,
+            consent_records.subscription_token_hash,
+            users.username AS "recorded_by?"
+        FROM consent_records
+        JOIN lists ON lists.list_id = consent_records.list_id
+        LEFT JOIN users ON users.user_id = consent_records.recorded_by
+        WHERE consent_records.subscriber_id = $1
+        ORDER BY consent_records.recorded_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(executor)
+    .await
+}
@@ -0,0 +1,312 @@
+//! src/csv_file.rs
+//!
+//! The CSV files admins upload and download. Parsing is left to `csv-core`,
+//! which copes with what spreadsheets commonly produce: bare `\n` line
+//! endings and blank lines. Files we hand out are written with `format_row`.
+
+use std::borrow::Cow;
+
+use csv::{Terminator, WriterBuilder};
+use csv_core::ReadRecordResult;
+
+const BYTE_ORDER_MARK: &[u8] = b"\xEF\xBB\xBF";
+
+/// One row of the file, with the line it starts on for error reports.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Record {
+    pub line: usize,
+    pub fields: Vec<String>,
+}
+
+/// A record that is not valid UTF-8. Only that record is lost: reading
+/// carries on with the next one.
+#[derive(Debug, PartialEq, Eq)]
+pub struct EncodingError {
+    pub line: usize,
+}
+
+impl std::fmt::Display for EncodingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Line {}: the file is not UTF-8 encoded.", self.line)
+    }
+}
+
+/// Reads a file handed to it a chunk at a time, so that it never has to be
+/// held in memory whole.
+pub struct RecordReader {
+    reader: csv_core::Reader,
+    /// The line the next byte of input is on.
+    line: usize,
+    /// The line the record being read starts on, if one is.
+    record_line: Option<usize>,
+    output: Vec<u8>,
+    output_len: usize,
+    ends: Vec<usize>,
+    ends_len: usize,
+    at_start: bool,
+}
+
+impl Default for RecordReader {
+    fn default() -> Self {
+        Self {
+            reader: csv_core::Reader::new(),
+            line: 1,
+            record_line: None,
+            output: vec![0; 1024],
+            output_len: 0,
+            ends: vec![0; 16],
+            ends_len: 0,
+            at_start: true,
+        }
+    }
+}
+
+impl RecordReader {
+    /// The records completed by the next chunk of the file.
+    pub fn push(&mut self, mut input: &[u8]) -> Vec<Result<Record, EncodingError>> {
+        if self.at_start && !input.is_empty() {
+            self.at_start = false;
+            input = input.strip_prefix(BYTE_ORDER_MARK).unwrap_or(input);
+        }
+
+        let mut records = Vec::new();
+        loop {
+            if self.record_line.is_none() {
+                // Skip blank lines here rather than in the parser, to know
+                // which line the record starts on.
+                let blank = input
+                    .iter()
+                    .take_while(|byte| matches!(byte, b'\r' | b'\n'))
+                    .count();
+                self.advance(&input[..blank]);
+                input = &input[blank..];
+                if !input.is_empty() {
+                    self.record_line = Some(self.line);
+                }
+            }
+            // An empty input would tell the parser the file is over.
+            if input.is_empty() {
+                return records;
+            }
+
+            let (result, n_in) = self.read(input);
+            self.advance(&input[..n_in]);
+            input = &input[n_in..];
+            if let ReadRecordResult::Record = result {
+                records.push(self.take_record());
+            }
+        }
+    }
+
+    /// The last record, once the whole file has been pushed.
+    pub fn finish(&mut self) -> Option<Result<Record, EncodingError>> {
+        loop {
+            match self.read(&[]).0 {
+                ReadRecordResult::Record => return Some(self.take_record()),
+                ReadRecordResult::End => return None,
+                _ => {}
+            }
+        }
+    }
+
+    /// Parse as much of `input` as possible, making room for the output as
+    /// needed. Returns how many bytes were read.
+    fn read(&mut self, input: &[u8]) -> (ReadRecordResult, usize) {
+        let (result, n_in, n_out, n_ends) = self.reader.read_record(
+            input,
+            &mut self.output[self.output_len..],
+            &mut self.ends[self.ends_len..],
+        );
+        self.output_len += n_out;
+        self.ends_len += n_ends;
+
+        match result {
+            ReadRecordResult::OutputFull => self.output.resize(self.output.len() * 2, 0),
+            ReadRecordResult::OutputEndsFull => self.ends.resize(self.ends.len() * 2, 0),
+            _ => {}
+        }
+        (result, n_in)
+    }
+
+    fn advance(&mut self, consumed: &[u8]) {
+        self.line += consumed.iter().filter(|byte| **byte == b'\n').count();
+    }
+
+    fn take_record(&mut self) -> Result<Record, EncodingError> {
+        let line = self.record_line.take().unwrap_or(self.line);
+        let ends = &self.ends[..self.ends_len];
+        self.output_len = 0;
+        self.ends_len = 0;
+
+        let mut start = 0;
+        let mut fields = Vec::with_capacity(ends.len());
+        for &end in ends {
+            let field = std::str::from_utf8(&self.output[start..end])
+                .map_err(|_| EncodingError { line })?;
+            fields.push(field.to_owned());
+            start = end;
+        }
+
+        Ok(Record { line, fields })
+    }
+}
+
+/// Every record of a file held in memory, which must all be readable.
+pub fn parse(input: &[u8]) -> Result<Vec<Record>, String> {
+    let mut reader = RecordReader::default();
+    reader
+        .push(input)
+        .into_iter()
+        .chain(reader.finish())
+        .collect::<Result<_, _>>()
+        .map_err(|e| e.to_string())
+}
+
+/// Spreadsheets evaluate a cell starting with one of these as a formula.
+const FORMULA_PREFIXES: [char; 4] = ['=', '+', '-', '@'];
+
+/// One RFC 4180 row, `\r\n` included. Fields are quoted when they need to be.
+///
+/// The fields come from subscribers, so any that a spreadsheet would take for
+/// a formula is prefixed with `'` to be shown as text instead.
+pub fn format_row<S: AsRef<str>>(fields: &[S]) -> String {
+    let mut writer = WriterBuilder::new()
+        .terminator(Terminator::CRLF)
+        .from_writer(Vec::new());
+    writer
+        .write_record(
+            fields
+                .iter()
+                .map(|field| neutralize_formula(field.as_ref())),
+        )
+        .expect("Writing to memory cannot fail.");
+    let row = writer.into_inner().expect("Writing to memory cannot fail.");
+
+    String::from_utf8(row).expect("The fields are UTF-8.")
+}
+
+fn neutralize_formula(field: &str) -> Cow<'_, [u8]> {
+    if field.starts_with(FORMULA_PREFIXES) {
+        Cow::Owned(format!("'{}", field).into_bytes())
+    } else {
+        Cow::Borrowed(field.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_row, parse, EncodingError, Record, RecordReader};
+    use claims::assert_err;
+
+    fn record(line: usize, fields: &[&str]) -> Record {
+        Record {
+            line,
+            fields: fields.iter().map(|field| field.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn plain_fields_are_split_on_commas_and_line_breaks() {
+        let records = parse(b"email,reason\r\na@example.com,manual\nb@example.com,").unwrap();
+
+        assert_eq!(
+            records,
+            vec![
+                record(1, &["email", "reason"]),
+                record(2, &["a@example.com", "manual"]),
+                record(3, &["b@example.com", ""]),
+            ]
+        );
+    }
+
+    #[test]
+    fn quoted_fields_may_hold_commas_quotes_and_line_breaks() {
+        let records =
+            parse(b"\"a,b\",\"say \"\"hi\"\"\"\r\n\"two\r\nlines\",x\r\nlast,\"\"\r\n").unwrap();
+
+        assert_eq!(
+            records,
+            vec![
+                record(1, &["a,b", "say \"hi\""]),
+                record(2, &["two\r\nlines", "x"]),
+                record(4, &["last", ""]),
+            ]
+        );
+    }
+
+    #[test]
+    fn a_byte_order_mark_and_blank_lines_are_ignored() {
+        let records = parse("\u{feff}email\n\n\r\na@example.com\n".as_bytes()).unwrap();
+
+        assert_eq!(
+            records,
+            vec![record(1, &["email"]), record(4, &["a@example.com"])]
+        );
+    }
+
+    #[test]
+    fn records_may_be_split_across_chunks() {
+        let input = b"email,name\r\n\r\n\"a@example.com\",\"Le Guin, Ursula\"\r\nb@example.com,B";
+        let mut reader = RecordReader::default();
+
+        let mut records = Vec::new();
+        for chunk in input.chunks(3) {
+            records.extend(reader.push(chunk).into_iter().map(Result::unwrap));
+        }
+        records.extend(reader.finish().map(Result::unwrap));
+
+        assert_eq!(records, parse(input).unwrap());
+        assert_eq!(records[1], record(3, &["a@example.com", "Le Guin, Ursula"]));
+        assert_eq!(records[2], record(4, &["b@example.com", "B"]));
+    }
+
+    #[test]
+    fn a_file_that_is_not_utf8_is_rejected() {
+        assert_err!(parse(b"email\n\xE9@example.com\n"));
+    }
+
+    #[test]
+    fn reading_carries_on_after_a_record_that_is_not_utf8() {
+        let mut reader = RecordReader::default();
+
+        let records = reader.push(b"email\n\xE9@example.com\na@example.com\n");
+
+        assert_eq!(
+            records,
+            vec![
+                Ok(record(1, &["email"])),
+                Err(EncodingError { line: 2 }),
+                Ok(record(3, &["a@example.com"])),
+            ]
+        );
+    }
+
+    #[test]
+    fn formatted_rows_read_back_the_same() {
+        let fields = ["plain", "a,b", "say \"hi\"", "two\r\nlines", ""];
+        let row = format_row(&fields);
+
+        assert_eq!(
+            row,
+            "plain,\"a,b\",\"say \"\"hi\"\"\",\"two\r\nlines\",\r\n"
+        );
+        assert_eq!(parse(row.as_bytes()).unwrap(), vec![record(1, &fields)]);
+    }
+
+    #[test]
+    fn fields_a_spreadsheet_would_evaluate_are_written_as_text() {
+        let row = format_row(&[
+            "=HYPERLINK(\"http://evil.example\",\"x\")",
+            "+1",
+            "-1+2",
+            "@SUM(A1:A2)",
+            "a=b",
+            "",
+        ]);
+
+        assert_eq!(
+            row,
+            "\"'=HYPERLINK(\"\"http://evil.example\"\",\"\"x\"\")\",'+1,'-1+2,'@SUM(A1:A2),a=b,\r\n"
+        );
+    }
+}
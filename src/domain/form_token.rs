@@ -0,0 +1,138 @@
+//! src/domain/form_token.rs
+
+use chrono::{DateTime, Utc};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+
+use crate::startup::HmacSecret;
+
+/// A token handed out with the subscription form, vouching that a sign-up
+/// was made from a copy of the form we served, and when it was served.
+///
+/// It is rendered as `<issued_at>.<nonce>.<signature>`, the issue time being
+/// a Unix timestamp. The random nonce makes every token unique, so that it
+/// can double as a proof-of-work challenge.
+#[derive(Debug)]
+pub struct FormToken {
+    issued_at: i64,
+    nonce: String,
+    signature: String,
+}
+
+impl FormToken {
+    pub fn generate(issued_at: DateTime<Utc>, secret: &HmacSecret) -> Self {
+        let issued_at = issued_at.timestamp();
+        let nonce: String = thread_rng()
+            .sample_iter(Alphanumeric)
+            .map(char::from)
+            .take(16)
+            .collect();
+        let signature = secret.sign(&Self::message(issued_at, &nonce));
+
+        Self {
+            issued_at,
+            nonce,
+            signature,
+        }
+    }
+
+    /// Check the token's signature. How old it is, is up to the caller.
+    pub fn parse(input: &str, secret: &HmacSecret) -> Result<Self, String> {
+        let invalid = || "The form is invalid. Please reload the page and try again.".to_string();
+
+        let mut parts = input.splitn(3, '.');
+        let (Some(issued_at), Some(nonce), Some(signature)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+
+        let issued_at: i64 = issued_at.parse().map_err(|_| invalid())?;
+
+        if !secret.verify(&Self::message(issued_at, nonce), signature) {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            issued_at,
+            nonce: nonce.to_owned(),
+            signature: signature.to_owned(),
+        })
+    }
+
+    pub fn issued_at(&self) -> DateTime<Utc> {
+        DateTime::from_timestamp(self.issued_at, 0).unwrap_or_default()
+    }
+
+    fn message(issued_at: i64, nonce: &str) -> String {
+        format!("subscribe-form.{}.{}", issued_at, nonce)
+    }
+}
+
+impl std::fmt::Display for FormToken {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            formatter,
+            "{}.{}.{}",
+            self.issued_at, self.nonce, self.signature
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FormToken;
+    use crate::startup::HmacSecret;
+    use chrono::{TimeDelta, Utc};
+    use claims::{assert_err, assert_ok};
+    use redact::Secret;
+
+    fn secret(value: &str) -> HmacSecret {
+        HmacSecret(Secret::new(value.to_string()))
+    }
+
+    #[test]
+    fn a_generated_token_round_trips() {
+        let secret = secret("super-secret");
+        let issued_at = Utc::now() - TimeDelta::minutes(5);
+        let token = FormToken::generate(issued_at, &secret).to_string();
+
+        let parsed = assert_ok!(FormToken::parse(&token, &secret));
+        assert_eq!(parsed.issued_at().timestamp(), issued_at.timestamp());
+    }
+
+    #[test]
+    fn tokens_are_unique() {
+        let secret = secret("super-secret");
+        let now = Utc::now();
+
+        assert_ne!(
+            FormToken::generate(now, &secret).to_string(),
+            FormToken::generate(now, &secret).to_string()
+        );
+    }
+
+    #[test]
+    fn a_backdated_token_is_rejected() {
+        let secret = secret("super-secret");
+        let token = FormToken::generate(Utc::now(), &secret).to_string();
+        let (issued_at, rest) = token.split_once('.').unwrap();
+        let backdated = format!("{}.{}", issued_at.parse::<i64>().unwrap() - 60, rest);
+
+        assert_err!(FormToken::parse(&backdated, &secret));
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let token = FormToken::generate(Utc::now(), &secret("one")).to_string();
+        assert_err!(FormToken::parse(&token, &secret("two")));
+    }
+
+    #[test]
+    fn malformed_tokens_are_rejected() {
+        let secret = secret("super-secret");
+        for token in ["", "not-a-token", "yesterday.nonce.signature"] {
+            assert_err!(FormToken::parse(token, &secret));
+        }
+    }
+}
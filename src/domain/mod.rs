@@ -1,7 +1,17 @@
+mod form_token;
+mod manage_token;
 mod new_subscriber;
+mod subscriber_attributes;
 mod subscriber_email;
 mod subscriber_name;
+mod subscription_token;
+mod unsubscribe_token;
 
-pub use new_subscriber::NewSubscriber;
+pub use form_token::FormToken;
+pub use manage_token::ManageToken;
+pub use new_subscriber::{NewSubscriber, NewSubscriberError};
+pub use subscriber_attributes::SubscriberAttributes;
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
+pub use subscription_token::SubscriptionToken;
+pub use unsubscribe_token::UnsubscribeToken;
@@ -1,19 +1,154 @@
+use std::collections::HashMap;
+
+use serde_json::{Map, Value};
+
 use crate::routes::FormData;
 
-use super::{SubscriberEmail, SubscriberName};
+use super::{SubscriberAttributes, SubscriberEmail, SubscriberName};
 
+#[derive(Debug)]
 pub struct NewSubscriber {
     pub email: SubscriberEmail,
     pub name: SubscriberName,
+    pub attributes: SubscriberAttributes,
+}
+
+/// Which part of a sign-up failed validation, so callers can point at it.
+#[derive(Debug, thiserror::Error)]
+pub enum NewSubscriberError {
+    #[error("{0}")]
+    InvalidName(String),
+    #[error("{0}")]
+    InvalidEmail(String),
+    #[error("{0}")]
+    InvalidAttributes(String),
+}
+
+impl NewSubscriberError {
+    /// The name of the input field holding the offending value.
+    pub fn field(&self) -> &'static str {
+        match self {
+            Self::InvalidName(_) => "name",
+            Self::InvalidEmail(_) => "email",
+            Self::InvalidAttributes(_) => "attributes",
+        }
+    }
 }
 
 impl TryFrom<FormData> for NewSubscriber {
-    type Error = String;
+    type Error = NewSubscriberError;
 
     fn try_from(value: FormData) -> Result<Self, Self::Error> {
-        let name = SubscriberName::parse(value.name)?;
-        let email = SubscriberEmail::parse(value.email)?;
+        let name = SubscriberName::parse(value.name).map_err(NewSubscriberError::InvalidName)?;
+        let email =
+            SubscriberEmail::parse(value.email).map_err(NewSubscriberError::InvalidEmail)?;
+        let attributes =
+            collect_attributes(value.extra).map_err(NewSubscriberError::InvalidAttributes)?;
+
+        Ok(Self {
+            name,
+            email,
+            attributes,
+        })
+    }
+}
+
+/// JSON sign-ups send an `attributes` object, forms an `attributes[<name>]`
+/// field per attribute. Other unknown fields are ignored.
+fn collect_attributes(extra: HashMap<String, Value>) -> Result<SubscriberAttributes, String> {
+    let mut attributes = Map::new();
+
+    for (key, value) in extra {
+        if key == "attributes" {
+            match value {
+                Value::Object(object) => attributes.extend(object),
+                _ => return Err("attributes must be an object.".into()),
+            }
+        } else if let Some(name) = key
+            .strip_prefix("attributes[")
+            .and_then(|key| key.strip_suffix(']'))
+        {
+            attributes.insert(name.to_owned(), value);
+        }
+    }
+
+    SubscriberAttributes::parse(attributes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NewSubscriber, NewSubscriberError};
+    use crate::attribution::Attribution;
+    use crate::routes::FormData;
+    use claims::{assert_err, assert_ok};
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+
+    fn form(name: &str, email: &str) -> FormData {
+        FormData {
+            name: name.into(),
+            email: email.into(),
+            list: None,
+            form_version: None,
+            website: None,
+            form_token: None,
+            proof_of_work: None,
+            attribution: Attribution::default(),
+            extra: HashMap::new(),
+        }
+    }
+
+    fn form_with(extra: &[(&str, Value)]) -> FormData {
+        let mut form = form("Ursula", "ursula@domain.com");
+        form.extra = extra
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.clone()))
+            .collect();
+
+        form
+    }
+
+    #[test]
+    fn an_invalid_name_is_blamed_on_the_name_field() {
+        let error = assert_err!(NewSubscriber::try_from(form("", "ursula@domain.com")));
+        assert!(matches!(error, NewSubscriberError::InvalidName(_)));
+        assert_eq!(error.field(), "name");
+    }
+
+    #[test]
+    fn an_invalid_email_is_blamed_on_the_email_field() {
+        let error = assert_err!(NewSubscriber::try_from(form("Ursula", "ursula.com")));
+        assert!(matches!(error, NewSubscriberError::InvalidEmail(_)));
+        assert_eq!(error.field(), "email");
+    }
+
+    #[test]
+    fn attributes_come_from_a_json_object_or_bracketed_form_fields() {
+        let from_json = assert_ok!(NewSubscriber::try_from(form_with(&[(
+            "attributes",
+            json!({ "company": "Earthsea" })
+        )])));
+        let from_form = assert_ok!(NewSubscriber::try_from(form_with(&[
+            ("attributes[company]", json!("Earthsea")),
+            ("utm_source", json!("ignored")),
+        ])));
+
+        assert_eq!(from_json.attributes, from_form.attributes);
+        assert_eq!(from_form.attributes.as_ref().len(), 1);
+    }
+
+    #[test]
+    fn invalid_attributes_are_blamed_on_the_attributes_field() {
+        let error = assert_err!(NewSubscriber::try_from(form_with(&[(
+            "attributes",
+            json!(["company"])
+        )])));
+        assert_eq!(error.field(), "attributes");
 
-        Ok(Self { name, email })
+        let error = assert_err!(NewSubscriber::try_from(form_with(&[(
+            "attributes[first name]",
+            json!("Ursula")
+        )])));
+        assert_eq!(error.field(), "attributes");
     }
 }
@@ -0,0 +1,126 @@
+//! src/domain/subscriber_attributes.rs
+
+use serde_json::{Map, Value};
+use unicode_segmentation::UnicodeSegmentation;
+
+const MAX_ATTRIBUTES: usize = 50;
+const MAX_KEY_LENGTH: usize = 64;
+const MAX_VALUE_LENGTH: usize = 500;
+
+/// Free-form details about a subscriber, e.g. their company, available to
+/// newsletter issues as `{{ attributes.<key> }}` merge tags.
+///
+/// Values are kept flat: a string, a number or a boolean each.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SubscriberAttributes(Map<String, Value>);
+
+impl SubscriberAttributes {
+    pub fn parse(input: Map<String, Value>) -> Result<Self, String> {
+        if input.len() > MAX_ATTRIBUTES {
+            return Err(format!(
+                "A subscriber can have at most {} attributes.",
+                MAX_ATTRIBUTES
+            ));
+        }
+
+        for (key, value) in &input {
+            if !Self::is_valid_key(key) {
+                return Err(format!(
+                    "{} is not a valid attribute name. Use up to {} letters, digits \
+                    and underscores.",
+                    key, MAX_KEY_LENGTH
+                ));
+            }
+            match value {
+                Value::String(s) if s.graphemes(true).count() > MAX_VALUE_LENGTH => {
+                    return Err(format!(
+                        "The attribute {} is longer than {} characters.",
+                        key, MAX_VALUE_LENGTH
+                    ))
+                }
+                Value::String(_) | Value::Number(_) | Value::Bool(_) => {}
+                Value::Null | Value::Array(_) | Value::Object(_) => {
+                    return Err(format!(
+                        "The attribute {} must be a string, a number or a boolean.",
+                        key
+                    ))
+                }
+            }
+        }
+
+        Ok(Self(input))
+    }
+
+    pub fn is_valid_key(key: &str) -> bool {
+        !key.is_empty()
+            && key.len() <= MAX_KEY_LENGTH
+            && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.0.get(key)
+    }
+
+    pub fn into_inner(self) -> Map<String, Value> {
+        self.0
+    }
+}
+
+impl AsRef<Map<String, Value>> for SubscriberAttributes {
+    fn as_ref(&self) -> &Map<String, Value> {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SubscriberAttributes;
+    use claims::{assert_err, assert_ok};
+    use serde_json::{json, Map, Value};
+
+    fn attributes(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn flat_values_are_accepted() {
+        assert_ok!(SubscriberAttributes::parse(attributes(json!({
+            "company": "Earthsea Ltd",
+            "seats": 12,
+            "is_customer": true,
+        }))));
+    }
+
+    #[test]
+    fn nested_and_null_values_are_rejected() {
+        assert_err!(SubscriberAttributes::parse(attributes(
+            json!({ "a": null })
+        )));
+        assert_err!(SubscriberAttributes::parse(attributes(json!({ "a": [1] }))));
+        assert_err!(SubscriberAttributes::parse(attributes(json!({ "a": {} }))));
+    }
+
+    #[test]
+    fn keys_must_be_usable_in_merge_tags() {
+        assert_err!(SubscriberAttributes::parse(attributes(json!({ "": "x" }))));
+        assert_err!(SubscriberAttributes::parse(attributes(
+            json!({ "first name": "x" })
+        )));
+        assert_err!(SubscriberAttributes::parse(attributes(
+            json!({ "a.b": "x" })
+        )));
+        assert_err!(SubscriberAttributes::parse(attributes(
+            json!({ "a".repeat(65): "x" })
+        )));
+    }
+
+    #[test]
+    fn long_values_are_rejected() {
+        assert_ok!(SubscriberAttributes::parse(attributes(
+            json!({ "bio": "ё".repeat(500) })
+        )));
+        assert_err!(SubscriberAttributes::parse(attributes(
+            json!({ "bio": "a".repeat(501) })
+        )));
+    }
+}
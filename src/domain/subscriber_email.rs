@@ -2,37 +2,80 @@
 
 use validator::ValidateEmail;
 
+/// An email address, normalized for delivery and duplicate detection.
+///
+/// The domain is lowercased and converted to punycode, while the local part is
+/// kept as typed: it is up to the receiving server to interpret its case.
 #[derive(Debug)]
-pub struct SubscriberEmail(String);
+pub struct SubscriberEmail {
+    address: String,
+    display: String,
+}
 
 impl SubscriberEmail {
     pub fn parse(input: String) -> Result<Self, String> {
-        if input.validate_email() {
-            return Ok(Self(input));
+        let display = input.trim();
+
+        let address = display
+            .rsplit_once('@')
+            .and_then(|(local_part, domain)| {
+                let domain = idna::domain_to_ascii(domain).ok()?;
+                Some(format!("{}@{}", local_part, domain))
+            })
+            .filter(|address| address.validate_email());
+
+        match address {
+            Some(address) => Ok(Self {
+                address,
+                display: display.to_owned(),
+            }),
+            None => Err(format!("{} is not a valid subscriber email.", input)),
         }
+    }
+
+    /// The normalized domain, in punycode.
+    pub fn domain(&self) -> &str {
+        let (_, domain) = self
+            .address
+            .rsplit_once('@')
+            .expect("A parsed address contains an `@`.");
+        domain
+    }
+
+    /// The address as the subscriber typed it, surrounding whitespace aside.
+    pub fn display(&self) -> &str {
+        &self.display
+    }
 
-        Err(format!("{} is not a valid subscriber email.", input))
+    /// The form two addresses are compared in to tell whether they belong to
+    /// the same person.
+    ///
+    /// The local part is lowercased too: in theory it is case-sensitive, but
+    /// no mainstream provider treats it so and people do not type it the same
+    /// way twice.
+    pub fn canonical(&self) -> String {
+        self.address.to_lowercase()
     }
 }
 
 impl AsRef<str> for SubscriberEmail {
     fn as_ref(&self) -> &str {
-        &self.0
+        &self.address
     }
 }
 
 impl std::fmt::Display for SubscriberEmail {
     fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         // We just forward to the Display implementation of
-        // the wrapped String.
-        self.0.fmt(formatter)
+        // the normalized address.
+        self.address.fmt(formatter)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::SubscriberEmail;
-    use claims::assert_err;
+    use claims::{assert_err, assert_ok};
 
     use fake::faker::internet::en::SafeEmail;
     use fake::Fake;
@@ -74,4 +117,38 @@ mod tests {
         let email = "@domain.com".to_string();
         assert_err!(SubscriberEmail::parse(email));
     }
+
+    #[test]
+    fn surrounding_whitespace_is_trimmed() {
+        let email = assert_ok!(SubscriberEmail::parse(" ursula@domain.com\t".to_string()));
+        assert_eq!(email.as_ref(), "ursula@domain.com");
+        assert_eq!(email.display(), "ursula@domain.com");
+    }
+
+    #[test]
+    fn the_domain_is_lowercased_but_the_local_part_is_kept() {
+        let email = assert_ok!(SubscriberEmail::parse("Ursula@Domain.COM".to_string()));
+        assert_eq!(email.as_ref(), "Ursula@domain.com");
+        assert_eq!(email.display(), "Ursula@Domain.COM");
+    }
+
+    #[test]
+    fn internationalized_domains_are_converted_to_punycode() {
+        let email = assert_ok!(SubscriberEmail::parse("ursula@Bücher.de".to_string()));
+        assert_eq!(email.as_ref(), "ursula@xn--bcher-kva.de");
+        assert_eq!(email.display(), "ursula@Bücher.de");
+    }
+
+    #[test]
+    fn case_and_whitespace_variants_share_a_canonical_form() {
+        let first = assert_ok!(SubscriberEmail::parse("Bob@Example.com ".to_string()));
+        let second = assert_ok!(SubscriberEmail::parse("bob@example.com".to_string()));
+        assert_eq!(first.canonical(), second.canonical());
+    }
+
+    #[test]
+    fn an_invalid_domain_is_rejected() {
+        let email = "ursula@xn--.com".to_string();
+        assert_err!(SubscriberEmail::parse(email));
+    }
 }
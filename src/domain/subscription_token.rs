@@ -0,0 +1,91 @@
+//! src/domain/subscription_token.rs
+
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use sha2::{Digest, Sha256};
+
+const TOKEN_LENGTH: usize = 25;
+
+/// The secret embedded in a confirmation link.
+///
+/// Only its hash is persisted: a leaked `subscription_tokens` table must not
+/// be enough to confirm pending subscribers. A plain SHA-256 is sufficient
+/// because tokens carry ~149 bits of randomness and cannot be brute-forced.
+#[derive(Debug)]
+pub struct SubscriptionToken(String);
+
+impl SubscriptionToken {
+    pub fn generate() -> Self {
+        let mut rng = thread_rng();
+
+        let token = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+            .map(char::from)
+            .take(TOKEN_LENGTH)
+            .collect();
+
+        Self(token)
+    }
+
+    pub fn parse(input: String) -> Result<Self, String> {
+        let is_well_formed =
+            input.len() == TOKEN_LENGTH && input.chars().all(|c| c.is_ascii_alphanumeric());
+
+        if is_well_formed {
+            Ok(Self(input))
+        } else {
+            Err(format!("{} is not a valid subscription token.", input))
+        }
+    }
+
+    /// The value stored in, and looked up from, the database.
+    pub fn hash(&self) -> String {
+        format!("{:x}", Sha256::digest(self.0.as_bytes()))
+    }
+}
+
+impl AsRef<str> for SubscriptionToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for SubscriptionToken {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(formatter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SubscriptionToken;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn a_generated_token_is_parsed_successfully() {
+        let token = SubscriptionToken::generate();
+        assert_ok!(SubscriptionToken::parse(token.to_string()));
+    }
+
+    #[test]
+    fn a_token_of_the_wrong_length_is_rejected() {
+        assert_err!(SubscriptionToken::parse("".to_string()));
+        assert_err!(SubscriptionToken::parse("a".repeat(24)));
+        assert_err!(SubscriptionToken::parse("a".repeat(26)));
+    }
+
+    #[test]
+    fn a_token_with_non_alphanumeric_characters_is_rejected() {
+        assert_err!(SubscriptionToken::parse(format!("{}'", "a".repeat(24))));
+        assert_err!(SubscriptionToken::parse(format!("{}ё", "a".repeat(23))));
+    }
+
+    #[test]
+    fn the_hash_is_the_hex_encoded_sha256_of_the_token() {
+        let token = SubscriptionToken::parse("a".repeat(25)).unwrap();
+
+        assert_eq!(
+            token.hash(),
+            "2f521e2a7d0bd812cbc035f4ed6806eb8d851793b04ba147e8f66b72f5d1f20f"
+        );
+    }
+}
@@ -0,0 +1,118 @@
+//! src/domain/unsubscribe_token.rs
+
+use uuid::Uuid;
+
+use crate::startup::HmacSecret;
+
+/// A per-subscriber token authorising a one-click unsubscribe.
+///
+/// It is rendered as `<subscriber_id>.<signature>`, where the signature is an
+/// HMAC of the subscriber id, tagged so that no other signed value passes for
+/// it: nothing needs to be stored to verify it.
+#[derive(Debug)]
+pub struct UnsubscribeToken {
+    subscriber_id: Uuid,
+    signature: String,
+}
+
+impl UnsubscribeToken {
+    pub fn generate(subscriber_id: Uuid, secret: &HmacSecret) -> Self {
+        let signature = secret.sign(&Self::message(subscriber_id));
+
+        Self {
+            subscriber_id,
+            signature,
+        }
+    }
+
+    pub fn parse(input: &str, secret: &HmacSecret) -> Result<Self, String> {
+        let invalid = || "The unsubscribe token is invalid.".to_string();
+
+        let (subscriber_id, signature) = input.split_once('.').ok_or_else(invalid)?;
+
+        let subscriber_id = Uuid::parse_str(subscriber_id).map_err(|_| invalid())?;
+
+        if !secret.verify(&Self::message(subscriber_id), signature) {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            subscriber_id,
+            signature: signature.to_owned(),
+        })
+    }
+
+    pub fn subscriber_id(&self) -> Uuid {
+        self.subscriber_id
+    }
+
+    fn message(subscriber_id: Uuid) -> String {
+        format!("unsubscribe.{}", subscriber_id)
+    }
+}
+
+impl std::fmt::Display for UnsubscribeToken {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(formatter, "{}.{}", self.subscriber_id, self.signature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UnsubscribeToken;
+    use crate::startup::HmacSecret;
+    use claims::{assert_err, assert_ok};
+    use redact::Secret;
+    use uuid::Uuid;
+
+    fn secret(value: &str) -> HmacSecret {
+        HmacSecret(Secret::new(value.to_string()))
+    }
+
+    #[test]
+    fn a_generated_token_round_trips() {
+        let secret = secret("super-secret");
+        let subscriber_id = Uuid::new_v4();
+        let token = UnsubscribeToken::generate(subscriber_id, &secret).to_string();
+
+        let parsed = assert_ok!(UnsubscribeToken::parse(&token, &secret));
+        assert_eq!(parsed.subscriber_id(), subscriber_id);
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let token = UnsubscribeToken::generate(Uuid::new_v4(), &secret("one")).to_string();
+        assert_err!(UnsubscribeToken::parse(&token, &secret("two")));
+    }
+
+    #[test]
+    fn a_token_for_another_subscriber_is_rejected() {
+        let secret = secret("super-secret");
+        let token = UnsubscribeToken::generate(Uuid::new_v4(), &secret).to_string();
+        let (_, signature) = token.split_once('.').unwrap();
+        let forged = format!("{}.{}", Uuid::new_v4(), signature);
+
+        assert_err!(UnsubscribeToken::parse(&forged, &secret));
+    }
+
+    #[test]
+    fn a_signature_of_the_bare_subscriber_id_is_rejected() {
+        let secret = secret("super-secret");
+        let subscriber_id = Uuid::new_v4();
+        let forged = format!(
+            "{}.{}",
+            subscriber_id,
+            secret.sign(&subscriber_id.to_string())
+        );
+
+        assert_err!(UnsubscribeToken::parse(&forged, &secret));
+    }
+
+    #[test]
+    fn malformed_tokens_are_rejected() {
+        let secret = secret("super-secret");
+        for token in ["", "not-a-token", "not-a-uuid.signature"] {
+            assert_err!(UnsubscribeToken::parse(token, &secret));
+        }
+    }
+}
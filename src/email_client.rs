@@ -13,6 +13,24 @@ struct SendEmailRequest<'a> {
     subject: &'a str,
     html_body: &'a str,
     text_body: &'a str,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    headers: &'a [EmailHeader<'a>],
+}
+
+/// A custom header attached to an outgoing email, e.g. `List-Unsubscribe`.
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct EmailHeader<'a> {
+    pub name: &'a str,
+    pub value: &'a str,
+}
+
+/// What Postmark must present when it calls our webhook: either basic auth
+/// with these credentials or the secret alone, in `X-Webhook-Secret`.
+#[derive(Clone)]
+pub struct PostmarkWebhookCredentials {
+    pub username: String,
+    pub secret: Secret<String>,
 }
 
 pub struct EmailClient {
@@ -45,6 +63,18 @@ impl EmailClient {
         subject: &str,
         html_content: &str,
         text_content: &str,
+    ) -> Result<(), reqwest::Error> {
+        self.send_email_with_headers(recipient, subject, html_content, text_content, &[])
+            .await
+    }
+
+    pub async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader<'_>],
     ) -> Result<(), reqwest::Error> {
         let url = format!("{}/email", self.base_url);
 
@@ -54,6 +84,7 @@ impl EmailClient {
             subject,
             html_body: html_content,
             text_body: text_content,
+            headers,
         };
 
         self.http_client
@@ -74,7 +105,7 @@ impl EmailClient {
 #[cfg(test)]
 mod tests {
     use crate::domain::SubscriberEmail;
-    use crate::email_client::EmailClient;
+    use crate::email_client::{EmailClient, EmailHeader};
     use claims::{assert_err, assert_ok};
     use fake::faker::internet::en::SafeEmail;
     use fake::faker::lorem::en::{Paragraph, Sentence};
@@ -153,6 +184,46 @@ mod tests {
         // Assert
     }
 
+    struct HeadersMatcher;
+
+    impl Match for HeadersMatcher {
+        fn matches(&self, request: &Request) -> bool {
+            let result: Result<Value, _> = from_slice(&request.body);
+
+            if let Ok(body) = result {
+                body["Headers"][0]["Name"] == "List-Unsubscribe"
+                    && body["Headers"][0]["Value"] == "<https://example.com/unsubscribe>"
+            } else {
+                false
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn send_email_with_headers_forwards_the_headers() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let headers = [EmailHeader {
+            name: "List-Unsubscribe",
+            value: "<https://example.com/unsubscribe>",
+        }];
+
+        Mock::given(HeadersMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email_with_headers(&email(), &subject(), &content(), &content(), &headers)
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
     #[tokio::test]
     async fn send_email_succeeds_if_the_server_returns_200() {
         // Arrange
@@ -0,0 +1,67 @@
+//! src/email_validation/disposable.rs
+
+use std::collections::HashSet;
+
+use super::{domain_and_parents, EmailCheck, Verdict};
+use crate::domain::SubscriberEmail;
+
+const DISPOSABLE_DOMAINS: &str = include_str!("disposable_domains.txt");
+
+/// Turns down throwaway mailboxes: they are gone before the first issue ships.
+pub struct DisposableDomainCheck {
+    domains: HashSet<&'static str>,
+}
+
+impl Default for DisposableDomainCheck {
+    fn default() -> Self {
+        let domains = DISPOSABLE_DOMAINS
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .collect();
+
+        Self { domains }
+    }
+}
+
+impl DisposableDomainCheck {
+    fn is_disposable(&self, domain: &str) -> bool {
+        domain_and_parents(domain).any(|domain| self.domains.contains(domain))
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailCheck for DisposableDomainCheck {
+    async fn check(&self, email: &SubscriberEmail) -> Result<Verdict, anyhow::Error> {
+        if self.is_disposable(email.domain()) {
+            return Ok(Verdict::Reject(format!(
+                "{} is a disposable email provider. Please use a permanent address.",
+                email.domain()
+            )));
+        }
+
+        Ok(Verdict::Pass)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DisposableDomainCheck;
+
+    #[test]
+    fn bundled_domains_and_their_subdomains_are_disposable() {
+        let check = DisposableDomainCheck::default();
+
+        assert!(check.is_disposable("mailinator.com"));
+        assert!(check.is_disposable("inbox.mailinator.com"));
+    }
+
+    #[test]
+    fn comments_and_other_domains_are_not_disposable() {
+        let check = DisposableDomainCheck::default();
+
+        assert!(!check.is_disposable("gmail.com"));
+        assert!(!check.is_disposable("com"));
+        assert!(!check.domains.iter().any(|domain| domain.starts_with('#')));
+    }
+}
@@ -0,0 +1,115 @@
+//! src/email_validation/domain_rules.rs
+
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+use super::{domain_and_parents, EmailCheck, Verdict};
+use crate::domain::SubscriberEmail;
+
+pub struct DomainRule {
+    pub domain: String,
+    pub rule: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Applies the domains admins allowed or denied by hand.
+///
+/// The most specific rule wins, so `corp.example.com` can be allowed while
+/// the rest of `example.com` is denied. An allowed domain skips the checks
+/// that come after this one.
+pub struct DomainRuleCheck {
+    pool: PgPool,
+}
+
+impl DomainRuleCheck {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailCheck for DomainRuleCheck {
+    async fn check(&self, email: &SubscriberEmail) -> Result<Verdict, anyhow::Error> {
+        let domains: Vec<String> = domain_and_parents(email.domain())
+            .map(str::to_owned)
+            .collect();
+
+        let rule = sqlx::query_scalar!(
+            r#"
+            SELECT rule
+            FROM email_domain_rules
+            WHERE domain = ANY($1)
+            ORDER BY length(domain) DESC
+            LIMIT 1
+            "#,
+            &domains
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let verdict = match rule.as_deref() {
+            Some("allow") => Verdict::Accept,
+            Some(_) => Verdict::Reject(format!(
+                "We do not accept sign-ups from {}.",
+                email.domain()
+            )),
+            None => Verdict::Pass,
+        };
+
+        Ok(verdict)
+    }
+}
+
+#[tracing::instrument(name = "Get email domain rules", skip(pool))]
+pub async fn get_domain_rules(pool: &PgPool) -> Result<Vec<DomainRule>, sqlx::Error> {
+    sqlx::query_as!(
+        DomainRule,
+        r#"
+        SELECT domain, rule, created_at
+        FROM email_domain_rules
+        ORDER BY domain
+        "#
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Allow or deny `domain`, replacing any rule it already had.
+#[tracing::instrument(name = "Save an email domain rule", skip(pool))]
+pub async fn upsert_domain_rule(
+    pool: &PgPool,
+    domain: &str,
+    rule: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO email_domain_rules (domain, rule, created_at)
+        VALUES ($1, $2, now())
+        ON CONFLICT (domain) DO UPDATE
+        SET rule = EXCLUDED.rule, created_at = EXCLUDED.created_at
+        "#,
+        domain,
+        rule
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Returns `false` if `domain` had no rule.
+#[tracing::instrument(name = "Delete an email domain rule", skip(pool))]
+pub async fn delete_domain_rule(pool: &PgPool, domain: &str) -> Result<bool, sqlx::Error> {
+    let deleted = sqlx::query!(
+        r#"
+        DELETE FROM email_domain_rules
+        WHERE domain = $1
+        "#,
+        domain
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    Ok(deleted > 0)
+}
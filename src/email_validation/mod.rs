@@ -0,0 +1,132 @@
+//! src/email_validation/mod.rs
+
+mod disposable;
+mod domain_rules;
+mod mx;
+mod typo;
+
+pub use disposable::DisposableDomainCheck;
+pub use domain_rules::{
+    delete_domain_rule, get_domain_rules, upsert_domain_rule, DomainRule, DomainRuleCheck,
+};
+pub use mx::{DnsMxResolver, MxCheck, MxResolver};
+pub use typo::{suggest_domain, TypoCheck};
+
+use crate::domain::SubscriberEmail;
+
+/// What a single check makes of an address.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    /// Nothing against the address: the next check gets a say.
+    Pass,
+    /// Take the address without running the remaining checks.
+    Accept,
+    /// Turn the address down, telling the subscriber why.
+    Reject(String),
+}
+
+/// One link in the chain run on an address before we sign it up.
+#[async_trait::async_trait]
+pub trait EmailCheck: Send + Sync {
+    async fn check(&self, email: &SubscriberEmail) -> Result<Verdict, anyhow::Error>;
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum EmailValidationError {
+    #[error("{0}")]
+    Rejected(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+/// Checks an address goes through, beyond its syntax, in the configured order.
+#[derive(Default)]
+pub struct EmailValidator {
+    checks: Vec<Box<dyn EmailCheck>>,
+}
+
+impl EmailValidator {
+    pub fn new(checks: Vec<Box<dyn EmailCheck>>) -> Self {
+        Self { checks }
+    }
+
+    #[tracing::instrument(name = "Validate an email address", skip_all)]
+    pub async fn validate(&self, email: &SubscriberEmail) -> Result<(), EmailValidationError> {
+        for check in &self.checks {
+            match check.check(email).await? {
+                Verdict::Pass => continue,
+                Verdict::Accept => return Ok(()),
+                Verdict::Reject(reason) => return Err(EmailValidationError::Rejected(reason)),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// `domain` followed by each of its parents, stopping short of the top-level
+/// domain: a rule for `example.com` also covers `mail.example.com`.
+fn domain_and_parents(domain: &str) -> impl Iterator<Item = &str> {
+    std::iter::successors(Some(domain), |current| {
+        current
+            .split_once('.')
+            .map(|(_, parent)| parent)
+            .filter(|parent| parent.contains('.'))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{domain_and_parents, EmailCheck, EmailValidationError, EmailValidator, Verdict};
+    use crate::domain::SubscriberEmail;
+    use claims::{assert_err, assert_ok};
+
+    struct Fixed(fn() -> Verdict);
+
+    #[async_trait::async_trait]
+    impl EmailCheck for Fixed {
+        async fn check(&self, _email: &SubscriberEmail) -> Result<Verdict, anyhow::Error> {
+            Ok((self.0)())
+        }
+    }
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse("ursula@domain.com".into()).unwrap()
+    }
+
+    #[test]
+    fn parents_stop_before_the_top_level_domain() {
+        let domains: Vec<_> = domain_and_parents("a.mail.example.co").collect();
+        assert_eq!(
+            domains,
+            ["a.mail.example.co", "mail.example.co", "example.co"]
+        );
+    }
+
+    #[tokio::test]
+    async fn an_empty_chain_accepts_everything() {
+        assert_ok!(EmailValidator::default().validate(&email()).await);
+    }
+
+    #[tokio::test]
+    async fn the_first_rejection_wins() {
+        let validator = EmailValidator::new(vec![
+            Box::new(Fixed(|| Verdict::Pass)),
+            Box::new(Fixed(|| Verdict::Reject("first".into()))),
+            Box::new(Fixed(|| Verdict::Reject("second".into()))),
+        ]);
+
+        let error = assert_err!(validator.validate(&email()).await);
+        assert!(matches!(error, EmailValidationError::Rejected(reason) if reason == "first"));
+    }
+
+    #[tokio::test]
+    async fn an_acceptance_skips_the_remaining_checks() {
+        let validator = EmailValidator::new(vec![
+            Box::new(Fixed(|| Verdict::Accept)),
+            Box::new(Fixed(|| Verdict::Reject("too late".into()))),
+        ]);
+
+        assert_ok!(validator.validate(&email()).await);
+    }
+}
@@ -0,0 +1,274 @@
+//! src/email_validation/mx.rs
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use hickory_resolver::config::{NameServerConfigGroup, ResolverConfig, ResolverOpts};
+use hickory_resolver::error::{ResolveError, ResolveErrorKind};
+use hickory_resolver::proto::op::ResponseCode;
+use hickory_resolver::TokioAsyncResolver;
+
+use super::{EmailCheck, Verdict};
+use crate::domain::SubscriberEmail;
+
+/// Finds out whether a domain can receive email.
+///
+/// Kept behind a trait so tests can stand in for the DNS.
+#[async_trait::async_trait]
+pub trait MxResolver: Send + Sync {
+    async fn accepts_mail(&self, domain: &str) -> Result<bool, anyhow::Error>;
+}
+
+/// Turns down domains with nowhere to deliver mail to.
+///
+/// A failed lookup lets the address through: an unreachable nameserver must
+/// not stop people from signing up.
+pub struct MxCheck {
+    resolver: Arc<dyn MxResolver>,
+}
+
+impl MxCheck {
+    pub fn new(resolver: Arc<dyn MxResolver>) -> Self {
+        Self { resolver }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailCheck for MxCheck {
+    async fn check(&self, email: &SubscriberEmail) -> Result<Verdict, anyhow::Error> {
+        match self.resolver.accepts_mail(email.domain()).await {
+            Ok(true) => Ok(Verdict::Pass),
+            Ok(false) => Ok(Verdict::Reject(format!(
+                "{} does not accept email. Please check the address.",
+                email.domain()
+            ))),
+            Err(error) => {
+                tracing::warn!(
+                    error.cause_chain = ?error,
+                    error.message = %error,
+                    "Failed to look up mail exchangers. Letting the address through."
+                );
+                Ok(Verdict::Pass)
+            }
+        }
+    }
+}
+
+/// Asks a nameserver for MX records, over UDP and then TCP when the answer
+/// does not fit in a datagram.
+///
+/// Follows RFC 5321: a domain without MX records still accepts mail if it
+/// resolves to an address, while a lone null MX (RFC 7505) opts out.
+pub struct DnsMxResolver {
+    resolver: TokioAsyncResolver,
+    timeout: Duration,
+}
+
+impl DnsMxResolver {
+    pub fn new(nameserver: SocketAddr, timeout: Duration) -> Self {
+        let nameservers =
+            NameServerConfigGroup::from_ips_clear(&[nameserver.ip()], nameserver.port(), true);
+        let config = ResolverConfig::from_parts(None, vec![], nameservers);
+        let mut options = ResolverOpts::default();
+        options.timeout = timeout;
+        options.use_hosts_file = false;
+
+        Self {
+            resolver: TokioAsyncResolver::tokio(config, options),
+            timeout,
+        }
+    }
+
+    async fn resolves_to_an_address(&self, domain: &str) -> Result<bool, anyhow::Error> {
+        match self.resolver.lookup_ip(domain).await {
+            Ok(addresses) => Ok(addresses.iter().next().is_some()),
+            Err(error) if is_negative(&error) => Ok(false),
+            Err(error) => Err(error).context("Failed to look up the domain's addresses."),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl MxResolver for DnsMxResolver {
+    async fn accepts_mail(&self, domain: &str) -> Result<bool, anyhow::Error> {
+        // Fully qualified, so that no search domain is ever appended.
+        let domain = format!("{}.", domain.trim_end_matches('.'));
+        let lookup = tokio::time::timeout(self.timeout, self.resolver.mx_lookup(domain.as_str()))
+            .await
+            .context("The MX lookup timed out.")?;
+
+        let records = match lookup {
+            Ok(records) => records,
+            Err(error) if is_no_such_domain(&error) => return Ok(false),
+            Err(error) if is_negative(&error) => {
+                return tokio::time::timeout(self.timeout, self.resolves_to_an_address(&domain))
+                    .await
+                    .context("The address lookup timed out.")?;
+            }
+            Err(error) => return Err(error).context("Failed to look up mail exchangers."),
+        };
+
+        // A null MX is a single record whose exchange is the root.
+        let has_exchangers = records.iter().any(|mx| !mx.exchange().is_root());
+        Ok(has_exchangers)
+    }
+}
+
+/// The nameserver answered, but with no record of the type asked for.
+fn is_negative(error: &ResolveError) -> bool {
+    matches!(error.kind(), ResolveErrorKind::NoRecordsFound { .. })
+}
+
+fn is_no_such_domain(error: &ResolveError) -> bool {
+    matches!(
+        error.kind(),
+        ResolveErrorKind::NoRecordsFound {
+            response_code: ResponseCode::NXDomain,
+            ..
+        }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DnsMxResolver, MxResolver};
+    use claims::{assert_err, assert_ok};
+    use hickory_resolver::proto::op::{Message, MessageType, ResponseCode};
+    use hickory_resolver::proto::rr::rdata::MX;
+    use hickory_resolver::proto::rr::{Name, RData, Record, RecordType};
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, UdpSocket};
+
+    /// The answer to `query`, with an MX record for each of `exchanges`.
+    fn response(query: &Message, response_code: ResponseCode, exchanges: &[&str]) -> Message {
+        let mut response = Message::new();
+        response
+            .set_id(query.id())
+            .set_message_type(MessageType::Response)
+            .set_recursion_desired(true)
+            .set_recursion_available(true)
+            .set_response_code(response_code)
+            .add_queries(query.queries().to_vec());
+        for exchange in exchanges {
+            response.add_answer(Record::from_rdata(
+                query.queries()[0].name().clone(),
+                3600,
+                RData::MX(MX::new(10, Name::from_ascii(exchange).unwrap())),
+            ));
+        }
+        response
+    }
+
+    async fn nameserver() -> (UdpSocket, DnsMxResolver) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let resolver = DnsMxResolver::new(socket.local_addr().unwrap(), Duration::from_secs(1));
+        (socket, resolver)
+    }
+
+    /// Answer a single query over UDP, returning where it came from.
+    async fn answer_over_udp(
+        socket: &UdpSocket,
+        answer: impl FnOnce(&Message) -> Message,
+    ) -> SocketAddr {
+        let mut buffer = [0; 512];
+        let (length, client) = socket.recv_from(&mut buffer).await.unwrap();
+        let query = Message::from_vec(&buffer[..length]).unwrap();
+        socket
+            .send_to(&answer(&query).to_vec().unwrap(), client)
+            .await
+            .unwrap();
+        client
+    }
+
+    #[tokio::test]
+    async fn domains_with_mail_exchangers_accept_mail() {
+        let (socket, resolver) = nameserver().await;
+
+        let lookup = tokio::spawn(async move { resolver.accepts_mail("example.com").await });
+        answer_over_udp(&socket, |query| {
+            assert_eq!(query.queries()[0].name().to_ascii(), "example.com.");
+            response(query, ResponseCode::NoError, &["mail.example.com."])
+        })
+        .await;
+
+        assert!(assert_ok!(lookup.await.unwrap()));
+    }
+
+    #[tokio::test]
+    async fn a_null_mx_or_nxdomain_means_no_mail() {
+        for (response_code, exchanges) in [
+            (ResponseCode::NoError, &["."][..]),
+            (ResponseCode::NXDomain, &[][..]),
+        ] {
+            let (socket, resolver) = nameserver().await;
+
+            let lookup = tokio::spawn(async move { resolver.accepts_mail("example.com").await });
+            answer_over_udp(&socket, |query| response(query, response_code, exchanges)).await;
+
+            assert!(!assert_ok!(lookup.await.unwrap()));
+        }
+    }
+
+    #[tokio::test]
+    async fn a_domain_without_mx_records_accepts_mail_at_its_address() {
+        let (socket, resolver) = nameserver().await;
+
+        let lookup = tokio::spawn(async move { resolver.accepts_mail("example.com").await });
+        answer_over_udp(&socket, |query| response(query, ResponseCode::NoError, &[])).await;
+        answer_over_udp(&socket, |query| {
+            assert_eq!(query.queries()[0].query_type(), RecordType::A);
+            let mut answer = response(query, ResponseCode::NoError, &[]);
+            answer.add_answer(Record::from_rdata(
+                query.queries()[0].name().clone(),
+                3600,
+                RData::A(Ipv4Addr::new(192, 0, 2, 1).into()),
+            ));
+            answer
+        })
+        .await;
+
+        assert!(assert_ok!(lookup.await.unwrap()));
+    }
+
+    #[tokio::test]
+    async fn truncated_answers_are_asked_again_over_tcp() {
+        let (socket, resolver) = nameserver().await;
+        let listener = TcpListener::bind(socket.local_addr().unwrap())
+            .await
+            .unwrap();
+
+        let lookup = tokio::spawn(async move { resolver.accepts_mail("example.com").await });
+        answer_over_udp(&socket, |query| {
+            let mut truncated = response(query, ResponseCode::NoError, &[]);
+            truncated.set_truncated(true);
+            truncated
+        })
+        .await;
+
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let length = stream.read_u16().await.unwrap();
+        let mut buffer = vec![0; length.into()];
+        stream.read_exact(&mut buffer).await.unwrap();
+        let query = Message::from_vec(&buffer).unwrap();
+        let answer = response(&query, ResponseCode::NoError, &["mail.example.com."])
+            .to_vec()
+            .unwrap();
+        stream.write_u16(answer.len() as u16).await.unwrap();
+        stream.write_all(&answer).await.unwrap();
+
+        assert!(assert_ok!(lookup.await.unwrap()));
+    }
+
+    #[tokio::test]
+    async fn the_resolver_gives_up_on_a_silent_nameserver() {
+        let nameserver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let resolver =
+            DnsMxResolver::new(nameserver.local_addr().unwrap(), Duration::from_millis(100));
+
+        assert_err!(resolver.accepts_mail("example.com").await);
+    }
+}
@@ -0,0 +1,174 @@
+//! src/email_validation/typo.rs
+
+use super::{EmailCheck, Verdict};
+use crate::domain::SubscriberEmail;
+
+/// Mailbox providers most of our subscribers use, most popular first.
+///
+/// Very short domains are left out: one edit away from `me.com` lies a lot
+/// of legitimate domains.
+const POPULAR_DOMAINS: &[&str] = &[
+    "gmail.com",
+    "yahoo.com",
+    "hotmail.com",
+    "outlook.com",
+    "icloud.com",
+    "googlemail.com",
+    "hotmail.co.uk",
+    "yahoo.co.uk",
+    "live.com",
+    "aol.com",
+    "protonmail.com",
+    "proton.me",
+    "gmx.com",
+    "gmx.net",
+    "mail.com",
+    "yandex.com",
+    "yandex.ru",
+    "mail.ru",
+    "zoho.com",
+    "fastmail.com",
+    "comcast.net",
+    "verizon.net",
+];
+
+/// Real mailbox providers a single edit away from a popular one, which must
+/// not be mistaken for a typo of it.
+const KNOWN_DOMAINS: &[&str] = &[
+    "ymail.com",
+    "email.com",
+    "rocketmail.com",
+    "gmx.de",
+    "gmx.at",
+    "gmx.ch",
+    "web.de",
+    "mail.de",
+    "live.co.uk",
+    "live.ca",
+    "live.fr",
+    "msn.com",
+    "mac.com",
+    "me.com",
+    "aim.com",
+    "inbox.ru",
+    "list.ru",
+    "bk.ru",
+    "hotmail.fr",
+    "hotmail.de",
+    "hotmail.it",
+    "yahoo.fr",
+    "yahoo.de",
+    "yahoo.ca",
+    "outlook.de",
+    "outlook.fr",
+    "yandex.ua",
+    "zoho.eu",
+];
+
+/// The popular domain `domain` is most likely a typo of, if any.
+pub fn suggest_domain(domain: &str) -> Option<&'static str> {
+    if POPULAR_DOMAINS.contains(&domain) || KNOWN_DOMAINS.contains(&domain) {
+        return None;
+    }
+
+    POPULAR_DOMAINS
+        .iter()
+        .find(|candidate| edit_distance(domain, candidate) == 1)
+        .copied()
+}
+
+/// Number of insertions, deletions, substitutions and swaps of adjacent
+/// characters needed to turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    // distances[i][j] is the distance between the first i characters of `a`
+    // and the first j characters of `b`.
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, distance) in distances[0].iter_mut().enumerate() {
+        *distance = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let substitution = usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = (distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1)
+                .min(distances[i - 1][j - 1] + substitution);
+
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+
+            distances[i][j] = distance;
+        }
+    }
+
+    distances[a.len()][b.len()]
+}
+
+/// Turns down addresses at a near miss of a popular provider, suggesting the
+/// likely fix: a confirmation sent to `gmial.com` never arrives.
+pub struct TypoCheck;
+
+#[async_trait::async_trait]
+impl EmailCheck for TypoCheck {
+    async fn check(&self, email: &SubscriberEmail) -> Result<Verdict, anyhow::Error> {
+        let Some(suggestion) = suggest_domain(email.domain()) else {
+            return Ok(Verdict::Pass);
+        };
+
+        let (local_part, _) = email
+            .as_ref()
+            .rsplit_once('@')
+            .expect("A parsed address contains an `@`.");
+
+        Ok(Verdict::Reject(format!(
+            "{} looks mistyped. Did you mean {}@{}?",
+            email.display(),
+            local_part,
+            suggestion
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{edit_distance, suggest_domain};
+    use claims::{assert_none, assert_some_eq};
+
+    #[test]
+    fn swapped_letters_count_as_a_single_edit() {
+        assert_eq!(edit_distance("gmial.com", "gmail.com"), 1);
+        assert_eq!(edit_distance("gmail.com", "gmail.com"), 0);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn near_misses_of_popular_domains_get_a_suggestion() {
+        assert_some_eq!(suggest_domain("gmial.com"), "gmail.com");
+        assert_some_eq!(suggest_domain("gmail.con"), "gmail.com");
+        assert_some_eq!(suggest_domain("hotmial.com"), "hotmail.com");
+        assert_some_eq!(suggest_domain("yaho.com"), "yahoo.com");
+    }
+
+    #[test]
+    fn popular_and_unrelated_domains_get_no_suggestion() {
+        assert_none!(suggest_domain("gmail.com"));
+        assert_none!(suggest_domain("mail.com"));
+        assert_none!(suggest_domain("example.com"));
+        assert_none!(suggest_domain("domain.com"));
+    }
+
+    #[test]
+    fn real_domains_close_to_popular_ones_get_no_suggestion() {
+        assert_none!(suggest_domain("ymail.com"));
+        assert_none!(suggest_domain("email.com"));
+        assert_none!(suggest_domain("gmx.de"));
+    }
+}
@@ -0,0 +1,399 @@
+//! src/gdpr.rs
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::consent::{get_consent_trail, ConsentRecord};
+use crate::domain::SubscriberEmail;
+
+/// Everything held about a subscriber, as handed over on a data subject
+/// request.
+///
+/// Token hashes are left out: they are credentials, not personal data, and
+/// the timestamps around them are what tells the subscriber's story.
+#[derive(serde::Serialize)]
+pub struct SubscriberDataExport {
+    pub exported_at: DateTime<Utc>,
+    pub subscriber: SubscriberRecord,
+    pub list_memberships: Vec<MembershipRecord>,
+    pub subscription_tokens: Vec<SubscriptionTokenRecord>,
+    pub confirmation_reminders: Vec<ReminderRecord>,
+    pub email_change_requests: Vec<EmailChangeRecord>,
+    pub queued_deliveries: Vec<QueuedDeliveryRecord>,
+    pub deliveries: Vec<DeliveryRecord>,
+    pub consent_records: Vec<ConsentRecord>,
+    pub suppression: Option<SuppressionRecord>,
+}
+
+#[derive(serde::Serialize)]
+pub struct SubscriberRecord {
+    pub id: Uuid,
+    pub email: String,
+    /// The address as the subscriber typed it.
+    pub email_display: String,
+    pub name: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+    pub attributes: serde_json::Value,
+    pub utm_source: Option<String>,
+    pub utm_medium: Option<String>,
+    pub utm_campaign: Option<String>,
+    pub referrer: Option<String>,
+    pub source: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct MembershipRecord {
+    pub list: String,
+    pub status: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct SubscriptionTokenRecord {
+    pub list: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub consumed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ReminderRecord {
+    pub sent_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct EmailChangeRecord {
+    pub new_email: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub consumed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize)]
+pub struct QueuedDeliveryRecord {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct DeliveryRecord {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub outcome: String,
+    pub attempted_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct SuppressionRecord {
+    pub reason: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Nobody can have subscribed with an address that does not parse.
+#[tracing::instrument(name = "Find subscriber by email", skip(pool, email))]
+pub async fn find_subscriber_id(pool: &PgPool, email: &str) -> Result<Option<Uuid>, sqlx::Error> {
+    let Ok(email) = SubscriberEmail::parse(email.to_owned()) else {
+        return Ok(None);
+    };
+
+    let record = sqlx::query!(
+        r#"
+        SELECT id
+        FROM subscriptions
+        WHERE email_canonical = $1
+        "#,
+        email.canonical()
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(record.map(|row| row.id))
+}
+
+/// Collect everything held about a subscriber, `None` if they do not exist.
+///
+/// All queries run in a single transaction, so the export is a consistent
+/// snapshot even if the worker is delivering to them at the same time.
+#[tracing::instrument(name = "Export subscriber data", skip(pool))]
+pub async fn export_subscriber_data(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<SubscriberDataExport>, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    transaction
+        .execute("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ")
+        .await
+        .context("Failed to set the transaction isolation level.")?;
+
+    let Some(subscriber) = sqlx::query_as!(
+        SubscriberRecord,
+        r#"
+        SELECT
+            id,
+            email,
+            email_display,
+            name,
+            status,
+            subscribed_at,
+            attributes,
+            utm_source,
+            utm_medium,
+            utm_campaign,
+            referrer,
+            source
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to retrieve the subscriber.")?
+    else {
+        return Ok(None);
+    };
+
+    let list_memberships = sqlx::query_as!(
+        MembershipRecord,
+        r#"
+        SELECT lists.slug AS list, list_memberships.status, list_memberships.created_at
+        FROM list_memberships
+        JOIN lists ON lists.list_id = list_memberships.list_id
+        WHERE list_memberships.subscriber_id = $1
+        ORDER BY list_memberships.created_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to retrieve list memberships.")?;
+
+    let subscription_tokens = sqlx::query_as!(
+        SubscriptionTokenRecord,
+        r#"
+        SELECT
+            lists.slug AS list,
+            subscription_tokens.created_at,
+            subscription_tokens.expires_at,
+            subscription_tokens.consumed_at
+        FROM subscription_tokens
+        JOIN lists ON lists.list_id = subscription_tokens.list_id
+        WHERE subscription_tokens.subscriber_id = $1
+        ORDER BY subscription_tokens.created_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to retrieve subscription tokens.")?;
+
+    let confirmation_reminders = sqlx::query_as!(
+        ReminderRecord,
+        r#"
+        SELECT sent_at
+        FROM confirmation_reminders
+        WHERE subscriber_id = $1
+        ORDER BY sent_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to retrieve confirmation reminders.")?;
+
+    let email_change_requests = sqlx::query_as!(
+        EmailChangeRecord,
+        r#"
+        SELECT new_email, created_at, expires_at, consumed_at
+        FROM email_change_requests
+        WHERE subscriber_id = $1
+        ORDER BY created_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to retrieve email change requests.")?;
+
+    let queued_deliveries = sqlx::query_as!(
+        QueuedDeliveryRecord,
+        r#"
+        SELECT newsletter_issues.newsletter_issue_id, newsletter_issues.title
+        FROM issue_delivery_queue
+        JOIN newsletter_issues
+            ON newsletter_issues.newsletter_issue_id = issue_delivery_queue.newsletter_issue_id
+        WHERE issue_delivery_queue.subscriber_email = $1
+        "#,
+        subscriber.email
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to retrieve queued deliveries.")?;
+
+    let deliveries = sqlx::query_as!(
+        DeliveryRecord,
+        r#"
+        SELECT
+            newsletter_issues.newsletter_issue_id,
+            newsletter_issues.title,
+            issue_deliveries.outcome,
+            issue_deliveries.attempted_at
+        FROM issue_deliveries
+        JOIN newsletter_issues
+            ON newsletter_issues.newsletter_issue_id = issue_deliveries.newsletter_issue_id
+        WHERE issue_deliveries.subscriber_id = $1
+        ORDER BY issue_deliveries.attempted_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to retrieve the delivery history.")?;
+
+    let consent_records = get_consent_trail(&mut *transaction, subscriber_id)
+        .await
+        .context("Failed to retrieve the consent trail.")?;
+
+    let suppression = sqlx::query_as!(
+        SuppressionRecord,
+        r#"
+        SELECT suppressions.reason, suppressions.created_at
+        FROM suppressions
+        JOIN subscriptions ON subscriptions.email_canonical = suppressions.email_canonical
+        WHERE subscriptions.id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to retrieve the suppression of the subscriber's address.")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to export subscriber data.")?;
+
+    Ok(Some(SubscriberDataExport {
+        exported_at: Utc::now(),
+        subscriber,
+        list_memberships,
+        subscription_tokens,
+        confirmation_reminders,
+        email_change_requests,
+        queued_deliveries,
+        deliveries,
+        consent_records,
+        suppression,
+    }))
+}
+
+/// Delete everything held about a subscriber in one transaction.
+///
+/// Rows keyed by subscriber id go with the `subscriptions` row through
+/// `ON DELETE CASCADE`; rows that only carry the address are deleted, or
+/// scrubbed from import reports, by hand.
+/// The address stays on the suppression list, if it was there: forgetting
+/// it would let the next import mail someone who asked us to stop.
+/// Returns `false` if there was no such subscriber.
+#[tracing::instrument(name = "Erase subscriber data", skip(pool))]
+pub async fn erase_subscriber_data(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let erased = delete_subscriber(&mut transaction, subscriber_id).await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to erase subscriber data.")?;
+
+    Ok(erased)
+}
+
+/// The deletions behind `erase_subscriber_data`, within the caller's
+/// transaction.
+#[tracing::instrument(name = "Delete a subscriber", skip(transaction))]
+pub async fn delete_subscriber(
+    transaction: &mut Transaction<'static, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let Some(subscriber) = sqlx::query!(
+        r#"
+        SELECT email, email_canonical
+        FROM subscriptions
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .context("Failed to retrieve the subscriber.")?
+    else {
+        return Ok(false);
+    };
+
+    let query = sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE subscriber_email = $1
+        "#,
+        subscriber.email
+    );
+    transaction
+        .execute(query)
+        .await
+        .context("Failed to delete queued deliveries.")?;
+
+    // Another subscriber may have asked to move to this address.
+    let query = sqlx::query!(
+        r#"
+        DELETE FROM email_change_requests
+        WHERE new_email = $1
+        "#,
+        subscriber.email
+    );
+    transaction
+        .execute(query)
+        .await
+        .context("Failed to delete email change requests.")?;
+
+    // Import reports keep the row, and why it was turned down, but not who.
+    let query = sqlx::query!(
+        r#"
+        UPDATE subscriber_import_rejections
+        SET email = '', email_canonical = NULL, name = ''
+        WHERE email_canonical = $1
+        "#,
+        subscriber.email_canonical
+    );
+    transaction
+        .execute(query)
+        .await
+        .context("Failed to scrub the subscriber from import reports.")?;
+
+    let query = sqlx::query!(
+        r#"
+        DELETE FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    );
+    transaction
+        .execute(query)
+        .await
+        .context("Failed to delete the subscriber.")?;
+
+    Ok(true)
+}
@@ -12,7 +12,7 @@ struct HeaderPairRecord {
 }
 
 pub enum NextAction {
-    StartProcessing(Transaction<'static, Postgres>),
+    StartProcessing(Box<Transaction<'static, Postgres>>),
     ReturnSavedResponse(HttpResponse),
 }
 
@@ -134,7 +134,7 @@ pub async fn try_processing(
     let n_inserted_rows = transaction.execute(query).await?.rows_affected();
 
     if n_inserted_rows > 0 {
-        Ok(NextAction::StartProcessing(transaction))
+        Ok(NextAction::StartProcessing(Box::new(transaction)))
     } else {
         let saved_response = get_saved_response(pool, idempotency_key, user_id)
             .await?
@@ -1,12 +1,17 @@
 use std::time::Duration;
 
+use serde_json::{Map, Value};
 use sqlx::{Executor, PgPool, Postgres, Transaction};
 use tracing::{field::display, Span};
 use uuid::Uuid;
 
 use crate::{
-    configuration::Settings, domain::SubscriberEmail, email_client::EmailClient,
-    startup::Application,
+    configuration::Settings,
+    domain::{SubscriberEmail, UnsubscribeToken},
+    email_client::{EmailClient, EmailHeader},
+    merge_tags::{Recipient, Template},
+    routes::manage_link,
+    startup::{Application, ApplicationBaseUrl, HmacSecret},
 };
 
 type PgTransaction = Transaction<'static, Postgres>;
@@ -17,6 +22,12 @@ struct NewsletterIssue {
     html_content: String,
 }
 
+struct ConfirmedSubscriber {
+    id: Uuid,
+    name: String,
+    attributes: Value,
+}
+
 pub enum ExecutionOutcome {
     TaskCompleted,
     EmptyQueue,
@@ -26,13 +37,20 @@ pub async fn run_worker_until_stopped(configuration: Settings) -> Result<(), any
     let connection_pool = Application::db_connection_pool(&configuration.database)?;
 
     let email_client = configuration.email_client.client();
+    let base_url = ApplicationBaseUrl(configuration.application.base_url);
+    let hmac_secret = HmacSecret(configuration.application.hmac_secret);
 
-    worker_loop(connection_pool, email_client).await
+    worker_loop(connection_pool, email_client, base_url, hmac_secret).await
 }
 
-async fn worker_loop(pool: PgPool, email_client: EmailClient) -> Result<(), anyhow::Error> {
+async fn worker_loop(
+    pool: PgPool,
+    email_client: EmailClient,
+    base_url: ApplicationBaseUrl,
+    hmac_secret: HmacSecret,
+) -> Result<(), anyhow::Error> {
     loop {
-        match try_execute_task(&pool, &email_client).await {
+        match try_execute_task(&pool, &email_client, &base_url, &hmac_secret).await {
             Ok(ExecutionOutcome::EmptyQueue) => {
                 tokio::time::sleep(Duration::from_secs(10)).await;
             }
@@ -55,6 +73,8 @@ async fn worker_loop(pool: PgPool, email_client: EmailClient) -> Result<(), anyh
 pub async fn try_execute_task(
     pool: &PgPool,
     email_client: &EmailClient,
+    base_url: &ApplicationBaseUrl,
+    hmac_secret: &HmacSecret,
 ) -> Result<ExecutionOutcome, anyhow::Error> {
     let task = dequeue_task(pool).await?;
 
@@ -62,30 +82,86 @@ pub async fn try_execute_task(
         return Ok(ExecutionOutcome::EmptyQueue);
     }
 
-    let (transaction, issue_id, email) = task.unwrap();
+    let (mut transaction, issue_id, email) = task.unwrap();
 
     Span::current()
         .record("newsletter_issue_id", display(issue_id))
         .record("subscriber_email", display(&email));
 
-    match SubscriberEmail::parse(email.clone()) {
+    let subscriber = match get_confirmed_subscriber(pool, issue_id, &email).await? {
+        Some(subscriber) => subscriber,
+        None => {
+            // They unsubscribed, left the list or were suppressed after the
+            // issue was published.
+            tracing::info!("Skipping a subscriber who is no longer confirmed.");
+            delete_task(transaction, issue_id, &email).await?;
+
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+    };
+
+    let subscriber_id = subscriber.id;
+
+    let outcome = match SubscriberEmail::parse(email.clone()) {
         Ok(email) => {
             let issue = get_issue(pool, issue_id).await?;
+            let no_attributes = Map::new();
+            let recipient = Recipient {
+                name: &subscriber.name,
+                email: email.as_ref(),
+                attributes: subscriber.attributes.as_object().unwrap_or(&no_attributes),
+            };
+            let title = render(&issue.title, |template| template.render_text(&recipient));
+            let issue_html = render(&issue.html_content, |template| {
+                template.render_html(&recipient)
+            });
+            let issue_text = render(&issue.text_content, |template| {
+                template.render_text(&recipient)
+            });
+
+            let unsubscribe_link = format!(
+                "{}/subscriptions/unsubscribe?token={}",
+                base_url.0,
+                UnsubscribeToken::generate(subscriber_id, hmac_secret)
+            );
+            let manage_link = manage_link(&base_url.0, subscriber_id, hmac_secret);
+            let list_unsubscribe = format!("<{}>", unsubscribe_link);
+            let headers = [
+                EmailHeader {
+                    name: "List-Unsubscribe",
+                    value: &list_unsubscribe,
+                },
+                EmailHeader {
+                    name: "List-Unsubscribe-Post",
+                    value: "List-Unsubscribe=One-Click",
+                },
+            ];
+
+            let html_content = format!(
+                "{}<hr />\
+                <p><a href=\"{}\">Manage your preferences</a></p>\
+                <p>Don't want these emails? <a href=\"{}\">Unsubscribe</a>.</p>",
+                issue_html, manage_link, unsubscribe_link
+            );
+            let text_content = format!(
+                "{}\n\n--\nManage your preferences: {}\n\
+                Don't want these emails? Unsubscribe: {}",
+                issue_text, manage_link, unsubscribe_link
+            );
 
-            if let Err(error) = email_client
-                .send_email(
-                    &email,
-                    &issue.title,
-                    &issue.html_content,
-                    &issue.text_content,
-                )
+            match email_client
+                .send_email_with_headers(&email, &title, &html_content, &text_content, &headers)
                 .await
             {
-                tracing::error!(
-                    error.cause_chain = ?error,
-                    error.message = %error,
-                    "Failed to deliver issue to a confirmed subscriber. \n Skipping..."
-                )
+                Ok(()) => "sent",
+                Err(error) => {
+                    tracing::error!(
+                        error.cause_chain = ?error,
+                        error.message = %error,
+                        "Failed to deliver issue to a confirmed subscriber. \n Skipping..."
+                    );
+                    "failed"
+                }
             }
         }
         Err(error) => {
@@ -95,9 +171,11 @@ pub async fn try_execute_task(
                 "Skipping a confirmed subscriber. \
                 Their stored contact details are invalid",
             );
+            "invalid_address"
         }
-    }
+    };
 
+    record_delivery(&mut transaction, issue_id, subscriber_id, outcome).await?;
     delete_task(transaction, issue_id, &email).await?;
 
     Ok(ExecutionOutcome::TaskCompleted)
@@ -150,6 +228,86 @@ async fn delete_task(
     Ok(())
 }
 
+/// Log the attempt, so it shows up in the subscriber's delivery history.
+#[tracing::instrument(skip(transaction))]
+async fn record_delivery(
+    transaction: &mut PgTransaction,
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+    outcome: &str,
+) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!(
+        r#"
+            INSERT INTO issue_deliveries (
+                newsletter_issue_id,
+                subscriber_id,
+                outcome,
+                attempted_at
+            )
+            VALUES ($1, $2, $3, now())
+            ON CONFLICT (newsletter_issue_id, subscriber_id) DO UPDATE
+            SET outcome = EXCLUDED.outcome, attempted_at = EXCLUDED.attempted_at
+        "#,
+        issue_id,
+        subscriber_id,
+        outcome
+    );
+
+    transaction.execute(query).await?;
+
+    Ok(())
+}
+
+/// `email`'s subscriber, if they are still confirmed on the list the issue
+/// was published to and their address has not been suppressed since.
+#[tracing::instrument(skip_all)]
+async fn get_confirmed_subscriber(
+    pool: &PgPool,
+    issue_id: Uuid,
+    email: &str,
+) -> Result<Option<ConfirmedSubscriber>, anyhow::Error> {
+    let record = sqlx::query_as!(
+        ConfirmedSubscriber,
+        r#"
+            SELECT subscriptions.id, subscriptions.name, subscriptions.attributes
+            FROM subscriptions
+            JOIN list_memberships
+                ON list_memberships.subscriber_id = subscriptions.id
+            JOIN newsletter_issues
+                ON newsletter_issues.list_id = list_memberships.list_id
+            WHERE subscriptions.email = $1
+            AND subscriptions.status = 'confirmed'
+            AND list_memberships.status = 'confirmed'
+            AND newsletter_issues.newsletter_issue_id = $2
+            AND NOT EXISTS (
+                SELECT 1
+                FROM suppressions
+                WHERE suppressions.email_canonical = subscriptions.email_canonical
+            )
+        "#,
+        email,
+        issue_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(record)
+}
+
+/// Fill in an issue's merge tags for one recipient.
+///
+/// Tags are checked when the issue is published; should a stored issue still
+/// not parse, it goes out as written rather than blocking the queue.
+fn render(content: &str, render: impl FnOnce(&Template) -> String) -> String {
+    match Template::parse(content) {
+        Ok(template) => render(&template),
+        Err(error) => {
+            tracing::warn!(error.message = %error, "Sending an issue without filling in its merge tags.");
+            content.to_owned()
+        }
+    }
+}
+
 async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, anyhow::Error> {
     let issue = sqlx::query_as!(
         NewsletterIssue,
@@ -1,13 +1,30 @@
 //! src/lib.rs
 
+pub mod attribution;
 pub mod authentication;
+pub mod bot_protection;
+pub mod client_ip;
 pub mod configuration;
+pub mod consent;
+pub mod csv_file;
 pub mod domain;
 pub mod email_client;
+pub mod email_validation;
+pub mod gdpr;
 pub mod idempotency;
 pub mod issue_delivery_worker;
+pub mod mailing_lists;
+pub mod merge_tags;
+pub mod pending_subscriber_worker;
+pub mod rate_limit;
 pub mod routes;
+pub mod segments;
 pub mod session_state;
 pub mod startup;
+pub mod subscriber_export;
+pub mod subscriber_import;
+pub mod subscribers;
+pub mod suppressions;
 pub mod telemetry;
+pub mod upload;
 pub mod utils;
@@ -0,0 +1,45 @@
+//! src/mailing_lists.rs
+
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+/// The list sign-ups and issues go to when none is specified.
+pub const DEFAULT_LIST: &str = "newsletter";
+
+pub struct MailingList {
+    pub list_id: Uuid,
+    pub slug: String,
+    pub name: String,
+}
+
+#[tracing::instrument(name = "Look up a mailing list", skip(executor))]
+pub async fn get_list_by_slug(
+    executor: impl PgExecutor<'_>,
+    slug: &str,
+) -> Result<Option<MailingList>, sqlx::Error> {
+    sqlx::query_as!(
+        MailingList,
+        r#"
+        SELECT list_id, slug, name
+        FROM lists
+        WHERE slug = $1
+        "#,
+        slug
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+#[tracing::instrument(name = "Get all mailing lists", skip(pool))]
+pub async fn get_all_lists(pool: &PgPool) -> Result<Vec<MailingList>, sqlx::Error> {
+    sqlx::query_as!(
+        MailingList,
+        r#"
+        SELECT list_id, slug, name
+        FROM lists
+        ORDER BY created_at, slug
+        "#
+    )
+    .fetch_all(pool)
+    .await
+}
@@ -2,9 +2,9 @@ use std::fmt::{Debug, Display};
 
 use tokio::task::JoinError;
 use zero_to_prod::configuration::Configuration;
-use zero_to_prod::issue_delivery_worker::run_worker_until_stopped;
 use zero_to_prod::startup::Application;
 use zero_to_prod::telemetry::Telemetry;
+use zero_to_prod::{issue_delivery_worker, pending_subscriber_worker};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -16,14 +16,17 @@ async fn main() -> anyhow::Result<()> {
         Application::db_connection_pool(&config.database).expect("Failed to connect to Postgres.");
 
     let application = Application::build(config.clone(), connection_pool).await?;
-    let worker = run_worker_until_stopped(config);
+    let worker = issue_delivery_worker::run_worker_until_stopped(config.clone());
+    let pending_worker = pending_subscriber_worker::run_worker_until_stopped(config);
 
     let application_task = tokio::spawn(application.run_until_stopped());
     let worker_task = tokio::spawn(worker);
+    let pending_worker_task = tokio::spawn(pending_worker);
 
     tokio::select! {
         outcome = application_task => report_exit("API", outcome),
         outcome = worker_task => report_exit("Background worker", outcome),
+        outcome = pending_worker_task => report_exit("Pending subscribers worker", outcome),
     }
 
     Ok(())
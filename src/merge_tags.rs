@@ -0,0 +1,225 @@
+//! src/merge_tags.rs
+//!
+//! Per-recipient placeholders in newsletter issues, e.g. `{{ name }}` or
+//! `{{ attributes.company | default: "friend" }}`.
+
+use htmlescape::encode_minimal;
+use serde_json::{Map, Value};
+
+use crate::domain::SubscriberAttributes;
+
+/// An issue's title or body, split into literal text and merge tags.
+#[derive(Debug, PartialEq)]
+pub struct Template(Vec<Segment>);
+
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Text(String),
+    Tag {
+        variable: Variable,
+        default: Option<String>,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+enum Variable {
+    Name,
+    Email,
+    Attribute(String),
+}
+
+/// What merge tags are filled in with for one recipient.
+pub struct Recipient<'a> {
+    pub name: &'a str,
+    pub email: &'a str,
+    pub attributes: &'a Map<String, Value>,
+}
+
+impl Template {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let mut segments = Vec::new();
+        let mut rest = input;
+
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                segments.push(Segment::Text(rest[..start].to_owned()));
+            }
+            let tag = &rest[start + 2..];
+            let end = tag.find("}}").ok_or_else(|| {
+                format!(
+                    "The merge tag starting with {{{{{} is never closed.",
+                    tag.chars().take(20).collect::<String>()
+                )
+            })?;
+            segments.push(parse_tag(&tag[..end])?);
+            rest = &tag[end + 2..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Text(rest.to_owned()));
+        }
+
+        Ok(Self(segments))
+    }
+
+    /// Fill in the tags for a plain-text part, or the subject line.
+    pub fn render_text(&self, recipient: &Recipient) -> String {
+        self.render(recipient, |value| value.to_owned())
+    }
+
+    /// Fill in the tags for an HTML part: what they expand to is escaped,
+    /// so subscribers cannot inject markup through their details.
+    pub fn render_html(&self, recipient: &Recipient) -> String {
+        self.render(recipient, encode_minimal)
+    }
+
+    fn render(&self, recipient: &Recipient, escape: impl Fn(&str) -> String) -> String {
+        let mut output = String::new();
+
+        for segment in &self.0 {
+            match segment {
+                Segment::Text(text) => output.push_str(text),
+                Segment::Tag { variable, default } => {
+                    let value = match variable {
+                        Variable::Name => Some(recipient.name.to_owned()),
+                        Variable::Email => Some(recipient.email.to_owned()),
+                        Variable::Attribute(key) => match recipient.attributes.get(key) {
+                            Some(Value::String(s)) => Some(s.clone()),
+                            Some(Value::Null) | None => None,
+                            Some(other) => Some(other.to_string()),
+                        },
+                    };
+                    let value = value
+                        .filter(|value| !value.is_empty())
+                        .or_else(|| default.clone())
+                        .unwrap_or_default();
+                    output.push_str(&escape(&value));
+                }
+            }
+        }
+
+        output
+    }
+}
+
+fn parse_tag(tag: &str) -> Result<Segment, String> {
+    let (variable, filter) = match tag.split_once('|') {
+        Some((variable, filter)) => (variable.trim(), Some(filter.trim())),
+        None => (tag.trim(), None),
+    };
+
+    let variable = match variable {
+        "name" => Variable::Name,
+        "email" => Variable::Email,
+        _ => match variable.strip_prefix("attributes.") {
+            Some(key) if SubscriberAttributes::is_valid_key(key) => {
+                Variable::Attribute(key.to_owned())
+            }
+            _ => {
+                return Err(format!(
+                    "{{{{ {} }}}} is not a known merge tag. \
+                    Use name, email or attributes.<name>.",
+                    variable
+                ))
+            }
+        },
+    };
+
+    let default = filter
+        .map(|filter| {
+            filter
+                .strip_prefix("default:")
+                .map(str::trim)
+                .and_then(|literal| {
+                    let quote = literal.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+                    literal
+                        .strip_prefix(quote)?
+                        .strip_suffix(quote)
+                        .filter(|inner| !inner.contains(quote))
+                })
+                .map(str::to_owned)
+                .ok_or_else(|| {
+                    format!(
+                        "{} is not a valid filter. Only default: \"...\" is supported.",
+                        filter
+                    )
+                })
+        })
+        .transpose()?;
+
+    Ok(Segment::Tag { variable, default })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Recipient, Template};
+    use claims::assert_err;
+    use serde_json::json;
+
+    fn render(template: &str, attributes: serde_json::Value) -> (String, String) {
+        let recipient = Recipient {
+            name: "Ursula",
+            email: "ursula@example.com",
+            attributes: attributes.as_object().unwrap(),
+        };
+        let template = Template::parse(template).unwrap();
+
+        (
+            template.render_text(&recipient),
+            template.render_html(&recipient),
+        )
+    }
+
+    #[test]
+    fn tags_are_replaced_with_the_recipients_details() {
+        let (text, _) = render(
+            "Hi {{name}}, of {{ attributes.company }} ({{ email }}): {{attributes.seats}} seats",
+            json!({ "company": "Earthsea", "seats": 3 }),
+        );
+
+        assert_eq!(text, "Hi Ursula, of Earthsea (ursula@example.com): 3 seats");
+    }
+
+    #[test]
+    fn the_default_stands_in_for_missing_or_empty_attributes() {
+        let template =
+            r#"Dear {{ attributes.nickname | default: "friend" }}{{ attributes.x|default:'' }}!"#;
+
+        assert_eq!(render(template, json!({})).0, "Dear friend!");
+        assert_eq!(
+            render(template, json!({ "nickname": "" })).0,
+            "Dear friend!"
+        );
+        assert_eq!(
+            render(template, json!({ "nickname": "Ged" })).0,
+            "Dear Ged!"
+        );
+    }
+
+    #[test]
+    fn only_the_html_part_is_escaped() {
+        let (text, html) = render(
+            "<p>{{ attributes.company }}</p>",
+            json!({ "company": "<b>Tom & Jerry</b>" }),
+        );
+
+        assert_eq!(text, "<p><b>Tom & Jerry</b></p>");
+        assert_eq!(html, "<p>&lt;b&gt;Tom &amp; Jerry&lt;/b&gt;</p>");
+    }
+
+    #[test]
+    fn text_without_tags_is_left_alone() {
+        assert_eq!(render("Just text } {", json!({})).0, "Just text } {");
+        assert_eq!(render("", json!({})).0, "");
+    }
+
+    #[test]
+    fn malformed_tags_are_rejected() {
+        assert_err!(Template::parse("Hi {{ name"));
+        assert_err!(Template::parse("Hi {{ surname }}"));
+        assert_err!(Template::parse("Hi {{ attributes. }}"));
+        assert_err!(Template::parse("Hi {{ attributes.first name }}"));
+        assert_err!(Template::parse("Hi {{ name | upcase }}"));
+        assert_err!(Template::parse("Hi {{ name | default: friend }}"));
+        assert_err!(Template::parse(r#"Hi {{ name | default: "a"b" }}"#));
+    }
+}
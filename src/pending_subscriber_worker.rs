@@ -0,0 +1,440 @@
+use chrono::Utc;
+use sqlx::{Connection, Executor, PgExecutor, PgPool, Postgres, Transaction};
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+use crate::{
+    configuration::{PendingSubscriberSettings, Settings},
+    domain::SubscriberEmail,
+    email_client::EmailClient,
+    issue_delivery_worker::ExecutionOutcome,
+    mailing_lists::MailingList,
+    routes::{confirmation_link, issue_subscription_token, manage_link, send_confirmation_email},
+    startup::{Application, ApplicationBaseUrl, HmacSecret},
+};
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+pub async fn run_worker_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+    let connection_pool = Application::db_connection_pool(&configuration.database)?;
+
+    let email_client = configuration.email_client.client();
+    let base_url = ApplicationBaseUrl(configuration.application.base_url);
+    let hmac_secret = HmacSecret(configuration.application.hmac_secret);
+
+    worker_loop(
+        connection_pool,
+        email_client,
+        base_url,
+        hmac_secret,
+        configuration.pending_subscribers,
+    )
+    .await
+}
+
+async fn worker_loop(
+    pool: PgPool,
+    email_client: EmailClient,
+    base_url: ApplicationBaseUrl,
+    hmac_secret: HmacSecret,
+    settings: PendingSubscriberSettings,
+) -> Result<(), anyhow::Error> {
+    loop {
+        // Errors are already logged by the tasks; we try again at the next tick.
+        let _ = purge_stale_pending_subscribers(&pool, &settings).await;
+
+        while let Ok(ExecutionOutcome::TaskCompleted) =
+            try_send_queued_confirmation(&pool, &email_client, &base_url, &hmac_secret).await
+        {
+        }
+
+        while let Ok(ExecutionOutcome::TaskCompleted) =
+            try_send_reminder(&pool, &email_client, &base_url, &hmac_secret, &settings).await
+        {
+        }
+
+        tokio::time::sleep(settings.poll_interval()).await;
+    }
+}
+
+/// Delete sign-ups that were never confirmed within the retention window.
+///
+/// A sign-up counts as stale only if no confirmation token was issued during
+/// the window either, so people who just signed up again are left alone.
+/// Their tokens and reminders are removed by `ON DELETE CASCADE`.
+#[tracing::instrument(skip_all, err)]
+pub async fn purge_stale_pending_subscribers(
+    pool: &PgPool,
+    settings: &PendingSubscriberSettings,
+) -> Result<u64, anyhow::Error> {
+    let cutoff = Utc::now() - settings.retention();
+
+    let n_purged = sqlx::query!(
+        r#"
+            DELETE FROM subscriptions
+            WHERE status = 'pending_confirmation'
+            AND subscribed_at < $1
+            AND NOT EXISTS (
+                SELECT 1
+                FROM subscription_tokens
+                WHERE subscriber_id = subscriptions.id
+                AND created_at >= $1
+            )
+        "#,
+        cutoff
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    tracing::info!(
+        purged_subscribers = n_purged,
+        "Purged pending subscribers past the retention window"
+    );
+
+    Ok(n_purged)
+}
+
+/// Send the first confirmation link to one subscriber imported with double
+/// opt-in, if any is waiting for it.
+///
+/// They leave the queue whether or not delivery succeeds: like anyone who
+/// has not confirmed, they are reminded later with a fresh link.
+#[tracing::instrument(
+    skip_all,
+    fields(
+        subscriber_id=tracing::field::Empty,
+        subscriber_email=tracing::field::Empty
+    ),
+    err
+)]
+pub async fn try_send_queued_confirmation(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &ApplicationBaseUrl,
+    hmac_secret: &HmacSecret,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let Some(queued) = sqlx::query!(
+        r#"
+            SELECT
+                confirmation_email_queue.subscriber_id,
+                subscriptions.email,
+                lists.list_id,
+                lists.slug,
+                lists.name,
+                (
+                    subscriptions.status <> 'pending_confirmation'
+                    OR list_memberships.status IS DISTINCT FROM 'pending_confirmation'
+                    OR EXISTS (
+                        SELECT 1
+                        FROM suppressions
+                        WHERE suppressions.email_canonical = subscriptions.email_canonical
+                        AND suppressions.reason <> 'unsubscribe'
+                    )
+                ) AS "stale!"
+            FROM confirmation_email_queue
+            JOIN subscriptions ON subscriptions.id = confirmation_email_queue.subscriber_id
+            JOIN lists ON lists.list_id = confirmation_email_queue.list_id
+            LEFT JOIN list_memberships
+                ON list_memberships.subscriber_id = confirmation_email_queue.subscriber_id
+                AND list_memberships.list_id = confirmation_email_queue.list_id
+            FOR UPDATE OF confirmation_email_queue
+            SKIP LOCKED
+            LIMIT 1
+        "#
+    )
+    .fetch_optional(&mut *transaction)
+    .await?
+    else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+
+    Span::current()
+        .record("subscriber_id", display(queued.subscriber_id))
+        .record("subscriber_email", display(&queued.email));
+
+    forget_queued_confirmations(
+        &mut *transaction,
+        queued.subscriber_id,
+        Some(queued.list_id),
+    )
+    .await?;
+
+    // They confirmed, left or can no longer be emailed since the import.
+    if queued.stale {
+        transaction.commit().await?;
+        return Ok(ExecutionOutcome::TaskCompleted);
+    }
+
+    match SubscriberEmail::parse(queued.email) {
+        Ok(email) => {
+            let list = MailingList {
+                list_id: queued.list_id,
+                slug: queued.slug,
+                name: queued.name,
+            };
+            let mut token_transaction = transaction.begin().await?;
+            let subscription_token = issue_subscription_token(
+                &mut token_transaction,
+                queued.subscriber_id,
+                list.list_id,
+            )
+            .await?;
+
+            match send_confirmation_email(
+                email_client,
+                &email,
+                &base_url.0,
+                hmac_secret,
+                queued.subscriber_id,
+                &subscription_token,
+                &list,
+            )
+            .await
+            {
+                Ok(()) => token_transaction.commit().await?,
+                Err(error) => {
+                    token_transaction.rollback().await?;
+                    tracing::error!(
+                        error.cause_chain = ?error,
+                        error.message = %error,
+                        "Failed to send a confirmation email to an imported subscriber. \n \
+                        Skipping..."
+                    )
+                }
+            }
+        }
+        Err(error) => {
+            tracing::error!(
+                error.cause_chain = ?error,
+                error.message = %error,
+                "Skipping an imported subscriber. \
+                Their stored contact details are invalid",
+            );
+        }
+    }
+
+    transaction.commit().await?;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+/// Drop the confirmation emails still queued for a subscriber, for `list_id`
+/// or for every list, once they are no longer waiting to confirm it.
+#[tracing::instrument(skip(executor))]
+pub async fn forget_queued_confirmations(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+    list_id: Option<Uuid>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+            DELETE FROM confirmation_email_queue
+            WHERE subscriber_id = $1
+            AND ($2::uuid IS NULL OR list_id = $2)
+        "#,
+        subscriber_id,
+        list_id
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Remind one pending subscriber, if any is due, to confirm their subscription.
+///
+/// Only the hash of a confirmation token is stored, so the original link cannot
+/// be sent again: each reminder carries a freshly issued one instead. It only
+/// replaces the original once the reminder is out, so a failed delivery never
+/// leaves the subscriber without a working link.
+#[tracing::instrument(
+    skip_all,
+    fields(
+        subscriber_id=tracing::field::Empty,
+        subscriber_email=tracing::field::Empty
+    ),
+    err
+)]
+pub async fn try_send_reminder(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &ApplicationBaseUrl,
+    hmac_secret: &HmacSecret,
+    settings: &PendingSubscriberSettings,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let Some((mut transaction, due)) = dequeue_due_subscriber(pool, settings).await? else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+    let DueSubscriber {
+        subscriber_id,
+        email,
+        list_id,
+        list_slug,
+    } = due;
+
+    Span::current()
+        .record("subscriber_id", display(subscriber_id))
+        .record("subscriber_email", display(&email));
+
+    // The reminder is recorded even if delivery fails, so a broken address
+    // cannot get more than `max_reminders` attempts.
+    record_reminder(&mut transaction, subscriber_id).await?;
+
+    match SubscriberEmail::parse(email) {
+        Ok(email) => {
+            let mut token_transaction = transaction.begin().await?;
+            let subscription_token =
+                issue_subscription_token(&mut token_transaction, subscriber_id, list_id).await?;
+            let confirmation_link = confirmation_link(&base_url.0, &subscription_token, &list_slug);
+            let manage_link = manage_link(&base_url.0, subscriber_id, hmac_secret);
+
+            match send_reminder_email(email_client, &email, &confirmation_link, &manage_link).await
+            {
+                Ok(()) => token_transaction.commit().await?,
+                Err(error) => {
+                    token_transaction.rollback().await?;
+                    tracing::error!(
+                        error.cause_chain = ?error,
+                        error.message = %error,
+                        "Failed to send a confirmation reminder. \n Skipping..."
+                    )
+                }
+            }
+        }
+        Err(error) => {
+            tracing::error!(
+                error.cause_chain = ?error,
+                error.message = %error,
+                "Skipping a pending subscriber. \
+                Their stored contact details are invalid",
+            );
+        }
+    }
+
+    transaction.commit().await?;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+struct DueSubscriber {
+    subscriber_id: Uuid,
+    email: String,
+    list_id: Uuid,
+    list_slug: String,
+}
+
+/// Lock a pending subscriber due for a reminder, along with the oldest list
+/// they are still waiting to confirm: the reminder's link is issued for it.
+#[tracing::instrument(skip_all)]
+async fn dequeue_due_subscriber(
+    pool: &PgPool,
+    settings: &PendingSubscriberSettings,
+) -> Result<Option<(PgTransaction, DueSubscriber)>, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let record = sqlx::query_as!(
+        DueSubscriber,
+        r#"
+            SELECT
+                subscriptions.id AS subscriber_id,
+                subscriptions.email,
+                membership.list_id,
+                lists.slug AS list_slug
+            FROM subscriptions
+            JOIN LATERAL (
+                SELECT list_id
+                FROM list_memberships
+                WHERE subscriber_id = subscriptions.id
+                AND status = 'pending_confirmation'
+                ORDER BY created_at
+                LIMIT 1
+            ) membership ON true
+            JOIN lists ON lists.list_id = membership.list_id
+            WHERE subscriptions.status = 'pending_confirmation'
+            -- Imported subscribers get their first link before any reminder.
+            AND NOT EXISTS (
+                SELECT 1
+                FROM confirmation_email_queue
+                WHERE subscriber_id = subscriptions.id
+            )
+            AND NOT EXISTS (
+                SELECT 1
+                FROM suppressions
+                WHERE suppressions.email_canonical = subscriptions.email_canonical
+                AND suppressions.reason <> 'unsubscribe'
+            )
+            AND (
+                SELECT COUNT(*)
+                FROM confirmation_reminders
+                WHERE subscriber_id = subscriptions.id
+            ) < $1
+            AND COALESCE(
+                (
+                    SELECT MAX(sent_at)
+                    FROM confirmation_reminders
+                    WHERE subscriber_id = subscriptions.id
+                ),
+                subscriptions.subscribed_at
+            ) < $2
+            FOR UPDATE OF subscriptions
+            SKIP LOCKED
+            LIMIT 1
+        "#,
+        settings.max_reminders,
+        Utc::now() - settings.reminder_after()
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+
+    Ok(record.map(|due| (transaction, due)))
+}
+
+#[tracing::instrument(skip_all)]
+async fn record_reminder(
+    transaction: &mut PgTransaction,
+    subscriber_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!(
+        r#"
+            INSERT INTO confirmation_reminders (subscriber_id, sent_at)
+            VALUES ($1, now())
+        "#,
+        subscriber_id
+    );
+
+    transaction.execute(query).await?;
+
+    Ok(())
+}
+
+async fn send_reminder_email(
+    email_client: &EmailClient,
+    recipient: &SubscriberEmail,
+    confirmation_link: &str,
+    manage_link: &str,
+) -> Result<(), reqwest::Error> {
+    let html_email = format!(
+        "You signed up for our newsletter but have not confirmed yet.<br />\
+        Click <a href=\"{}\">here</a> to confirm your subscription.<hr />\
+        <p><a href=\"{}\">Manage your preferences</a></p>",
+        confirmation_link, manage_link
+    );
+
+    let text_email = format!(
+        "You signed up for our newsletter but have not confirmed yet.\n\
+        Visit {} to confirm your subscription.\n\n\
+        --\nManage your preferences: {}",
+        confirmation_link, manage_link
+    );
+
+    email_client
+        .send_email(
+            recipient,
+            "Please confirm your subscription",
+            &html_email,
+            &text_email,
+        )
+        .await
+}
@@ -0,0 +1,283 @@
+//! src/rate_limit.rs
+//!
+//! Sliding-window limits on the public endpoints, counted in Redis so every
+//! instance of the application shares them.
+
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header;
+use actix_web::web::Bytes;
+use actix_web::{HttpMessage, HttpResponse};
+use anyhow::Context;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::Utc;
+use futures::future::LocalBoxFuture;
+use futures::stream;
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::client_ip::TrustedProxies;
+use crate::configuration::{RouteRateLimits, WindowLimit};
+use crate::domain::SubscriberEmail;
+
+#[derive(serde::Deserialize)]
+struct EmailField {
+    email: String,
+}
+
+/// A hit counted against a limit, which can still be taken back.
+pub struct Hit {
+    key: String,
+    member: String,
+}
+
+pub enum HitOutcome {
+    Counted(Hit),
+    /// The window is full: the client should try again after this long.
+    Limited(Duration),
+}
+
+/// Counts requests against their limits.
+#[derive(Clone)]
+pub struct RateLimiter {
+    redis: ConnectionManager,
+    /// Keeps the counters of several deployments sharing a Redis apart.
+    key_prefix: Arc<str>,
+    trusted_proxies: TrustedProxies,
+}
+
+impl RateLimiter {
+    pub async fn connect(
+        redis_uri: &str,
+        key_prefix: String,
+        trusted_proxies: TrustedProxies,
+    ) -> Result<Self, anyhow::Error> {
+        let client = redis::Client::open(redis_uri).context("Failed to parse the Redis URI.")?;
+        let redis = ConnectionManager::new(client)
+            .await
+            .context("Failed to connect to Redis.")?;
+
+        Ok(Self {
+            redis,
+            key_prefix: key_prefix.into(),
+            trusted_proxies,
+        })
+    }
+
+    /// Middleware applying `limits` to a route, under counters named after
+    /// `route`: routes given the same name share their counters.
+    pub fn limit(&self, route: &'static str, limits: &RouteRateLimits) -> RateLimit {
+        RateLimit {
+            limiter: self.clone(),
+            route,
+            limits: limits.clone(),
+        }
+    }
+
+    /// Record a hit on `key`, or say how long to wait if its window is full.
+    ///
+    /// Each key is a sorted set of the hits within the window, scored by
+    /// when they happened: older ones are dropped as the window slides.
+    /// Hits that are turned down are taken back out, so that a client who
+    /// keeps retrying is let back in on schedule.
+    #[tracing::instrument(name = "Count a hit against a rate limit", skip(self))]
+    pub async fn hit(
+        &self,
+        route: &str,
+        key: &str,
+        limit: &WindowLimit,
+    ) -> Result<HitOutcome, anyhow::Error> {
+        let mut redis = self.redis.clone();
+        let hit = Hit {
+            key: format!("{}:{}:{}", self.key_prefix, route, key),
+            member: Uuid::new_v4().to_string(),
+        };
+        let Hit { key, member } = &hit;
+        let now = Utc::now().timestamp_millis();
+        let window = limit.window().as_millis() as i64;
+
+        let (hits, oldest): (u32, Vec<(String, f64)>) = redis::pipe()
+            .atomic()
+            .zrembyscore(key, "-inf", now - window)
+            .ignore()
+            .zadd(key, member, now)
+            .ignore()
+            .zcard(key)
+            .zrange_withscores(key, 0, 0)
+            .pexpire(key, window)
+            .ignore()
+            .query_async(&mut redis)
+            .await
+            .context("Failed to count a hit against a rate limit.")?;
+
+        if hits <= limit.max_requests {
+            return Ok(HitOutcome::Counted(hit));
+        }
+
+        self.take_back(&hit).await?;
+        let oldest = oldest.first().map_or(now, |(_, at)| *at as i64);
+
+        Ok(HitOutcome::Limited(Duration::from_millis(
+            (oldest + window - now).max(1) as u64,
+        )))
+    }
+
+    /// Uncount a hit, e.g. when another limit turns the request down.
+    pub async fn take_back(&self, hit: &Hit) -> Result<(), anyhow::Error> {
+        let mut redis = self.redis.clone();
+        let _: () = redis
+            .zrem(&hit.key, &hit.member)
+            .await
+            .context("Failed to take back a hit on a rate limit.")?;
+
+        Ok(())
+    }
+}
+
+/// Turns requests beyond a route's limits away with `429 Too Many Requests`.
+///
+/// Requests are counted by client address and, where the route has a
+/// `per_email` limit, by the address in the `email` field of their form or
+/// JSON body. A request turned down by one of them is not counted against
+/// the others. If Redis cannot be reached requests are let through: the
+/// limits protect the service, they should not take it down.
+pub struct RateLimit {
+    limiter: RateLimiter,
+    route: &'static str,
+    limits: RouteRateLimits,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = RateLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddleware {
+            service: Rc::new(service),
+            limiter: self.limiter.clone(),
+            route: self.route,
+            limits: self.limits.clone(),
+        }))
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: Rc<S>,
+    limiter: RateLimiter,
+    route: &'static str,
+    limits: RouteRateLimits,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut request: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let limiter = self.limiter.clone();
+        let route = self.route;
+        let limits = self.limits.clone();
+
+        Box::pin(async move {
+            let mut keys = Vec::new();
+            if let Some(limit) = limits.per_ip {
+                if let Some(ip) = limiter.trusted_proxies.client_ip(request.request()) {
+                    keys.push((format!("ip:{}", ip), limit));
+                }
+            }
+            if let Some(limit) = limits.per_email {
+                if let Some(email) = read_email(&mut request).await? {
+                    keys.push((format!("email:{}", email), limit));
+                }
+            }
+
+            let mut counted = Vec::new();
+            for (key, limit) in keys {
+                match limiter.hit(route, &key, &limit).await {
+                    Ok(HitOutcome::Counted(hit)) => counted.push(hit),
+                    Ok(HitOutcome::Limited(retry_after)) => {
+                        for hit in &counted {
+                            if let Err(e) = limiter.take_back(hit).await {
+                                tracing::error!(
+                                    error.cause_chain = ?e,
+                                    error.message = %e,
+                                    "Failed to take back a hit on a rate limit."
+                                );
+                            }
+                        }
+                        return Ok(request
+                            .into_response(too_many_requests(retry_after))
+                            .map_into_right_body());
+                    }
+                    Err(e) => {
+                        tracing::error!(
+                            error.cause_chain = ?e,
+                            error.message = %e,
+                            "Failed to apply a rate limit, letting the request through."
+                        );
+                    }
+                }
+            }
+
+            service
+                .call(request)
+                .await
+                .map(ServiceResponse::map_into_left_body)
+        })
+    }
+}
+
+/// The email address the request is about, hashed so that it is not
+/// written to Redis in the clear.
+///
+/// The body is put back for the handler to read. Bodies without a valid
+/// address are left for the handler to reject.
+async fn read_email(request: &mut ServiceRequest) -> Result<Option<String>, actix_web::Error> {
+    let body = request.extract::<Bytes>().await?;
+    request.set_payload(Payload::Stream {
+        payload: Box::pin(stream::once(ready(Ok(body.clone())))),
+    });
+
+    let field = if request.content_type() == "application/json" {
+        serde_json::from_slice::<EmailField>(&body).ok()
+    } else {
+        serde_urlencoded::from_bytes::<EmailField>(&body).ok()
+    };
+
+    Ok(field
+        .and_then(|field| SubscriberEmail::parse(field.email).ok())
+        .map(|email| URL_SAFE_NO_PAD.encode(Sha256::digest(email.canonical().as_bytes()))))
+}
+
+fn too_many_requests(retry_after: Duration) -> HttpResponse {
+    let seconds = retry_after.as_millis().div_ceil(1000).max(1);
+
+    HttpResponse::TooManyRequests()
+        .insert_header((header::RETRY_AFTER, seconds.to_string()))
+        .body(format!(
+            "Too many requests. Please try again in {} seconds.",
+            seconds
+        ))
+}
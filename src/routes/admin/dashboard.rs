@@ -37,6 +37,13 @@ pub async fn admin_dashboard(
                 <p>Welcome {username}!</p>
                 <p>Available actions:</p>
                 <ol>
+                    <li><a href="/admin/subscribers">Browse, search and export subscribers</a></li>
+                    <li><a href="/admin/subscribers/import">Import subscribers from a CSV file</a></li>
+                    <li><a href="/admin/sign-up-sources">See where confirmed sign-ups come from</a></li>
+                    <li><a href="/admin/suppressions">Manage the suppression list</a></li>
+                    <li><a href="/admin/segments">Manage audience segments</a></li>
+                    <li><a href="/admin/email-domains">Allow or deny email domains</a></li>
+                    <li><a href="/admin/subscriber-data">Export or erase subscriber data</a></li>
                     <li><a href="/admin/password">Change password</a></li>
                     <li>
                         <form name="logoutForm" action="/admin/logout" method="post">
@@ -0,0 +1,77 @@
+//! src/routes/admin/email_domains/get.rs
+use actix_web::http::header::ContentType;
+use actix_web::{web::Data, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::email_validation::get_domain_rules;
+use crate::utils::error_500;
+
+pub async fn email_domains_form(
+    flash_messages: IncomingFlashMessages,
+    pool: Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for msg in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(msg.content())).unwrap();
+    }
+
+    let mut rules_html = String::new();
+    for rule in get_domain_rules(&pool).await.map_err(error_500)? {
+        let domain = encode_minimal(&rule.domain);
+        writeln!(
+            rules_html,
+            r#"<tr>
+                <td>{domain}</td>
+                <td>{}</td>
+                <td>{}</td>
+                <td>
+                    <form action="/admin/email-domains/remove" method="post">
+                        <input hidden type="text" name="domain" value="{domain}">
+                        <button type="submit">Remove</button>
+                    </form>
+                </td>
+            </tr>"#,
+            rule.rule,
+            rule.created_at.to_rfc3339(),
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+            <!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta http-equiv="content-type" content="text/html; charset=utf-8">
+                <title>Email domain rules</title>
+            </head>
+            <body>
+                {msg_html}
+                <p>Allowed domains skip the automatic checks; denied ones cannot sign up.
+                A rule covers subdomains too, and the most specific rule wins.</p>
+                <table>
+                    <tr><th>Domain</th><th>Rule</th><th>Since</th><th></th></tr>
+                    {rules_html}
+                </table>
+                <form action="/admin/email-domains" method="post">
+                    <label>Domain
+                        <input type="text" placeholder="example.com" name="domain">
+                    </label>
+                    <label>Rule
+                        <select name="rule">
+                            <option value="deny">Deny</option>
+                            <option value="allow">Allow</option>
+                        </select>
+                    </label>
+                    <button type="submit">Save rule</button>
+                </form>
+                <p><a href="/admin/dashboard">&lt;- Back</a></p>
+            </body>
+            </html>"#
+        )))
+}
@@ -0,0 +1,7 @@
+//! src/routes/admin/email_domains/mod.rs
+
+mod get;
+mod post;
+
+pub use get::email_domains_form;
+pub use post::{add_email_domain_rule, remove_email_domain_rule};
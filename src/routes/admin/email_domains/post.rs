@@ -0,0 +1,116 @@
+//! src/routes/admin/email_domains/post.rs
+use actix_web::{
+    web::{self, Data},
+    HttpResponse,
+};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+
+use crate::email_validation::{delete_domain_rule, upsert_domain_rule};
+use crate::utils::{error_500, see_other};
+
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Rule {
+    Allow,
+    Deny,
+}
+
+impl AsRef<str> for Rule {
+    fn as_ref(&self) -> &str {
+        match self {
+            Self::Allow => "allow",
+            Self::Deny => "deny",
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct AddRuleFormData {
+    domain: String,
+    rule: Rule,
+}
+
+#[derive(serde::Deserialize)]
+pub struct RemoveRuleFormData {
+    domain: String,
+}
+
+#[tracing::instrument(name = "Add an email domain rule", skip(form, pool))]
+pub async fn add_email_domain_rule(
+    form: web::Form<AddRuleFormData>,
+    pool: Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(domain) = normalize_domain(&form.domain) else {
+        FlashMessage::error(format!("{} is not a valid domain.", form.domain.trim())).send();
+        return Ok(see_other("/admin/email-domains"));
+    };
+
+    upsert_domain_rule(&pool, &domain, form.rule.as_ref())
+        .await
+        .map_err(error_500)?;
+
+    let message = match form.rule {
+        Rule::Allow => format!("Sign-ups from {} now skip the automatic checks.", domain),
+        Rule::Deny => format!("Sign-ups from {} are now denied.", domain),
+    };
+    FlashMessage::info(message).send();
+
+    Ok(see_other("/admin/email-domains"))
+}
+
+#[tracing::instrument(name = "Remove an email domain rule", skip(form, pool))]
+pub async fn remove_email_domain_rule(
+    form: web::Form<RemoveRuleFormData>,
+    pool: Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let domain = normalize_domain(&form.domain).unwrap_or_default();
+
+    if delete_domain_rule(&pool, &domain)
+        .await
+        .map_err(error_500)?
+    {
+        FlashMessage::info(format!("The rule for {} has been removed.", domain)).send();
+    } else {
+        FlashMessage::info(format!("There is no rule for {}.", form.domain.trim())).send();
+    }
+
+    Ok(see_other("/admin/email-domains"))
+}
+
+/// Rules are stored the way `SubscriberEmail` normalizes domains: lowercase
+/// and in punycode.
+fn normalize_domain(input: &str) -> Option<String> {
+    let domain = idna::domain_to_ascii(input.trim().trim_start_matches('@')).ok()?;
+
+    let is_valid = domain.contains('.')
+        && domain
+            .split('.')
+            .all(|label| !label.is_empty() && label.len() <= 63)
+        && domain
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.');
+
+    is_valid.then_some(domain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::normalize_domain;
+    use claims::{assert_none, assert_some_eq};
+
+    #[test]
+    fn domains_are_lowercased_and_converted_to_punycode() {
+        assert_some_eq!(normalize_domain(" Example.COM "), "example.com");
+        assert_some_eq!(normalize_domain("@bücher.de"), "xn--bcher-kva.de");
+    }
+
+    #[test]
+    fn things_that_are_not_domains_are_rejected() {
+        assert_none!(normalize_domain(""));
+        assert_none!(normalize_domain("localhost"));
+        assert_none!(normalize_domain("example..com"));
+        assert_none!(normalize_domain("exa mple.com"));
+        assert_none!(normalize_domain("ursula@example.com"));
+    }
+}
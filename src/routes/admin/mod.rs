@@ -1,9 +1,25 @@
 mod dashboard;
+mod email_domains;
 mod logout;
 mod newsletter;
 mod password;
+mod segments;
+mod sign_up_sources;
+mod subscriber_data;
+mod subscriber_export;
+mod subscriber_import;
+mod subscribers;
+mod suppressions;
 
 pub use dashboard::admin_dashboard;
+pub use email_domains::*;
 pub use logout::*;
 pub use newsletter::*;
 pub use password::*;
+pub use segments::*;
+pub use sign_up_sources::*;
+pub use subscriber_data::*;
+pub use subscriber_export::*;
+pub use subscriber_import::*;
+pub use subscribers::*;
+pub use suppressions::*;
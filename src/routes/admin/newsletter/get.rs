@@ -1,11 +1,17 @@
+use crate::mailing_lists::{get_all_lists, DEFAULT_LIST};
+use crate::segments::get_all_segments;
+use crate::utils::error_500;
 use actix_web::http::header::ContentType;
-use actix_web::HttpResponse;
+use actix_web::{web, HttpResponse};
 use actix_web_flash_messages::IncomingFlashMessages;
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
 use std::fmt::Write;
 use uuid::Uuid;
 
 pub async fn publish_newsletter_form(
     flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
 ) -> Result<HttpResponse, actix_web::Error> {
     let mut msg_html = String::new();
 
@@ -13,6 +19,36 @@ pub async fn publish_newsletter_form(
         writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
     }
 
+    let mut list_options = String::new();
+
+    for list in get_all_lists(&pool).await.map_err(error_500)? {
+        let selected = if list.slug == DEFAULT_LIST {
+            " selected"
+        } else {
+            ""
+        };
+        writeln!(
+            list_options,
+            r#"<option value="{}"{}>{}</option>"#,
+            encode_minimal(&list.slug),
+            selected,
+            encode_minimal(&list.name)
+        )
+        .unwrap();
+    }
+
+    let mut segment_options = String::new();
+
+    for segment in get_all_segments(&pool).await.map_err(error_500)? {
+        writeln!(
+            segment_options,
+            r#"<option value="{}">{}</option>"#,
+            segment.segment_id,
+            encode_minimal(&segment.name)
+        )
+        .unwrap();
+    }
+
     let idempotency_key = Uuid::new_v4();
 
     Ok(HttpResponse::Ok()
@@ -28,6 +64,19 @@ pub async fn publish_newsletter_form(
 <body>
     {msg_html}
     <form action="/admin/newsletters" method="post">
+        <label>List:<br>
+            <select name="list">
+                {list_options}
+            </select>
+        </label>
+        <br>
+        <label>Send to:<br>
+            <select name="segment">
+                <option value="" selected>Everyone on the list</option>
+                {segment_options}
+            </select>
+        </label>
+        <br>
         <label>Title:<br>
             <input
                 type="text"
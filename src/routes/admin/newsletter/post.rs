@@ -1,11 +1,14 @@
 use crate::authentication::UserId;
 use crate::idempotency::{save_response, try_processing, IdempotencyKey, NextAction};
+use crate::mailing_lists::{get_list_by_slug, DEFAULT_LIST};
+use crate::merge_tags::Template;
+use crate::segments::{get_segment, push_audience_sql, Filter};
 use crate::utils::{error_400, error_500, see_other};
 use actix_web::web::ReqData;
 use actix_web::{web, HttpResponse};
 use actix_web_flash_messages::FlashMessage;
 use anyhow::Context;
-use sqlx::{Executor, PgPool, Postgres, Transaction};
+use sqlx::{Executor, PgPool, Postgres, QueryBuilder, Transaction};
 use uuid::Uuid;
 
 #[derive(serde::Deserialize)]
@@ -14,6 +17,12 @@ pub struct FormData {
     text_content: String,
     html_content: String,
     idempotency_key: String,
+    /// Slug of the list to publish to, the default list if omitted.
+    #[serde(default)]
+    list: Option<String>,
+    /// Id of the segment to send to, everyone on the list if omitted or empty.
+    #[serde(default)]
+    segment: Option<String>,
 }
 
 #[tracing::instrument(
@@ -31,31 +40,83 @@ pub async fn publish_newsletter(
         text_content,
         html_content,
         idempotency_key,
+        list,
+        segment,
     } = form.0;
 
+    // Merge tags are filled in per recipient by the delivery worker, so any
+    // mistake in them has to be caught before the issue is queued.
+    for (field, content) in [
+        ("title", &title),
+        ("text content", &text_content),
+        ("HTML content", &html_content),
+    ] {
+        if let Err(e) = Template::parse(content) {
+            FlashMessage::error(format!("The {} is invalid: {}", field, e)).send();
+            return Ok(see_other("/admin/newsletters"));
+        }
+    }
+
     let user_id = user_id.into_inner();
     let idempotency_key: IdempotencyKey = idempotency_key.try_into().map_err(error_400)?;
 
+    let list_slug = list.unwrap_or_else(|| DEFAULT_LIST.into());
+    let list = get_list_by_slug(&**pool, &list_slug)
+        .await
+        .context("Failed to look up the mailing list")
+        .map_err(error_500)?
+        .ok_or_else(|| error_400(format!("{} is not a known mailing list.", list_slug)))?;
+
+    let segment = match segment.filter(|segment_id| !segment_id.is_empty()) {
+        Some(segment_id) => {
+            let segment_id = Uuid::parse_str(&segment_id)
+                .map_err(|_| error_400(format!("{} is not a known segment.", segment_id)))?;
+            let segment = get_segment(&**pool, segment_id)
+                .await
+                .context("Failed to look up the segment")
+                .map_err(error_500)?
+                .ok_or_else(|| error_400(format!("{} is not a known segment.", segment_id)))?;
+            let filter = Filter::parse(&segment.expression)
+                .map_err(anyhow::Error::msg)
+                .context("A saved segment could not be parsed")
+                .map_err(error_500)?;
+            Some((segment_id, filter))
+        }
+        None => None,
+    };
+
     let mut transaction = match try_processing(&pool, &idempotency_key, *user_id)
         .await
         .map_err(error_500)?
     {
-        NextAction::StartProcessing(db_transaction) => db_transaction,
+        NextAction::StartProcessing(db_transaction) => *db_transaction,
         NextAction::ReturnSavedResponse(saved_response) => {
             FlashMessage::info("The newsletter issue has been published!").send();
             return Ok(saved_response);
         }
     };
 
-    let issue_id = insert_newsletter_issue(&mut transaction, &title, &text_content, &html_content)
-        .await
-        .context("Failed to store newsletter issue details")
-        .map_err(error_500)?;
-
-    enqueue_delivery_tasks(&mut transaction, issue_id)
-        .await
-        .context("Failed to enqueue delivery tasks")
-        .map_err(error_500)?;
+    let issue_id = insert_newsletter_issue(
+        &mut transaction,
+        list.list_id,
+        segment.as_ref().map(|(segment_id, _)| *segment_id),
+        &title,
+        &text_content,
+        &html_content,
+    )
+    .await
+    .context("Failed to store newsletter issue details")
+    .map_err(error_500)?;
+
+    enqueue_delivery_tasks(
+        &mut transaction,
+        issue_id,
+        list.list_id,
+        segment.as_ref().map(|(_, filter)| filter),
+    )
+    .await
+    .context("Failed to enqueue delivery tasks")
+    .map_err(error_500)?;
 
     let response = see_other("/admin/newsletters");
     let response = save_response(transaction, &idempotency_key, *user_id, response)
@@ -70,6 +131,8 @@ pub async fn publish_newsletter(
 #[tracing::instrument(skip_all)]
 async fn insert_newsletter_issue(
     transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    segment_id: Option<Uuid>,
     title: &str,
     text_content: &str,
     html_content: &str,
@@ -83,13 +146,17 @@ async fn insert_newsletter_issue(
                 title,
                 text_content,
                 html_content,
+                list_id,
+                segment_id,
                 published_at
-            ) VALUES ($1, $2, $3, $4, now())
+            ) VALUES ($1, $2, $3, $4, $5, $6, now())
         "#,
         newsletter_issue_id,
         title,
         text_content,
-        html_content
+        html_content,
+        list_id,
+        segment_id
     );
 
     transaction.execute(query).await?;
@@ -97,28 +164,28 @@ async fn insert_newsletter_issue(
     Ok(newsletter_issue_id)
 }
 
+/// Queue the issue for everyone it reaches, as told by `push_audience_sql`.
 #[tracing::instrument(skip_all)]
 async fn enqueue_delivery_tasks(
     transaction: &mut Transaction<'_, Postgres>,
     newsletter_issue_id: Uuid,
+    list_id: Uuid,
+    segment: Option<&Filter>,
 ) -> Result<(), sqlx::Error> {
-    let query = sqlx::query!(
-        // This query inserts a delivery task into issue_delivery_queue for each confirmed subscriber
-        // It pairs the provided newsletter_issue_id with all confirmed subscriber emails
-        // from the subscriptions table in a single bulk insert operation
+    let mut query = QueryBuilder::<Postgres>::new(
         r#"
             INSERT INTO issue_delivery_queue (
                 newsletter_issue_id,
                 subscriber_email
             )
-            SELECT $1, email
-            FROM subscriptions
-            WHERE status = 'confirmed'
-        "#,
-        newsletter_issue_id,
+            SELECT "#,
     );
+    query
+        .push_bind(newsletter_issue_id)
+        .push(", subscriptions.email");
+    push_audience_sql(&mut query, list_id, segment);
 
-    transaction.execute(query).await?;
+    transaction.execute(query.build()).await?;
 
     Ok(())
 }
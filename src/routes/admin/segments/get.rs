@@ -0,0 +1,290 @@
+//! src/routes/admin/segments/get.rs
+use actix_web::error::ErrorNotFound;
+use actix_web::http::header::ContentType;
+use actix_web::{
+    web::{self, Data},
+    HttpResponse,
+};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::mailing_lists::{get_all_lists, get_list_by_slug, MailingList, DEFAULT_LIST};
+use crate::segments::{self, get_all_segments, get_segment, Filter};
+use crate::utils::{error_500, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct PreviewQueryParams {
+    #[serde(default)]
+    name: String,
+    expression: String,
+    /// Slug of the list the preview counts the members of, the default list
+    /// if omitted.
+    #[serde(default)]
+    list: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ListQueryParams {
+    #[serde(default)]
+    list: Option<String>,
+}
+
+pub async fn segments_form(
+    flash_messages: IncomingFlashMessages,
+    pool: Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for msg in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(msg.content())).unwrap();
+    }
+
+    let mut segments_html = String::new();
+    for segment in get_all_segments(&pool).await.map_err(error_500)? {
+        let segment_id = segment.segment_id;
+        writeln!(
+            segments_html,
+            r#"<tr>
+                <td><a href="/admin/segments/{segment_id}">{}</a></td>
+                <td><code>{}</code></td>
+                <td>
+                    <form action="/admin/segments/remove" method="post">
+                        <input hidden type="text" name="segment_id" value="{segment_id}">
+                        <button type="submit">Delete</button>
+                    </form>
+                </td>
+            </tr>"#,
+            encode_minimal(&segment.name),
+            encode_minimal(&segment.expression),
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+            <!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta http-equiv="content-type" content="text/html; charset=utf-8">
+                <title>Segments</title>
+            </head>
+            <body>
+                {msg_html}
+                <p>Segments narrow an issue down to the members of its list who match
+                them.</p>
+                <table>
+                    <tr><th>Name</th><th>Filter</th><th></th></tr>
+                    {segments_html}
+                </table>
+                {}
+                <p><a href="/admin/dashboard">&lt;- Back</a></p>
+            </body>
+            </html>"#,
+            segment_form("", "", &list_options(&pool, DEFAULT_LIST).await?)
+        )))
+}
+
+/// Show who an unsaved segment would match, so it can be tried out first.
+#[tracing::instrument(name = "Preview a new segment", skip(query, pool))]
+pub async fn preview_segment(
+    query: web::Query<PreviewQueryParams>,
+    pool: Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let filter = match Filter::parse(&query.expression) {
+        Ok(filter) => filter,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/segments"));
+        }
+    };
+
+    let Some(list) = get_list(&pool, query.list.as_deref()).await? else {
+        return Ok(see_other("/admin/segments"));
+    };
+    let preview = render_preview(&pool, &list, &filter).await?;
+
+    Ok(preview_page(
+        "Segment preview",
+        &preview,
+        &segment_form(
+            &query.name,
+            &query.expression,
+            &list_options(&pool, &list.slug).await?,
+        ),
+    ))
+}
+
+#[tracing::instrument(name = "Preview a saved segment", skip(query, pool))]
+pub async fn preview_saved_segment(
+    segment_id: web::Path<Uuid>,
+    query: web::Query<ListQueryParams>,
+    pool: Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let segment_id = segment_id.into_inner();
+    let segment = get_segment(&**pool, segment_id)
+        .await
+        .context("Failed to retrieve the segment.")
+        .map_err(error_500)?
+        .ok_or_else(|| ErrorNotFound("There is no such segment."))?;
+    let filter = Filter::parse(&segment.expression)
+        .map_err(anyhow::Error::msg)
+        .context("A saved segment could not be parsed.")
+        .map_err(error_500)?;
+
+    let Some(list) = get_list(&pool, query.list.as_deref()).await? else {
+        return Ok(see_other("/admin/segments"));
+    };
+    let preview = render_preview(&pool, &list, &filter).await?;
+
+    Ok(preview_page(
+        &format!("Segment {}", encode_minimal(&segment.name)),
+        &preview,
+        &format!(
+            r#"<p><code>{}</code></p>
+            <form action="/admin/segments/{segment_id}" method="get">
+                {}
+                <button type="submit">Preview</button>
+            </form>"#,
+            encode_minimal(&segment.expression),
+            list_select(&list_options(&pool, &list.slug).await?),
+        ),
+    ))
+}
+
+/// The list picked for a preview, flashing an error if there is no such list.
+async fn get_list(
+    pool: &PgPool,
+    slug: Option<&str>,
+) -> Result<Option<MailingList>, actix_web::Error> {
+    let slug = slug.unwrap_or(DEFAULT_LIST);
+    let list = get_list_by_slug(pool, slug)
+        .await
+        .context("Failed to look up the mailing list.")
+        .map_err(error_500)?;
+    if list.is_none() {
+        FlashMessage::error(format!("{} is not a known mailing list.", slug)).send();
+    }
+
+    Ok(list)
+}
+
+/// An option for each list, `selected_slug` selected.
+async fn list_options(pool: &PgPool, selected_slug: &str) -> Result<String, actix_web::Error> {
+    let mut list_options = String::new();
+    for list in get_all_lists(pool).await.map_err(error_500)? {
+        let selected = if list.slug == selected_slug {
+            " selected"
+        } else {
+            ""
+        };
+        writeln!(
+            list_options,
+            r#"<option value="{}"{}>{}</option>"#,
+            encode_minimal(&list.slug),
+            selected,
+            encode_minimal(&list.name)
+        )
+        .unwrap();
+    }
+
+    Ok(list_options)
+}
+
+fn list_select(list_options: &str) -> String {
+    format!(
+        r#"<label>Preview against the members of
+            <select name="list">
+                {list_options}
+            </select>
+        </label>"#
+    )
+}
+
+/// The form to save a segment, with a button to preview it beforehand.
+fn segment_form(name: &str, expression: &str, list_options: &str) -> String {
+    format!(
+        r#"<form action="/admin/segments" method="post">
+            <label>Name
+                <input type="text" placeholder="Enter the segment's name" name="name" value="{}">
+            </label>
+            <br>
+            <label>Filter
+                <textarea
+                    placeholder='status = confirmed AND attributes.plan = "pro"'
+                    name="expression"
+                    rows="4"
+                    cols="50"
+                >{}</textarea>
+            </label>
+            <br>
+            {}
+            <br>
+            <button type="submit" formaction="/admin/segments/preview" formmethod="get">
+                Preview
+            </button>
+            <button type="submit">Save</button>
+        </form>"#,
+        encode_minimal(name),
+        encode_minimal(expression),
+        list_select(list_options),
+    )
+}
+
+async fn render_preview(
+    pool: &PgPool,
+    list: &MailingList,
+    filter: &Filter,
+) -> Result<String, actix_web::Error> {
+    let preview = segments::preview_segment(pool, list.list_id, filter)
+        .await
+        .context("Failed to preview the segment.")
+        .map_err(error_500)?;
+
+    let mut sample_html = String::new();
+    for subscriber in &preview.sample {
+        writeln!(
+            sample_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            encode_minimal(&subscriber.email),
+            encode_minimal(&subscriber.name),
+            subscriber.status,
+            subscriber.subscribed_at.to_rfc3339(),
+        )
+        .unwrap();
+    }
+
+    Ok(format!(
+        r#"<p>{} subscribers match among the confirmed members of {}.</p>
+        <table>
+            <tr><th>Email</th><th>Name</th><th>Status</th><th>Subscribed at</th></tr>
+            {sample_html}
+        </table>"#,
+        preview.count,
+        encode_minimal(&list.name)
+    ))
+}
+
+fn preview_page(title: &str, preview: &str, footer: &str) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+            <!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta http-equiv="content-type" content="text/html; charset=utf-8">
+                <title>{title}</title>
+            </head>
+            <body>
+                {preview}
+                {footer}
+                <p><a href="/admin/segments">&lt;- Back</a></p>
+            </body>
+            </html>"#
+        ))
+}
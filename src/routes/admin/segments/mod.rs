@@ -0,0 +1,7 @@
+//! src/routes/admin/segments/mod.rs
+
+mod get;
+mod post;
+
+pub use get::{preview_saved_segment, preview_segment, segments_form};
+pub use post::{add_segment, remove_segment};
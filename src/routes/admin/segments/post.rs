@@ -0,0 +1,68 @@
+//! src/routes/admin/segments/post.rs
+use actix_web::{
+    web::{self, Data},
+    HttpResponse,
+};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::segments::{delete_segment, insert_segment, Filter};
+use crate::utils::{error_500, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct AddSegmentFormData {
+    name: String,
+    expression: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct RemoveSegmentFormData {
+    segment_id: Uuid,
+}
+
+#[tracing::instrument(name = "Add a segment", skip(form, pool))]
+pub async fn add_segment(
+    form: web::Form<AddSegmentFormData>,
+    pool: Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let name = form.name.trim();
+    let expression = form.expression.trim();
+
+    if name.is_empty() {
+        FlashMessage::error("The segment needs a name.").send();
+        return Ok(see_other("/admin/segments"));
+    }
+    if let Err(e) = Filter::parse(expression) {
+        FlashMessage::error(e).send();
+        return Ok(see_other("/admin/segments"));
+    }
+
+    if insert_segment(&pool, name, expression)
+        .await
+        .map_err(error_500)?
+    {
+        FlashMessage::info(format!("The segment {} has been saved.", name)).send();
+    } else {
+        FlashMessage::error(format!("There already is a segment named {}.", name)).send();
+    }
+
+    Ok(see_other("/admin/segments"))
+}
+
+#[tracing::instrument(name = "Remove a segment", skip(form, pool))]
+pub async fn remove_segment(
+    form: web::Form<RemoveSegmentFormData>,
+    pool: Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if delete_segment(&pool, form.segment_id)
+        .await
+        .map_err(error_500)?
+    {
+        FlashMessage::info("The segment has been deleted.").send();
+    } else {
+        FlashMessage::info("The segment was already deleted.").send();
+    }
+
+    Ok(see_other("/admin/segments"))
+}
@@ -0,0 +1,103 @@
+//! src/routes/admin/sign_up_sources/get.rs
+use actix_web::http::header::ContentType;
+use actix_web::{
+    web::{self, Data},
+    HttpResponse,
+};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::attribution::count_sign_ups_by_source;
+use crate::subscribers::SubscriberFilter;
+use crate::utils::{error_500, non_empty, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct QueryParams {
+    #[serde(default)]
+    from: Option<String>,
+    #[serde(default)]
+    to: Option<String>,
+}
+
+/// How many confirmed subscribers each source brought in, among those who
+/// signed up between two dates. Either end may be left open.
+#[tracing::instrument(name = "Show sign-ups by source", skip(query, pool, flash_messages))]
+pub async fn sign_up_sources(
+    query: web::Query<QueryParams>,
+    pool: Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let from = non_empty(&query.from);
+    let to = non_empty(&query.to);
+    let filter = match SubscriberFilter::parse(None, Some("confirmed"), from, to) {
+        Ok(filter) => filter,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/sign-up-sources"));
+        }
+    };
+
+    let mut msg_html = String::new();
+    for msg in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(msg.content())).unwrap();
+    }
+
+    let sources = count_sign_ups_by_source(&pool, &filter)
+        .await
+        .map_err(error_500)?;
+    let total: i64 = sources.iter().map(|source| source.sign_ups).sum();
+
+    let mut sources_html = String::new();
+    for source in &sources {
+        writeln!(
+            sources_html,
+            "<tr><td>{}</td><td>{}</td><td>{:.1}%</td></tr>",
+            encode_minimal(&source.source),
+            source.sign_ups,
+            100.0 * source.sign_ups as f64 / total as f64
+        )
+        .unwrap();
+    }
+    if sources.is_empty() {
+        sources_html.push_str(r#"<tr><td colspan="3">No confirmed sign-ups.</td></tr>"#);
+    }
+
+    let from = encode_minimal(from.unwrap_or_default());
+    let to = encode_minimal(to.unwrap_or_default());
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+            <!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta http-equiv="content-type" content="text/html; charset=utf-8">
+                <title>Sign-ups by source</title>
+            </head>
+            <body>
+                {msg_html}
+                <form action="/admin/sign-up-sources" method="get">
+                    <label>Signed up from
+                        <input type="date" name="from" value="{from}">
+                    </label>
+                    <label>to
+                        <input type="date" name="to" value="{to}">
+                    </label>
+                    <button type="submit">Show</button>
+                </form>
+                <p>A sign-up's source is the <code>source</code> tag it was sent with,
+                failing that its <code>utm_source</code>, failing that the site that
+                referred it. Only subscribers who are still confirmed are counted.</p>
+                <table>
+                    <tr><th>Source</th><th>Confirmed sign-ups</th><th>Share</th></tr>
+                    {sources_html}
+                    <tr><th>Total</th><th>{total}</th><th></th></tr>
+                </table>
+                <p><a href="/admin/dashboard">&lt;- Back</a></p>
+            </body>
+            </html>"#
+        )))
+}
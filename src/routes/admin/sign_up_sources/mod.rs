@@ -0,0 +1,5 @@
+//! src/routes/admin/sign_up_sources/mod.rs
+
+mod get;
+
+pub use get::sign_up_sources;
@@ -0,0 +1,52 @@
+//! src/routes/admin/subscriber_data/get.rs
+use actix_web::http::header::ContentType;
+use actix_web::HttpResponse;
+use actix_web_flash_messages::IncomingFlashMessages;
+use htmlescape::encode_minimal;
+use std::fmt::Write;
+
+pub async fn subscriber_data_form(
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for msg in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(msg.content())).unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+            <!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta http-equiv="content-type" content="text/html; charset=utf-8">
+                <title>Subscriber data requests</title>
+            </head>
+            <body>
+                {msg_html}
+                <form action="/admin/subscriber-data/export" method="post">
+                    <label>Email
+                        <input
+                            type="email"
+                            placeholder="Enter the subscriber's email"
+                            name="email"
+                        >
+                    </label>
+                    <button type="submit">Export as JSON</button>
+                </form>
+                <form action="/admin/subscriber-data/erase" method="post">
+                    <label>Email
+                        <input
+                            type="email"
+                            placeholder="Enter the subscriber's email"
+                            name="email"
+                        >
+                    </label>
+                    <button type="submit">Erase all data</button>
+                </form>
+                <p><a href="/admin/dashboard">&lt;- Back</a></p>
+            </body>
+            </html>"#
+        )))
+}
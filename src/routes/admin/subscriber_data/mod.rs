@@ -0,0 +1,7 @@
+//! src/routes/admin/subscriber_data/mod.rs
+
+mod get;
+mod post;
+
+pub use get::subscriber_data_form;
+pub use post::{erase_subscriber, export_subscriber};
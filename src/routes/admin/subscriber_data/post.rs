@@ -0,0 +1,72 @@
+//! src/routes/admin/subscriber_data/post.rs
+use actix_web::{
+    web::{self, Data},
+    HttpResponse,
+};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+
+use crate::gdpr::{erase_subscriber_data, export_subscriber_data, find_subscriber_id};
+use crate::utils::{error_500, json_download, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    email: String,
+}
+
+#[tracing::instrument(name = "Export subscriber data on request", skip(form, pool))]
+pub async fn export_subscriber(
+    form: web::Form<FormData>,
+    pool: Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let export = match find_subscriber_id(&pool, form.email.trim())
+        .await
+        .map_err(error_500)?
+    {
+        Some(subscriber_id) => export_subscriber_data(&pool, subscriber_id)
+            .await
+            .map_err(error_500)?,
+        None => None,
+    };
+
+    let Some(export) = export else {
+        FlashMessage::info(no_data_message(&form.email)).send();
+        return Ok(see_other("/admin/subscriber-data"));
+    };
+
+    let filename = format!("subscriber-{}.json", export.subscriber.id);
+
+    Ok(json_download(&export, &filename))
+}
+
+#[tracing::instrument(name = "Erase subscriber data on request", skip(form, pool))]
+pub async fn erase_subscriber(
+    form: web::Form<FormData>,
+    pool: Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let erased = match find_subscriber_id(&pool, form.email.trim())
+        .await
+        .map_err(error_500)?
+    {
+        Some(subscriber_id) => erase_subscriber_data(&pool, subscriber_id)
+            .await
+            .map_err(error_500)?,
+        None => false,
+    };
+
+    if erased {
+        FlashMessage::info(format!(
+            "All data held about {} has been erased.",
+            form.email.trim()
+        ))
+        .send();
+    } else {
+        FlashMessage::info(no_data_message(&form.email)).send();
+    }
+
+    Ok(see_other("/admin/subscriber-data"))
+}
+
+fn no_data_message(email: &str) -> String {
+    format!("No data is held about {}.", email.trim())
+}
@@ -0,0 +1,66 @@
+//! src/routes/admin/subscriber_export/get.rs
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::{
+    web::{self, Data},
+    HttpResponse,
+};
+use actix_web_flash_messages::FlashMessage;
+use chrono::Utc;
+use sqlx::PgPool;
+
+use crate::subscriber_export::{self, ExportFormat};
+use crate::subscribers::SubscriberFilter;
+use crate::utils::{non_empty, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct QueryParams {
+    #[serde(default)]
+    format: Option<String>,
+    #[serde(default)]
+    q: Option<String>,
+    #[serde(default)]
+    status: Option<String>,
+    #[serde(default)]
+    from: Option<String>,
+    #[serde(default)]
+    to: Option<String>,
+}
+
+/// Download every subscriber matching the query, streamed page by page.
+///
+/// Left empty, a field of the export form does not filter anything.
+#[tracing::instrument(name = "Export subscribers", skip(query, pool))]
+pub async fn export_subscribers(
+    query: web::Query<QueryParams>,
+    pool: Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let format = non_empty(&query.format).map_or(Ok(ExportFormat::Csv), ExportFormat::parse);
+    let filter = SubscriberFilter::parse(
+        non_empty(&query.q),
+        non_empty(&query.status),
+        non_empty(&query.from),
+        non_empty(&query.to),
+    );
+    let (format, filter) = match (format, filter) {
+        (Ok(format), Ok(filter)) => (format, filter),
+        (Err(e), _) | (_, Err(e)) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/subscribers"));
+        }
+    };
+
+    let filename = format!(
+        "subscribers-{}.{}",
+        Utc::now().format("%Y-%m-%d"),
+        format.extension()
+    );
+    let body = subscriber_export::export_subscribers(pool.get_ref().clone(), format, filter);
+
+    Ok(HttpResponse::Ok()
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(filename)],
+        })
+        .content_type(format.content_type())
+        .streaming(body))
+}
@@ -0,0 +1,5 @@
+//! src/routes/admin/subscriber_export/mod.rs
+
+mod get;
+
+pub use get::export_subscribers;
@@ -0,0 +1,156 @@
+//! src/routes/admin/subscriber_import/get.rs
+use actix_web::error::ErrorNotFound;
+use actix_web::http::header::ContentType;
+use actix_web::{
+    web::{self, Data},
+    HttpResponse,
+};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::mailing_lists::{get_all_lists, DEFAULT_LIST};
+use crate::subscriber_import::{get_import, get_import_report};
+use crate::utils::{csv_download, error_500};
+
+pub async fn import_subscribers_form(
+    flash_messages: IncomingFlashMessages,
+    pool: Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for msg in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(msg.content())).unwrap();
+    }
+
+    let mut list_options = String::new();
+    for list in get_all_lists(&pool).await.map_err(error_500)? {
+        writeln!(
+            list_options,
+            r#"<option value="{}"{}>{}</option>"#,
+            encode_minimal(&list.slug),
+            if list.slug == DEFAULT_LIST {
+                " selected"
+            } else {
+                ""
+            },
+            encode_minimal(&list.name)
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+            <!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta http-equiv="content-type" content="text/html; charset=utf-8">
+                <title>Import subscribers</title>
+            </head>
+            <body>
+                {msg_html}
+                <p>Upload a CSV file whose first row names its columns. It needs an
+                <code>email</code> and a <code>name</code> column; any other column is
+                stored as an attribute. Addresses already on file or suppressed are
+                skipped.</p>
+                <form action="/admin/subscribers/import" method="post" enctype="multipart/form-data">
+                    <label>List
+                        <select name="list">
+                            {list_options}
+                        </select>
+                    </label>
+                    <br>
+                    <label>
+                        <input type="radio" name="mode" value="double_opt_in" checked>
+                        Send each subscriber a confirmation link
+                    </label>
+                    <label>
+                        <input type="radio" name="mode" value="confirmed">
+                        Import as confirmed: they opted in with the previous provider
+                    </label>
+                    <br>
+                    <!-- Last: the file is imported as it arrives, after the list and mode. -->
+                    <label>File
+                        <input type="file" accept=".csv,text/csv" name="file">
+                    </label>
+                    <br>
+                    <button type="submit">Import</button>
+                </form>
+                <p><a href="/admin/dashboard">&lt;- Back</a></p>
+            </body>
+            </html>"#
+        )))
+}
+
+#[tracing::instrument(name = "Show a subscriber import", skip(pool))]
+pub async fn subscriber_import_results(
+    import_id: web::Path<Uuid>,
+    pool: Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let import = get_import(&pool, import_id.into_inner())
+        .await
+        .context("Failed to retrieve the import.")
+        .map_err(error_500)?
+        .ok_or_else(|| ErrorNotFound("There is no such import."))?;
+
+    let import_id = import.import_id;
+    let list_name = encode_minimal(&import.list_name);
+    let n_imported = import.n_imported;
+    let n_rejected = import.n_rejected;
+    let created_at = import.created_at.to_rfc3339();
+    let status = if import.confirmed {
+        "confirmed"
+    } else {
+        "pending confirmation, a link was sent to each"
+    };
+    let report_html = if n_rejected > 0 {
+        format!(
+            r#"<p><a href="/admin/subscribers/import/{import_id}/report">Download the
+            rejected rows and why</a></p>"#
+        )
+    } else {
+        String::new()
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+            <!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta http-equiv="content-type" content="text/html; charset=utf-8">
+                <title>Subscriber import</title>
+            </head>
+            <body>
+                <p>Imported {n_imported} subscribers to {list_name} on {created_at}, as
+                {status}.</p>
+                <p>{n_rejected} rows were rejected.</p>
+                {report_html}
+                <p><a href="/admin/subscribers/import">&lt;- Back</a></p>
+            </body>
+            </html>"#
+        )))
+}
+
+#[tracing::instrument(name = "Download a subscriber import report", skip(pool))]
+pub async fn subscriber_import_report(
+    import_id: web::Path<Uuid>,
+    pool: Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let import_id = import_id.into_inner();
+    let report = get_import_report(&pool, import_id)
+        .await
+        .context("Failed to retrieve the import report.")
+        .map_err(error_500)?
+        .ok_or_else(|| ErrorNotFound("There is no such import."))?;
+
+    Ok(csv_download(
+        report,
+        &format!("subscriber-import-{}-rejected.csv", import_id),
+    ))
+}
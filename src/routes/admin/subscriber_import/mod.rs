@@ -0,0 +1,7 @@
+//! src/routes/admin/subscriber_import/mod.rs
+
+mod get;
+mod post;
+
+pub use get::{import_subscribers_form, subscriber_import_report, subscriber_import_results};
+pub use post::import_subscribers;
@@ -0,0 +1,118 @@
+//! src/routes/admin/subscriber_import/post.rs
+use actix_multipart::{Field, Multipart};
+use actix_web::{web::Data, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use futures::StreamExt;
+use sqlx::PgPool;
+
+use crate::csv_file::RecordReader;
+use crate::mailing_lists::{get_list_by_slug, DEFAULT_LIST};
+use crate::subscriber_import::{ImportError, ImportMode, SubscriberImporter};
+use crate::upload::{read_text, upload_error};
+use crate::utils::{error_500, see_other};
+
+/// Sign up every subscriber of an uploaded CSV file.
+///
+/// The file is imported as it is read, so the form sends the list and the
+/// mode ahead of it. Nothing is imported unless the first row of the file can
+/// be understood; individual rows that do not make sense end up in the
+/// import's report.
+#[tracing::instrument(name = "Import subscribers from a file", skip_all)]
+pub async fn import_subscribers(
+    mut payload: Multipart,
+    pool: Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut list_slug = String::new();
+    let mut mode = String::new();
+
+    while let Some(field) = payload.next().await {
+        let mut field = match field {
+            Ok(field) => field,
+            Err(e) => return Ok(import_failed(upload_error(e))),
+        };
+        let value = match field.name() {
+            Some("list") => &mut list_slug,
+            Some("mode") => &mut mode,
+            Some("file") => return import_file(&pool, &list_slug, &mode, field).await,
+            _ => continue,
+        };
+        match read_text(&mut field).await {
+            Ok(text) => *value = text,
+            Err(e) => return Ok(import_failed(e)),
+        }
+    }
+
+    Ok(import_failed("Choose a CSV file to import.".into()))
+}
+
+async fn import_file(
+    pool: &PgPool,
+    list_slug: &str,
+    mode: &str,
+    file: Field,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mode = match ImportMode::parse(mode) {
+        Ok(mode) => mode,
+        Err(e) => return Ok(import_failed(e)),
+    };
+    let list_slug = match list_slug.trim() {
+        "" => DEFAULT_LIST,
+        slug => slug,
+    };
+    let Some(list) = get_list_by_slug(pool, list_slug)
+        .await
+        .context("Failed to look up the mailing list.")
+        .map_err(error_500)?
+    else {
+        return Ok(import_failed(format!(
+            "{} is not a known mailing list.",
+            list_slug
+        )));
+    };
+
+    let mut importer = SubscriberImporter::new(pool, &list, mode);
+    let imported = match read_file(&mut importer, file).await {
+        Ok(()) => importer.finish().await,
+        Err(e) => Err(e),
+    };
+    match imported {
+        Ok(import_id) => Ok(see_other(&format!("/admin/subscribers/import/{import_id}"))),
+        Err(ImportError::UnusableFile(e)) => Ok(import_failed(e)),
+        Err(ImportError::UnexpectedError(e)) => Err(error_500(e)),
+    }
+}
+
+/// Hand the records of the file to the importer as its chunks arrive. Should
+/// the upload break off, the batches already imported stay.
+async fn read_file(
+    importer: &mut SubscriberImporter<'_>,
+    mut file: Field,
+) -> Result<(), ImportError> {
+    let mut reader = RecordReader::default();
+    let mut empty = true;
+
+    while let Some(chunk) = file.next().await {
+        let chunk = chunk.map_err(|e| ImportError::UnusableFile(upload_error(e)))?;
+        empty &= chunk.is_empty();
+        for record in reader.push(&chunk) {
+            importer.push(record).await?;
+        }
+    }
+    // A file input left empty is still sent, with no content.
+    if empty {
+        return Err(ImportError::UnusableFile(
+            "Choose a CSV file to import.".into(),
+        ));
+    }
+    if let Some(record) = reader.finish() {
+        importer.push(record).await?;
+    }
+
+    Ok(())
+}
+
+fn import_failed(message: String) -> HttpResponse {
+    FlashMessage::error(message).send();
+    see_other("/admin/subscribers/import")
+}
@@ -0,0 +1,457 @@
+//! src/routes/admin/subscribers/get.rs
+use actix_web::error::ErrorNotFound;
+use actix_web::http::header::ContentType;
+use actix_web::{
+    web::{self, Data},
+    HttpResponse,
+};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::gdpr::{export_subscriber_data, find_subscriber_id, MembershipRecord};
+use crate::mailing_lists::{get_all_lists, MailingList};
+use crate::segments::STATUSES;
+use crate::subscribers::{get_subscribers_page, Cursor, SubscriberFilter};
+use crate::utils::{error_500, non_empty, see_other};
+
+/// Subscribers listed per page.
+const PAGE_SIZE: i64 = 50;
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct QueryParams {
+    /// An exact address, to go straight to its subscriber.
+    #[serde(default, skip_serializing)]
+    email: Option<String>,
+    #[serde(default, skip_serializing_if = "is_empty")]
+    q: Option<String>,
+    #[serde(default, skip_serializing_if = "is_empty")]
+    status: Option<String>,
+    #[serde(default, skip_serializing_if = "is_empty")]
+    from: Option<String>,
+    #[serde(default, skip_serializing_if = "is_empty")]
+    to: Option<String>,
+    #[serde(default, skip_serializing)]
+    after: Option<String>,
+}
+
+fn is_empty(value: &Option<String>) -> bool {
+    non_empty(value).is_none()
+}
+
+/// Page through the subscribers, newest first, searched and filtered.
+#[tracing::instrument(name = "Browse subscribers", skip(query, pool, flash_messages))]
+pub async fn browse_subscribers(
+    query: web::Query<QueryParams>,
+    pool: Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    if let Some(email) = non_empty(&query.email) {
+        match find_subscriber_id(&pool, email).await.map_err(error_500)? {
+            Some(subscriber_id) => {
+                return Ok(see_other(&format!("/admin/subscribers/{subscriber_id}")));
+            }
+            None => {
+                FlashMessage::info(format!("There is no subscriber with the email {email}."))
+                    .send();
+                return Ok(see_other("/admin/subscribers"));
+            }
+        }
+    }
+
+    let filter = SubscriberFilter::parse(
+        non_empty(&query.q),
+        non_empty(&query.status),
+        non_empty(&query.from),
+        non_empty(&query.to),
+    );
+    let after = non_empty(&query.after).map(Cursor::parse).transpose();
+    let (filter, after) = match (filter, after) {
+        (Ok(filter), Ok(after)) => (filter, after),
+        (Err(e), _) | (_, Err(e)) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/subscribers"));
+        }
+    };
+
+    // One more than shown tells whether there is a next page.
+    let mut subscribers = get_subscribers_page(&pool, &filter, after, PAGE_SIZE + 1)
+        .await
+        .map_err(error_500)?;
+    let next = if subscribers.len() as i64 > PAGE_SIZE {
+        subscribers.truncate(PAGE_SIZE as usize);
+        subscribers.last().map(|last| last.cursor())
+    } else {
+        None
+    };
+
+    let mut msg_html = String::new();
+    for msg in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(msg.content())).unwrap();
+    }
+
+    let mut rows_html = String::new();
+    for subscriber in &subscribers {
+        writeln!(
+            rows_html,
+            r#"<tr><td><a href="/admin/subscribers/{}">{}</a></td><td>{}</td><td>{}</td><td>{}</td></tr>"#,
+            subscriber.id,
+            encode_minimal(&subscriber.email),
+            encode_minimal(&subscriber.name),
+            subscriber.status,
+            subscriber.subscribed_at.to_rfc3339(),
+        )
+        .unwrap();
+    }
+    if subscribers.is_empty() {
+        rows_html.push_str(r#"<tr><td colspan="4">No subscribers match.</td></tr>"#);
+    }
+
+    // The filters, carried over to the other pages and to the exports.
+    let filters = serde_urlencoded::to_string(&*query)
+        .context("Failed to encode the subscriber filters.")
+        .map_err(error_500)?;
+    let separator = if filters.is_empty() { "" } else { "&" };
+    let mut pages_html = String::new();
+    if after.is_some() {
+        writeln!(
+            pages_html,
+            r#"<a href="/admin/subscribers?{}">First page</a>"#,
+            encode_minimal(&filters)
+        )
+        .unwrap();
+    }
+    if let Some(next) = next {
+        writeln!(
+            pages_html,
+            r#"<a href="/admin/subscribers?{}{}after={}">Next page -&gt;</a>"#,
+            encode_minimal(&filters),
+            separator,
+            next
+        )
+        .unwrap();
+    }
+
+    let search = encode_minimal(non_empty(&query.q).unwrap_or_default());
+    let from = encode_minimal(non_empty(&query.from).unwrap_or_default());
+    let to = encode_minimal(non_empty(&query.to).unwrap_or_default());
+    let mut status_options = String::new();
+    for status in STATUSES {
+        let selected = if non_empty(&query.status) == Some(status) {
+            " selected"
+        } else {
+            ""
+        };
+        writeln!(
+            status_options,
+            r#"<option value="{status}"{selected}>{status}</option>"#
+        )
+        .unwrap();
+    }
+    let filters = encode_minimal(&filters);
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+            <!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta http-equiv="content-type" content="text/html; charset=utf-8">
+                <title>Subscribers</title>
+            </head>
+            <body>
+                {msg_html}
+                <form action="/admin/subscribers" method="get">
+                    <label>Email or name starts with
+                        <input type="search" name="q" value="{search}">
+                    </label>
+                    <label>Status
+                        <select name="status">
+                            <option value="">Any</option>
+                            {status_options}
+                        </select>
+                    </label>
+                    <label>Subscribed from
+                        <input type="date" name="from" value="{from}">
+                    </label>
+                    <label>to
+                        <input type="date" name="to" value="{to}">
+                    </label>
+                    <button type="submit">Filter</button>
+                </form>
+                <table>
+                    <tr><th>Email</th><th>Name</th><th>Status</th><th>Subscribed at</th></tr>
+                    {rows_html}
+                </table>
+                <p>{pages_html}</p>
+                <p>Export these subscribers as
+                <a href="/admin/subscribers/export?{filters}{separator}format=csv">CSV</a> or
+                <a href="/admin/subscribers/export?{filters}{separator}format=ndjson">JSON, one
+                subscriber per line</a>.</p>
+                <p><a href="/admin/dashboard">&lt;- Back</a></p>
+            </body>
+            </html>"#
+        )))
+}
+
+/// Everything held about a subscriber: their details and consent trail, the
+/// lists they are on, their confirmation links and the issues sent to them.
+#[tracing::instrument(name = "Show a subscriber", skip(pool, flash_messages))]
+pub async fn subscriber_details(
+    subscriber_id: web::Path<Uuid>,
+    pool: Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+
+    let data = export_subscriber_data(&pool, subscriber_id)
+        .await
+        .map_err(error_500)?
+        .ok_or_else(|| ErrorNotFound("There is no such subscriber."))?;
+    let subscriber = data.subscriber;
+
+    let mut consent_html = String::new();
+    for record in &data.consent_records {
+        writeln!(
+            consent_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            record.recorded_at.to_rfc3339(),
+            encode_minimal(&record.event),
+            encode_minimal(&record.list),
+            encode_minimal(record.ip_address.as_deref().unwrap_or("-")),
+            encode_minimal(record.user_agent.as_deref().unwrap_or("-")),
+            encode_minimal(record.form_version.as_deref().unwrap_or("-")),
+            record.subscription_token_hash.as_deref().unwrap_or("-"),
+            encode_minimal(record.recorded_by.as_deref().unwrap_or("-")),
+        )
+        .unwrap();
+    }
+
+    let mut memberships_html = String::new();
+    for membership in &data.list_memberships {
+        writeln!(
+            memberships_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+            encode_minimal(&membership.list),
+            membership.status,
+            membership.created_at.to_rfc3339(),
+        )
+        .unwrap();
+    }
+
+    let mut tokens_html = String::new();
+    for token in &data.subscription_tokens {
+        writeln!(
+            tokens_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            encode_minimal(&token.list),
+            token.created_at.to_rfc3339(),
+            token.expires_at.to_rfc3339(),
+            token
+                .consumed_at
+                .map_or_else(|| "-".into(), |consumed_at| consumed_at.to_rfc3339()),
+        )
+        .unwrap();
+    }
+
+    let mut deliveries_html = String::new();
+    for delivery in &data.deliveries {
+        writeln!(
+            deliveries_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+            encode_minimal(&delivery.title),
+            delivery.outcome,
+            delivery.attempted_at.to_rfc3339(),
+        )
+        .unwrap();
+    }
+    for delivery in &data.queued_deliveries {
+        writeln!(
+            deliveries_html,
+            "<tr><td>{}</td><td>queued</td><td>-</td></tr>",
+            encode_minimal(&delivery.title),
+        )
+        .unwrap();
+    }
+
+    let suppression_html = match &data.suppression {
+        Some(suppression) => format!(
+            "<dt>Suppressed</dt><dd>{}, since {}</dd>",
+            encode_minimal(&suppression.reason),
+            suppression.created_at.to_rfc3339()
+        ),
+        None => String::new(),
+    };
+
+    let mut msg_html = String::new();
+    for msg in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(msg.content())).unwrap();
+    }
+
+    let mut attributes_html = String::new();
+    if let Some(attributes) = subscriber.attributes.as_object() {
+        for (key, value) in attributes {
+            writeln!(
+                attributes_html,
+                "<tr><td>{}</td><td>{}</td></tr>",
+                encode_minimal(key),
+                encode_minimal(&value.to_string()),
+            )
+            .unwrap();
+        }
+    }
+    let attributes_json = encode_minimal(
+        &serde_json::to_string_pretty(&subscriber.attributes)
+            .context("Failed to serialize the subscriber's attributes.")
+            .map_err(error_500)?,
+    );
+
+    let email = encode_minimal(&subscriber.email);
+    let name = encode_minimal(&subscriber.name);
+    let actions_html = actions_html(
+        subscriber_id,
+        &data.list_memberships,
+        &get_all_lists(&pool).await.map_err(error_500)?,
+    );
+
+    let status = subscriber.status;
+    let subscribed_at = subscriber.subscribed_at.to_rfc3339();
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+            <!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta http-equiv="content-type" content="text/html; charset=utf-8">
+                <title>Subscriber {email}</title>
+            </head>
+            <body>
+                {msg_html}
+                <dl>
+                    <dt>Email</dt><dd>{email}</dd>
+                    <dt>Name</dt><dd>{name}</dd>
+                    <dt>Status</dt><dd>{status}</dd>
+                    <dt>Subscribed at</dt><dd>{subscribed_at}</dd>
+                    {suppression_html}
+                </dl>
+                <h2>Lists</h2>
+                <table>
+                    <tr><th>List</th><th>Status</th><th>Since</th></tr>
+                    {memberships_html}
+                </table>
+                <h2>Confirmation links</h2>
+                <table>
+                    <tr><th>List</th><th>Sent at</th><th>Expires at</th><th>Used at</th></tr>
+                    {tokens_html}
+                </table>
+                <h2>Issues</h2>
+                <table>
+                    <tr><th>Title</th><th>Outcome</th><th>Attempted at</th></tr>
+                    {deliveries_html}
+                </table>
+                <h2>Consent trail</h2>
+                <table>
+                    <tr>
+                        <th>Recorded at</th>
+                        <th>Event</th>
+                        <th>List</th>
+                        <th>IP address</th>
+                        <th>User agent</th>
+                        <th>Form</th>
+                        <th>Token hash</th>
+                        <th>Recorded by</th>
+                    </tr>
+                    {consent_html}
+                </table>
+                <h2>Attributes</h2>
+                <table>
+                    <tr><th>Name</th><th>Value</th></tr>
+                    {attributes_html}
+                </table>
+                <form action="/admin/subscribers/{subscriber_id}/attributes" method="post">
+                    <label>Attributes, as a JSON object
+                        <textarea name="attributes" rows="10" cols="50">{attributes_json}</textarea>
+                    </label>
+                    <br>
+                    <button type="submit">Save attributes</button>
+                </form>
+                <h2>Actions</h2>
+                {actions_html}
+                <p><a href="/admin/subscribers">&lt;- Back</a></p>
+            </body>
+            </html>"#
+        )))
+}
+
+/// A form per action, each with its own idempotency key so that they do not
+/// replay one another.
+fn actions_html(
+    subscriber_id: Uuid,
+    memberships: &[MembershipRecord],
+    lists: &[MailingList],
+) -> String {
+    let mut html = String::new();
+    for (action, label) in [
+        ("confirm", "Confirm"),
+        ("resend", "Resend the confirmation email"),
+        ("unsubscribe", "Unsubscribe from every list"),
+    ] {
+        writeln!(
+            html,
+            r#"<form action="/admin/subscribers/{subscriber_id}/{action}" method="post">
+                <input hidden type="text" name="idempotency_key" value="{}">
+                <button type="submit">{label}</button>
+            </form>"#,
+            Uuid::new_v4()
+        )
+        .unwrap();
+    }
+
+    let mut from_options = String::new();
+    for membership in memberships {
+        writeln!(
+            from_options,
+            r#"<option value="{0}">{0}</option>"#,
+            encode_minimal(&membership.list)
+        )
+        .unwrap();
+    }
+    let mut to_options = String::new();
+    for list in lists {
+        writeln!(
+            to_options,
+            r#"<option value="{}">{}</option>"#,
+            encode_minimal(&list.slug),
+            encode_minimal(&list.name)
+        )
+        .unwrap();
+    }
+    writeln!(
+        html,
+        r#"<form action="/admin/subscribers/{subscriber_id}/move" method="post">
+            <input hidden type="text" name="idempotency_key" value="{}">
+            <label>Move from
+                <select name="from">{from_options}</select>
+            </label>
+            <label>to
+                <select name="to">{to_options}</select>
+            </label>
+            <button type="submit">Move</button>
+        </form>
+        <form action="/admin/subscribers/{subscriber_id}/delete" method="post">
+            <input hidden type="text" name="idempotency_key" value="{}">
+            <button type="submit">Delete permanently, with all their data</button>
+        </form>"#,
+        Uuid::new_v4(),
+        Uuid::new_v4()
+    )
+    .unwrap();
+
+    html
+}
@@ -0,0 +1,10 @@
+//! src/routes/admin/subscribers/mod.rs
+
+mod get;
+mod post;
+
+pub use get::{browse_subscribers, subscriber_details};
+pub use post::{
+    admin_confirm_subscriber, admin_delete_subscriber, admin_resend_confirmation,
+    admin_unsubscribe_subscriber, move_subscriber, update_subscriber_attributes,
+};
@@ -0,0 +1,597 @@
+//! src/routes/admin/subscribers/post.rs
+use actix_web::error::ErrorNotFound;
+use actix_web::{
+    web::{self, Data, ReqData},
+    HttpResponse,
+};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use serde_json::Value;
+use sqlx::{Connection, Executor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::authentication::UserId;
+use crate::consent::record_admin_confirmation;
+use crate::domain::{SubscriberAttributes, SubscriberEmail};
+use crate::email_client::EmailClient;
+use crate::gdpr::delete_subscriber;
+use crate::idempotency::{save_response, try_processing, IdempotencyKey, NextAction};
+use crate::mailing_lists::{get_list_by_slug, MailingList};
+use crate::pending_subscriber_worker::forget_queued_confirmations;
+use crate::routes::{
+    confirm_subscriber, issue_subscription_token, mark_subscriber_as_unsubscribed,
+    send_confirmation_email, unknown_list_message,
+};
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+use crate::suppressions::{blocks_all_email, lift_unsubscribe_suppression};
+use crate::utils::{error_400, error_500, see_other};
+
+/// Flashed when an action's form is submitted again, e.g. on a double click.
+const ALREADY_DONE: &str = "This action has already been carried out.";
+
+#[derive(serde::Deserialize)]
+pub struct AttributesFormData {
+    /// The subscriber's attributes as a JSON object, replacing the old ones.
+    attributes: String,
+}
+
+#[tracing::instrument(name = "Update a subscriber's attributes", skip(form, pool))]
+pub async fn update_subscriber_attributes(
+    subscriber_id: web::Path<Uuid>,
+    form: web::Form<AttributesFormData>,
+    pool: Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let location = format!("/admin/subscribers/{subscriber_id}");
+
+    let attributes = match parse_attributes(&form.attributes) {
+        Ok(attributes) => attributes,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&location));
+        }
+    };
+
+    let updated = sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET attributes = $2
+        WHERE id = $1
+        "#,
+        subscriber_id,
+        Value::Object(attributes.into_inner())
+    )
+    .execute(&**pool)
+    .await
+    .context("Failed to update the subscriber's attributes.")
+    .map_err(error_500)?;
+
+    if updated.rows_affected() == 0 {
+        return Err(ErrorNotFound("There is no such subscriber."));
+    }
+
+    FlashMessage::info("The attributes have been saved.").send();
+
+    Ok(see_other(&location))
+}
+
+fn parse_attributes(input: &str) -> Result<SubscriberAttributes, String> {
+    // An emptied textarea clears every attribute.
+    if input.trim().is_empty() {
+        return Ok(SubscriberAttributes::default());
+    }
+
+    match serde_json::from_str(input) {
+        Ok(Value::Object(attributes)) => SubscriberAttributes::parse(attributes),
+        Ok(_) => Err("The attributes must be a JSON object.".into()),
+        Err(e) => Err(format!("The attributes are not valid JSON: {}", e)),
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct ActionFormData {
+    idempotency_key: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct MoveFormData {
+    idempotency_key: String,
+    /// Slug of the list the subscriber leaves.
+    from: String,
+    /// Slug of the list they join instead.
+    to: String,
+}
+
+/// An admin action on a subscriber, under way.
+///
+/// Its transaction also holds the idempotency record, so a form submitted
+/// twice only acts once, and the subscriber's row stays locked until the
+/// action is done.
+struct SubscriberAction {
+    transaction: Transaction<'static, Postgres>,
+    idempotency_key: IdempotencyKey,
+    user_id: Uuid,
+    subscriber_id: Uuid,
+    email: String,
+    status: String,
+}
+
+enum ActionStart {
+    Fresh(Box<SubscriberAction>),
+    Replayed(HttpResponse),
+}
+
+impl SubscriberAction {
+    async fn begin(
+        pool: &PgPool,
+        idempotency_key: String,
+        user_id: Uuid,
+        subscriber_id: Uuid,
+    ) -> Result<ActionStart, actix_web::Error> {
+        let idempotency_key: IdempotencyKey = idempotency_key.try_into().map_err(error_400)?;
+        let mut transaction = match try_processing(pool, &idempotency_key, user_id)
+            .await
+            .map_err(error_500)?
+        {
+            NextAction::StartProcessing(transaction) => *transaction,
+            NextAction::ReturnSavedResponse(saved_response) => {
+                FlashMessage::info(ALREADY_DONE).send();
+                return Ok(ActionStart::Replayed(saved_response));
+            }
+        };
+
+        let subscriber = sqlx::query!(
+            r#"
+            SELECT email, status
+            FROM subscriptions
+            WHERE id = $1
+            FOR UPDATE
+            "#,
+            subscriber_id
+        )
+        .fetch_optional(&mut *transaction)
+        .await
+        .context("Failed to retrieve the subscriber.")
+        .map_err(error_500)?
+        .ok_or_else(|| ErrorNotFound("There is no such subscriber."))?;
+
+        Ok(ActionStart::Fresh(Box::new(Self {
+            transaction,
+            idempotency_key,
+            user_id,
+            subscriber_id,
+            email: subscriber.email,
+            status: subscriber.status,
+        })))
+    }
+
+    /// Commit the action and report its outcome to the admin.
+    ///
+    /// An action turned down is committed too, so that submitting the same
+    /// form again gets the same answer.
+    async fn finish(
+        self,
+        location: &str,
+        outcome: Result<String, String>,
+    ) -> Result<HttpResponse, actix_web::Error> {
+        let response = save_response(
+            self.transaction,
+            &self.idempotency_key,
+            self.user_id,
+            see_other(location),
+        )
+        .await
+        .map_err(error_500)?;
+
+        match outcome {
+            Ok(message) => FlashMessage::info(message).send(),
+            Err(message) => FlashMessage::error(message).send(),
+        }
+
+        Ok(response)
+    }
+
+    fn location(&self) -> String {
+        format!("/admin/subscribers/{}", self.subscriber_id)
+    }
+}
+
+/// Confirm a pending subscriber on every list they are waiting on, as if
+/// they had clicked their links.
+#[tracing::instrument(
+    name = "Confirm a subscriber by hand",
+    skip(form, pool, user_id),
+    fields(user_id = %*user_id)
+)]
+pub async fn admin_confirm_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    form: web::Form<ActionFormData>,
+    pool: Data<PgPool>,
+    user_id: ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut action = match SubscriberAction::begin(
+        &pool,
+        form.0.idempotency_key,
+        **user_id,
+        subscriber_id.into_inner(),
+    )
+    .await?
+    {
+        ActionStart::Fresh(action) => *action,
+        ActionStart::Replayed(response) => return Ok(response),
+    };
+    let location = action.location();
+
+    if action.status != "pending_confirmation" {
+        let outcome = Err(format!("{} is not waiting for confirmation.", action.email));
+        return action.finish(&location, outcome).await;
+    }
+
+    confirm_subscriber(&mut action.transaction, action.subscriber_id)
+        .await
+        .context("Failed to mark the subscriber as confirmed.")
+        .map_err(error_500)?;
+
+    let list_ids: Vec<Uuid> = sqlx::query!(
+        r#"
+        UPDATE list_memberships
+        SET status = 'confirmed'
+        WHERE subscriber_id = $1 AND status = 'pending_confirmation'
+        RETURNING list_id
+        "#,
+        action.subscriber_id
+    )
+    .fetch_all(&mut *action.transaction)
+    .await
+    .context("Failed to mark the list memberships as confirmed.")
+    .map_err(error_500)?
+    .into_iter()
+    .map(|row| row.list_id)
+    .collect();
+
+    record_admin_confirmation(
+        &mut action.transaction,
+        action.subscriber_id,
+        &list_ids,
+        action.user_id,
+    )
+    .await
+    .context("Failed to record the admin's confirmation.")
+    .map_err(error_500)?;
+
+    // The links still in their inbox have nothing left to confirm.
+    let query = sqlx::query!(
+        r#"
+        UPDATE subscription_tokens
+        SET consumed_at = now()
+        WHERE subscriber_id = $1 AND consumed_at IS NULL
+        "#,
+        action.subscriber_id
+    );
+    action
+        .transaction
+        .execute(query)
+        .await
+        .context("Failed to mark the subscription tokens as used.")
+        .map_err(error_500)?;
+
+    lift_unsubscribe_suppression(&mut *action.transaction, action.subscriber_id)
+        .await
+        .context("Failed to lift the subscriber's unsubscribe suppression.")
+        .map_err(error_500)?;
+
+    forget_queued_confirmations(&mut *action.transaction, action.subscriber_id, None)
+        .await
+        .context("Failed to drop the subscriber's queued confirmation emails.")
+        .map_err(error_500)?;
+
+    let outcome = Ok(format!("{} has been confirmed.", action.email));
+    action.finish(&location, outcome).await
+}
+
+/// Send a pending subscriber a fresh link for every list they are waiting on.
+#[tracing::instrument(
+    name = "Resend a confirmation email by hand",
+    skip(form, pool, email_client, base_url, hmac_secret, user_id),
+    fields(user_id = %*user_id)
+)]
+pub async fn admin_resend_confirmation(
+    subscriber_id: web::Path<Uuid>,
+    form: web::Form<ActionFormData>,
+    pool: Data<PgPool>,
+    email_client: Data<EmailClient>,
+    base_url: Data<ApplicationBaseUrl>,
+    hmac_secret: Data<HmacSecret>,
+    user_id: ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut action = match SubscriberAction::begin(
+        &pool,
+        form.0.idempotency_key,
+        **user_id,
+        subscriber_id.into_inner(),
+    )
+    .await?
+    {
+        ActionStart::Fresh(action) => *action,
+        ActionStart::Replayed(response) => return Ok(response),
+    };
+    let location = action.location();
+
+    let pending_lists = sqlx::query_as!(
+        MailingList,
+        r#"
+        SELECT lists.list_id, lists.slug, lists.name
+        FROM list_memberships
+        JOIN lists ON lists.list_id = list_memberships.list_id
+        WHERE list_memberships.subscriber_id = $1
+            AND list_memberships.status = 'pending_confirmation'
+        ORDER BY lists.slug
+        "#,
+        action.subscriber_id
+    )
+    .fetch_all(&mut *action.transaction)
+    .await
+    .context("Failed to retrieve the subscriber's pending list memberships.")
+    .map_err(error_500)?;
+
+    if action.status != "pending_confirmation" || pending_lists.is_empty() {
+        let outcome = Err(format!("{} is not waiting for confirmation.", action.email));
+        return action.finish(&location, outcome).await;
+    }
+
+    let email = SubscriberEmail::parse(action.email.clone()).map_err(error_500)?;
+    if blocks_all_email(&mut *action.transaction, &email)
+        .await
+        .context("Failed to check the suppression list.")
+        .map_err(error_500)?
+    {
+        let outcome = Err(format!("{} is on the suppression list.", action.email));
+        return action.finish(&location, outcome).await;
+    }
+
+    // Each list's token is issued in a savepoint that is only released once
+    // its email is out: a link that was sent stays valid even if a later one
+    // fails, and a link that was not sent does not replace the old one.
+    let mut unsent = Vec::new();
+    for list in &pending_lists {
+        let mut token_transaction = action
+            .transaction
+            .begin()
+            .await
+            .context("Failed to open a savepoint.")
+            .map_err(error_500)?;
+        let subscription_token =
+            issue_subscription_token(&mut token_transaction, action.subscriber_id, list.list_id)
+                .await
+                .map_err(error_500)?;
+        let sent = send_confirmation_email(
+            &email_client,
+            &email,
+            &base_url.0,
+            &hmac_secret,
+            action.subscriber_id,
+            &subscription_token,
+            list,
+        )
+        .await;
+        match sent {
+            Ok(()) => token_transaction.commit().await,
+            Err(error) => {
+                tracing::error!(
+                    error.cause_chain = ?error,
+                    error.message = %error,
+                    list = %list.slug,
+                    "Failed to send a confirmation email."
+                );
+                unsent.push(list.name.as_str());
+                token_transaction.rollback().await
+            }
+        }
+        .context("Failed to save the subscription token.")
+        .map_err(error_500)?;
+    }
+
+    let outcome = if unsent.is_empty() {
+        Ok(format!(
+            "A new confirmation link has been sent to {}.",
+            action.email
+        ))
+    } else {
+        Err(format!(
+            "The confirmation link for {} could not be sent to {}. Please try again.",
+            unsent.join(", "),
+            action.email
+        ))
+    };
+    action.finish(&location, outcome).await
+}
+
+/// Take the subscriber off every list, as their unsubscribe link would.
+#[tracing::instrument(
+    name = "Unsubscribe a subscriber by hand",
+    skip(form, pool, user_id),
+    fields(user_id = %*user_id)
+)]
+pub async fn admin_unsubscribe_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    form: web::Form<ActionFormData>,
+    pool: Data<PgPool>,
+    user_id: ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut action = match SubscriberAction::begin(
+        &pool,
+        form.0.idempotency_key,
+        **user_id,
+        subscriber_id.into_inner(),
+    )
+    .await?
+    {
+        ActionStart::Fresh(action) => *action,
+        ActionStart::Replayed(response) => return Ok(response),
+    };
+    let location = action.location();
+
+    if action.status == "unsubscribed" {
+        let outcome = Err(format!("{} is already unsubscribed.", action.email));
+        return action.finish(&location, outcome).await;
+    }
+
+    mark_subscriber_as_unsubscribed(&mut action.transaction, action.subscriber_id)
+        .await
+        .context("Failed to mark the subscriber as unsubscribed.")
+        .map_err(error_500)?;
+
+    let outcome = Ok(format!("{} has been unsubscribed.", action.email));
+    action.finish(&location, outcome).await
+}
+
+/// Move the subscriber's membership from one list to another, keeping its
+/// status.
+#[tracing::instrument(
+    name = "Move a subscriber to another list",
+    skip(form, pool, user_id),
+    fields(user_id = %*user_id, from = %form.from, to = %form.to)
+)]
+pub async fn move_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    form: web::Form<MoveFormData>,
+    pool: Data<PgPool>,
+    user_id: ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let MoveFormData {
+        idempotency_key,
+        from,
+        to,
+    } = form.0;
+    let mut action = match SubscriberAction::begin(
+        &pool,
+        idempotency_key,
+        **user_id,
+        subscriber_id.into_inner(),
+    )
+    .await?
+    {
+        ActionStart::Fresh(action) => *action,
+        ActionStart::Replayed(response) => return Ok(response),
+    };
+    let location = action.location();
+
+    let outcome = move_membership(&mut action, &from, &to)
+        .await
+        .map_err(error_500)?;
+    action.finish(&location, outcome).await
+}
+
+async fn move_membership(
+    action: &mut SubscriberAction,
+    from: &str,
+    to: &str,
+) -> Result<Result<String, String>, anyhow::Error> {
+    if from == to {
+        return Ok(Err("Pick two different lists.".into()));
+    }
+    let Some(from) = get_list_by_slug(&mut *action.transaction, from)
+        .await
+        .context("Failed to look up the mailing list.")?
+    else {
+        return Ok(Err(unknown_list_message(from)));
+    };
+    let Some(to) = get_list_by_slug(&mut *action.transaction, to)
+        .await
+        .context("Failed to look up the mailing list.")?
+    else {
+        return Ok(Err(unknown_list_message(to)));
+    };
+
+    let is_member = |list_id: Uuid| {
+        sqlx::query!(
+            r#"
+            SELECT 1 AS "exists!"
+            FROM list_memberships
+            WHERE subscriber_id = $1 AND list_id = $2
+            "#,
+            action.subscriber_id,
+            list_id
+        )
+    };
+    if is_member(from.list_id)
+        .fetch_optional(&mut *action.transaction)
+        .await
+        .context("Failed to look up the subscriber's list membership.")?
+        .is_none()
+    {
+        return Ok(Err(format!("{} is not on {}.", action.email, from.name)));
+    }
+    if is_member(to.list_id)
+        .fetch_optional(&mut *action.transaction)
+        .await
+        .context("Failed to look up the subscriber's list membership.")?
+        .is_some()
+    {
+        return Ok(Err(format!("{} is already on {}.", action.email, to.name)));
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE list_memberships
+        SET list_id = $3
+        WHERE subscriber_id = $1 AND list_id = $2
+        "#,
+        action.subscriber_id,
+        from.list_id,
+        to.list_id
+    )
+    .execute(&mut *action.transaction)
+    .await
+    .context("Failed to move the list membership.")?;
+
+    // Outstanding links name the old list and would confirm nothing.
+    sqlx::query!(
+        r#"
+        DELETE FROM subscription_tokens
+        WHERE subscriber_id = $1 AND list_id = $2 AND consumed_at IS NULL
+        "#,
+        action.subscriber_id,
+        from.list_id
+    )
+    .execute(&mut *action.transaction)
+    .await
+    .context("Failed to delete the confirmation links for the old list.")?;
+
+    Ok(Ok(format!(
+        "{} has been moved from {} to {}.",
+        action.email, from.name, to.name
+    )))
+}
+
+/// Erase the subscriber and everything held about them.
+#[tracing::instrument(
+    name = "Delete a subscriber by hand",
+    skip(form, pool, user_id),
+    fields(user_id = %*user_id)
+)]
+pub async fn admin_delete_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    form: web::Form<ActionFormData>,
+    pool: Data<PgPool>,
+    user_id: ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut action = match SubscriberAction::begin(
+        &pool,
+        form.0.idempotency_key,
+        **user_id,
+        subscriber_id.into_inner(),
+    )
+    .await?
+    {
+        ActionStart::Fresh(action) => *action,
+        ActionStart::Replayed(response) => return Ok(response),
+    };
+
+    delete_subscriber(&mut action.transaction, action.subscriber_id)
+        .await
+        .map_err(error_500)?;
+
+    let outcome = Ok(format!("{} has been deleted.", action.email));
+    action.finish("/admin/subscribers", outcome).await
+}
@@ -0,0 +1,105 @@
+//! src/routes/admin/suppressions/get.rs
+use actix_web::http::header::ContentType;
+use actix_web::{web::Data, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::suppressions::{get_suppressions, SuppressionReason};
+use crate::utils::error_500;
+
+pub async fn suppressions_form(
+    flash_messages: IncomingFlashMessages,
+    pool: Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for msg in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(msg.content())).unwrap();
+    }
+
+    let mut suppressions_html = String::new();
+    for suppression in get_suppressions(&pool).await.map_err(error_500)? {
+        let email = encode_minimal(&suppression.email);
+        writeln!(
+            suppressions_html,
+            r#"<tr>
+                <td>{email}</td>
+                <td>{}</td>
+                <td>{}</td>
+                <td>
+                    <form action="/admin/suppressions/remove" method="post">
+                        <input hidden type="text" name="email" value="{email}">
+                        <button type="submit">Remove</button>
+                    </form>
+                </td>
+            </tr>"#,
+            suppression.reason.label(),
+            suppression.created_at.to_rfc3339(),
+        )
+        .unwrap();
+    }
+
+    let mut reasons_html = String::new();
+    for reason in SuppressionReason::ALL {
+        writeln!(
+            reasons_html,
+            r#"<option value="{}"{}>{}</option>"#,
+            reason.as_ref(),
+            if reason == SuppressionReason::Manual {
+                " selected"
+            } else {
+                ""
+            },
+            reason.label()
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+            <!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta http-equiv="content-type" content="text/html; charset=utf-8">
+                <title>Suppression list</title>
+            </head>
+            <body>
+                {msg_html}
+                <p>Suppressed addresses receive no newsletters. Apart from those who
+                unsubscribed, they cannot sign up again either.</p>
+                <table>
+                    <tr><th>Email</th><th>Reason</th><th>Since</th><th></th></tr>
+                    {suppressions_html}
+                </table>
+                <form action="/admin/suppressions" method="post">
+                    <label>Email
+                        <input type="text" placeholder="Enter an email address" name="email">
+                    </label>
+                    <label>Reason
+                        <select name="reason">
+                            {reasons_html}
+                        </select>
+                    </label>
+                    <button type="submit">Suppress</button>
+                </form>
+                <form action="/admin/suppressions/import" method="post" enctype="multipart/form-data">
+                    <p>Import a CSV file with an email address per row and, optionally,
+                    a reason in the second column.</p>
+                    <label>File
+                        <input type="file" accept=".csv,text/csv" name="file">
+                    </label>
+                    <label>Reason for rows without one
+                        <select name="reason">
+                            {reasons_html}
+                        </select>
+                    </label>
+                    <button type="submit">Import</button>
+                </form>
+                <p><a href="/admin/dashboard">&lt;- Back</a></p>
+            </body>
+            </html>"#
+        )))
+}
@@ -0,0 +1,221 @@
+//! src/routes/admin/suppressions/post.rs
+use actix_multipart::Multipart;
+use actix_web::{
+    web::{self, Data},
+    HttpResponse,
+};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+use std::collections::HashSet;
+
+use crate::csv_file::{self, Record};
+use crate::domain::SubscriberEmail;
+use crate::suppressions::{suppress, suppress_all, unsuppress, SuppressionReason};
+use crate::upload::UploadForm;
+use crate::utils::{error_500, see_other};
+
+/// Rejected rows listed one by one after an import; the rest are counted.
+const MAX_REPORTED_ROWS: usize = 10;
+
+#[derive(serde::Deserialize)]
+pub struct AddSuppressionFormData {
+    email: String,
+    reason: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct RemoveSuppressionFormData {
+    email: String,
+}
+
+#[tracing::instrument(name = "Add a suppression", skip(form, pool))]
+pub async fn add_suppression(
+    form: web::Form<AddSuppressionFormData>,
+    pool: Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let AddSuppressionFormData { email, reason } = form.0;
+    let parsed = SubscriberEmail::parse(email)
+        .and_then(|email| Ok((email, SuppressionReason::parse(&reason)?)));
+    let (email, reason) = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/suppressions"));
+        }
+    };
+
+    if suppress(&**pool, &email, reason).await.map_err(error_500)? {
+        FlashMessage::info(format!("{} is now suppressed.", email.display())).send();
+    } else {
+        FlashMessage::info(format!("{} was already suppressed.", email.display())).send();
+    }
+
+    Ok(see_other("/admin/suppressions"))
+}
+
+#[tracing::instrument(name = "Remove a suppression", skip(form, pool))]
+pub async fn remove_suppression(
+    form: web::Form<RemoveSuppressionFormData>,
+    pool: Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let removed = match SubscriberEmail::parse(form.0.email.clone()) {
+        Ok(email) => unsuppress(&pool, &email).await.map_err(error_500)?,
+        Err(_) => false,
+    };
+
+    if removed {
+        FlashMessage::info(format!("{} is no longer suppressed.", form.email.trim())).send();
+    } else {
+        FlashMessage::info(format!("{} is not suppressed.", form.email.trim())).send();
+    }
+
+    Ok(see_other("/admin/suppressions"))
+}
+
+/// Suppress every address of an uploaded CSV file at once.
+///
+/// Nothing is imported unless the file as a whole can be read; individual
+/// rows that do not make sense are reported and skipped.
+#[tracing::instrument(name = "Import suppressions", skip_all)]
+pub async fn import_suppressions(
+    payload: Multipart,
+    pool: Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (records, default_reason) = match read_upload(payload).await {
+        Ok(upload) => upload,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/suppressions"));
+        }
+    };
+
+    let (entries, rejected) = prepare_entries(records, default_reason);
+    let n_added = if entries.is_empty() {
+        0
+    } else {
+        suppress_all(&**pool, &entries).await.map_err(error_500)?
+    };
+
+    FlashMessage::info(format!(
+        "Suppressed {} new addresses; {} were already on the list.",
+        n_added,
+        entries.len() as u64 - n_added
+    ))
+    .send();
+    if !rejected.is_empty() {
+        FlashMessage::error(format!("{} rows were rejected:", rejected.len())).send();
+        for row in rejected.iter().take(MAX_REPORTED_ROWS) {
+            FlashMessage::error(row.to_owned()).send();
+        }
+        if rejected.len() > MAX_REPORTED_ROWS {
+            FlashMessage::error(format!(
+                "... and {} more.",
+                rejected.len() - MAX_REPORTED_ROWS
+            ))
+            .send();
+        }
+    }
+
+    Ok(see_other("/admin/suppressions"))
+}
+
+/// The rows of the uploaded file and the reason for those that give none.
+async fn read_upload(payload: Multipart) -> Result<(Vec<Record>, SuppressionReason), String> {
+    let form = UploadForm::read(payload).await?;
+
+    let default_reason = form
+        .text("reason")
+        .map(|reason| SuppressionReason::parse(&reason))
+        .transpose()?
+        .unwrap_or(SuppressionReason::Manual);
+
+    let file = form
+        .file("file")
+        .ok_or_else(|| "Choose a CSV file to import.".to_string())?;
+
+    Ok((csv_file::parse(file)?, default_reason))
+}
+
+/// Turn rows into suppressions, keeping the first row for each address.
+/// A leading `email` header row is skipped.
+fn prepare_entries(
+    records: Vec<Record>,
+    default_reason: SuppressionReason,
+) -> (Vec<(SubscriberEmail, SuppressionReason)>, Vec<String>) {
+    let mut seen = HashSet::new();
+    let mut entries = Vec::new();
+    let mut rejected = Vec::new();
+
+    for (i, record) in records.into_iter().enumerate() {
+        let mut fields = record
+            .fields
+            .into_iter()
+            .map(|field| field.trim().to_owned());
+        let email = fields.next().unwrap_or_default();
+        if i == 0 && email.eq_ignore_ascii_case("email") {
+            continue;
+        }
+
+        let reason = match fields.next().filter(|reason| !reason.is_empty()) {
+            Some(reason) => SuppressionReason::parse(&reason),
+            None => Ok(default_reason),
+        };
+        match SubscriberEmail::parse(email).and_then(|email| Ok((email, reason?))) {
+            Ok((email, reason)) => {
+                if seen.insert(email.canonical()) {
+                    entries.push((email, reason));
+                }
+            }
+            Err(e) => rejected.push(format!("Line {}: {}", record.line, e)),
+        }
+    }
+
+    (entries, rejected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::prepare_entries;
+    use crate::csv_file;
+    use crate::suppressions::SuppressionReason;
+
+    #[test]
+    fn rows_become_suppressions_once_per_address() {
+        let records = csv_file::parse(
+            "Email,Reason\n\
+            a@example.com,complaint\n\
+            b@example.com\n\
+            A@Example.com,hard bounce\n"
+                .as_bytes(),
+        )
+        .unwrap();
+
+        let (entries, rejected) = prepare_entries(records, SuppressionReason::Manual);
+
+        let entries: Vec<_> = entries
+            .iter()
+            .map(|(email, reason)| (email.as_ref(), *reason))
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                ("a@example.com", SuppressionReason::Complaint),
+                ("b@example.com", SuppressionReason::Manual),
+            ]
+        );
+        assert!(rejected.is_empty());
+    }
+
+    #[test]
+    fn invalid_rows_are_reported_with_their_line() {
+        let records = csv_file::parse(b"not-an-email\nc@example.com,soft bounce\n,\n").unwrap();
+
+        let (entries, rejected) = prepare_entries(records, SuppressionReason::Manual);
+
+        assert!(entries.is_empty());
+        assert_eq!(rejected.len(), 3);
+        assert!(rejected[0].starts_with("Line 1: "));
+        assert!(rejected[1].starts_with("Line 2: "));
+        assert!(rejected[2].starts_with("Line 3: "));
+    }
+}
@@ -0,0 +1,5 @@
+mod problem;
+mod subscriptions;
+
+pub use problem::*;
+pub use subscriptions::*;
@@ -0,0 +1,42 @@
+//! src/routes/api/problem.rs
+
+use actix_web::http::{header::ContentType, StatusCode};
+use actix_web::HttpResponse;
+
+/// An RFC 9457 problem document, the error body of every `/api` endpoint.
+#[derive(serde::Serialize)]
+pub struct ProblemDetails {
+    #[serde(rename = "type")]
+    pub problem_type: &'static str,
+    pub title: &'static str,
+    pub status: u16,
+    pub detail: String,
+    /// The request field that failed validation, when there is one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<&'static str>,
+}
+
+impl ProblemDetails {
+    pub fn new(status: StatusCode, title: &'static str, detail: String) -> Self {
+        Self {
+            problem_type: "about:blank",
+            title,
+            status: status.as_u16(),
+            detail,
+            field: None,
+        }
+    }
+
+    pub fn with_field(mut self, field: &'static str) -> Self {
+        self.field = Some(field);
+        self
+    }
+
+    pub fn into_response(self) -> HttpResponse {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+
+        HttpResponse::build(status)
+            .content_type(ContentType("application/problem+json".parse().unwrap()))
+            .json(self)
+    }
+}
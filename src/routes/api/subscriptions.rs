@@ -0,0 +1,193 @@
+//! src/routes/api/subscriptions.rs
+
+use actix_web::error::JsonPayloadError;
+use actix_web::http::header::{CacheControl, CacheDirective};
+use actix_web::http::StatusCode;
+use actix_web::web::{Data, Json};
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+
+use crate::bot_protection::{BotCheck, BotProtection};
+use crate::client_ip::TrustedProxies;
+use crate::consent::ConsentContext;
+use crate::domain::{NewSubscriber, NewSubscriberError};
+use crate::email_client::EmailClient;
+use crate::email_validation::{EmailValidationError, EmailValidator};
+use crate::mailing_lists::{get_list_by_slug, DEFAULT_LIST};
+use crate::routes::{
+    error_chain_fmt, register_subscriber, unknown_list_message, FormData, ProblemDetails,
+};
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+
+#[derive(serde::Serialize)]
+struct FormTokenResponse {
+    form_token: String,
+    proof_of_work_bits: u32,
+}
+
+/// What the home page embeds in the form, for clients of the API: sign-ups
+/// through it go through the same bot checks.
+pub async fn api_form_token(bot_protection: Data<BotProtection>) -> HttpResponse {
+    let form_token = bot_protection.issue_form_token(Utc::now());
+
+    HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .json(FormTokenResponse {
+            form_token: form_token.to_string(),
+            proof_of_work_bits: bot_protection.proof_of_work_bits(),
+        })
+}
+
+/// JSON counterpart of the `/subscriptions` form, for the site and mobile app.
+///
+/// It is held to the same bot checks as the form: clients fetch a token from
+/// `/api/v1/subscriptions/form-token` and send it back as `form_token`, along
+/// with a `proof_of_work` nonce when the token asks for one.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Adding a new subscriber via the API",
+    skip(
+        body,
+        request,
+        pool,
+        email_client,
+        email_validator,
+        base_url,
+        hmac_secret,
+        trusted_proxies,
+        bot_protection
+    ),
+    fields(
+        subscriber_email = %body.email,
+        subscriber_name = %body.name
+    )
+)]
+pub async fn api_subscribe(
+    body: Json<FormData>,
+    request: HttpRequest,
+    pool: Data<PgPool>,
+    email_client: Data<EmailClient>,
+    email_validator: Data<EmailValidator>,
+    base_url: Data<ApplicationBaseUrl>,
+    hmac_secret: Data<HmacSecret>,
+    trusted_proxies: Data<TrustedProxies>,
+    bot_protection: Data<BotProtection>,
+) -> Result<HttpResponse, ApiSubscribeError> {
+    match bot_protection.check_sign_up(&body, Utc::now()) {
+        BotCheck::Passed => {}
+        BotCheck::Caught => {
+            tracing::info!("Dropping a sign-up that filled in the honeypot.");
+            return Ok(HttpResponse::Ok().finish());
+        }
+        BotCheck::Rejected(reason) => return Err(ApiSubscribeError::BotCheckFailed(reason)),
+    }
+
+    let list_slug = body.list.clone().unwrap_or_else(|| DEFAULT_LIST.into());
+    let consent =
+        ConsentContext::from_request(&request, &trusted_proxies, body.form_version.clone());
+    let attribution = body.attribution.for_sign_up(&request, &hmac_secret);
+    let new_subscriber: NewSubscriber = body.into_inner().try_into()?;
+
+    email_validator.validate(&new_subscriber.email).await?;
+
+    let list = get_list_by_slug(&**pool, &list_slug)
+        .await
+        .context("Failed to look up the mailing list.")?
+        .ok_or_else(|| ApiSubscribeError::UnknownList(unknown_list_message(&list_slug)))?;
+
+    register_subscriber(
+        &pool,
+        &email_client,
+        &base_url.0,
+        &hmac_secret,
+        &new_subscriber,
+        &list,
+        &consent,
+        &attribution,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Keep rejected payloads in the same problem format as validation errors.
+pub fn json_error_handler(error: JsonPayloadError, _request: &HttpRequest) -> actix_web::Error {
+    ApiSubscribeError::MalformedBody(error).into()
+}
+
+#[derive(thiserror::Error)]
+pub enum ApiSubscribeError {
+    #[error(transparent)]
+    ValidationError(#[from] NewSubscriberError),
+    #[error("{0}")]
+    UnknownList(String),
+    #[error("{0}")]
+    BotCheckFailed(String),
+    #[error(transparent)]
+    MalformedBody(JsonPayloadError),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl From<EmailValidationError> for ApiSubscribeError {
+    fn from(error: EmailValidationError) -> Self {
+        match error {
+            EmailValidationError::Rejected(reason) => {
+                Self::ValidationError(NewSubscriberError::InvalidEmail(reason))
+            }
+            EmailValidationError::UnexpectedError(error) => Self::UnexpectedError(error),
+        }
+    }
+}
+
+impl std::fmt::Debug for ApiSubscribeError {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, formatter)
+    }
+}
+
+impl ResponseError for ApiSubscribeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::ValidationError(_) | Self::UnknownList(_) | Self::BotCheckFailed(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            Self::MalformedBody(JsonPayloadError::ContentType) => {
+                StatusCode::UNSUPPORTED_MEDIA_TYPE
+            }
+            Self::MalformedBody(error) => error.status_code(),
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+
+        let problem = match self {
+            Self::ValidationError(error) => {
+                ProblemDetails::new(status, "Invalid subscriber details", error.to_string())
+                    .with_field(error.field())
+            }
+            Self::UnknownList(message) => {
+                ProblemDetails::new(status, "Invalid subscriber details", message.clone())
+                    .with_field("list")
+            }
+            Self::BotCheckFailed(message) => {
+                ProblemDetails::new(status, "Sign-up not verified", message.clone())
+            }
+            Self::MalformedBody(error) => {
+                ProblemDetails::new(status, "Malformed request body", error.to_string())
+            }
+            // Do not leak internals: the cause chain is only logged.
+            Self::UnexpectedError(_) => ProblemDetails::new(
+                status,
+                "Internal server error",
+                "Something went wrong on our side. Please try again later.".into(),
+            ),
+        };
+
+        problem.into_response()
+    }
+}
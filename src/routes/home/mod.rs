@@ -1,7 +1,65 @@
-use actix_web::{http::header::ContentType, HttpResponse};
+use actix_web::http::header::{CacheControl, CacheDirective, ContentType};
+use actix_web::web::{Data, Query};
+use actix_web::{HttpRequest, HttpResponse};
+use chrono::Utc;
+use htmlescape::encode_minimal;
+use std::fmt::Write;
+
+use crate::attribution::{external_referrer, sign_referrer, Attribution};
+use crate::bot_protection::BotProtection;
+use crate::startup::HmacSecret;
+
+/// The sign-up form. Campaign parameters in the link that led here are
+/// passed on to the sign-up, and so is the page that linked here: the
+/// sign-up itself is always sent from this one.
+pub async fn home(
+    campaign: Query<Attribution>,
+    request: HttpRequest,
+    bot_protection: Data<BotProtection>,
+    hmac_secret: Data<HmacSecret>,
+) -> HttpResponse {
+    let mut attribution_html = String::new();
+    for (name, value) in [
+        ("utm_source", &campaign.utm_source),
+        ("utm_medium", &campaign.utm_medium),
+        ("utm_campaign", &campaign.utm_campaign),
+        ("source", &campaign.source),
+    ] {
+        if let Some(value) = value {
+            write!(
+                attribution_html,
+                r#"<input hidden type="text" name="{name}" value="{}">"#,
+                encode_minimal(value)
+            )
+            .unwrap();
+        }
+    }
+
+    // Signed, so that a sign-up cannot claim to come from anywhere it likes.
+    if let Some(referrer) = external_referrer(&request) {
+        let signature = sign_referrer(&referrer, &hmac_secret);
+        for (name, value) in [("referrer", &referrer), ("referrer_signature", &signature)] {
+            write!(
+                attribution_html,
+                r#"<input hidden type="text" name="{name}" value="{}">"#,
+                encode_minimal(value)
+            )
+            .unwrap();
+        }
+    }
+
+    // Every page load gets its own form token, so the page must not be cached.
+    let form_token = bot_protection.issue_form_token(Utc::now());
+    let page = include_str!("home.html")
+        .replace("{form_token}", &form_token.to_string())
+        .replace(
+            "{proof_of_work_bits}",
+            &bot_protection.proof_of_work_bits().to_string(),
+        )
+        .replace("{attribution_fields}", &attribution_html);
 
-pub async fn home() -> HttpResponse {
     HttpResponse::Ok()
         .content_type(ContentType::html())
-        .body(include_str!("home.html"))
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .body(page)
 }
@@ -1,13 +1,23 @@
 mod admin;
+mod api;
 mod health_check;
 mod home;
 mod login;
+mod postmark_webhook;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_manage;
+mod subscriptions_resend;
+mod subscriptions_unsubscribe;
 
 pub use admin::*;
+pub use api::*;
 pub use health_check::*;
 pub use home::*;
 pub use login::*;
+pub use postmark_webhook::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use subscriptions_manage::*;
+pub use subscriptions_resend::*;
+pub use subscriptions_unsubscribe::*;
@@ -0,0 +1,300 @@
+//! src/routes/postmark_webhook.rs
+
+use actix_web::http::header::{HeaderMap, HeaderValue, AUTHORIZATION, WWW_AUTHENTICATE};
+use actix_web::http::StatusCode;
+use actix_web::web::{Bytes, Data};
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use redact::Secret;
+use sha2::{Digest, Sha256};
+use sqlx::{Executor, PgPool};
+
+use crate::domain::SubscriberEmail;
+use crate::email_client::PostmarkWebhookCredentials;
+use crate::routes::error_chain_fmt;
+use crate::suppressions::{suppress, SuppressionReason};
+
+/// The header carrying the shared secret when basic auth is not used.
+const SECRET_HEADER: &str = "X-Webhook-Secret";
+
+/// The events Postmark reports about messages we sent, told apart by their
+/// `RecordType`. Only the fields we act upon are kept.
+#[derive(serde::Deserialize, Debug)]
+#[serde(tag = "RecordType")]
+enum PostmarkEvent {
+    Bounce(Bounce),
+    SpamComplaint(SpamComplaint),
+    Delivery(Delivery),
+    /// Opens, clicks and whatever else the server is set up to report.
+    #[serde(other)]
+    Other,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct Bounce {
+    #[serde(rename = "ID")]
+    id: i64,
+    #[serde(rename = "Type")]
+    bounce_type: BounceType,
+    email: String,
+    bounced_at: DateTime<Utc>,
+    description: String,
+    /// Whether Postmark itself stopped sending to the address.
+    inactive: bool,
+}
+
+impl Bounce {
+    /// Whether the address will never accept mail, as opposed to a full
+    /// mailbox or a server that is down for now.
+    fn is_permanent(&self) -> bool {
+        self.inactive
+            || matches!(
+                self.bounce_type,
+                BounceType::HardBounce | BounceType::BadEmailAddress
+            )
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+enum BounceType {
+    HardBounce,
+    SoftBounce,
+    Transient,
+    BadEmailAddress,
+    #[serde(other)]
+    Other,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct SpamComplaint {
+    #[serde(rename = "ID")]
+    id: i64,
+    email: String,
+    bounced_at: DateTime<Utc>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct Delivery {
+    #[serde(rename = "MessageID")]
+    message_id: String,
+    recipient: String,
+    delivered_at: DateTime<Utc>,
+}
+
+#[derive(thiserror::Error)]
+pub enum WebhookError {
+    #[error("Authentication failed.")]
+    AuthError(#[source] anyhow::Error),
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for WebhookError {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, formatter)
+    }
+}
+
+impl ResponseError for WebhookError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::AuthError(_) => StatusCode::UNAUTHORIZED,
+            Self::ValidationError(_) => StatusCode::BAD_REQUEST,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::new(self.status_code());
+        if let Self::AuthError(_) = self {
+            response.headers_mut().insert(
+                WWW_AUTHENTICATE,
+                HeaderValue::from_static(r#"Basic realm="postmark""#),
+            );
+        }
+
+        response
+    }
+}
+
+/// Act on what Postmark learnt after accepting one of our emails.
+///
+/// Hard bounces and spam complaints put the address on the suppression list
+/// and mark its subscriber accordingly. Anything we cannot act upon is still
+/// acknowledged, or Postmark would keep retrying it.
+#[tracing::instrument(
+    name = "Handle a Postmark webhook",
+    skip_all,
+    fields(record_type = tracing::field::Empty)
+)]
+pub async fn postmark_webhook(
+    request: HttpRequest,
+    body: Bytes,
+    pool: Data<PgPool>,
+    credentials: Data<PostmarkWebhookCredentials>,
+) -> Result<HttpResponse, WebhookError> {
+    authenticate(request.headers(), &credentials).map_err(WebhookError::AuthError)?;
+
+    let event: PostmarkEvent = serde_json::from_slice(&body)
+        .map_err(|e| WebhookError::ValidationError(format!("Invalid Postmark payload: {}", e)))?;
+
+    match event {
+        PostmarkEvent::Bounce(bounce) if bounce.is_permanent() => {
+            tracing::Span::current().record("record_type", "Bounce");
+            tracing::info!(
+                bounce_id = bounce.id,
+                bounced_at = %bounce.bounced_at,
+                description = %bounce.description,
+                "An address bounced for good."
+            );
+            suppress_recipient(&pool, &bounce.email, SuppressionReason::HardBounce).await?;
+        }
+        PostmarkEvent::Bounce(bounce) => {
+            tracing::Span::current().record("record_type", "Bounce");
+            tracing::info!(
+                bounce_id = bounce.id,
+                bounce_type = ?bounce.bounce_type,
+                "Ignoring a temporary bounce."
+            );
+        }
+        PostmarkEvent::SpamComplaint(complaint) => {
+            tracing::Span::current().record("record_type", "SpamComplaint");
+            tracing::info!(
+                complaint_id = complaint.id,
+                complained_at = %complaint.bounced_at,
+                "A recipient marked our email as spam."
+            );
+            suppress_recipient(&pool, &complaint.email, SuppressionReason::Complaint).await?;
+        }
+        PostmarkEvent::Delivery(delivery) => {
+            tracing::Span::current().record("record_type", "Delivery");
+            tracing::info!(
+                message_id = %delivery.message_id,
+                recipient = %delivery.recipient,
+                delivered_at = %delivery.delivered_at,
+                "An email was delivered."
+            );
+        }
+        PostmarkEvent::Other => {}
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Accept either basic auth with the configured credentials or the secret
+/// on its own in `X-Webhook-Secret`.
+fn authenticate(
+    headers: &HeaderMap,
+    credentials: &PostmarkWebhookCredentials,
+) -> Result<(), anyhow::Error> {
+    if let Some(secret) = headers.get(SECRET_HEADER) {
+        let secret = secret.to_str().context(format!(
+            "The '{}' header was not valid UTF8.",
+            SECRET_HEADER
+        ))?;
+        anyhow::ensure!(
+            is_secret(secret, &credentials.secret),
+            "Invalid webhook secret."
+        );
+        return Ok(());
+    }
+
+    let header = headers
+        .get(AUTHORIZATION)
+        .context("The request carries no credentials.")?
+        .to_str()
+        .context("The 'Authorization' header was not valid UTF8.")?;
+    let encoded = header
+        .strip_prefix("Basic ")
+        .context("The authorization scheme was not 'Basic'.")?;
+    let decoded = STANDARD
+        .decode(encoded)
+        .context("Failed to base64-decode 'Basic' credentials.")?;
+    let decoded = String::from_utf8(decoded).context("The decoded credentials are not UTF8.")?;
+    let (username, password) = decoded
+        .split_once(':')
+        .context("A password must be provided in 'Basic' auth.")?;
+
+    anyhow::ensure!(
+        username == credentials.username && is_secret(password, &credentials.secret),
+        "Invalid username or password."
+    );
+
+    Ok(())
+}
+
+/// Comparing digests rather than the strings themselves keeps the time taken
+/// unrelated to how much of the secret a caller guessed right.
+fn is_secret(candidate: &str, secret: &Secret<String>) -> bool {
+    Sha256::digest(candidate.as_bytes()) == Sha256::digest(secret.expose_secret().as_bytes())
+}
+
+/// Suppress `email` and flag its subscriber, if there is one.
+#[tracing::instrument(name = "Suppress a Postmark recipient", skip(pool))]
+async fn suppress_recipient(
+    pool: &PgPool,
+    email: &str,
+    reason: SuppressionReason,
+) -> Result<(), anyhow::Error> {
+    let email = match SubscriberEmail::parse(email.to_owned()) {
+        Ok(email) => email,
+        Err(e) => {
+            tracing::warn!("Ignoring a Postmark event for an invalid address: {}", e);
+            return Ok(());
+        }
+    };
+    let status = match reason {
+        SuppressionReason::Complaint => "complained",
+        _ => "bounced",
+    };
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    suppress(&mut *transaction, &email, reason)
+        .await
+        .context("Failed to suppress the address.")?;
+
+    let query = sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET status = $2
+        WHERE email_canonical = $1
+        "#,
+        email.canonical(),
+        status
+    );
+    transaction
+        .execute(query)
+        .await
+        .context("Failed to update the subscriber's status.")?;
+
+    let query = sqlx::query!(
+        r#"
+        DELETE FROM confirmation_email_queue
+        WHERE subscriber_id IN (SELECT id FROM subscriptions WHERE email_canonical = $1)
+        "#,
+        email.canonical()
+    );
+    transaction
+        .execute(query)
+        .await
+        .context("Failed to drop the subscriber's queued confirmation emails.")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to suppress a Postmark recipient.")?;
+
+    Ok(())
+}
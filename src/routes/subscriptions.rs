@@ -1,29 +1,73 @@
 //! src/routes/subscriptions
 
+use std::collections::HashMap;
+
 use actix_web::http::StatusCode;
 use actix_web::web::{Data, Form};
-use actix_web::{HttpResponse, ResponseError};
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
 use anyhow::Context;
-use chrono::Utc;
+use chrono::{TimeDelta, Utc};
+use htmlescape::encode_minimal;
+use serde_json::Value;
 use sqlx::{Executor, PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
-use rand::distributions::Alphanumeric;
-use rand::{thread_rng, Rng};
-
-use crate::domain::NewSubscriber;
+use crate::attribution::Attribution;
+use crate::bot_protection::{BotCheck, BotProtection};
+use crate::client_ip::TrustedProxies;
+use crate::consent::{record_consent, ConsentContext, ConsentEvent};
+use crate::domain::{NewSubscriber, NewSubscriberError, SubscriberEmail, SubscriptionToken};
 use crate::email_client::EmailClient;
-use crate::startup::ApplicationBaseUrl;
+use crate::email_validation::{EmailValidationError, EmailValidator};
+use crate::mailing_lists::{get_list_by_slug, MailingList, DEFAULT_LIST};
+use crate::routes::manage_link;
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+use crate::suppressions::blocks_all_email;
+
+/// How long a confirmation link stays valid after it has been sent.
+pub const SUBSCRIPTION_TOKEN_TTL: TimeDelta = TimeDelta::hours(48);
 
 #[derive(serde::Deserialize)]
 pub struct FormData {
     pub email: String,
     pub name: String,
+    /// Slug of the mailing list to join, the default list if omitted.
+    #[serde(default)]
+    pub list: Option<String>,
+    /// Identifies the form the person filled in, kept as evidence of consent.
+    #[serde(default)]
+    pub form_version: Option<String>,
+    /// The honeypot: hidden from people, so only bots fill it in.
+    #[serde(default)]
+    pub website: Option<String>,
+    /// The [`FormToken`](crate::domain::FormToken) served with the form.
+    #[serde(default)]
+    pub form_token: Option<String>,
+    /// The nonce solving the form's proof-of-work challenge.
+    #[serde(default)]
+    pub proof_of_work: Option<String>,
+    /// The campaign that brought the person here.
+    #[serde(flatten)]
+    pub attribution: Attribution,
+    /// Every other field, custom attributes among them.
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
 }
 
+#[allow(clippy::too_many_arguments)]
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, pool, email_client, base_url),
+    skip(
+        form,
+        request,
+        pool,
+        email_client,
+        email_validator,
+        base_url,
+        hmac_secret,
+        trusted_proxies,
+        bot_protection
+    ),
     fields(
         subscriber_email = %form.email,
         subscriber_name = %form.name
@@ -31,25 +75,140 @@ pub struct FormData {
 )]
 pub async fn subscribe(
     form: Form<FormData>,
+    request: HttpRequest,
     pool: Data<PgPool>,
     email_client: Data<EmailClient>,
+    email_validator: Data<EmailValidator>,
     base_url: Data<ApplicationBaseUrl>,
+    hmac_secret: Data<HmacSecret>,
+    trusted_proxies: Data<TrustedProxies>,
+    bot_protection: Data<BotProtection>,
 ) -> Result<HttpResponse, SubscribeError> {
-    let new_subscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    match bot_protection.check_sign_up(&form, Utc::now()) {
+        BotCheck::Passed => {}
+        BotCheck::Caught => {
+            tracing::info!("Dropping a sign-up that filled in the honeypot.");
+            return Ok(HttpResponse::Ok().finish());
+        }
+        BotCheck::Rejected(reason) => return Err(SubscribeError::ValidationError(reason)),
+    }
+
+    let list_slug = form.list.clone().unwrap_or_else(|| DEFAULT_LIST.into());
+    let consent =
+        ConsentContext::from_request(&request, &trusted_proxies, form.form_version.clone());
+    let attribution = form.attribution.for_sign_up(&request, &hmac_secret);
+    let new_subscriber: NewSubscriber = form
+        .0
+        .try_into()
+        .map_err(|e: NewSubscriberError| SubscribeError::ValidationError(e.to_string()))?;
+
+    email_validator.validate(&new_subscriber.email).await?;
+
+    let list = get_list_by_slug(&**pool, &list_slug)
+        .await
+        .context("Failed to look up the mailing list.")?
+        .ok_or_else(|| SubscribeError::ValidationError(unknown_list_message(&list_slug)))?;
+
+    register_subscriber(
+        &pool,
+        &email_client,
+        &base_url.0,
+        &hmac_secret,
+        &new_subscriber,
+        &list,
+        &consent,
+        &attribution,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub fn unknown_list_message(list_slug: &str) -> String {
+    format!("{} is not a known mailing list.", list_slug)
+}
 
+/// Record a sign-up to `list` and send out its confirmation link.
+///
+/// Shared by every front door to the newsletter, whatever format it speaks.
+/// Each sign-up that issues a confirmation link leaves a consent record.
+#[allow(clippy::too_many_arguments)]
+pub async fn register_subscriber(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    hmac_secret: &HmacSecret,
+    new_subscriber: &NewSubscriber,
+    list: &MailingList,
+    consent: &ConsentContext,
+    attribution: &Attribution,
+) -> Result<(), anyhow::Error> {
     let mut transaction = pool
         .begin()
         .await
         .context("Failed to acquire a Postgres connection from the pool")?;
 
-    let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
+    // Answer as if all went well: the outcome must not reveal the list.
+    if blocks_all_email(&mut *transaction, &new_subscriber.email)
+        .await
+        .context("Failed to check the suppression list.")?
+    {
+        tracing::info!("Not sending a confirmation email to a suppressed address.");
+        return Ok(());
+    }
+
+    let inserted_subscriber_id = insert_subscriber(&mut transaction, new_subscriber, attribution)
         .await
         .context("Failed to insert new subscriber in the database.")?;
 
-    let subscription_token = ganerate_subscription_token();
-    store_subscription_token(&mut transaction, subscriber_id, &subscription_token)
+    // Repeated sign-ups must look exactly like first-time ones from the outside,
+    // otherwise the endpoint would reveal who is on our list. They leave the
+    // stored name, attributes and attribution alone: anyone can sign up with
+    // any address.
+    let subscriber_id = match inserted_subscriber_id {
+        Some(subscriber_id) => subscriber_id,
+        None => {
+            let ExistingSubscriber { id, status } =
+                get_existing_subscriber(&mut transaction, &new_subscriber.email)
+                    .await
+                    .context("Failed to look up an existing subscriber by email.")?
+                    .context("The subscriber was deleted while signing up again.")?;
+
+            if is_inactive(&status) {
+                mark_subscriber_as_pending(&mut transaction, id)
+                    .await
+                    .context("Failed to move the subscriber back to pending confirmation.")?;
+            }
+
+            id
+        }
+    };
+
+    let membership_status = get_membership_status(&mut transaction, subscriber_id, list.list_id)
         .await
-        .context("Failed to store the confirmation token for a new subscriber.")?;
+        .context("Failed to look up the subscriber's list membership.")?;
+
+    if membership_status.as_deref() == Some("confirmed") {
+        return Ok(());
+    }
+
+    upsert_pending_membership(&mut transaction, subscriber_id, list.list_id)
+        .await
+        .context("Failed to store the subscriber's list membership.")?;
+
+    let subscription_token =
+        issue_subscription_token(&mut transaction, subscriber_id, list.list_id).await?;
+
+    record_consent(
+        &mut transaction,
+        subscriber_id,
+        list.list_id,
+        ConsentEvent::SignUp,
+        consent,
+        &subscription_token,
+    )
+    .await
+    .context("Failed to record the subscriber's consent.")?;
 
     transaction
         .commit()
@@ -57,82 +216,251 @@ pub async fn subscribe(
         .context("Failed to commit SQL transaction to store a new subscriber.")?;
 
     send_confirmation_email(
-        &email_client,
-        new_subscriber,
-        &base_url.0,
+        email_client,
+        &new_subscriber.email,
+        base_url,
+        hmac_secret,
+        subscriber_id,
         &subscription_token,
+        list,
     )
     .await
     .context("Failed to send a confirmation email.")?;
 
-    Ok(HttpResponse::Ok().finish())
+    Ok(())
+}
+
+/// Replace any outstanding confirmation token for `list_id` with a freshly
+/// generated one.
+pub async fn issue_subscription_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+) -> Result<SubscriptionToken, anyhow::Error> {
+    delete_subscription_tokens(transaction, subscriber_id, list_id)
+        .await
+        .context("Failed to delete outstanding subscription tokens.")?;
+
+    let subscription_token = SubscriptionToken::generate();
+    store_subscription_token(transaction, subscriber_id, list_id, &subscription_token)
+        .await
+        .context("Failed to store the confirmation token for a new subscriber.")?;
+
+    Ok(subscription_token)
 }
 
 #[tracing::instrument(
     name = "Send a confirmation email to a new subscriber",
-    skip(email_client, new_subscriber, base_url, subscription_token)
+    skip(
+        email_client,
+        recipient,
+        base_url,
+        hmac_secret,
+        subscription_token,
+        list
+    )
 )]
-async fn send_confirmation_email(
+pub async fn send_confirmation_email(
     email_client: &EmailClient,
-    new_subscriber: NewSubscriber,
+    recipient: &SubscriberEmail,
     base_url: &str,
-    subscription_token: &str,
+    hmac_secret: &HmacSecret,
+    subscriber_id: Uuid,
+    subscription_token: &SubscriptionToken,
+    list: &MailingList,
 ) -> Result<(), reqwest::Error> {
-    let confirmation_link = format!(
-        "{}/subscriptions/confirm?subscription_token={}",
-        base_url, subscription_token
-    );
+    let confirmation_link = confirmation_link(base_url, subscription_token, &list.slug);
+    let manage_link = manage_link(base_url, subscriber_id, hmac_secret);
 
     let html_email = format!(
         "Welcome to our newsletter!<br />\
-        Click <a href=\"{}\">here</a> to confirm your subscription.",
-        confirmation_link
+        Click <a href=\"{}\">here</a> to confirm your subscription to {}.<hr />\
+        <p><a href=\"{}\">Manage your preferences</a></p>",
+        confirmation_link,
+        encode_minimal(&list.name),
+        manage_link
     );
 
     let text_email = format!(
-        "Welcome to our newsletter!\nVisit {} to confirm your subscription.",
-        confirmation_link
+        "Welcome to our newsletter!\nVisit {} to confirm your subscription to {}.\n\n\
+        --\nManage your preferences: {}",
+        confirmation_link, list.name, manage_link
     );
 
     email_client
-        .send_email(&new_subscriber.email, "Welcome!", &html_email, &text_email)
+        .send_email(recipient, "Welcome!", &html_email, &text_email)
         .await
 }
 
-#[tracing::instrument(
-    name = "Saving new subscriber details in the database",
-    skip(transaction, subscriber)
-)]
-async fn insert_subscriber(
+pub fn confirmation_link(
+    base_url: &str,
+    subscription_token: &SubscriptionToken,
+    list_slug: &str,
+) -> String {
+    format!(
+        "{}/subscriptions/confirm?subscription_token={}&list={}",
+        base_url,
+        subscription_token,
+        urlencoding::encode(list_slug)
+    )
+}
+
+pub struct ExistingSubscriber {
+    pub id: Uuid,
+    pub status: String,
+}
+
+#[tracing::instrument(name = "Looking up an existing subscriber", skip(transaction, email))]
+pub async fn get_existing_subscriber(
     transaction: &mut Transaction<'static, Postgres>,
-    subscriber: &NewSubscriber,
-) -> Result<Uuid, sqlx::Error> {
-    let subscriber_id = Uuid::new_v4();
+    email: &SubscriberEmail,
+) -> Result<Option<ExistingSubscriber>, sqlx::Error> {
+    // `FOR UPDATE` serialises concurrent sign-ups for an address already on
+    // file. First sign-ups are serialised by the unique index instead, see
+    // `insert_subscriber`.
+    sqlx::query_as!(
+        ExistingSubscriber,
+        r#"
+        SELECT id, status
+        FROM subscriptions
+        WHERE email_canonical = $1
+        FOR UPDATE
+        "#,
+        email.canonical(),
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+}
+
+/// Whether the subscriber stopped receiving email, by choice or because
+/// their address bounced or complained. Signing up again starts afresh.
+pub fn is_inactive(status: &str) -> bool {
+    matches!(status, "unsubscribed" | "bounced" | "complained")
+}
 
+#[tracing::instrument(name = "Mark subscriber as pending confirmation", skip(transaction))]
+async fn mark_subscriber_as_pending(
+    transaction: &mut Transaction<'static, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
     let query = sqlx::query!(
         r#"
-        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-        VALUES ($1, $2, $3, $4, 'pending_confirmation')
+        UPDATE subscriptions
+        SET status = 'pending_confirmation'
+        WHERE id = $1
+        "#,
+        subscriber_id
+    );
+
+    transaction.execute(query).await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(name = "Get list membership status", skip(transaction))]
+pub async fn get_membership_status(
+    transaction: &mut Transaction<'static, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+) -> Result<Option<String>, sqlx::Error> {
+    let record = sqlx::query!(
+        r#"
+        SELECT status
+        FROM list_memberships
+        WHERE subscriber_id = $1 AND list_id = $2
         "#,
         subscriber_id,
-        subscriber.email.as_ref(),
-        subscriber.name.as_ref(),
-        Utc::now()
+        list_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+
+    Ok(record.map(|row| row.status))
+}
+
+#[tracing::instrument(name = "Store a pending list membership", skip(transaction))]
+async fn upsert_pending_membership(
+    transaction: &mut Transaction<'static, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO list_memberships (subscriber_id, list_id, status)
+        VALUES ($1, $2, 'pending_confirmation')
+        ON CONFLICT (subscriber_id, list_id) DO UPDATE
+        SET status = 'pending_confirmation'
+        "#,
+        subscriber_id,
+        list_id
     );
 
     transaction.execute(query).await?;
 
-    Ok(subscriber_id)
+    Ok(())
 }
 
-#[tracing::instrument(name = "Generating subscription token")]
-fn ganerate_subscription_token() -> String {
-    let mut rng = thread_rng();
+#[tracing::instrument(name = "Delete outstanding subscription tokens", skip(transaction))]
+async fn delete_subscription_tokens(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        DELETE FROM subscription_tokens
+        WHERE subscriber_id = $1 AND list_id = $2
+        "#,
+        subscriber_id,
+        list_id
+    );
+
+    transaction.execute(query).await?;
 
-    std::iter::repeat_with(|| rng.sample(Alphanumeric))
-        .map(char::from)
-        .take(25)
-        .collect()
+    Ok(())
+}
+
+/// Store a first-time subscriber, returning `None` if the address is already
+/// on file.
+///
+/// Concurrent first sign-ups for the same address wait on each other at the
+/// unique index: whichever commits first wins, the others get `None`.
+#[tracing::instrument(
+    name = "Saving new subscriber details in the database",
+    skip(transaction, subscriber, attribution)
+)]
+async fn insert_subscriber(
+    transaction: &mut Transaction<'static, Postgres>,
+    subscriber: &NewSubscriber,
+    attribution: &Attribution,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        INSERT INTO subscriptions (
+            id, email, email_canonical, email_display, name, attributes, subscribed_at,
+            status, utm_source, utm_medium, utm_campaign, referrer, source
+        )
+        VALUES (
+            $1, $2, $3, $4, $5, $6, $7, 'pending_confirmation', $8, $9, $10, $11, $12
+        )
+        ON CONFLICT DO NOTHING
+        RETURNING id
+        "#,
+        Uuid::new_v4(),
+        subscriber.email.as_ref(),
+        subscriber.email.canonical(),
+        subscriber.email.display(),
+        subscriber.name.as_ref(),
+        Value::Object(subscriber.attributes.as_ref().clone()),
+        Utc::now(),
+        attribution.utm_source,
+        attribution.utm_medium,
+        attribution.utm_campaign,
+        attribution.referrer,
+        attribution.source
+    )
+    .fetch_optional(&mut **transaction)
+    .await
 }
 
 #[tracing::instrument(
@@ -140,17 +468,22 @@ fn ganerate_subscription_token() -> String {
     skip(subscription_token, transaction)
 )]
 pub async fn store_subscription_token(
-    transaction: &mut Transaction<'static, Postgres>,
+    transaction: &mut Transaction<'_, Postgres>,
     subscriber_id: Uuid,
-    subscription_token: &str,
+    list_id: Uuid,
+    subscription_token: &SubscriptionToken,
 ) -> Result<(), StoreTokenError> {
     let query = sqlx::query!(
         r#"
-        INSERT INTO subscription_tokens (subscription_token, subscriber_id)
-        VALUES ($1, $2)
+        INSERT INTO subscription_tokens (
+            subscription_token_hash, subscriber_id, list_id, expires_at
+        )
+        VALUES ($1, $2, $3, $4)
         "#,
-        subscription_token,
-        subscriber_id
+        subscription_token.hash(),
+        subscriber_id,
+        list_id,
+        Utc::now() + SUBSCRIPTION_TOKEN_TTL
     );
 
     transaction.execute(query).await.map_err(StoreTokenError)?;
@@ -192,6 +525,15 @@ pub enum SubscribeError {
     UnexpectedError(#[from] anyhow::Error),
 }
 
+impl From<EmailValidationError> for SubscribeError {
+    fn from(error: EmailValidationError) -> Self {
+        match error {
+            EmailValidationError::Rejected(reason) => Self::ValidationError(reason),
+            EmailValidationError::UnexpectedError(error) => Self::UnexpectedError(error),
+        }
+    }
+}
+
 impl std::fmt::Debug for SubscribeError {
     fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         error_chain_fmt(self, formatter)
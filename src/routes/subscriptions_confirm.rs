@@ -1,49 +1,190 @@
 use actix_web::{
-    http::StatusCode,
+    http::{header::ContentType, StatusCode},
     web::{self, Data},
-    HttpResponse, ResponseError,
+    HttpRequest, HttpResponse, ResponseError,
 };
-use sqlx::PgPool;
+use anyhow::Context;
+use chrono::Utc;
+use htmlescape::encode_minimal;
+use sqlx::{Executor, PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
+use crate::client_ip::TrustedProxies;
+use crate::consent::{record_consent, ConsentContext, ConsentEvent, CONFIRMATION_FORM_VERSION};
+use crate::domain::SubscriptionToken;
+use crate::pending_subscriber_worker::forget_queued_confirmations;
 use crate::routes::error_chain_fmt;
+use crate::startup::ConfirmationRedirectUrl;
+use crate::suppressions::lift_unsubscribe_suppression;
+use crate::utils::see_other;
 
 #[derive(serde::Deserialize)]
 pub struct Parameters {
     subscription_token: String,
+    /// Slug of the list the link was sent for, checked against the token's.
+    #[serde(default)]
+    list: Option<String>,
+    /// Version of the confirmation page that was submitted.
+    #[serde(default)]
+    form_version: Option<String>,
 }
 
-#[tracing::instrument(name = "Confirm a pending subscriber", skip(pool, parameters))]
-pub async fn confirm(pool: Data<PgPool>, parameters: web::Query<Parameters>) -> HttpResponse {
-    let id = match get_subscriber_id_from_token(&pool, &parameters.subscription_token).await {
-        Ok(id) => id,
-        Err(_) => return HttpResponse::InternalServerError().finish(),
-    };
-
-    match id {
-        None => HttpResponse::Unauthorized().finish(),
-        Some(subscriber_id) => {
-            if confirm_subscriber(&pool, subscriber_id).await.is_err() {
-                return HttpResponse::InternalServerError().finish();
-            }
-            HttpResponse::Ok().finish()
-        }
+/// Render the page a confirmation link points to.
+///
+/// Mail scanners follow every link they find, so visiting the link must not
+/// have side effects: the subscriber confirms by submitting the form instead.
+#[tracing::instrument(name = "Render the confirmation page", skip(pool, parameters))]
+pub async fn confirm_form(
+    pool: Data<PgPool>,
+    parameters: web::Query<Parameters>,
+) -> Result<HttpResponse, ConfirmationError> {
+    let Parameters {
+        subscription_token,
+        list,
+        ..
+    } = parameters.0;
+    let subscription_token =
+        SubscriptionToken::parse(subscription_token).map_err(ConfirmationError::ValidationError)?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    // Check the token up front, so a stale link says so before any click.
+    let membership =
+        get_pending_membership_from_token(&mut transaction, &subscription_token, list.as_deref())
+            .await?;
+
+    let list_name = encode_minimal(&membership.list_name);
+    let list_slug = encode_minimal(&membership.list_slug);
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(confirmation_page(
+            "Confirm your subscription",
+            &format!(
+                r#"<p>Please confirm that you want to receive {list_name}.</p>
+                <form action="/subscriptions/confirm" method="post">
+                    <input hidden type="text" name="subscription_token" value="{subscription_token}">
+                    <input hidden type="text" name="list" value="{list_slug}">
+                    <input hidden type="text" name="form_version" value="{CONFIRMATION_FORM_VERSION}">
+                    <button type="submit">Confirm subscription</button>
+                </form>"#
+            ),
+        )))
+}
+
+#[tracing::instrument(
+    name = "Confirm a pending subscriber",
+    skip(pool, form, request, confirmation_redirect, trusted_proxies)
+)]
+pub async fn confirm(
+    pool: Data<PgPool>,
+    form: web::Form<Parameters>,
+    request: HttpRequest,
+    confirmation_redirect: Data<ConfirmationRedirectUrl>,
+    trusted_proxies: Data<TrustedProxies>,
+) -> Result<HttpResponse, ConfirmationError> {
+    let Parameters {
+        subscription_token,
+        list,
+        form_version,
+    } = form.0;
+    let consent = ConsentContext::from_request(&request, &trusted_proxies, form_version);
+    let subscription_token =
+        SubscriptionToken::parse(subscription_token).map_err(ConfirmationError::ValidationError)?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let membership =
+        get_pending_membership_from_token(&mut transaction, &subscription_token, list.as_deref())
+            .await?;
+
+    consume_subscription_token(&mut transaction, &subscription_token)
+        .await
+        .context("Failed to mark the subscription token as used.")?;
+
+    confirm_subscriber(&mut transaction, membership.subscriber_id)
+        .await
+        .context("Failed to mark the subscriber as confirmed.")?;
+
+    confirm_membership(
+        &mut transaction,
+        membership.subscriber_id,
+        membership.list_id,
+    )
+    .await
+    .context("Failed to mark the list membership as confirmed.")?;
+
+    lift_unsubscribe_suppression(&mut *transaction, membership.subscriber_id)
+        .await
+        .context("Failed to lift the subscriber's unsubscribe suppression.")?;
+
+    record_consent(
+        &mut transaction,
+        membership.subscriber_id,
+        membership.list_id,
+        ConsentEvent::Confirmation,
+        &consent,
+        &subscription_token,
+    )
+    .await
+    .context("Failed to record the subscriber's consent.")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to confirm a subscriber.")?;
+
+    if let Some(redirect_url) = &confirmation_redirect.0 {
+        return Ok(see_other(redirect_url));
     }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(confirmation_page(
+            "Subscription confirmed",
+            "<p>Thank you! Your subscription has been confirmed.</p>",
+        )))
+}
+
+fn confirmation_page(title: &str, content: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+        <html lang="en">
+        <head>
+            <meta http-equiv="content-type" content="text/html; charset=utf-8">
+            <title>{title}</title>
+        </head>
+        <body>
+            {content}
+        </body>
+        </html>"#
+    )
 }
 
-#[tracing::instrument(name = "Mark subscriber as confirmed", skip(subscriber_id, pool))]
-pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
-    sqlx::query!(
+#[tracing::instrument(
+    name = "Mark subscriber as confirmed",
+    skip(subscriber_id, transaction)
+)]
+pub async fn confirm_subscriber(
+    transaction: &mut Transaction<'static, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
         r#"
         UPDATE subscriptions
         SET status = 'confirmed'
         WHERE id = $1
         "#,
         subscriber_id
-    )
-    .execute(pool)
-    .await
-    .map_err(|e| {
+    );
+
+    transaction.execute(query).await.map_err(|e| {
         tracing::error!("Failed to execute query: {:?}", e);
         e
     })?;
@@ -51,34 +192,118 @@ pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<()
     Ok(())
 }
 
-#[tracing::instrument(name = "Get subscriber_id from token", skip(subscription_token, pool))]
-pub async fn get_subscriber_id_from_token(
-    pool: &PgPool,
-    subscription_token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    let result = sqlx::query!(
+#[tracing::instrument(
+    name = "Mark list membership as confirmed",
+    skip(subscriber_id, list_id, transaction)
+)]
+pub async fn confirm_membership(
+    transaction: &mut Transaction<'static, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
         r#"
-        SELECT subscriber_id FROM subscription_tokens
-        WHERE subscription_token = $1
+        UPDATE list_memberships
+        SET status = 'confirmed'
+        WHERE subscriber_id = $1 AND list_id = $2
+        "#,
+        subscriber_id,
+        list_id
+    );
+
+    transaction.execute(query).await?;
+
+    forget_queued_confirmations(&mut **transaction, subscriber_id, Some(list_id)).await?;
+
+    Ok(())
+}
+
+/// The list membership a confirmation token was issued for.
+pub struct PendingMembership {
+    pub subscriber_id: Uuid,
+    pub list_id: Uuid,
+    pub list_slug: String,
+    pub list_name: String,
+}
+
+/// Resolve a token to its pending membership, rejecting tokens that have
+/// expired or have already been used.
+///
+/// A link naming a different list than the one its token was issued for is
+/// treated as unknown. The row is locked until the end of the transaction,
+/// so concurrent clicks on the same link cannot both succeed.
+#[tracing::instrument(
+    name = "Get pending membership from token",
+    skip(subscription_token, transaction)
+)]
+pub async fn get_pending_membership_from_token(
+    transaction: &mut Transaction<'static, Postgres>,
+    subscription_token: &SubscriptionToken,
+    list_slug: Option<&str>,
+) -> Result<PendingMembership, ConfirmationError> {
+    let record = sqlx::query!(
+        r#"
+        SELECT t.subscriber_id, t.list_id, t.expires_at, t.consumed_at, l.slug, l.name
+        FROM subscription_tokens t
+        JOIN lists l ON l.list_id = t.list_id
+        WHERE t.subscription_token_hash = $1
+        FOR UPDATE OF t
         "#,
-        subscription_token
+        subscription_token.hash()
     )
-    .fetch_optional(pool)
+    .fetch_optional(&mut **transaction)
     .await
-    .map_err(|error| {
-        tracing::error!("Failed to execute query: {:?}", error);
-        error
-    })?;
+    .context("Failed to retrieve the subscription token.")?
+    .ok_or(ConfirmationError::UnknownToken)?;
+
+    if list_slug.is_some_and(|slug| slug != record.slug) {
+        return Err(ConfirmationError::UnknownToken);
+    }
 
-    Ok(result.map(|value| value.subscriber_id))
+    if record.consumed_at.is_some() || record.expires_at < Utc::now() {
+        return Err(ConfirmationError::ExpiredToken);
+    }
+
+    Ok(PendingMembership {
+        subscriber_id: record.subscriber_id,
+        list_id: record.list_id,
+        list_slug: record.slug,
+        list_name: record.name,
+    })
+}
+
+#[tracing::instrument(
+    name = "Consume subscription token",
+    skip(subscription_token, transaction)
+)]
+async fn consume_subscription_token(
+    transaction: &mut Transaction<'static, Postgres>,
+    subscription_token: &SubscriptionToken,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        UPDATE subscription_tokens
+        SET consumed_at = now()
+        WHERE subscription_token_hash = $1
+        "#,
+        subscription_token.hash()
+    );
+
+    transaction.execute(query).await?;
+
+    Ok(())
 }
 
 #[derive(thiserror::Error)]
 pub enum ConfirmationError {
+    #[error("{0}")]
+    ValidationError(String),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
     #[error("There is no subscriber associated with the provided token.")]
     UnknownToken,
+    #[error("The provided token has expired or has already been used.")]
+    ExpiredToken,
 }
 
 impl std::fmt::Debug for ConfirmationError {
@@ -90,8 +315,32 @@ impl std::fmt::Debug for ConfirmationError {
 impl ResponseError for ConfirmationError {
     fn status_code(&self) -> StatusCode {
         match self {
+            Self::ValidationError(_) => StatusCode::BAD_REQUEST,
             Self::UnknownToken => StatusCode::UNAUTHORIZED,
+            Self::ExpiredToken => StatusCode::GONE,
             Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        let message = match self {
+            Self::ValidationError(_) => "This confirmation link is not valid.",
+            Self::UnknownToken => {
+                "We could not find a subscription for this link. \
+                It may have been replaced by a more recent email."
+            }
+            Self::ExpiredToken => {
+                "This confirmation link has expired or has already been used. \
+                Sign up again to receive a new one."
+            }
+            Self::UnexpectedError(_) => "Something went wrong. Please try again later.",
+        };
+
+        HttpResponse::build(self.status_code())
+            .content_type(ContentType::html())
+            .body(confirmation_page(
+                "Subscription not confirmed",
+                &format!("<p>{message}</p>"),
+            ))
+    }
 }
@@ -0,0 +1,909 @@
+//! src/routes/subscriptions_manage.rs
+
+use actix_web::{
+    http::{header::ContentType, StatusCode},
+    web::{self, Data},
+    HttpRequest, HttpResponse, ResponseError,
+};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use chrono::{TimeDelta, Utc};
+use htmlescape::encode_minimal;
+use sqlx::{Executor, PgExecutor, PgPool, Postgres, Transaction};
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::client_ip::TrustedProxies;
+use crate::consent::{
+    record_consent, ConsentContext, ConsentEvent, PREFERENCE_CENTER_FORM_VERSION,
+};
+use crate::domain::{ManageToken, SubscriberEmail, SubscriberName, SubscriptionToken};
+use crate::email_client::EmailClient;
+use crate::email_validation::{EmailValidationError, EmailValidator};
+use crate::gdpr::{erase_subscriber_data, export_subscriber_data};
+use crate::mailing_lists::MailingList;
+use crate::routes::{
+    error_chain_fmt, is_inactive, issue_subscription_token, send_confirmation_email,
+    SUBSCRIPTION_TOKEN_TTL,
+};
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+use crate::suppressions::{blocks_all_email, suppress_subscriber, SuppressionReason};
+use crate::utils::{json_download, see_other};
+
+/// How long the preferences link in an email footer keeps working.
+pub const MANAGE_LINK_TTL: TimeDelta = TimeDelta::days(30);
+
+#[derive(serde::Deserialize)]
+pub struct ManageParameters {
+    token: String,
+}
+
+pub fn manage_link(base_url: &str, subscriber_id: Uuid, hmac_secret: &HmacSecret) -> String {
+    let token = ManageToken::generate(subscriber_id, Utc::now() + MANAGE_LINK_TTL, hmac_secret);
+
+    format!("{}/subscriptions/manage?token={}", base_url, token)
+}
+
+#[tracing::instrument(
+    name = "Render the preference center",
+    skip(parameters, pool, hmac_secret, flash_messages),
+    fields(subscriber_id = tracing::field::Empty)
+)]
+pub async fn manage_form(
+    parameters: web::Query<ManageParameters>,
+    pool: Data<PgPool>,
+    hmac_secret: Data<HmacSecret>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, ManageError> {
+    let token = authorise(&parameters.token, &hmac_secret)?;
+    let subscriber_id = token.subscriber_id();
+
+    let subscriber = get_subscriber(&**pool, subscriber_id)
+        .await
+        .context("Failed to retrieve the subscriber's details.")?
+        .ok_or(ManageError::UnknownSubscriber)?;
+
+    let memberships = get_memberships(&pool, subscriber_id)
+        .await
+        .context("Failed to retrieve the subscriber's lists.")?;
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", encode_minimal(m.content())).unwrap();
+    }
+
+    let mut lists_html = String::new();
+    for membership in &memberships {
+        let checked = match membership.status.as_deref() {
+            Some("confirmed") | Some("pending_confirmation") => " checked",
+            _ => "",
+        };
+        writeln!(
+            lists_html,
+            r#"<label><input type="checkbox" name="list" value="{}"{}> {}</label><br>"#,
+            encode_minimal(&membership.slug),
+            checked,
+            encode_minimal(&membership.name)
+        )
+        .unwrap();
+    }
+
+    let (subscribed, unsubscribed) = if subscriber.status == "unsubscribed" {
+        ("", " checked")
+    } else {
+        (" checked", "")
+    };
+    let token = encode_minimal(&parameters.token);
+    let name = encode_minimal(&subscriber.name);
+    let email = encode_minimal(&subscriber.email);
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta http-equiv="content-type" content="text/html; charset=utf-8">
+                <title>Your preferences</title>
+            </head>
+            <body>
+                {msg_html}
+                <form action="/subscriptions/manage?token={token}" method="post">
+                    <label>Name:<br>
+                        <input type="text" name="name" value="{name}">
+                    </label>
+                    <br>
+                    <label>Email:<br>
+                        <input type="email" name="email" value="{email}">
+                    </label>
+                    <br>
+                    <fieldset>
+                        <legend>Lists</legend>
+                        {lists_html}
+                    </fieldset>
+                    <fieldset>
+                        <legend>Status</legend>
+                        <label><input type="radio" name="status" value="subscribed"{subscribed}> Receive the lists above</label><br>
+                        <label><input type="radio" name="status" value="unsubscribed"{unsubscribed}> Stop all emails</label>
+                    </fieldset>
+                    <button type="submit">Save preferences</button>
+                </form>
+                <h2>Your data</h2>
+                <p><a href="/subscriptions/manage/export?token={token}">Download everything we hold about you</a></p>
+                <form action="/subscriptions/manage/erase?token={token}" method="post">
+                    <button type="submit">Delete all my data</button>
+                </form>
+            </body>
+            </html>"#,
+        )))
+}
+
+/// The submitted preference form.
+///
+/// Checkboxes repeat the `list` key, which `serde_urlencoded` cannot collect
+/// into a struct field, so the form is read as raw pairs.
+struct PreferencesForm {
+    name: String,
+    email: String,
+    unsubscribed: bool,
+    lists: Vec<String>,
+}
+
+impl PreferencesForm {
+    fn from_fields(fields: Vec<(String, String)>) -> Self {
+        let mut form = Self {
+            name: String::new(),
+            email: String::new(),
+            unsubscribed: false,
+            lists: Vec::new(),
+        };
+
+        for (key, value) in fields {
+            match key.as_str() {
+                "name" => form.name = value,
+                "email" => form.email = value,
+                "status" => form.unsubscribed = value == "unsubscribed",
+                "list" => form.lists.push(value),
+                _ => {}
+            }
+        }
+
+        form
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Update subscriber preferences",
+    skip(
+        parameters,
+        form,
+        request,
+        pool,
+        email_client,
+        email_validator,
+        base_url,
+        hmac_secret,
+        trusted_proxies
+    ),
+    fields(subscriber_id = tracing::field::Empty)
+)]
+pub async fn manage(
+    parameters: web::Query<ManageParameters>,
+    form: web::Form<Vec<(String, String)>>,
+    request: HttpRequest,
+    pool: Data<PgPool>,
+    email_client: Data<EmailClient>,
+    email_validator: Data<EmailValidator>,
+    base_url: Data<ApplicationBaseUrl>,
+    hmac_secret: Data<HmacSecret>,
+    trusted_proxies: Data<TrustedProxies>,
+) -> Result<HttpResponse, ManageError> {
+    let token = authorise(&parameters.token, &hmac_secret)?;
+    let subscriber_id = token.subscriber_id();
+    let form = PreferencesForm::from_fields(form.into_inner());
+    let consent = ConsentContext::from_request(
+        &request,
+        &trusted_proxies,
+        Some(PREFERENCE_CENTER_FORM_VERSION.into()),
+    );
+
+    let name = SubscriberName::parse(form.name).map_err(ManageError::ValidationError)?;
+    let email = SubscriberEmail::parse(form.email).map_err(ManageError::ValidationError)?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let subscriber = get_subscriber(&mut *transaction, subscriber_id)
+        .await
+        .context("Failed to retrieve the subscriber's details.")?
+        .ok_or(ManageError::UnknownSubscriber)?;
+
+    update_name(&mut transaction, subscriber_id, &name)
+        .await
+        .context("Failed to update the subscriber's name.")?;
+
+    let lists_to_confirm = update_memberships(
+        &mut transaction,
+        subscriber_id,
+        &subscriber.status,
+        &form.lists,
+        form.unsubscribed,
+    )
+    .await
+    .context("Failed to update the subscriber's lists.")?;
+
+    let current_email = SubscriberEmail::parse(subscriber.email)
+        .map_err(|e| anyhow::anyhow!(e).context("A stored subscriber email is invalid."))?;
+    let mut confirmations = Vec::new();
+    if !lists_to_confirm.is_empty() {
+        if blocks_all_email(&mut *transaction, &current_email)
+            .await
+            .context("Failed to check the suppression list.")?
+        {
+            return Err(ManageError::ValidationError(format!(
+                "We cannot send email to {}.",
+                current_email.display()
+            )));
+        }
+
+        for list in lists_to_confirm {
+            let subscription_token =
+                issue_subscription_token(&mut transaction, subscriber_id, list.list_id).await?;
+            record_consent(
+                &mut transaction,
+                subscriber_id,
+                list.list_id,
+                ConsentEvent::SignUp,
+                &consent,
+                &subscription_token,
+            )
+            .await
+            .context("Failed to record the subscriber's consent.")?;
+            confirmations.push((list, subscription_token));
+        }
+    }
+
+    let email_change = if email.canonical() != subscriber.email_canonical {
+        // The new address gets the same scrutiny as one used to sign up.
+        email_validator.validate(&email).await?;
+        if blocks_all_email(&mut *transaction, &email)
+            .await
+            .context("Failed to check the suppression list.")?
+        {
+            return Err(ManageError::ValidationError(format!(
+                "We cannot send email to {}.",
+                email.display()
+            )));
+        }
+
+        let token = SubscriptionToken::generate();
+        store_email_change_request(&mut transaction, subscriber_id, &email, &token)
+            .await
+            .context("Failed to store the email change request.")?;
+        Some(token)
+    } else {
+        None
+    };
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to update preferences.")?;
+
+    let mut message = String::from("Your preferences have been saved.");
+
+    for (list, subscription_token) in &confirmations {
+        send_confirmation_email(
+            &email_client,
+            &current_email,
+            &base_url.0,
+            &hmac_secret,
+            subscriber_id,
+            subscription_token,
+            list,
+        )
+        .await
+        .context("Failed to send a confirmation email.")?;
+    }
+    if !confirmations.is_empty() {
+        write!(
+            message,
+            " Follow the links we sent to {} to confirm the lists you joined.",
+            current_email.display()
+        )
+        .unwrap();
+    }
+
+    if let Some(token) = email_change {
+        send_email_change_confirmation(&email_client, &email, &base_url.0, &token)
+            .await
+            .context("Failed to send the email change confirmation.")?;
+
+        write!(
+            message,
+            " Follow the link we sent to {} to start using that address.",
+            email.display()
+        )
+        .unwrap();
+    }
+
+    FlashMessage::info(message).send();
+
+    Ok(see_other(&format!(
+        "/subscriptions/manage?token={}",
+        urlencoding::encode(&parameters.token)
+    )))
+}
+
+#[tracing::instrument(
+    name = "Export subscriber data via the preference center",
+    skip(parameters, pool, hmac_secret),
+    fields(subscriber_id = tracing::field::Empty)
+)]
+pub async fn export_my_data(
+    parameters: web::Query<ManageParameters>,
+    pool: Data<PgPool>,
+    hmac_secret: Data<HmacSecret>,
+) -> Result<HttpResponse, ManageError> {
+    let token = authorise(&parameters.token, &hmac_secret)?;
+
+    let export = export_subscriber_data(&pool, token.subscriber_id())
+        .await?
+        .ok_or(ManageError::UnknownSubscriber)?;
+
+    Ok(json_download(&export, "my-subscription-data.json"))
+}
+
+#[tracing::instrument(
+    name = "Erase subscriber data via the preference center",
+    skip(parameters, pool, hmac_secret),
+    fields(subscriber_id = tracing::field::Empty)
+)]
+pub async fn erase_my_data(
+    parameters: web::Query<ManageParameters>,
+    pool: Data<PgPool>,
+    hmac_secret: Data<HmacSecret>,
+) -> Result<HttpResponse, ManageError> {
+    let token = authorise(&parameters.token, &hmac_secret)?;
+
+    if !erase_subscriber_data(&pool, token.subscriber_id()).await? {
+        return Err(ManageError::UnknownSubscriber);
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(manage_page(
+            "Data deleted",
+            "<p>All the data we held about you has been deleted. \
+            You will not hear from us again.</p>",
+        )))
+}
+
+#[derive(serde::Deserialize)]
+pub struct EmailChangeParameters {
+    token: String,
+}
+
+/// Like subscription confirmations, visiting the link has no side effects:
+/// mail scanners would otherwise apply the change for the subscriber.
+#[tracing::instrument(name = "Render the email change page", skip(parameters, pool))]
+pub async fn confirm_email_change_form(
+    parameters: web::Query<EmailChangeParameters>,
+    pool: Data<PgPool>,
+) -> Result<HttpResponse, ManageError> {
+    let token =
+        SubscriptionToken::parse(parameters.0.token).map_err(ManageError::ValidationError)?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let request = get_email_change_request(&mut transaction, &token).await?;
+    let new_email = encode_minimal(&request.new_email);
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(manage_page(
+            "Confirm your new address",
+            &format!(
+                r#"<p>Please confirm that you want to receive our emails at {new_email}.</p>
+                <form action="/subscriptions/manage/confirm-email" method="post">
+                    <input hidden type="text" name="token" value="{token}">
+                    <button type="submit">Confirm new address</button>
+                </form>"#
+            ),
+        )))
+}
+
+#[tracing::instrument(name = "Confirm an email change", skip(form, pool))]
+pub async fn confirm_email_change(
+    form: web::Form<EmailChangeParameters>,
+    pool: Data<PgPool>,
+) -> Result<HttpResponse, ManageError> {
+    let token = SubscriptionToken::parse(form.0.token).map_err(ManageError::ValidationError)?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let request = get_email_change_request(&mut transaction, &token).await?;
+
+    consume_email_change_request(&mut transaction, &token)
+        .await
+        .context("Failed to mark the email change request as used.")?;
+
+    let new_email = SubscriberEmail::parse(request.new_email)
+        .map_err(|e| anyhow::anyhow!(e).context("A stored email change request is invalid."))?;
+
+    update_email(&mut transaction, request.subscriber_id, &new_email)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::Database(e) if e.is_unique_violation() => ManageError::EmailInUse,
+            e => ManageError::UnexpectedError(
+                anyhow::Error::new(e).context("Failed to update the subscriber's email."),
+            ),
+        })?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to change an email address.")?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(manage_page(
+            "Address updated",
+            "<p>Thank you! We will use your new address from now on.</p>",
+        )))
+}
+
+fn manage_page(title: &str, content: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+        <html lang="en">
+        <head>
+            <meta http-equiv="content-type" content="text/html; charset=utf-8">
+            <title>{title}</title>
+        </head>
+        <body>
+            {content}
+        </body>
+        </html>"#
+    )
+}
+
+fn authorise(token: &str, hmac_secret: &HmacSecret) -> Result<ManageToken, ManageError> {
+    let token = ManageToken::parse(token, hmac_secret).map_err(ManageError::InvalidToken)?;
+
+    if token.is_expired() {
+        return Err(ManageError::ExpiredLink);
+    }
+
+    tracing::Span::current().record(
+        "subscriber_id",
+        tracing::field::display(token.subscriber_id()),
+    );
+
+    Ok(token)
+}
+
+struct SubscriberDetails {
+    email: String,
+    email_canonical: String,
+    name: String,
+    status: String,
+}
+
+/// The row is locked when `executor` is a transaction, so concurrent
+/// submissions of the preference form are applied one after the other.
+#[tracing::instrument(name = "Get subscriber details", skip(executor))]
+async fn get_subscriber(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+) -> Result<Option<SubscriberDetails>, sqlx::Error> {
+    sqlx::query_as!(
+        SubscriberDetails,
+        r#"
+        SELECT email, email_canonical, name, status
+        FROM subscriptions
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+struct Membership {
+    slug: String,
+    name: String,
+    status: Option<String>,
+}
+
+/// Every list, along with the subscriber's membership status where they have one.
+#[tracing::instrument(name = "Get list memberships", skip(pool))]
+async fn get_memberships(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Vec<Membership>, sqlx::Error> {
+    sqlx::query_as!(
+        Membership,
+        r#"
+        SELECT lists.slug, lists.name, list_memberships.status AS "status?"
+        FROM lists
+        LEFT JOIN list_memberships
+            ON list_memberships.list_id = lists.list_id
+            AND list_memberships.subscriber_id = $1
+        ORDER BY lists.created_at, lists.slug
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+#[tracing::instrument(name = "Update subscriber name", skip(transaction, name))]
+async fn update_name(
+    transaction: &mut Transaction<'static, Postgres>,
+    subscriber_id: Uuid,
+    name: &SubscriberName,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET name = $2
+        WHERE id = $1
+        "#,
+        subscriber_id,
+        name.as_ref()
+    );
+
+    transaction.execute(query).await?;
+
+    Ok(())
+}
+
+/// Apply the submitted list choices, returning the lists the subscriber has
+/// yet to confirm.
+///
+/// Opting out of everything is the same global unsubscribe as the link in
+/// the footer; otherwise unticked lists are left. Newly ticked lists go
+/// through double opt-in like any sign-up: the link only proves the address
+/// could be read when it was sent. A subscriber who had stopped receiving
+/// email has to confirm every list again, and is pending until they do.
+#[tracing::instrument(name = "Update list memberships", skip(transaction))]
+async fn update_memberships(
+    transaction: &mut Transaction<'static, Postgres>,
+    subscriber_id: Uuid,
+    subscriber_status: &str,
+    list_slugs: &[String],
+    unsubscribed: bool,
+) -> Result<Vec<MailingList>, sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        UPDATE list_memberships
+        SET status = 'unsubscribed'
+        WHERE subscriber_id = $1
+        AND ($2 OR list_id NOT IN (SELECT list_id FROM lists WHERE slug = ANY($3)))
+        "#,
+        subscriber_id,
+        unsubscribed,
+        list_slugs
+    );
+    transaction.execute(query).await?;
+
+    let query = sqlx::query!(
+        r#"
+        DELETE FROM confirmation_email_queue
+        WHERE subscriber_id = $1
+        AND ($2 OR list_id NOT IN (SELECT list_id FROM lists WHERE slug = ANY($3)))
+        "#,
+        subscriber_id,
+        unsubscribed,
+        list_slugs
+    );
+    transaction.execute(query).await?;
+
+    // Opting out is remembered even if the subscriber is later erased. The
+    // suppression is lifted once they confirm a list again.
+    if unsubscribed {
+        let query = sqlx::query!(
+            r#"
+            UPDATE subscriptions
+            SET status = 'unsubscribed'
+            WHERE id = $1
+            "#,
+            subscriber_id
+        );
+        transaction.execute(query).await?;
+
+        suppress_subscriber(
+            &mut **transaction,
+            subscriber_id,
+            SuppressionReason::Unsubscribe,
+        )
+        .await?;
+
+        return Ok(Vec::new());
+    }
+
+    let inactive = is_inactive(subscriber_status);
+    let lists_to_confirm = sqlx::query_as!(
+        MailingList,
+        r#"
+        SELECT lists.list_id, lists.slug, lists.name
+        FROM lists
+        LEFT JOIN list_memberships
+            ON list_memberships.list_id = lists.list_id
+            AND list_memberships.subscriber_id = $1
+        WHERE lists.slug = ANY($2)
+        AND (
+            $3
+            OR list_memberships.status IS NULL
+            OR list_memberships.status NOT IN ('confirmed', 'pending_confirmation')
+        )
+        ORDER BY lists.created_at, lists.slug
+        "#,
+        subscriber_id,
+        list_slugs,
+        inactive
+    )
+    .fetch_all(&mut **transaction)
+    .await?;
+
+    if lists_to_confirm.is_empty() {
+        return Ok(lists_to_confirm);
+    }
+
+    let list_ids: Vec<Uuid> = lists_to_confirm.iter().map(|list| list.list_id).collect();
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO list_memberships (subscriber_id, list_id, status)
+        SELECT $1, list_id, 'pending_confirmation'
+        FROM UNNEST($2::uuid[]) AS rows(list_id)
+        ON CONFLICT (subscriber_id, list_id) DO UPDATE
+        SET status = 'pending_confirmation'
+        "#,
+        subscriber_id,
+        &list_ids
+    );
+    transaction.execute(query).await?;
+
+    if inactive {
+        let query = sqlx::query!(
+            r#"
+            UPDATE subscriptions
+            SET status = 'pending_confirmation'
+            WHERE id = $1
+            "#,
+            subscriber_id
+        );
+        transaction.execute(query).await?;
+    }
+
+    Ok(lists_to_confirm)
+}
+
+#[tracing::instrument(
+    name = "Store an email change request",
+    skip(transaction, new_email, token)
+)]
+async fn store_email_change_request(
+    transaction: &mut Transaction<'static, Postgres>,
+    subscriber_id: Uuid,
+    new_email: &SubscriberEmail,
+    token: &SubscriptionToken,
+) -> Result<(), sqlx::Error> {
+    // Only the latest request stays valid.
+    let query = sqlx::query!(
+        r#"
+        DELETE FROM email_change_requests
+        WHERE subscriber_id = $1
+        "#,
+        subscriber_id
+    );
+    transaction.execute(query).await?;
+
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO email_change_requests (token_hash, subscriber_id, new_email, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        token.hash(),
+        subscriber_id,
+        new_email.as_ref(),
+        Utc::now() + SUBSCRIPTION_TOKEN_TTL
+    );
+    transaction.execute(query).await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Send an email change confirmation",
+    skip(email_client, recipient, base_url, token)
+)]
+async fn send_email_change_confirmation(
+    email_client: &EmailClient,
+    recipient: &SubscriberEmail,
+    base_url: &str,
+    token: &SubscriptionToken,
+) -> Result<(), reqwest::Error> {
+    let confirmation_link = format!(
+        "{}/subscriptions/manage/confirm-email?token={}",
+        base_url, token
+    );
+
+    let html_email = format!(
+        "You asked us to send our emails to this address.<br />\
+        Click <a href=\"{}\">here</a> to confirm the change.",
+        confirmation_link
+    );
+
+    let text_email = format!(
+        "You asked us to send our emails to this address.\n\
+        Visit {} to confirm the change.",
+        confirmation_link
+    );
+
+    email_client
+        .send_email(
+            recipient,
+            "Confirm your new address",
+            &html_email,
+            &text_email,
+        )
+        .await
+}
+
+struct EmailChangeRequest {
+    subscriber_id: Uuid,
+    new_email: String,
+}
+
+#[tracing::instrument(name = "Get email change request", skip(transaction, token))]
+async fn get_email_change_request(
+    transaction: &mut Transaction<'static, Postgres>,
+    token: &SubscriptionToken,
+) -> Result<EmailChangeRequest, ManageError> {
+    let record = sqlx::query!(
+        r#"
+        SELECT subscriber_id, new_email, expires_at, consumed_at
+        FROM email_change_requests
+        WHERE token_hash = $1
+        FOR UPDATE
+        "#,
+        token.hash()
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .context("Failed to retrieve the email change request.")?
+    .ok_or(ManageError::UnknownEmailChange)?;
+
+    if record.consumed_at.is_some() || record.expires_at < Utc::now() {
+        return Err(ManageError::ExpiredLink);
+    }
+
+    Ok(EmailChangeRequest {
+        subscriber_id: record.subscriber_id,
+        new_email: record.new_email,
+    })
+}
+
+#[tracing::instrument(name = "Consume email change request", skip(transaction, token))]
+async fn consume_email_change_request(
+    transaction: &mut Transaction<'static, Postgres>,
+    token: &SubscriptionToken,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        UPDATE email_change_requests
+        SET consumed_at = now()
+        WHERE token_hash = $1
+        "#,
+        token.hash()
+    );
+
+    transaction.execute(query).await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(name = "Update subscriber email", skip(transaction, new_email))]
+async fn update_email(
+    transaction: &mut Transaction<'static, Postgres>,
+    subscriber_id: Uuid,
+    new_email: &SubscriberEmail,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET email = $2, email_canonical = $3, email_display = $4
+        WHERE id = $1
+        "#,
+        subscriber_id,
+        new_email.as_ref(),
+        new_email.canonical(),
+        new_email.display()
+    );
+
+    transaction.execute(query).await?;
+
+    Ok(())
+}
+
+#[derive(thiserror::Error)]
+pub enum ManageError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("{0}")]
+    InvalidToken(String),
+    #[error("The link has expired or has already been used.")]
+    ExpiredLink,
+    #[error("The subscriber no longer exists.")]
+    UnknownSubscriber,
+    #[error("There is no email change associated with the provided token.")]
+    UnknownEmailChange,
+    #[error("The new address is already used by another subscription.")]
+    EmailInUse,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl From<EmailValidationError> for ManageError {
+    fn from(error: EmailValidationError) -> Self {
+        match error {
+            EmailValidationError::Rejected(reason) => Self::ValidationError(reason),
+            EmailValidationError::UnexpectedError(error) => Self::UnexpectedError(error),
+        }
+    }
+}
+
+impl std::fmt::Debug for ManageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ManageError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::ValidationError(_) | Self::InvalidToken(_) => StatusCode::BAD_REQUEST,
+            Self::UnknownEmailChange => StatusCode::UNAUTHORIZED,
+            Self::ExpiredLink | Self::UnknownSubscriber => StatusCode::GONE,
+            Self::EmailInUse => StatusCode::CONFLICT,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let message = match self {
+            Self::ValidationError(message) => message.as_str(),
+            Self::InvalidToken(_) => "This link is not valid.",
+            Self::ExpiredLink => {
+                "This link has expired or has already been used. \
+                Use the link in our most recent email instead."
+            }
+            Self::UnknownSubscriber => "We could not find your subscription.",
+            Self::UnknownEmailChange => {
+                "We could not find this address change. \
+                It may have been replaced by a more recent request."
+            }
+            Self::EmailInUse => "This address is already used by another subscription.",
+            Self::UnexpectedError(_) => "Something went wrong. Please try again later.",
+        };
+
+        HttpResponse::build(self.status_code())
+            .content_type(ContentType::html())
+            .body(manage_page(
+                "Preferences not updated",
+                &format!("<p>{}</p>", encode_minimal(message)),
+            ))
+    }
+}
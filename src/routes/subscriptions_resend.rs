@@ -0,0 +1,100 @@
+//! src/routes/subscriptions_resend.rs
+
+use actix_web::web::{Data, Form};
+use actix_web::HttpResponse;
+use anyhow::Context;
+use sqlx::PgPool;
+
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::mailing_lists::{get_list_by_slug, DEFAULT_LIST};
+use crate::routes::{
+    get_existing_subscriber, get_membership_status, is_inactive, issue_subscription_token,
+    send_confirmation_email, unknown_list_message, SubscribeError,
+};
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+use crate::suppressions::blocks_all_email;
+
+#[derive(serde::Deserialize)]
+pub struct ResendFormData {
+    email: String,
+    #[serde(default)]
+    list: Option<String>,
+}
+
+/// Send a new confirmation link to an address that is still pending.
+///
+/// The response is the same whatever state the address is in, so the
+/// endpoint cannot be used to probe our list.
+#[tracing::instrument(
+    name = "Resend a confirmation email",
+    skip(form, pool, email_client, base_url, hmac_secret),
+    fields(subscriber_email = %form.email)
+)]
+pub async fn resend_confirmation(
+    form: Form<ResendFormData>,
+    pool: Data<PgPool>,
+    email_client: Data<EmailClient>,
+    base_url: Data<ApplicationBaseUrl>,
+    hmac_secret: Data<HmacSecret>,
+) -> Result<HttpResponse, SubscribeError> {
+    let ResendFormData { email, list } = form.0;
+    let email = SubscriberEmail::parse(email).map_err(SubscribeError::ValidationError)?;
+    let list_slug = list.unwrap_or_else(|| DEFAULT_LIST.into());
+
+    let list = get_list_by_slug(&**pool, &list_slug)
+        .await
+        .context("Failed to look up the mailing list.")?
+        .ok_or_else(|| SubscribeError::ValidationError(unknown_list_message(&list_slug)))?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let subscriber = get_existing_subscriber(&mut transaction, &email)
+        .await
+        .context("Failed to look up an existing subscriber by email.")?;
+
+    let subscriber_id = match subscriber {
+        Some(subscriber) if !is_inactive(&subscriber.status) => subscriber.id,
+        _ => return Ok(HttpResponse::Ok().finish()),
+    };
+
+    let membership_status = get_membership_status(&mut transaction, subscriber_id, list.list_id)
+        .await
+        .context("Failed to look up the subscriber's list membership.")?;
+
+    if membership_status.as_deref() != Some("pending_confirmation") {
+        return Ok(HttpResponse::Ok().finish());
+    }
+
+    if blocks_all_email(&mut *transaction, &email)
+        .await
+        .context("Failed to check the suppression list.")?
+    {
+        return Ok(HttpResponse::Ok().finish());
+    }
+
+    let subscription_token =
+        issue_subscription_token(&mut transaction, subscriber_id, list.list_id).await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store a new subscription token.")?;
+
+    send_confirmation_email(
+        &email_client,
+        &email,
+        &base_url.0,
+        &hmac_secret,
+        subscriber_id,
+        &subscription_token,
+        &list,
+    )
+    .await
+    .context("Failed to send a confirmation email.")?;
+
+    Ok(HttpResponse::Ok().finish())
+}
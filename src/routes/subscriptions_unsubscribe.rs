@@ -0,0 +1,157 @@
+//! src/routes/subscriptions_unsubscribe.rs
+
+use actix_web::{
+    http::{header::ContentType, StatusCode},
+    web::{self, Data},
+    HttpResponse, ResponseError,
+};
+use anyhow::Context;
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::domain::UnsubscribeToken;
+use crate::pending_subscriber_worker::forget_queued_confirmations;
+use crate::routes::error_chain_fmt;
+use crate::startup::HmacSecret;
+use crate::suppressions::{suppress_subscriber, SuppressionReason};
+
+#[derive(serde::Deserialize)]
+pub struct UnsubscribeParameters {
+    token: String,
+}
+
+#[tracing::instrument(name = "Render the unsubscribe page", skip(parameters, hmac_secret))]
+pub async fn unsubscribe_form(
+    parameters: web::Query<UnsubscribeParameters>,
+    hmac_secret: Data<HmacSecret>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    let token = UnsubscribeToken::parse(&parameters.token, &hmac_secret)
+        .map_err(UnsubscribeError::InvalidToken)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta http-equiv="content-type" content="text/html; charset=utf-8">
+                <title>Unsubscribe</title>
+            </head>
+            <body>
+                <p>Do you want to stop receiving our newsletter?</p>
+                <form action="/subscriptions/unsubscribe?token={token}" method="post">
+                    <button type="submit">Unsubscribe</button>
+                </form>
+            </body>
+            </html>"#,
+        )))
+}
+
+/// Handles both the button on the unsubscribe page and RFC 8058 one-click
+/// requests fired by mail clients via the `List-Unsubscribe-Post` header.
+#[tracing::instrument(
+    name = "Unsubscribe a subscriber",
+    skip(parameters, pool, hmac_secret),
+    fields(subscriber_id = tracing::field::Empty)
+)]
+pub async fn unsubscribe(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: Data<PgPool>,
+    hmac_secret: Data<HmacSecret>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    let token = UnsubscribeToken::parse(&parameters.token, &hmac_secret)
+        .map_err(UnsubscribeError::InvalidToken)?;
+
+    tracing::Span::current().record(
+        "subscriber_id",
+        tracing::field::display(token.subscriber_id()),
+    );
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    mark_subscriber_as_unsubscribed(&mut transaction, token.subscriber_id())
+        .await
+        .context("Failed to mark the subscriber as unsubscribed.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to unsubscribe a subscriber.")?;
+
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(
+        r#"<!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta http-equiv="content-type" content="text/html; charset=utf-8">
+                <title>Unsubscribed</title>
+            </head>
+            <body>
+                <p>You have been unsubscribed. You will not receive any further issues.</p>
+            </body>
+            </html>"#,
+    ))
+}
+
+/// Unsubscribing is global: the subscriber leaves every list they were on,
+/// so signing up again later only rejoins the list they ask for. Their
+/// address goes on the suppression list until they confirm a new sign-up.
+#[tracing::instrument(name = "Mark subscriber as unsubscribed", skip(transaction))]
+pub async fn mark_subscriber_as_unsubscribed(
+    transaction: &mut Transaction<'static, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET status = 'unsubscribed'
+        WHERE id = $1
+        "#,
+        subscriber_id
+    );
+    transaction.execute(query).await?;
+
+    let query = sqlx::query!(
+        r#"
+        UPDATE list_memberships
+        SET status = 'unsubscribed'
+        WHERE subscriber_id = $1
+        "#,
+        subscriber_id
+    );
+    transaction.execute(query).await?;
+
+    forget_queued_confirmations(&mut **transaction, subscriber_id, None).await?;
+
+    suppress_subscriber(
+        &mut **transaction,
+        subscriber_id,
+        SuppressionReason::Unsubscribe,
+    )
+    .await?;
+
+    Ok(())
+}
+
+#[derive(thiserror::Error)]
+pub enum UnsubscribeError {
+    #[error("{0}")]
+    InvalidToken(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for UnsubscribeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for UnsubscribeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::InvalidToken(_) => StatusCode::BAD_REQUEST,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
@@ -0,0 +1,545 @@
+//! src/segments/filter.rs
+//!
+//! The language segments are written in, e.g.
+//! `status = confirmed AND subscribed_at > 2024-01-01 AND attributes.plan = "pro"`.
+//!
+//! Comparisons are joined with `AND`, `OR` and `NOT`, and grouped with
+//! parentheses. Values may be quoted, or bare when they hold no spaces.
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde_json::Value;
+use sqlx::{Postgres, QueryBuilder};
+
+use crate::domain::SubscriberAttributes;
+
+/// How deeply parentheses and `NOT`s may nest.
+const MAX_DEPTH: usize = 32;
+
+/// Every status a subscriber can be in.
+pub const STATUSES: [&str; 5] = [
+    "pending_confirmation",
+    "confirmed",
+    "unsubscribed",
+    "bounced",
+    "complained",
+];
+
+/// A parsed segment definition, ready to become part of a `WHERE` clause.
+#[derive(Debug, PartialEq)]
+pub enum Filter {
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+    Condition(Condition),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Condition {
+    /// `status`, `name` or `email`, which only compare for (in)equality.
+    Text {
+        column: &'static str,
+        operator: Operator,
+        value: String,
+    },
+    SubscribedAt {
+        operator: Operator,
+        value: DateTime<Utc>,
+    },
+    Attribute {
+        key: String,
+        operator: Operator,
+        value: Value,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Operator {
+    fn as_sql(&self) -> &'static str {
+        match self {
+            Self::Equal => "=",
+            Self::NotEqual => "<>",
+            Self::Less => "<",
+            Self::LessOrEqual => "<=",
+            Self::Greater => ">",
+            Self::GreaterOrEqual => ">=",
+        }
+    }
+
+    fn is_equality(&self) -> bool {
+        matches!(self, Self::Equal | Self::NotEqual)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Operator(Operator),
+    /// A keyword, field or value written as is.
+    Word(String),
+    /// A value between quotes, never a keyword or a number.
+    Quoted(String),
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Self::Open => "(".into(),
+            Self::Close => ")".into(),
+            Self::Operator(operator) => match operator {
+                Operator::NotEqual => "!=".into(),
+                operator => operator.as_sql().into(),
+            },
+            Self::Word(word) => word.clone(),
+            Self::Quoted(value) => format!("\"{}\"", value),
+        }
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self, Self::Word(word) if word.eq_ignore_ascii_case(keyword))
+    }
+}
+
+impl Filter {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let tokens = tokenize(input)?;
+        if tokens.is_empty() {
+            return Err("The segment has no conditions.".into());
+        }
+
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+        };
+        let filter = parser.or(0)?;
+        match parser.peek() {
+            None => Ok(filter),
+            Some(token) => Err(format!("Unexpected {} in the segment.", token.describe())),
+        }
+    }
+
+    /// Append the filter to a query as a condition on `subscriptions`, with
+    /// every value bound as a parameter.
+    pub fn push_sql(&self, builder: &mut QueryBuilder<'_, Postgres>) {
+        match self {
+            Self::And(left, right) | Self::Or(left, right) => {
+                let joiner = if matches!(self, Self::And(..)) {
+                    " AND "
+                } else {
+                    " OR "
+                };
+                builder.push("(");
+                left.push_sql(builder);
+                builder.push(joiner);
+                right.push_sql(builder);
+                builder.push(")");
+            }
+            Self::Not(filter) => {
+                builder.push("NOT (");
+                filter.push_sql(builder);
+                builder.push(")");
+            }
+            Self::Condition(condition) => condition.push_sql(builder),
+        }
+    }
+}
+
+impl Condition {
+    fn push_sql(&self, builder: &mut QueryBuilder<'_, Postgres>) {
+        match self {
+            Self::Text {
+                column,
+                operator,
+                value,
+            } => {
+                builder
+                    .push(format_args!(
+                        "subscriptions.{} {} ",
+                        column,
+                        operator.as_sql()
+                    ))
+                    .push_bind(value.clone());
+            }
+            Self::SubscribedAt { operator, value } => {
+                builder
+                    .push(format_args!(
+                        "subscriptions.subscribed_at {} ",
+                        operator.as_sql()
+                    ))
+                    .push_bind(*value);
+            }
+            // A missing attribute differs from every value, and only values
+            // of the same JSON type are ordered against each other.
+            Self::Attribute {
+                key,
+                operator: Operator::NotEqual,
+                value,
+            } => {
+                builder
+                    .push("(subscriptions.attributes -> ")
+                    .push_bind(key.clone())
+                    .push(") IS DISTINCT FROM ")
+                    .push_bind(value.clone());
+            }
+            Self::Attribute {
+                key,
+                operator: Operator::Equal,
+                value,
+            } => {
+                builder
+                    .push("subscriptions.attributes -> ")
+                    .push_bind(key.clone())
+                    .push(" = ")
+                    .push_bind(value.clone());
+            }
+            Self::Attribute {
+                key,
+                operator,
+                value,
+            } => {
+                builder
+                    .push("(jsonb_typeof(subscriptions.attributes -> ")
+                    .push_bind(key.clone())
+                    .push(") = jsonb_typeof(")
+                    .push_bind(value.clone())
+                    .push(") AND subscriptions.attributes -> ")
+                    .push_bind(key.clone())
+                    .push(format_args!(" {} ", operator.as_sql()))
+                    .push_bind(value.clone())
+                    .push(")");
+            }
+        }
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self, expected: &str) -> Result<Token, String> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or_else(|| format!("The segment ends where {} was expected.", expected))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn or(&mut self, depth: usize) -> Result<Filter, String> {
+        let mut filter = self.and(depth)?;
+        while self.peek().is_some_and(|token| token.is_keyword("OR")) {
+            self.position += 1;
+            filter = Filter::Or(Box::new(filter), Box::new(self.and(depth)?));
+        }
+        Ok(filter)
+    }
+
+    fn and(&mut self, depth: usize) -> Result<Filter, String> {
+        let mut filter = self.unary(depth)?;
+        while self.peek().is_some_and(|token| token.is_keyword("AND")) {
+            self.position += 1;
+            filter = Filter::And(Box::new(filter), Box::new(self.unary(depth)?));
+        }
+        Ok(filter)
+    }
+
+    fn unary(&mut self, depth: usize) -> Result<Filter, String> {
+        if depth > MAX_DEPTH {
+            return Err(format!(
+                "The segment nests more than {} levels deep.",
+                MAX_DEPTH
+            ));
+        }
+
+        match self.next("a condition")? {
+            token if token.is_keyword("NOT") => Ok(Filter::Not(Box::new(self.unary(depth + 1)?))),
+            Token::Open => {
+                let filter = self.or(depth + 1)?;
+                match self.next(")")? {
+                    Token::Close => Ok(filter),
+                    token => Err(format!("Expected ) but found {}.", token.describe())),
+                }
+            }
+            Token::Word(field) => self.condition(&field).map(Filter::Condition),
+            token => Err(format!(
+                "Expected a condition but found {}.",
+                token.describe()
+            )),
+        }
+    }
+
+    fn condition(&mut self, field: &str) -> Result<Condition, String> {
+        let operator = match self.next("an operator")? {
+            Token::Operator(operator) => operator,
+            token => {
+                return Err(format!(
+                    "Expected an operator after {} but found {}.",
+                    field,
+                    token.describe()
+                ))
+            }
+        };
+        let (value, quoted) = match self.next("a value")? {
+            Token::Word(value) => (value, false),
+            Token::Quoted(value) => (value, true),
+            token => {
+                return Err(format!(
+                    "Expected a value after {} but found {}.",
+                    field,
+                    token.describe()
+                ))
+            }
+        };
+
+        match field {
+            "status" | "name" | "email" => {
+                if !operator.is_equality() {
+                    return Err(format!("{} can only be compared with = or !=.", field));
+                }
+                if field == "status" && !STATUSES.contains(&value.as_str()) {
+                    return Err(format!(
+                        "{} is not a known status. Use one of {}.",
+                        value,
+                        STATUSES.join(", ")
+                    ));
+                }
+                let column = match field {
+                    "status" => "status",
+                    "name" => "name",
+                    _ => "email",
+                };
+                Ok(Condition::Text {
+                    column,
+                    operator,
+                    value,
+                })
+            }
+            "subscribed_at" => Ok(Condition::SubscribedAt {
+                operator,
+                value: parse_timestamp(&value)?,
+            }),
+            _ => match field.strip_prefix("attributes.") {
+                Some(key) if SubscriberAttributes::is_valid_key(key) => {
+                    let value = if quoted {
+                        Value::String(value)
+                    } else {
+                        parse_bare_value(value)
+                    };
+                    Ok(Condition::Attribute {
+                        key: key.to_owned(),
+                        operator,
+                        value,
+                    })
+                }
+                _ => Err(format!(
+                    "{} is not a known field. Use status, name, email, subscribed_at \
+                    or attributes.<name>.",
+                    field
+                )),
+            },
+        }
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '=' => Token::Operator(Operator::Equal),
+            '!' if chars.next_if_eq(&'=').is_some() => Token::Operator(Operator::NotEqual),
+            '<' if chars.next_if_eq(&'=').is_some() => Token::Operator(Operator::LessOrEqual),
+            '<' => Token::Operator(Operator::Less),
+            '>' if chars.next_if_eq(&'=').is_some() => Token::Operator(Operator::GreaterOrEqual),
+            '>' => Token::Operator(Operator::Greater),
+            '"' | '\'' => {
+                let quote = c;
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') => match chars.next() {
+                            Some(escaped) => value.push(escaped),
+                            None => break,
+                        },
+                        Some(c) if c == quote => {
+                            tokens.push(Token::Quoted(value));
+                            break;
+                        }
+                        Some(c) => value.push(c),
+                        None => return Err("A quoted value is never closed.".into()),
+                    }
+                }
+                continue;
+            }
+            c if is_word_char(c) => {
+                let mut word = String::from(c);
+                while let Some(c) = chars.next_if(|c| is_word_char(*c)) {
+                    word.push(c);
+                }
+                Token::Word(word)
+            }
+            c => return Err(format!("Unexpected {} in the segment.", c)),
+        };
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+fn is_word_char(c: char) -> bool {
+    !c.is_whitespace() && !matches!(c, '(' | ')' | '=' | '!' | '<' | '>' | '"' | '\'')
+}
+
+/// A date stands for its midnight, in UTC.
+fn parse_timestamp(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap().and_utc());
+    }
+    DateTime::parse_from_rfc3339(value)
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .map_err(|_| {
+            format!(
+                "{} is not a date. Use YYYY-MM-DD or an RFC 3339 timestamp.",
+                value
+            )
+        })
+}
+
+/// Bare values are numbers or booleans when they read as one, like in JSON.
+fn parse_bare_value(value: String) -> Value {
+    match value.as_str() {
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        _ => match serde_json::from_str::<serde_json::Number>(&value) {
+            Ok(number) => Value::Number(number),
+            Err(_) => Value::String(value),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Condition, Filter, Operator};
+    use claims::assert_err;
+    use serde_json::json;
+    use sqlx::{Execute, Postgres, QueryBuilder};
+
+    fn to_sql(input: &str) -> String {
+        let mut builder = QueryBuilder::<Postgres>::new("");
+        Filter::parse(input).unwrap().push_sql(&mut builder);
+        builder.build().sql().to_owned()
+    }
+
+    #[test]
+    fn the_example_segment_compiles_to_parameterized_sql() {
+        assert_eq!(
+            to_sql(
+                r#"status = confirmed AND subscribed_at > 2024-01-01 AND attributes.plan = "pro""#
+            ),
+            "((subscriptions.status = $1 AND subscriptions.subscribed_at > $2) \
+            AND subscriptions.attributes -> $3 = $4)"
+        );
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(
+            to_sql("name = a OR name = b and NOT (email = c OR email = d)"),
+            "(subscriptions.name = $1 OR (subscriptions.name = $2 \
+            AND NOT ((subscriptions.email = $3 OR subscriptions.email = $4))))"
+        );
+    }
+
+    #[test]
+    fn bare_attribute_values_are_typed_like_json() {
+        let filter = Filter::parse("attributes.seats >= 10 AND attributes.paid != true").unwrap();
+
+        assert_eq!(
+            filter,
+            Filter::And(
+                Box::new(Filter::Condition(Condition::Attribute {
+                    key: "seats".into(),
+                    operator: Operator::GreaterOrEqual,
+                    value: json!(10),
+                })),
+                Box::new(Filter::Condition(Condition::Attribute {
+                    key: "paid".into(),
+                    operator: Operator::NotEqual,
+                    value: json!(true),
+                })),
+            )
+        );
+        assert_eq!(
+            Filter::parse(r#"attributes.seats = "10""#).unwrap(),
+            Filter::Condition(Condition::Attribute {
+                key: "seats".into(),
+                operator: Operator::Equal,
+                value: json!("10"),
+            })
+        );
+    }
+
+    #[test]
+    fn quoted_values_can_hold_anything() {
+        assert_eq!(
+            Filter::parse(r#"name = 'Ursula K. \'le\' Guin (AND)'"#).unwrap(),
+            Filter::Condition(Condition::Text {
+                column: "name",
+                operator: Operator::Equal,
+                value: "Ursula K. 'le' Guin (AND)".into(),
+            })
+        );
+    }
+
+    #[test]
+    fn malformed_segments_are_rejected() {
+        for input in [
+            "",
+            "status",
+            "status =",
+            "status = confirmed AND",
+            "status = confirmed)",
+            "(status = confirmed",
+            "status = subscribed",
+            "name > a",
+            "subscribed_at > yesterday",
+            "plan = pro",
+            "attributes.first-name = x",
+            "name = \"a",
+            "name == a",
+            "NOT",
+        ] {
+            assert_err!(Filter::parse(input), "{} was accepted", input);
+        }
+    }
+
+    #[test]
+    fn deep_nesting_is_rejected() {
+        assert_err!(Filter::parse(&format!(
+            "{}name = a{}",
+            "(".repeat(100),
+            ")".repeat(100)
+        )));
+        assert_err!(Filter::parse(&format!("{}name = a", "NOT ".repeat(100))));
+    }
+}
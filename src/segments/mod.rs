@@ -0,0 +1,173 @@
+//! src/segments/mod.rs
+//!
+//! Saved audiences an issue can be sent to instead of a whole list.
+
+mod filter;
+
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool, Postgres, QueryBuilder, Row};
+use uuid::Uuid;
+
+pub use filter::{Filter, STATUSES};
+
+/// Matching subscribers shown when previewing a segment.
+const PREVIEW_SAMPLE_SIZE: i64 = 10;
+
+pub struct Segment {
+    pub segment_id: Uuid,
+    pub name: String,
+    pub expression: String,
+}
+
+pub struct SegmentPreview {
+    pub count: i64,
+    pub sample: Vec<SampleSubscriber>,
+}
+
+pub struct SampleSubscriber {
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "Get all segments", skip(pool))]
+pub async fn get_all_segments(pool: &PgPool) -> Result<Vec<Segment>, sqlx::Error> {
+    sqlx::query_as!(
+        Segment,
+        r#"
+        SELECT segment_id, name, expression
+        FROM segments
+        ORDER BY name
+        "#
+    )
+    .fetch_all(pool)
+    .await
+}
+
+#[tracing::instrument(name = "Look up a segment", skip(executor))]
+pub async fn get_segment(
+    executor: impl PgExecutor<'_>,
+    segment_id: Uuid,
+) -> Result<Option<Segment>, sqlx::Error> {
+    sqlx::query_as!(
+        Segment,
+        r#"
+        SELECT segment_id, name, expression
+        FROM segments
+        WHERE segment_id = $1
+        "#,
+        segment_id
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+/// Save a segment, unless one with the same name already exists.
+///
+/// Returns whether the segment was saved.
+#[tracing::instrument(name = "Save a segment", skip(pool))]
+pub async fn insert_segment(
+    pool: &PgPool,
+    name: &str,
+    expression: &str,
+) -> Result<bool, sqlx::Error> {
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO segments (segment_id, name, expression)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (name) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        name,
+        expression
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(inserted.rows_affected() == 1)
+}
+
+/// Issues already sent to the segment keep their recipients but forget it.
+#[tracing::instrument(name = "Delete a segment", skip(pool))]
+pub async fn delete_segment(pool: &PgPool, segment_id: Uuid) -> Result<bool, sqlx::Error> {
+    let deleted = sqlx::query!("DELETE FROM segments WHERE segment_id = $1", segment_id)
+        .execute(pool)
+        .await?;
+
+    Ok(deleted.rows_affected() == 1)
+}
+
+/// Who an issue sent to `list_id` reaches, narrowed down to `segment` if
+/// any: a `FROM` and `WHERE` clause over `subscriptions`.
+///
+/// Only confirmed subscribers who confirmed their membership of the list are
+/// reached, and suppressed addresses are left out whatever their
+/// subscription says.
+pub fn push_audience_sql(
+    builder: &mut QueryBuilder<'_, Postgres>,
+    list_id: Uuid,
+    segment: Option<&Filter>,
+) {
+    builder
+        .push(
+            r#"
+            FROM subscriptions
+            JOIN list_memberships
+                ON list_memberships.subscriber_id = subscriptions.id
+            WHERE subscriptions.status = 'confirmed'
+            AND list_memberships.list_id = "#,
+        )
+        .push_bind(list_id)
+        .push(
+            r#"
+            AND list_memberships.status = 'confirmed'
+            AND NOT EXISTS (
+                SELECT 1
+                FROM suppressions
+                WHERE suppressions.email_canonical = subscriptions.email_canonical
+            )"#,
+        );
+    if let Some(segment) = segment {
+        builder.push(" AND ");
+        segment.push_sql(builder);
+    }
+}
+
+/// How many of the subscribers an issue sent to `list_id` would reach match
+/// `filter`, and a few of them, newest first.
+#[tracing::instrument(name = "Preview a segment", skip(pool, filter))]
+pub async fn preview_segment(
+    pool: &PgPool,
+    list_id: Uuid,
+    filter: &Filter,
+) -> Result<SegmentPreview, sqlx::Error> {
+    let mut builder = QueryBuilder::<Postgres>::new(
+        r#"
+        SELECT
+            subscriptions.email,
+            subscriptions.name,
+            subscriptions.status,
+            subscriptions.subscribed_at,
+            COUNT(*) OVER () AS count"#,
+    );
+    push_audience_sql(&mut builder, list_id, Some(filter));
+    builder
+        .push(" ORDER BY subscriptions.subscribed_at DESC LIMIT ")
+        .push_bind(PREVIEW_SAMPLE_SIZE);
+
+    let rows = builder.build().fetch_all(pool).await?;
+
+    let count = rows.first().map(|row| row.get("count")).unwrap_or(0);
+    let sample = rows
+        .into_iter()
+        .map(|row| SampleSubscriber {
+            email: row.get("email"),
+            name: row.get("name"),
+            status: row.get("status"),
+            subscribed_at: row.get("subscribed_at"),
+        })
+        .collect();
+
+    Ok(SegmentPreview { count, sample })
+}
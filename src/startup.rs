@@ -3,23 +3,42 @@ use actix_session::SessionMiddleware;
 use actix_web::cookie::Key;
 use actix_web::dev::Server;
 use actix_web::middleware::from_fn;
-use actix_web::web::{get, post, scope, Data};
+use actix_web::web::{get, post, resource, scope, Data, JsonConfig};
 use actix_web::{App, HttpServer};
 use actix_web_flash_messages::storage::CookieMessageStore;
 use actix_web_flash_messages::FlashMessagesFramework;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
 use redact::Secret;
+use sha2::Sha256;
 use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
 use std::io::Error;
 use std::net::TcpListener;
+use std::sync::Arc;
 use tracing_actix_web::TracingLogger; // Transmission Control Protocol: [TCP]
 
 use crate::authentication::reject_anonymous_users;
-use crate::configuration::{DatabaseSettings, Settings};
-use crate::email_client::EmailClient;
+use crate::bot_protection::BotProtection;
+use crate::client_ip::TrustedProxies;
+use crate::configuration::{ApplicationSettings, DatabaseSettings, Settings};
+use crate::email_client::{EmailClient, PostmarkWebhookCredentials};
+use crate::email_validation::{EmailValidator, MxResolver};
+use crate::rate_limit::RateLimiter;
 use crate::routes::{
-    admin_dashboard, change_password, change_password_form, confirm, health_check, home, log_out,
-    login, login_form, publish_newsletter, publish_newsletter_form, subscribe,
+    add_email_domain_rule, add_segment, add_suppression, admin_confirm_subscriber, admin_dashboard,
+    admin_delete_subscriber, admin_resend_confirmation, admin_unsubscribe_subscriber,
+    api_form_token, api_subscribe, browse_subscribers, change_password, change_password_form,
+    confirm, confirm_email_change, confirm_email_change_form, confirm_form, email_domains_form,
+    erase_my_data, erase_subscriber, export_my_data, export_subscriber, export_subscribers,
+    health_check, home, import_subscribers, import_subscribers_form, import_suppressions,
+    json_error_handler, log_out, login, login_form, manage, manage_form, move_subscriber,
+    postmark_webhook, preview_saved_segment, preview_segment, publish_newsletter,
+    publish_newsletter_form, remove_email_domain_rule, remove_segment, remove_suppression,
+    resend_confirmation, segments_form, sign_up_sources, subscribe, subscriber_data_form,
+    subscriber_details, subscriber_import_report, subscriber_import_results, suppressions_form,
+    unsubscribe, unsubscribe_form, update_subscriber_attributes,
 };
 
 // NOTE: HTTP & TCP is a protocol
@@ -27,8 +46,38 @@ use crate::routes::{
 #[derive(Clone)]
 pub struct HmacSecret(pub Secret<String>);
 
+impl HmacSecret {
+    /// Compute a URL-safe HMAC-SHA256 tag for `message`.
+    pub fn sign(&self, message: &str) -> String {
+        let mut mac = self.mac();
+        mac.update(message.as_bytes());
+
+        URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+    }
+
+    /// Check, in constant time, that `signature` is a valid tag for `message`.
+    pub fn verify(&self, message: &str, signature: &str) -> bool {
+        let Ok(signature) = URL_SAFE_NO_PAD.decode(signature) else {
+            return false;
+        };
+
+        let mut mac = self.mac();
+        mac.update(message.as_bytes());
+
+        mac.verify_slice(&signature).is_ok()
+    }
+
+    fn mac(&self) -> Hmac<Sha256> {
+        Hmac::<Sha256>::new_from_slice(self.0.expose_secret().as_bytes())
+            .expect("HMAC can take a key of any size")
+    }
+}
+
 pub struct ApplicationBaseUrl(pub String);
 
+/// Where to send subscribers once they have confirmed, if anywhere.
+pub struct ConfirmationRedirectUrl(pub Option<String>);
+
 pub struct Application {
     port: u16,
     server: Server,
@@ -39,7 +88,22 @@ impl Application {
         config: Settings,
         connection_pool: PgPool,
     ) -> Result<Application, anyhow::Error> {
+        let mx_resolver = Arc::new(config.email_validation.mx_resolver());
+
+        Self::build_with_mx_resolver(config, connection_pool, mx_resolver).await
+    }
+
+    /// Build the application with another way of looking up mail exchangers.
+    pub async fn build_with_mx_resolver(
+        config: Settings,
+        connection_pool: PgPool,
+        mx_resolver: Arc<dyn MxResolver>,
+    ) -> Result<Application, anyhow::Error> {
+        let webhook_credentials = config.email_client.webhook_credentials();
         let email_client = config.email_client.client();
+        let email_validator = config
+            .email_validation
+            .validator(connection_pool.clone(), mx_resolver);
 
         let address = format!("{}:{}", config.application.host, config.application.port);
         let listener = TcpListener::bind(address)?;
@@ -49,8 +113,9 @@ impl Application {
             listener,
             connection_pool,
             email_client,
-            config.application.base_url,
-            config.application.hmac_secret,
+            email_validator,
+            webhook_credentials,
+            config.application,
             config.redis_uri,
         )
         .await?;
@@ -66,20 +131,39 @@ impl Application {
         listener: TcpListener,
         db_pool: PgPool,
         email_client: EmailClient,
-        base_url: String,
-        hmac_secret: Secret<String>,
+        email_validator: EmailValidator,
+        webhook_credentials: PostmarkWebhookCredentials,
+        settings: ApplicationSettings,
         redis_uri: Secret<String>,
     ) -> Result<Server, anyhow::Error> {
         let db_pool = Data::new(db_pool);
         let email_client = Data::new(email_client);
-        let base_url = Data::new(ApplicationBaseUrl(base_url));
-        let hmac_secret = HmacSecret(hmac_secret);
+        let email_validator = Data::new(email_validator);
+        let webhook_credentials = Data::new(webhook_credentials);
+        let base_url = Data::new(ApplicationBaseUrl(settings.base_url));
+        let confirmation_redirect_url =
+            Data::new(ConfirmationRedirectUrl(settings.confirmation_redirect_url));
+        let hmac_secret = HmacSecret(settings.hmac_secret);
+        let hmac_data = Data::new(hmac_secret.clone());
+        let bot_protection = Data::new(BotProtection::new(
+            settings.bot_protection,
+            hmac_secret.clone(),
+        ));
+        let trusted_proxies = TrustedProxies(settings.trusted_proxies);
+        let rate_limits = settings.rate_limits;
 
         let secret_key = Key::from(hmac_secret.0.expose_secret().as_bytes());
         let message_store = CookieMessageStore::builder(secret_key.clone()).build();
         let message_framework = FlashMessagesFramework::builder(message_store).build();
 
         let redis_store = RedisSessionStore::new(redis_uri.expose_secret()).await?;
+        let rate_limiter = RateLimiter::connect(
+            redis_uri.expose_secret(),
+            rate_limits.key_prefix.clone(),
+            trusted_proxies.clone(),
+        )
+        .await?;
+        let trusted_proxies = Data::new(trusted_proxies);
 
         let server = HttpServer::new(move || {
             App::new()
@@ -91,24 +175,133 @@ impl Application {
                 ))
                 .route("/", get().to(home))
                 .route("/login", get().to(login_form))
-                .route("/login", post().to(login))
+                .route(
+                    "/login",
+                    post()
+                        .to(login)
+                        .wrap(rate_limiter.limit("login", &rate_limits.login)),
+                )
                 .route("/health-check", get().to(health_check))
-                .route("/subscriptions", post().to(subscribe))
-                .route("/subscriptions/confirm", get().to(confirm))
+                .route(
+                    "/subscriptions",
+                    post()
+                        .to(subscribe)
+                        .wrap(rate_limiter.limit("subscribe", &rate_limits.subscribe)),
+                )
+                .service(
+                    resource("/subscriptions/confirm")
+                        .wrap(rate_limiter.limit("confirm", &rate_limits.confirm))
+                        .route(get().to(confirm_form))
+                        .route(post().to(confirm)),
+                )
+                .route(
+                    "/subscriptions/resend-confirmation",
+                    post()
+                        .to(resend_confirmation)
+                        .wrap(rate_limiter.limit("subscribe", &rate_limits.subscribe)),
+                )
+                .route("/subscriptions/manage", get().to(manage_form))
+                .route("/subscriptions/manage", post().to(manage))
+                .route("/subscriptions/manage/export", get().to(export_my_data))
+                .route("/subscriptions/manage/erase", post().to(erase_my_data))
+                .route(
+                    "/subscriptions/manage/confirm-email",
+                    get().to(confirm_email_change_form),
+                )
+                .route(
+                    "/subscriptions/manage/confirm-email",
+                    post().to(confirm_email_change),
+                )
+                .route("/subscriptions/unsubscribe", get().to(unsubscribe_form))
+                .route("/subscriptions/unsubscribe", post().to(unsubscribe))
                 .route("/newsletters", post().to(publish_newsletter))
+                .route("/webhooks/postmark", post().to(postmark_webhook))
+                .service(
+                    scope("/api/v1")
+                        .app_data(JsonConfig::default().error_handler(json_error_handler))
+                        .route(
+                            "/subscriptions",
+                            post()
+                                .to(api_subscribe)
+                                .wrap(rate_limiter.limit("subscribe", &rate_limits.subscribe)),
+                        )
+                        .route("/subscriptions/form-token", get().to(api_form_token)),
+                )
                 .service(
                     scope("/admin")
                         .wrap(from_fn(reject_anonymous_users))
                         .route("/dashboard", get().to(admin_dashboard))
                         .route("/newsletters", get().to(publish_newsletter_form))
                         .route("/newsletters", post().to(publish_newsletter))
+                        .route("/email-domains", get().to(email_domains_form))
+                        .route("/email-domains", post().to(add_email_domain_rule))
+                        .route("/email-domains/remove", post().to(remove_email_domain_rule))
+                        .route("/segments", get().to(segments_form))
+                        .route("/segments", post().to(add_segment))
+                        .route("/segments/preview", get().to(preview_segment))
+                        .route("/segments/remove", post().to(remove_segment))
+                        .route("/segments/{segment_id}", get().to(preview_saved_segment))
+                        .route("/sign-up-sources", get().to(sign_up_sources))
+                        .route("/suppressions", get().to(suppressions_form))
+                        .route("/suppressions", post().to(add_suppression))
+                        .route("/suppressions/remove", post().to(remove_suppression))
+                        .route("/suppressions/import", post().to(import_suppressions))
+                        .route("/subscribers", get().to(browse_subscribers))
+                        .route("/subscribers/export", get().to(export_subscribers))
+                        .service(
+                            resource("/subscribers/import")
+                                .route(get().to(import_subscribers_form))
+                                .route(post().to(import_subscribers)),
+                        )
+                        .route(
+                            "/subscribers/import/{import_id}",
+                            get().to(subscriber_import_results),
+                        )
+                        .route(
+                            "/subscribers/import/{import_id}/report",
+                            get().to(subscriber_import_report),
+                        )
+                        .route("/subscribers/{subscriber_id}", get().to(subscriber_details))
+                        .route(
+                            "/subscribers/{subscriber_id}/attributes",
+                            post().to(update_subscriber_attributes),
+                        )
+                        .route(
+                            "/subscribers/{subscriber_id}/confirm",
+                            post().to(admin_confirm_subscriber),
+                        )
+                        .route(
+                            "/subscribers/{subscriber_id}/resend",
+                            post().to(admin_resend_confirmation),
+                        )
+                        .route(
+                            "/subscribers/{subscriber_id}/unsubscribe",
+                            post().to(admin_unsubscribe_subscriber),
+                        )
+                        .route(
+                            "/subscribers/{subscriber_id}/move",
+                            post().to(move_subscriber),
+                        )
+                        .route(
+                            "/subscribers/{subscriber_id}/delete",
+                            post().to(admin_delete_subscriber),
+                        )
+                        .route("/subscriber-data", get().to(subscriber_data_form))
+                        .route("/subscriber-data/export", post().to(export_subscriber))
+                        .route("/subscriber-data/erase", post().to(erase_subscriber))
                         .route("/password", get().to(change_password_form))
                         .route("/password", post().to(change_password))
                         .route("/logout", post().to(log_out)),
                 )
                 .app_data(db_pool.clone())
                 .app_data(email_client.clone())
+                .app_data(email_validator.clone())
+                .app_data(webhook_credentials.clone())
                 .app_data(base_url.clone())
+                .app_data(confirmation_redirect_url.clone())
+                .app_data(hmac_data.clone())
+                .app_data(trusted_proxies.clone())
+                .app_data(bot_protection.clone())
         })
         .listen(listener)?
         .run();
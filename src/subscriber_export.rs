@@ -0,0 +1,246 @@
+//! src/subscriber_export.rs
+//!
+//! The whole subscriber list as a file, for analysis and backups.
+
+use actix_web::web::Bytes;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use futures::stream::{self, Stream, StreamExt};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::csv_file::format_row;
+use crate::subscribers::{Cursor, SubscriberFilter};
+
+/// Subscribers read per query; only one page is held in memory at a time.
+const PAGE_SIZE: i64 = 500;
+
+const CSV_COLUMNS: [&str; 7] = [
+    "id",
+    "email",
+    "name",
+    "status",
+    "subscribed_at",
+    "lists",
+    "attributes",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Csv,
+    /// One JSON object per line.
+    Ndjson,
+}
+
+impl ExportFormat {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        match input.trim() {
+            "csv" => Ok(Self::Csv),
+            "ndjson" => Ok(Self::Ndjson),
+            other => Err(format!("{} is not a known export format.", other)),
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Ndjson => "ndjson",
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct ExportedSubscriber {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+    pub lists: Vec<ExportedMembership>,
+    pub attributes: serde_json::Value,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct ExportedMembership {
+    pub list: String,
+    pub status: String,
+}
+
+impl ExportedSubscriber {
+    /// Memberships read `slug:status`, separated by `;`; attributes stay JSON.
+    fn to_csv_row(&self) -> String {
+        let lists = self
+            .lists
+            .iter()
+            .map(|membership| format!("{}:{}", membership.list, membership.status))
+            .collect::<Vec<_>>()
+            .join(";");
+
+        format_row(&[
+            self.id.to_string(),
+            self.email.clone(),
+            self.name.clone(),
+            self.status.clone(),
+            self.subscribed_at.to_rfc3339(),
+            lists,
+            self.attributes.to_string(),
+        ])
+    }
+}
+
+/// Where the next page starts.
+enum Page {
+    First,
+    After(Cursor),
+    Done,
+}
+
+/// Every subscriber matching `filter`, oldest first, as chunks of the file.
+///
+/// Pages are read with a keyset on `(subscribed_at, id)`, each in its own
+/// query: a subscriber signing up mid-export is included or not depending
+/// on where the export has got to, but nobody is listed twice.
+pub fn export_subscribers(
+    pool: PgPool,
+    format: ExportFormat,
+    filter: SubscriberFilter,
+) -> impl Stream<Item = Result<Bytes, anyhow::Error>> {
+    let header = match format {
+        ExportFormat::Csv => Some(Ok(Bytes::from(format_row(&CSV_COLUMNS)))),
+        ExportFormat::Ndjson => None,
+    };
+
+    let pages = stream::unfold(Page::First, move |page| {
+        let pool = pool.clone();
+        let filter = filter.clone();
+        async move {
+            let after = match page {
+                Page::First => None,
+                Page::After(cursor) => Some(cursor),
+                Page::Done => return None,
+            };
+
+            match get_page(&pool, &filter, after).await {
+                Ok(subscribers) => {
+                    let next = match subscribers.last() {
+                        Some(last) if subscribers.len() as i64 == PAGE_SIZE => {
+                            Page::After(Cursor {
+                                subscribed_at: last.subscribed_at,
+                                id: last.id,
+                            })
+                        }
+                        _ => Page::Done,
+                    };
+                    Some((format_page(format, &subscribers), next))
+                }
+                Err(e) => {
+                    tracing::error!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        "Failed to export a page of subscribers."
+                    );
+                    Some((Err(e), Page::Done))
+                }
+            }
+        }
+    });
+
+    stream::iter(header).chain(pages)
+}
+
+fn format_page(
+    format: ExportFormat,
+    subscribers: &[ExportedSubscriber],
+) -> Result<Bytes, anyhow::Error> {
+    let mut chunk = String::new();
+    for subscriber in subscribers {
+        match format {
+            ExportFormat::Csv => chunk.push_str(&subscriber.to_csv_row()),
+            ExportFormat::Ndjson => {
+                chunk.push_str(
+                    &serde_json::to_string(subscriber)
+                        .context("Failed to serialise a subscriber.")?,
+                );
+                chunk.push('\n');
+            }
+        }
+    }
+
+    Ok(Bytes::from(chunk))
+}
+
+#[tracing::instrument(name = "Get a page of subscribers to export", skip(pool))]
+async fn get_page(
+    pool: &PgPool,
+    filter: &SubscriberFilter,
+    after: Option<Cursor>,
+) -> Result<Vec<ExportedSubscriber>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            subscriptions.id,
+            subscriptions.email,
+            subscriptions.name,
+            subscriptions.status,
+            subscriptions.subscribed_at,
+            subscriptions.attributes,
+            COALESCE(
+                jsonb_agg(
+                    jsonb_build_object('list', lists.slug, 'status', list_memberships.status)
+                    ORDER BY lists.slug
+                ) FILTER (WHERE lists.slug IS NOT NULL),
+                '[]'
+            ) AS "lists!"
+        FROM subscriptions
+        LEFT JOIN list_memberships ON list_memberships.subscriber_id = subscriptions.id
+        LEFT JOIN lists ON lists.list_id = list_memberships.list_id
+        WHERE (
+                $1::text IS NULL
+                OR subscriptions.email ILIKE $1
+                OR subscriptions.name ILIKE $1
+            )
+            AND ($2::text IS NULL OR subscriptions.status = $2)
+            AND ($3::timestamptz IS NULL OR subscriptions.subscribed_at >= $3)
+            AND ($4::timestamptz IS NULL OR subscriptions.subscribed_at < $4)
+            AND (
+                $5::timestamptz IS NULL
+                OR (subscriptions.subscribed_at, subscriptions.id) > ($5, $6::uuid)
+            )
+        GROUP BY subscriptions.id
+        ORDER BY subscriptions.subscribed_at, subscriptions.id
+        LIMIT $7
+        "#,
+        filter.search_pattern(),
+        filter.status,
+        filter.subscribed_from,
+        filter.subscribed_before,
+        after.map(|cursor| cursor.subscribed_at),
+        after.map(|cursor| cursor.id),
+        PAGE_SIZE
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve subscribers to export.")?;
+
+    rows.into_iter()
+        .map(|row| {
+            Ok(ExportedSubscriber {
+                id: row.id,
+                email: row.email,
+                name: row.name,
+                status: row.status,
+                subscribed_at: row.subscribed_at,
+                lists: serde_json::from_value(row.lists)
+                    .context("Failed to read the list memberships of a subscriber.")?,
+                attributes: row.attributes,
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,693 @@
+//! src/subscriber_import.rs
+//!
+//! Bulk sign-ups from a CSV file, e.g. when moving over from another provider.
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::attribution::Attribution;
+use crate::consent::ConsentEvent;
+use crate::csv_file::{format_row, EncodingError, Record};
+use crate::domain::{NewSubscriber, SubscriberAttributes, SubscriberEmail};
+use crate::mailing_lists::MailingList;
+use crate::routes::{error_chain_fmt, FormData};
+
+/// Rows inserted per transaction.
+const BATCH_SIZE: usize = 500;
+
+/// Recorded as the form imported subscribers consented through.
+pub const IMPORT_FORM_VERSION: &str = "admin-import-v1";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImportMode {
+    /// The subscribers already confirmed with the previous provider.
+    Confirmed,
+    /// Each subscriber is sent a confirmation link, as if they signed up. The
+    /// links go out from the background worker once the import is stored.
+    DoubleOptIn,
+}
+
+impl ImportMode {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        match input.trim() {
+            "confirmed" => Ok(Self::Confirmed),
+            "double_opt_in" => Ok(Self::DoubleOptIn),
+            other => Err(format!("{} is not a known import mode.", other)),
+        }
+    }
+
+    fn status(&self) -> &'static str {
+        match self {
+            Self::Confirmed => "confirmed",
+            Self::DoubleOptIn => "pending_confirmation",
+        }
+    }
+}
+
+/// A row of the file that makes a valid sign-up.
+#[derive(Debug)]
+pub struct ImportRow {
+    pub line: usize,
+    pub subscriber: NewSubscriber,
+}
+
+/// A row of the file that was not imported, and why.
+#[derive(Debug)]
+pub struct RejectedRow {
+    pub line: usize,
+    pub email: String,
+    pub name: String,
+    pub reason: String,
+}
+
+pub struct SubscriberImport {
+    pub import_id: Uuid,
+    pub list_name: String,
+    pub confirmed: bool,
+    pub n_imported: i32,
+    pub n_rejected: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Where the fields of a sign-up are in each row, as named by the first.
+struct Columns {
+    count: usize,
+    email: usize,
+    name: usize,
+    /// Every other named column, with the attribute it becomes.
+    attributes: Vec<(usize, String)>,
+}
+
+impl Columns {
+    /// `email` and `name` are required, every other column becomes an
+    /// attribute, its name lowercased.
+    fn parse(header: &Record) -> Result<Self, String> {
+        let columns: Vec<String> = header
+            .fields
+            .iter()
+            .map(|column| column.trim().to_owned())
+            .collect();
+
+        let position = |name: &str| {
+            columns
+                .iter()
+                .position(|column| column.eq_ignore_ascii_case(name))
+                .ok_or_else(|| format!("The first row of the file has no {} column.", name))
+        };
+        let email = position("email")?;
+        let name = position("name")?;
+
+        let mut attributes = Vec::new();
+        for (i, column) in columns.iter().enumerate() {
+            if i == email || i == name || column.is_empty() {
+                continue;
+            }
+            if !SubscriberAttributes::is_valid_key(column) {
+                return Err(format!(
+                    "The column {} cannot be an attribute. Name it with letters, digits \
+                    and underscores only.",
+                    column
+                ));
+            }
+            let key = column.to_lowercase();
+            if attributes.iter().any(|(_, seen)| *seen == key) {
+                return Err(format!("The column {} appears twice.", column));
+            }
+            attributes.push((i, key));
+        }
+
+        Ok(Self {
+            count: columns.len(),
+            email,
+            name,
+            attributes,
+        })
+    }
+}
+
+/// Turns the rows of a file into sign-ups as they are read, validated like
+/// any other.
+pub struct RowPreparer {
+    columns: Columns,
+    /// The line each address was first seen on, to turn down repeats.
+    first_lines: HashMap<String, usize>,
+}
+
+impl RowPreparer {
+    /// Only a file whose first row cannot be understood is an error; bad rows
+    /// are set aside with their reason.
+    pub fn new(header: &Record) -> Result<Self, String> {
+        Ok(Self {
+            columns: Columns::parse(header)?,
+            first_lines: HashMap::new(),
+        })
+    }
+
+    pub fn prepare(&mut self, record: Record) -> Result<ImportRow, RejectedRow> {
+        let columns = &self.columns;
+        let field = |i: usize| record.fields.get(i).map(|f| f.trim()).unwrap_or_default();
+        let email = field(columns.email).to_owned();
+        let name = field(columns.name).to_owned();
+        let reject = |reason: String| RejectedRow {
+            line: record.line,
+            email: email.clone(),
+            name: name.clone(),
+            reason,
+        };
+
+        if record.fields.len() > columns.count {
+            return Err(reject("The row has more fields than the header.".into()));
+        }
+
+        let extra = columns
+            .attributes
+            .iter()
+            .filter(|(i, _)| !field(*i).is_empty())
+            .map(|(i, key)| (format!("attributes[{}]", key), Value::from(field(*i))))
+            .collect();
+        let form = FormData {
+            email: email.clone(),
+            name: name.clone(),
+            list: None,
+            form_version: None,
+            website: None,
+            form_token: None,
+            proof_of_work: None,
+            attribution: Attribution::default(),
+            extra,
+        };
+        let subscriber = NewSubscriber::try_from(form).map_err(|e| reject(e.to_string()))?;
+
+        if let Some(first_line) = self.first_lines.get(&subscriber.email.canonical()) {
+            return Err(reject(format!(
+                "The address already appears on line {}.",
+                first_line
+            )));
+        }
+        self.first_lines
+            .insert(subscriber.email.canonical(), record.line);
+
+        Ok(ImportRow {
+            line: record.line,
+            subscriber,
+        })
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum ImportError {
+    /// The file as a whole cannot be imported. Nothing was.
+    #[error("{0}")]
+    UnusableFile(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// Signs up the rows of a file to a list as they are read, a batch per
+/// transaction, so that the file never has to be held in memory whole.
+///
+/// Only new addresses are imported: existing subscribers are left as they
+/// are, and suppressed addresses are refused whatever the mode. Rows that are
+/// turned down are kept for the import's report. Should a batch fail, those
+/// before it stay imported.
+pub struct SubscriberImporter<'a> {
+    pool: &'a PgPool,
+    list: &'a MailingList,
+    mode: ImportMode,
+    import_id: Uuid,
+    /// Set once the first row of the file has been read.
+    preparer: Option<RowPreparer>,
+    batch: Vec<ImportRow>,
+    rejected: Vec<RejectedRow>,
+}
+
+impl<'a> SubscriberImporter<'a> {
+    pub fn new(pool: &'a PgPool, list: &'a MailingList, mode: ImportMode) -> Self {
+        Self {
+            pool,
+            list,
+            mode,
+            import_id: Uuid::new_v4(),
+            preparer: None,
+            batch: Vec::new(),
+            rejected: Vec::new(),
+        }
+    }
+
+    /// Take in the next record of the file. The import is stored as soon as
+    /// the first one shows the file can be understood.
+    pub async fn push(&mut self, record: Result<Record, EncodingError>) -> Result<(), ImportError> {
+        let Some(preparer) = &mut self.preparer else {
+            let header = record.map_err(|e| ImportError::UnusableFile(e.to_string()))?;
+            self.preparer = Some(RowPreparer::new(&header).map_err(ImportError::UnusableFile)?);
+            store_import(self.pool, self.import_id, self.list, self.mode).await?;
+            return Ok(());
+        };
+
+        match record.map(|record| preparer.prepare(record)) {
+            Ok(Ok(row)) => self.batch.push(row),
+            Ok(Err(rejected)) => self.rejected.push(rejected),
+            Err(e) => self.rejected.push(RejectedRow {
+                line: e.line,
+                email: String::new(),
+                name: String::new(),
+                reason: "The row is not UTF-8 encoded.".into(),
+            }),
+        }
+
+        if self.batch.len() + self.rejected.len() >= BATCH_SIZE {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Import what is left once the whole file has been read.
+    pub async fn finish(mut self) -> Result<Uuid, ImportError> {
+        if self.preparer.is_none() {
+            return Err(ImportError::UnusableFile("The file is empty.".into()));
+        }
+        self.flush().await?;
+
+        Ok(self.import_id)
+    }
+
+    #[tracing::instrument(
+        name = "Import a batch of subscribers",
+        skip_all,
+        fields(list = %self.list.slug, mode = ?self.mode, n_rows = self.batch.len())
+    )]
+    async fn flush(&mut self) -> Result<(), anyhow::Error> {
+        let batch = std::mem::take(&mut self.batch);
+        let rejected = std::mem::take(&mut self.rejected);
+
+        let mut transaction = self
+            .pool
+            .begin()
+            .await
+            .context("Failed to acquire a Postgres connection from the pool")?;
+        let (n_imported, rejected) =
+            import_batch(&mut transaction, self.list, self.mode, batch, rejected).await?;
+        let n_rejected = rejected.len();
+        store_rejected_rows(&mut transaction, self.import_id, rejected).await?;
+        sqlx::query!(
+            r#"
+            UPDATE subscriber_imports
+            SET n_imported = n_imported + $2, n_rejected = n_rejected + $3
+            WHERE import_id = $1
+            "#,
+            self.import_id,
+            n_imported as i32,
+            n_rejected as i32
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to update the import.")?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit SQL transaction to import subscribers.")?;
+
+        Ok(())
+    }
+}
+/// Store the import, before any of its rows, as they are reported against it.
+async fn store_import(
+    pool: &PgPool,
+    import_id: Uuid,
+    list: &MailingList,
+    mode: ImportMode,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriber_imports (
+            import_id, list_id, confirmed, n_imported, n_rejected, created_at
+        )
+        VALUES ($1, $2, $3, 0, 0, now())
+        "#,
+        import_id,
+        list.list_id,
+        mode == ImportMode::Confirmed
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store the import.")?;
+
+    Ok(())
+}
+
+/// Insert one batch of sign-ups.
+///
+/// Returns how many were imported, and the rows that were turned down added
+/// to `rejected`. Subscribers imported with double opt-in are queued for the
+/// worker to send their confirmation link.
+async fn import_batch(
+    transaction: &mut Transaction<'_, Postgres>,
+    list: &MailingList,
+    mode: ImportMode,
+    batch: Vec<ImportRow>,
+    mut rejected: Vec<RejectedRow>,
+) -> Result<(usize, Vec<RejectedRow>), anyhow::Error> {
+    let canonical: Vec<String> = batch
+        .iter()
+        .map(|row| row.subscriber.email.canonical())
+        .collect();
+    let suppressed: HashSet<String> = sqlx::query!(
+        r#"
+        SELECT email_canonical
+        FROM suppressions
+        WHERE email_canonical = ANY($1)
+        "#,
+        &canonical
+    )
+    .fetch_all(&mut **transaction)
+    .await
+    .context("Failed to check the suppression list.")?
+    .into_iter()
+    .map(|row| row.email_canonical)
+    .collect();
+
+    let mut candidates = Vec::new();
+    for row in batch {
+        if suppressed.contains(&row.subscriber.email.canonical()) {
+            rejected.push(rejected_row(row, "The address is on the suppression list."));
+        } else {
+            candidates.push((Uuid::new_v4(), row));
+        }
+    }
+
+    let ids: Vec<Uuid> = candidates.iter().map(|(id, _)| *id).collect();
+    let emails: Vec<&str> = candidates
+        .iter()
+        .map(|(_, row)| row.subscriber.email.as_ref())
+        .collect();
+    let canonical: Vec<String> = candidates
+        .iter()
+        .map(|(_, row)| row.subscriber.email.canonical())
+        .collect();
+    let displays: Vec<&str> = candidates
+        .iter()
+        .map(|(_, row)| row.subscriber.email.display())
+        .collect();
+    let names: Vec<&str> = candidates
+        .iter()
+        .map(|(_, row)| row.subscriber.name.as_ref())
+        .collect();
+    let attributes: Vec<Value> = candidates
+        .iter()
+        .map(|(_, row)| Value::Object(row.subscriber.attributes.as_ref().clone()))
+        .collect();
+
+    // Addresses already on file conflict and are skipped.
+    let inserted: HashSet<Uuid> = sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (
+            id, email, email_canonical, email_display, name, attributes, subscribed_at,
+            status
+        )
+        SELECT id, email, email_canonical, email_display, name, attributes, now(), $7
+        FROM UNNEST($1::uuid[], $2::text[], $3::text[], $4::text[], $5::text[], $6::jsonb[])
+            AS rows(id, email, email_canonical, email_display, name, attributes)
+        ON CONFLICT (email_canonical) DO NOTHING
+        RETURNING id
+        "#,
+        &ids,
+        &emails as &[&str],
+        &canonical,
+        &displays as &[&str],
+        &names as &[&str],
+        &attributes,
+        mode.status()
+    )
+    .fetch_all(&mut **transaction)
+    .await
+    .context("Failed to insert the imported subscribers.")?
+    .into_iter()
+    .map(|row| row.id)
+    .collect();
+
+    let mut ids = Vec::new();
+    for (id, row) in candidates {
+        if inserted.contains(&id) {
+            ids.push(id);
+        } else {
+            rejected.push(rejected_row(
+                row,
+                "There already is a subscriber with this address.",
+            ));
+        }
+    }
+
+    sqlx::query!(
+        r#"
+        INSERT INTO list_memberships (subscriber_id, list_id, status)
+        SELECT id, $2, $3
+        FROM UNNEST($1::uuid[]) AS rows(id)
+        "#,
+        &ids,
+        list.list_id,
+        mode.status()
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to store the imported list memberships.")?;
+
+    if mode == ImportMode::DoubleOptIn {
+        sqlx::query!(
+            r#"
+            INSERT INTO confirmation_email_queue (subscriber_id, list_id, enqueued_at)
+            SELECT id, $2, now()
+            FROM UNNEST($1::uuid[]) AS rows(id)
+            "#,
+            &ids,
+            list.list_id
+        )
+        .execute(&mut **transaction)
+        .await
+        .context("Failed to queue the confirmation emails of imported subscribers.")?;
+    }
+
+    sqlx::query!(
+        r#"
+        INSERT INTO consent_records (
+            consent_record_id,
+            subscriber_id,
+            list_id,
+            event,
+            recorded_at,
+            form_version
+        )
+        SELECT gen_random_uuid(), id, $2, $3, now(), $4
+        FROM UNNEST($1::uuid[]) AS rows(id)
+        "#,
+        &ids,
+        list.list_id,
+        ConsentEvent::Import.as_ref(),
+        IMPORT_FORM_VERSION
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to record the consent of imported subscribers.")?;
+
+    Ok((ids.len(), rejected))
+}
+
+/// Keep the rejected rows for the report, each with the canonical form of
+/// its address so that erasing the subscriber can scrub it.
+async fn store_rejected_rows(
+    transaction: &mut Transaction<'_, Postgres>,
+    import_id: Uuid,
+    rejected: Vec<RejectedRow>,
+) -> Result<(), anyhow::Error> {
+    let mut lines = Vec::with_capacity(rejected.len());
+    let mut emails = Vec::with_capacity(rejected.len());
+    let mut canonical_emails = Vec::with_capacity(rejected.len());
+    let mut names = Vec::with_capacity(rejected.len());
+    let mut reasons = Vec::with_capacity(rejected.len());
+    for row in rejected {
+        lines.push(row.line as i32);
+        canonical_emails.push(
+            SubscriberEmail::parse(row.email.clone())
+                .ok()
+                .map(|email| email.canonical()),
+        );
+        emails.push(row.email);
+        names.push(row.name);
+        reasons.push(row.reason);
+    }
+
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriber_import_rejections (
+            import_id, line, email, email_canonical, name, reason
+        )
+        SELECT $1, line, email, email_canonical, name, reason
+        FROM UNNEST($2::int4[], $3::text[], $4::text[], $5::text[], $6::text[])
+            AS rows(line, email, email_canonical, name, reason)
+        "#,
+        import_id,
+        &lines,
+        &emails,
+        &canonical_emails as &[Option<String>],
+        &names,
+        &reasons
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to store the rejected rows.")?;
+
+    Ok(())
+}
+
+fn rejected_row(row: ImportRow, reason: &str) -> RejectedRow {
+    RejectedRow {
+        line: row.line,
+        email: row.subscriber.email.as_ref().to_owned(),
+        name: row.subscriber.name.as_ref().to_owned(),
+        reason: reason.to_owned(),
+    }
+}
+
+#[tracing::instrument(name = "Get a subscriber import", skip(pool))]
+pub async fn get_import(
+    pool: &PgPool,
+    import_id: Uuid,
+) -> Result<Option<SubscriberImport>, sqlx::Error> {
+    sqlx::query_as!(
+        SubscriberImport,
+        r#"
+        SELECT
+            subscriber_imports.import_id,
+            lists.name AS list_name,
+            subscriber_imports.confirmed,
+            subscriber_imports.n_imported,
+            subscriber_imports.n_rejected,
+            subscriber_imports.created_at
+        FROM subscriber_imports
+        JOIN lists ON lists.list_id = subscriber_imports.list_id
+        WHERE import_id = $1
+        "#,
+        import_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// The rejected rows of an import, as a CSV file.
+#[tracing::instrument(name = "Get a subscriber import report", skip(pool))]
+pub async fn get_import_report(
+    pool: &PgPool,
+    import_id: Uuid,
+) -> Result<Option<String>, sqlx::Error> {
+    let import = sqlx::query!(
+        "SELECT import_id FROM subscriber_imports WHERE import_id = $1",
+        import_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    if import.is_none() {
+        return Ok(None);
+    }
+
+    let rows = sqlx::query!(
+        r#"
+        SELECT line, email, name, reason
+        FROM subscriber_import_rejections
+        WHERE import_id = $1
+        ORDER BY line
+        "#,
+        import_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut report = format_row(&["line", "email", "name", "reason"]);
+    for row in rows {
+        report.push_str(&format_row(&[
+            row.line.to_string().as_str(),
+            &row.email,
+            &row.name,
+            &row.reason,
+        ]));
+    }
+    Ok(Some(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ImportRow, RejectedRow, RowPreparer};
+    use crate::csv_file;
+    use claims::assert_err;
+    use serde_json::json;
+
+    fn prepare_rows(input: &[u8]) -> Result<(Vec<ImportRow>, Vec<RejectedRow>), String> {
+        let mut records = csv_file::parse(input).unwrap().into_iter();
+        let mut preparer = RowPreparer::new(&records.next().unwrap())?;
+
+        let mut rows = Vec::new();
+        let mut rejected = Vec::new();
+        for record in records {
+            match preparer.prepare(record) {
+                Ok(row) => rows.push(row),
+                Err(row) => rejected.push(row),
+            }
+        }
+        Ok((rows, rejected))
+    }
+
+    #[test]
+    fn rows_become_sign_ups_with_extra_columns_as_attributes() {
+        let (rows, rejected) = prepare_rows(
+            "Name,Email,Company,\n\
+            Ursula,ursula@example.com,Earthsea,\n\
+            Ged,ged@example.com,,ignored\n"
+                .as_bytes(),
+        )
+        .unwrap();
+
+        assert!(rejected.is_empty());
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].subscriber.email.as_ref(), "ursula@example.com");
+        assert_eq!(
+            rows[0].subscriber.attributes.get("company"),
+            Some(&json!("Earthsea"))
+        );
+        assert!(rows[1].subscriber.attributes.as_ref().is_empty());
+    }
+
+    #[test]
+    fn invalid_and_repeated_rows_are_rejected_with_their_line() {
+        let (rows, rejected) = prepare_rows(
+            "email,name\n\
+            not-an-email,Ursula\n\
+            ged@example.com,\n\
+            ursula@example.com,Ursula\n\
+            Ursula@EXAMPLE.com,Ursula again\n\
+            a@example.com,A,extra\n"
+                .as_bytes(),
+        )
+        .unwrap();
+
+        assert_eq!(rows.len(), 1);
+        let lines: Vec<_> = rejected.iter().map(|row| row.line).collect();
+        assert_eq!(lines, vec![2, 3, 5, 6]);
+        assert_eq!(rejected[2].reason, "The address already appears on line 4.");
+    }
+
+    #[test]
+    fn a_file_without_the_required_columns_is_rejected() {
+        assert_err!(prepare_rows(b"email\na@example.com\n"));
+        assert_err!(prepare_rows(b"email,name,first name\na@example.com,A,B\n"));
+        assert_err!(prepare_rows(b"email,name,plan,plan\na@example.com,A,B,C\n"));
+    }
+}
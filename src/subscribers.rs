@@ -0,0 +1,211 @@
+//! src/subscribers.rs
+//!
+//! Reading through the subscriber list. The admin browser and the exports
+//! narrow it down the same way.
+
+use anyhow::Context;
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::segments::STATUSES;
+
+/// Which subscribers to show or export. Every bound is optional.
+#[derive(Debug, Clone, Default)]
+pub struct SubscriberFilter {
+    /// The start of an email address or name, in any case.
+    pub search: Option<String>,
+    pub status: Option<String>,
+    /// Inclusive.
+    pub subscribed_from: Option<DateTime<Utc>>,
+    /// Exclusive.
+    pub subscribed_before: Option<DateTime<Utc>>,
+}
+
+impl SubscriberFilter {
+    /// Both dates are `YYYY-MM-DD`, in UTC, and included in the range.
+    pub fn parse(
+        search: Option<&str>,
+        status: Option<&str>,
+        from: Option<&str>,
+        to: Option<&str>,
+    ) -> Result<Self, String> {
+        let status = match status {
+            Some(status) if !STATUSES.contains(&status) => {
+                return Err(format!(
+                    "{} is not a known status. Use one of {}.",
+                    status,
+                    STATUSES.join(", ")
+                ));
+            }
+            status => status.map(str::to_owned),
+        };
+        let subscribed_from = from.map(parse_date).transpose()?;
+        let subscribed_before = to.map(parse_date).transpose()?.map(|to| to + Days::new(1));
+
+        Ok(Self {
+            search: search.map(str::to_owned),
+            status,
+            subscribed_from,
+            subscribed_before,
+        })
+    }
+
+    /// `search` as a `LIKE` pattern, its own wildcards taken literally.
+    pub fn search_pattern(&self) -> Option<String> {
+        self.search.as_ref().map(|search| {
+            let mut pattern = String::with_capacity(search.len() + 1);
+            for c in search.chars() {
+                if matches!(c, '\\' | '%' | '_') {
+                    pattern.push('\\');
+                }
+                pattern.push(c);
+            }
+            pattern.push('%');
+            pattern
+        })
+    }
+}
+
+fn parse_date(value: &str) -> Result<DateTime<Utc>, String> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map(|date| date.and_hms_opt(0, 0, 0).unwrap().and_utc())
+        .map_err(|_| format!("{} is not a date. Use YYYY-MM-DD.", value))
+}
+
+/// A position in the list, ordered by `(subscribed_at, id)`.
+///
+/// Written `<microseconds since the epoch>_<id>` in links.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cursor {
+    pub subscribed_at: DateTime<Utc>,
+    pub id: Uuid,
+}
+
+impl Cursor {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let error = || format!("{} is not a position in the subscriber list.", input);
+        let (micros, id) = input.split_once('_').ok_or_else(error)?;
+        let subscribed_at = micros
+            .parse()
+            .ok()
+            .and_then(DateTime::from_timestamp_micros)
+            .ok_or_else(error)?;
+        let id = Uuid::parse_str(id).map_err(|_| error())?;
+
+        Ok(Self { subscribed_at, id })
+    }
+}
+
+impl std::fmt::Display for Cursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}_{}", self.subscribed_at.timestamp_micros(), self.id)
+    }
+}
+
+pub struct SubscriberRow {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+}
+
+impl SubscriberRow {
+    pub fn cursor(&self) -> Cursor {
+        Cursor {
+            subscribed_at: self.subscribed_at,
+            id: self.id,
+        }
+    }
+}
+
+/// Up to `limit` subscribers matching `filter`, newest first, starting
+/// after `before` when paging on.
+#[tracing::instrument(name = "Get a page of subscribers", skip(pool))]
+pub async fn get_subscribers_page(
+    pool: &PgPool,
+    filter: &SubscriberFilter,
+    before: Option<Cursor>,
+    limit: i64,
+) -> Result<Vec<SubscriberRow>, anyhow::Error> {
+    sqlx::query_as!(
+        SubscriberRow,
+        r#"
+        SELECT id, email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE ($1::text IS NULL OR email ILIKE $1 OR name ILIKE $1)
+            AND ($2::text IS NULL OR status = $2)
+            AND ($3::timestamptz IS NULL OR subscribed_at >= $3)
+            AND ($4::timestamptz IS NULL OR subscribed_at < $4)
+            AND ($5::timestamptz IS NULL OR (subscribed_at, id) < ($5, $6::uuid))
+        ORDER BY subscribed_at DESC, id DESC
+        LIMIT $7
+        "#,
+        filter.search_pattern(),
+        filter.status,
+        filter.subscribed_from,
+        filter.subscribed_before,
+        before.map(|cursor| cursor.subscribed_at),
+        before.map(|cursor| cursor.id),
+        limit
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve a page of subscribers.")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cursor, SubscriberFilter};
+    use chrono::DateTime;
+    use claims::{assert_err, assert_ok};
+    use uuid::Uuid;
+
+    #[test]
+    fn the_date_range_includes_both_days() {
+        let filter =
+            SubscriberFilter::parse(None, None, Some("2024-01-01"), Some("2024-01-31")).unwrap();
+
+        assert_eq!(
+            filter.subscribed_from.unwrap().to_rfc3339(),
+            "2024-01-01T00:00:00+00:00"
+        );
+        assert_eq!(
+            filter.subscribed_before.unwrap().to_rfc3339(),
+            "2024-02-01T00:00:00+00:00"
+        );
+    }
+
+    #[test]
+    fn unknown_statuses_and_malformed_dates_are_rejected() {
+        assert_ok!(SubscriberFilter::parse(None, Some("confirmed"), None, None));
+        assert_err!(SubscriberFilter::parse(None, Some("active"), None, None));
+        assert_err!(SubscriberFilter::parse(
+            None,
+            None,
+            Some("01/02/2024"),
+            None
+        ));
+        assert_err!(SubscriberFilter::parse(None, None, None, Some("yesterday")));
+    }
+
+    #[test]
+    fn searches_match_wildcards_literally() {
+        let filter = SubscriberFilter::parse(Some(r"50%_off\"), None, None, None).unwrap();
+
+        assert_eq!(filter.search_pattern().unwrap(), r"50\%\_off\\%");
+    }
+
+    #[test]
+    fn cursors_read_back_the_same() {
+        let cursor = Cursor {
+            subscribed_at: DateTime::from_timestamp_micros(1_717_171_717_123_456).unwrap(),
+            id: Uuid::new_v4(),
+        };
+
+        assert_eq!(Cursor::parse(&cursor.to_string()), Ok(cursor));
+        assert_err!(Cursor::parse("yesterday"));
+        assert_err!(Cursor::parse("123_not-a-uuid"));
+    }
+}
@@ -0,0 +1,300 @@
+//! src/suppressions.rs
+
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+use crate::domain::SubscriberEmail;
+
+/// Why an address ended up on the suppression list.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SuppressionReason {
+    HardBounce,
+    Complaint,
+    Manual,
+    Unsubscribe,
+}
+
+impl SuppressionReason {
+    pub const ALL: [Self; 4] = [
+        Self::HardBounce,
+        Self::Complaint,
+        Self::Manual,
+        Self::Unsubscribe,
+    ];
+
+    /// Accepts the stored form along with the spellings other tools export,
+    /// e.g. `Hard bounce` or `spam-complaint`.
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let normalized = input.trim().to_lowercase().replace([' ', '-'], "_");
+
+        match normalized.as_str() {
+            "hard_bounce" | "hardbounce" | "bounce" | "bounced" => Ok(Self::HardBounce),
+            "complaint" | "spam_complaint" | "spam" => Ok(Self::Complaint),
+            "manual" => Ok(Self::Manual),
+            "unsubscribe" | "unsubscribed" => Ok(Self::Unsubscribe),
+            _ => Err(format!(
+                "{} is not a known suppression reason.",
+                input.trim()
+            )),
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::HardBounce => "Hard bounce",
+            Self::Complaint => "Spam complaint",
+            Self::Manual => "Added by an admin",
+            Self::Unsubscribe => "Unsubscribed",
+        }
+    }
+
+    /// Whether the address is off-limits even for a confirmation email.
+    ///
+    /// Someone who unsubscribed may sign up again: the double opt-in that
+    /// follows lifts their suppression.
+    pub fn blocks_all_email(&self) -> bool {
+        !matches!(self, Self::Unsubscribe)
+    }
+}
+
+impl AsRef<str> for SuppressionReason {
+    fn as_ref(&self) -> &str {
+        match self {
+            Self::HardBounce => "hard_bounce",
+            Self::Complaint => "complaint",
+            Self::Manual => "manual",
+            Self::Unsubscribe => "unsubscribe",
+        }
+    }
+}
+
+pub struct Suppression {
+    pub email: String,
+    pub reason: SuppressionReason,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Put `email` on the suppression list.
+///
+/// An unsubscribe is upgraded to a stronger reason, never the other way
+/// round. Returns `false` if the list already had the address.
+#[tracing::instrument(name = "Suppress an address", skip(executor, email))]
+pub async fn suppress(
+    executor: impl PgExecutor<'_>,
+    email: &SubscriberEmail,
+    reason: SuppressionReason,
+) -> Result<bool, sqlx::Error> {
+    let n_affected = sqlx::query!(
+        r#"
+        INSERT INTO suppressions (email_canonical, email, reason, created_at)
+        VALUES ($1, $2, $3, now())
+        ON CONFLICT (email_canonical) DO UPDATE
+        SET reason = EXCLUDED.reason, created_at = EXCLUDED.created_at
+        WHERE suppressions.reason = 'unsubscribe' AND EXCLUDED.reason <> 'unsubscribe'
+        "#,
+        email.canonical(),
+        email.as_ref(),
+        reason.as_ref()
+    )
+    .execute(executor)
+    .await?
+    .rows_affected();
+
+    Ok(n_affected > 0)
+}
+
+/// Suppress many addresses in one statement, with the same rules as
+/// [`suppress`]. Addresses must not repeat. Returns how many were added.
+#[tracing::instrument(name = "Suppress addresses in bulk", skip_all)]
+pub async fn suppress_all(
+    executor: impl PgExecutor<'_>,
+    entries: &[(SubscriberEmail, SuppressionReason)],
+) -> Result<u64, sqlx::Error> {
+    let (canonical, (emails, reasons)): (Vec<_>, (Vec<_>, Vec<_>)) = entries
+        .iter()
+        .map(|(email, reason)| {
+            (
+                email.canonical(),
+                (email.as_ref().to_owned(), reason.as_ref().to_owned()),
+            )
+        })
+        .unzip();
+
+    let n_affected = sqlx::query!(
+        r#"
+        INSERT INTO suppressions (email_canonical, email, reason, created_at)
+        SELECT *, now()
+        FROM UNNEST($1::TEXT[], $2::TEXT[], $3::TEXT[])
+        ON CONFLICT (email_canonical) DO UPDATE
+        SET reason = EXCLUDED.reason, created_at = EXCLUDED.created_at
+        WHERE suppressions.reason = 'unsubscribe' AND EXCLUDED.reason <> 'unsubscribe'
+        "#,
+        &canonical,
+        &emails,
+        &reasons
+    )
+    .execute(executor)
+    .await?
+    .rows_affected();
+
+    Ok(n_affected)
+}
+
+/// Suppress the address a subscriber is currently using.
+#[tracing::instrument(name = "Suppress a subscriber", skip(executor))]
+pub async fn suppress_subscriber(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+    reason: SuppressionReason,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO suppressions (email_canonical, email, reason, created_at)
+        SELECT email_canonical, email, $2, now()
+        FROM subscriptions
+        WHERE id = $1
+        ON CONFLICT (email_canonical) DO UPDATE
+        SET reason = EXCLUDED.reason, created_at = EXCLUDED.created_at
+        WHERE suppressions.reason = 'unsubscribe' AND EXCLUDED.reason <> 'unsubscribe'
+        "#,
+        subscriber_id,
+        reason.as_ref()
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Forget a subscriber's unsubscribe once they opted back in.
+#[tracing::instrument(name = "Lift an unsubscribe suppression", skip(executor))]
+pub async fn lift_unsubscribe_suppression(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM suppressions
+        USING subscriptions
+        WHERE subscriptions.id = $1
+        AND suppressions.email_canonical = subscriptions.email_canonical
+        AND suppressions.reason = 'unsubscribe'
+        "#,
+        subscriber_id
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(name = "Get the suppression of an address", skip(executor, email))]
+pub async fn get_suppression_reason(
+    executor: impl PgExecutor<'_>,
+    email: &SubscriberEmail,
+) -> Result<Option<SuppressionReason>, anyhow::Error> {
+    let reason = sqlx::query_scalar!(
+        r#"
+        SELECT reason
+        FROM suppressions
+        WHERE email_canonical = $1
+        "#,
+        email.canonical()
+    )
+    .fetch_optional(executor)
+    .await?;
+
+    reason
+        .map(|reason| SuppressionReason::parse(&reason).map_err(anyhow::Error::msg))
+        .transpose()
+}
+
+/// Whether `email` must not receive anything, confirmation emails included.
+pub async fn blocks_all_email(
+    executor: impl PgExecutor<'_>,
+    email: &SubscriberEmail,
+) -> Result<bool, anyhow::Error> {
+    let reason = get_suppression_reason(executor, email).await?;
+
+    Ok(reason.is_some_and(|reason| reason.blocks_all_email()))
+}
+
+/// Returns `false` if the address was not suppressed.
+#[tracing::instrument(name = "Remove a suppression", skip(pool, email))]
+pub async fn unsuppress(pool: &PgPool, email: &SubscriberEmail) -> Result<bool, sqlx::Error> {
+    let n_deleted = sqlx::query!(
+        r#"
+        DELETE FROM suppressions
+        WHERE email_canonical = $1
+        "#,
+        email.canonical()
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    Ok(n_deleted > 0)
+}
+
+/// The whole list, most recent first.
+#[tracing::instrument(name = "Get suppressions", skip(pool))]
+pub async fn get_suppressions(pool: &PgPool) -> Result<Vec<Suppression>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT email, reason, created_at
+        FROM suppressions
+        ORDER BY created_at DESC, email
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    rows.into_iter()
+        .map(|row| {
+            Ok(Suppression {
+                email: row.email,
+                reason: SuppressionReason::parse(&row.reason).map_err(anyhow::Error::msg)?,
+                created_at: row.created_at,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SuppressionReason;
+    use claims::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn every_reason_parses_back_from_its_stored_form() {
+        for reason in SuppressionReason::ALL {
+            assert_ok_eq!(SuppressionReason::parse(reason.as_ref()), reason);
+        }
+    }
+
+    #[test]
+    fn other_tools_spellings_are_understood() {
+        assert_ok_eq!(
+            SuppressionReason::parse(" Hard bounce "),
+            SuppressionReason::HardBounce
+        );
+        assert_ok_eq!(
+            SuppressionReason::parse("Spam-Complaint"),
+            SuppressionReason::Complaint
+        );
+        assert_ok_eq!(
+            SuppressionReason::parse("unsubscribed"),
+            SuppressionReason::Unsubscribe
+        );
+        assert_err!(SuppressionReason::parse("soft bounce"));
+    }
+
+    #[test]
+    fn only_an_unsubscribe_lets_confirmation_emails_through() {
+        assert!(SuppressionReason::HardBounce.blocks_all_email());
+        assert!(SuppressionReason::Complaint.blocks_all_email());
+        assert!(SuppressionReason::Manual.blocks_all_email());
+        assert!(!SuppressionReason::Unsubscribe.blocks_all_email());
+    }
+}
@@ -0,0 +1,80 @@
+//! src/upload.rs
+//!
+//! The `multipart/form-data` forms admins upload files through.
+
+use std::collections::HashMap;
+
+use actix_multipart::{Field, Multipart, MultipartError};
+use actix_web::web::Bytes;
+use futures::StreamExt;
+
+/// Uploads are buffered whole, so their size is capped.
+pub const MAX_UPLOAD_SIZE: usize = 10 * 1024 * 1024;
+
+/// Text fields read alongside a streamed file are short.
+const MAX_TEXT_FIELD_SIZE: usize = 1024;
+
+/// The fields of a submitted form, files included, by name.
+pub struct UploadForm {
+    fields: HashMap<String, Bytes>,
+}
+
+impl UploadForm {
+    /// Read every field of the form, up to `MAX_UPLOAD_SIZE` bytes in all.
+    pub async fn read(mut payload: Multipart) -> Result<Self, String> {
+        let mut fields = HashMap::new();
+        let mut remaining = MAX_UPLOAD_SIZE;
+
+        while let Some(field) = payload.next().await {
+            let mut field = field.map_err(upload_error)?;
+            let data = field
+                .bytes(remaining)
+                .await
+                .map_err(|_| "The upload is too large.".to_string())?
+                .map_err(upload_error)?;
+            remaining -= data.len();
+            if let Some(name) = field.name() {
+                fields.entry(name.to_owned()).or_insert(data);
+            }
+        }
+
+        Ok(Self { fields })
+    }
+
+    /// A text field, lossily decoded.
+    pub fn text(&self, name: &str) -> Option<String> {
+        self.fields
+            .get(name)
+            .map(|data| String::from_utf8_lossy(data).into_owned())
+    }
+
+    /// A file that was chosen, i.e. is not empty.
+    pub fn file(&self, name: &str) -> Option<&[u8]> {
+        self.fields
+            .get(name)
+            .filter(|data| !data.is_empty())
+            .map(|data| data.as_ref())
+    }
+}
+
+/// A text field of a form read a field at a time, lossily decoded.
+pub async fn read_text(field: &mut Field) -> Result<String, String> {
+    let data = field
+        .bytes(MAX_TEXT_FIELD_SIZE)
+        .await
+        .map_err(|_| "A form field is too large.".to_string())?
+        .map_err(upload_error)?;
+
+    Ok(String::from_utf8_lossy(&data).into_owned())
+}
+
+pub fn upload_error(error: MultipartError) -> String {
+    match error {
+        MultipartError::ContentTypeMissing
+        | MultipartError::ContentTypeParse
+        | MultipartError::ContentTypeIncompatible => {
+            "The request is not a multipart/form-data submission.".into()
+        }
+        _ => "The upload is malformed.".into(),
+    }
+}
@@ -1,7 +1,7 @@
 use std::fmt::{Debug, Display};
 
 use actix_web::error::ErrorInternalServerError;
-use actix_web::http::header::LOCATION;
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType, LOCATION};
 use actix_web::HttpResponse;
 
 // Return an opaque 500 while preserving the error root's cause for logging.
@@ -18,6 +18,27 @@ pub fn see_other(location: &str) -> HttpResponse {
         .finish()
 }
 
+// Serve `body` as a JSON file the browser saves rather than displays.
+pub fn json_download<T: serde::Serialize>(body: &T, filename: &str) -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(filename.to_owned())],
+        })
+        .json(body)
+}
+
+// Serve `body` as a CSV file the browser saves rather than displays.
+pub fn csv_download(body: String, filename: &str) -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(filename.to_owned())],
+        })
+        .content_type("text/csv; charset=utf-8")
+        .body(body)
+}
+
 // Return a 400 with the user-representation of the validation error as body.
 // The error root cause is preserved for logging purposes.
 pub fn error_400<T>(e: T) -> actix_web::Error
@@ -26,3 +47,11 @@ where
 {
     actix_web::error::ErrorBadRequest(e)
 }
+
+// A form field, unless left empty: empty fields filter nothing.
+pub fn non_empty(value: &Option<String>) -> Option<&str> {
+    value
+        .as_deref()
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
@@ -0,0 +1,131 @@
+//! tests/api/api_subscriptions.rs
+
+use serde_json::Value;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::TestApp;
+
+async fn assert_is_problem(response: reqwest::Response, status: u16) -> Value {
+    assert_eq!(status, response.status().as_u16());
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/problem+json"
+    );
+
+    let problem: Value = response.json().await.unwrap();
+    assert_eq!(problem["status"], status);
+    assert!(problem["title"].is_string());
+    assert!(problem["detail"].is_string());
+
+    problem
+}
+
+#[tokio::test]
+async fn api_subscribe_accepts_json_and_sends_a_confirmation_email() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let body = serde_json::json!({
+        "name": "le guin",
+        "email": "ursula_le_guin@gmail.com"
+    });
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_api_subscriptions(&body).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+
+    let saved = sqlx::query!("SELECT email, name, status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+
+    assert_eq!(saved.email, "ursula_le_guin@gmail.com");
+    assert_eq!(saved.name, "le guin");
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn api_subscribe_reports_which_field_failed_validation() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let test_cases = vec![
+        (
+            serde_json::json!({"name": "", "email": "ursula_le_guin@gmail.com"}),
+            "name",
+        ),
+        (
+            serde_json::json!({"name": "Ursula", "email": "definitely-not-an-email"}),
+            "email",
+        ),
+    ];
+
+    for (body, field) in test_cases {
+        // Act
+        let response = app.post_api_subscriptions(&body).await;
+
+        // Assert
+        let problem = assert_is_problem(response, 400).await;
+        assert_eq!(
+            problem["field"], field,
+            "The API did not blame `{}` for the payload {}.",
+            field, body
+        );
+    }
+}
+
+#[tokio::test]
+async fn api_subscribe_rejects_malformed_payloads_with_a_problem_document() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let test_cases = vec![
+        (serde_json::json!({"name": "le guin"}), "missing the email"),
+        (
+            serde_json::json!({"email": "ursula_le_guin@gmail.com"}),
+            "missing the name",
+        ),
+        (serde_json::json!([]), "not an object"),
+    ];
+
+    for (body, description) in test_cases {
+        // Act
+        let response = app.post_api_subscriptions(&body).await;
+
+        // Assert
+        let problem = assert_is_problem(response, 400).await;
+        assert!(
+            problem.get("field").is_none(),
+            "Unexpected field for a payload {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn api_subscribe_rejects_form_encoded_bodies_with_a_415() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/api/v1/subscriptions", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_is_problem(response, 415).await;
+}
@@ -0,0 +1,241 @@
+//! tests/api/bot_protection.rs
+
+use chrono::{TimeDelta, Utc};
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+use zero_to_prod::bot_protection::{proof_of_work_challenge, solves_challenge};
+use zero_to_prod::configuration::Settings;
+use zero_to_prod::domain::FormToken;
+
+use crate::helpers::TestApp;
+
+const EMAIL: &str = "ursula_le_guin@gmail.com";
+
+fn require_form_token(
+    min_fill_seconds: i64,
+    proof_of_work_bits: u32,
+) -> impl FnOnce(&mut Settings) {
+    move |config| {
+        let bot_protection = &mut config.application.bot_protection;
+        bot_protection.require_form_token = true;
+        bot_protection.min_fill_seconds = min_fill_seconds;
+        bot_protection.proof_of_work_bits = proof_of_work_bits;
+    }
+}
+
+/// The form token served with the home page.
+async fn get_form_token(app: &TestApp) -> String {
+    let html_page = app
+        .api_client
+        .get(format!("{}/", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .text()
+        .await
+        .unwrap();
+
+    let start = html_page.find(r#"name="form_token" value=""#).unwrap()
+        + r#"name="form_token" value=""#.len();
+    let end = start + html_page[start..].find('"').unwrap();
+    html_page[start..end].to_owned()
+}
+
+fn sign_up_body(extra: &[(&str, &str)]) -> String {
+    let mut fields = vec![("name", "le guin"), ("email", EMAIL)];
+    fields.extend_from_slice(extra);
+
+    serde_urlencoded::to_string(fields).unwrap()
+}
+
+async fn accept_all_emails(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+#[tokio::test]
+async fn every_visit_to_the_home_page_gets_its_own_form_token() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/", &app.address))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.headers()["Cache-Control"], "no-store");
+    let first = get_form_token(&app).await;
+    let second = get_form_token(&app).await;
+    assert_ne!(first, second);
+    FormToken::parse(&first, &app.hmac_secret).unwrap();
+}
+
+#[tokio::test]
+async fn sign_ups_filling_in_the_honeypot_are_dropped_quietly() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions(sign_up_body(&[("website", "https://example.com")]))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.is_empty());
+}
+
+#[tokio::test]
+async fn sign_ups_need_a_form_token_when_required() {
+    // Arrange
+    let app = TestApp::spawn_app_with(require_form_token(0, 0)).await;
+    accept_all_emails(&app).await;
+
+    // Act - Part 1 - Without a token
+    let response = app.post_subscriptions(sign_up_body(&[])).await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    // Act - Part 2 - With the one the form came with
+    let form_token = get_form_token(&app).await;
+    let response = app
+        .post_subscriptions(sign_up_body(&[("form_token", &form_token)]))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.email, EMAIL);
+}
+
+#[tokio::test]
+async fn api_sign_ups_are_held_to_the_same_checks() {
+    // Arrange
+    let app = TestApp::spawn_app_with(require_form_token(0, 0)).await;
+    accept_all_emails(&app).await;
+    let mut body = serde_json::json!({ "name": "le guin", "email": EMAIL });
+
+    // Act - Part 1 - Without a token
+    let response = app.post_api_subscriptions(&body).await;
+    assert_eq!(response.status().as_u16(), 400);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["title"], "Sign-up not verified");
+
+    // Act - Part 2 - Filling in the honeypot
+    body["website"] = "https://example.com".into();
+    let response = app.post_api_subscriptions(&body).await;
+    assert_eq!(response.status().as_u16(), 200);
+    body.as_object_mut().unwrap().remove("website");
+
+    // Act - Part 3 - With a token from the API
+    let token: serde_json::Value = app
+        .api_client
+        .get(format!("{}/api/v1/subscriptions/form-token", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(token["proof_of_work_bits"], 0);
+    body["form_token"] = token["form_token"].clone();
+    let response = app.post_api_subscriptions(&body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 1);
+    let emails = app.email_server.received_requests().await.unwrap();
+    assert_eq!(emails.len(), 1);
+}
+
+#[tokio::test]
+async fn sign_ups_sent_too_quickly_or_too_late_are_rejected() {
+    // Arrange
+    let app = TestApp::spawn_app_with(require_form_token(60, 0)).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let stale = FormToken::generate(Utc::now() - TimeDelta::hours(3), &app.hmac_secret);
+    let test_cases = vec![
+        (get_form_token(&app).await, "sent too quickly"),
+        (stale.to_string(), "has expired"),
+        ("1700000000.nonce.forged".to_string(), "is invalid"),
+    ];
+
+    for (form_token, error_message) in test_cases {
+        // Act
+        let response = app
+            .post_subscriptions(sign_up_body(&[("form_token", &form_token)]))
+            .await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 400);
+        assert!(response.text().await.unwrap().contains(error_message));
+    }
+}
+
+#[tokio::test]
+async fn sign_ups_must_solve_the_proof_of_work() {
+    // Arrange
+    let app = TestApp::spawn_app_with(require_form_token(0, 8)).await;
+    accept_all_emails(&app).await;
+    let form_token = get_form_token(&app).await;
+    let challenge = proof_of_work_challenge(&form_token, EMAIL);
+    let nonce = (0u64..)
+        .map(|nonce| nonce.to_string())
+        .find(|nonce| solves_challenge(&challenge, nonce, 8))
+        .unwrap();
+    let wrong_nonce = (0u64..)
+        .map(|nonce| nonce.to_string())
+        .find(|nonce| !solves_challenge(&challenge, nonce, 8))
+        .unwrap();
+
+    // Act - Part 1 - Without a solution
+    for extra in [
+        vec![("form_token", form_token.as_str())],
+        vec![
+            ("form_token", form_token.as_str()),
+            ("proof_of_work", wrong_nonce.as_str()),
+        ],
+    ] {
+        let response = app.post_subscriptions(sign_up_body(&extra)).await;
+        assert_eq!(response.status().as_u16(), 400);
+    }
+
+    // Act - Part 2 - With one
+    let response = app
+        .post_subscriptions(sign_up_body(&[
+            ("form_token", &form_token),
+            ("proof_of_work", &nonce),
+        ]))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
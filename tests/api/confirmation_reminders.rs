@@ -0,0 +1,273 @@
+//! tests/api/confirmation_reminders.rs
+
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{create_confirmed_subscriber, create_unconfirmed_subscriber, TestApp};
+
+/// Pretend every sign-up, token and reminder happened `hours` earlier than it did.
+async fn travel_forward_in_time(app: &TestApp, hours: i32) {
+    sqlx::query!(
+        "UPDATE subscriptions SET subscribed_at = subscribed_at - make_interval(hours => $1)",
+        hours
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    sqlx::query!(
+        "UPDATE subscription_tokens SET created_at = created_at - make_interval(hours => $1)",
+        hours
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    sqlx::query!(
+        "UPDATE confirmation_reminders SET sent_at = sent_at - make_interval(hours => $1)",
+        hours
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn recent_sign_ups_are_not_reminded() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.dispatch_due_reminders().await;
+
+    // Mock verifies on Drop that no reminder was sent
+}
+
+#[tokio::test]
+async fn stale_pending_subscribers_receive_a_working_confirmation_link() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    travel_forward_in_time(&app, 49).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.dispatch_due_reminders().await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let confirmation_links = app.get_confirmation_links(&email_request);
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert!(body["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains("Manage your preferences: http://"));
+
+    let response = app.post_confirmation(&confirmation_links.html).await;
+    assert_eq!(200, response.status().as_u16());
+
+    let reminders = sqlx::query!("SELECT subscriber_id FROM confirmation_reminders")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(reminders.len(), 1);
+}
+
+#[tokio::test]
+async fn a_reminder_that_fails_to_send_keeps_the_original_link() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    travel_forward_in_time(&app, 49).await;
+    let original_tokens = sqlx::query!("SELECT subscription_token_hash FROM subscription_tokens")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.dispatch_due_reminders().await;
+
+    // Assert
+    let tokens = sqlx::query!("SELECT subscription_token_hash FROM subscription_tokens")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tokens.len(), 1);
+    assert_eq!(
+        tokens[0].subscription_token_hash,
+        original_tokens[0].subscription_token_hash
+    );
+
+    // The attempt still counts towards the maximum
+    let reminders = sqlx::query!("SELECT subscriber_id FROM confirmation_reminders")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(reminders.len(), 1);
+}
+
+#[tokio::test]
+async fn reminders_stop_after_the_configured_maximum() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(app.pending_subscribers.max_reminders as u64)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    for _ in 0..app.pending_subscribers.max_reminders + 2 {
+        travel_forward_in_time(&app, 49).await;
+        app.dispatch_due_reminders().await;
+    }
+
+    // Mock verifies on Drop that we stopped after the last reminder
+}
+
+#[tokio::test]
+async fn confirmed_subscribers_are_not_reminded() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    travel_forward_in_time(&app, 49).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.dispatch_due_reminders().await;
+
+    // Mock verifies on Drop that no reminder was sent
+}
+
+#[tokio::test]
+async fn pending_subscribers_past_the_retention_window_are_purged() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Leave a reminder behind, so we check it is deleted as well
+    travel_forward_in_time(&app, 49).await;
+    app.dispatch_due_reminders().await;
+    let retention_hours = app.pending_subscribers.retention_days as i32 * 24;
+    travel_forward_in_time(&app, retention_hours + 1).await;
+
+    // Act
+    let n_purged = app.purge_stale_pending_subscribers().await;
+
+    // Assert
+    assert_eq!(n_purged, 1);
+
+    let subscribers = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(subscribers.is_empty());
+
+    let tokens = sqlx::query!("SELECT subscriber_id FROM subscription_tokens")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(tokens.is_empty());
+
+    let reminders = sqlx::query!("SELECT subscriber_id FROM confirmation_reminders")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(reminders.is_empty());
+}
+
+#[tokio::test]
+async fn pending_subscribers_within_the_retention_window_are_kept() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    let retention_hours = app.pending_subscribers.retention_days as i32 * 24;
+    travel_forward_in_time(&app, retention_hours - 1).await;
+
+    // Act
+    let n_purged = app.purge_stale_pending_subscribers().await;
+
+    // Assert
+    assert_eq!(n_purged, 0);
+}
+
+#[tokio::test]
+async fn signing_up_again_restarts_the_retention_window() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    app.post_subscriptions(body.into()).await;
+    let retention_hours = app.pending_subscribers.retention_days as i32 * 24;
+    travel_forward_in_time(&app, retention_hours + 1).await;
+    app.post_subscriptions(body.into()).await;
+
+    // Act
+    let n_purged = app.purge_stale_pending_subscribers().await;
+
+    // Assert
+    assert_eq!(n_purged, 0);
+}
+
+#[tokio::test]
+async fn confirmed_subscribers_are_never_purged() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let retention_hours = app.pending_subscribers.retention_days as i32 * 24;
+    travel_forward_in_time(&app, retention_hours + 1).await;
+
+    // Act
+    let n_purged = app.purge_stale_pending_subscribers().await;
+
+    // Assert
+    assert_eq!(n_purged, 0);
+}
@@ -0,0 +1,310 @@
+//! tests/api/consent_records.rs
+
+use reqwest::Response;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero_to_prod::consent::CONFIRMATION_FORM_VERSION;
+
+use crate::helpers::{create_unconfirmed_subscriber, TestApp};
+
+const USER_AGENT: &str = "Mozilla/5.0 (X11; Linux x86_64) Firefox/132.0";
+
+async fn post_sign_up(app: &TestApp, forwarded_for: Option<&str>) -> Response {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let mut request = app
+        .api_client
+        .post(format!("{}/subscriptions", &app.address))
+        .header("User-Agent", USER_AGENT)
+        .form(&[
+            ("name", "le guin"),
+            ("email", "ursula_le_guin@gmail.com"),
+            ("form_version", "home-page-v1"),
+        ]);
+    if let Some(forwarded_for) = forwarded_for {
+        request = request.header("X-Forwarded-For", forwarded_for);
+    }
+
+    request.send().await.expect("Failed to execute request.")
+}
+
+struct SavedConsent {
+    event: String,
+    ip_address: Option<String>,
+    user_agent: Option<String>,
+    form_version: Option<String>,
+    subscription_token_hash: Option<String>,
+}
+
+async fn get_consent_records(app: &TestApp) -> Vec<SavedConsent> {
+    sqlx::query_as!(
+        SavedConsent,
+        r#"
+        SELECT event, ip_address, user_agent, form_version, subscription_token_hash
+        FROM consent_records
+        ORDER BY recorded_at
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn subscribe_records_the_consent_given_at_sign_up() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let response = post_sign_up(&app, None).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+
+    let token_hash = sqlx::query!("SELECT subscription_token_hash FROM subscription_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .subscription_token_hash;
+
+    let records = get_consent_records(&app).await;
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].event, "sign_up");
+    assert_eq!(records[0].ip_address.as_deref(), Some("127.0.0.1"));
+    assert_eq!(records[0].user_agent.as_deref(), Some(USER_AGENT));
+    assert_eq!(records[0].form_version.as_deref(), Some("home-page-v1"));
+    assert_eq!(records[0].subscription_token_hash, Some(token_hash));
+}
+
+#[tokio::test]
+async fn form_versions_sent_by_clients_are_bounded() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let form_version = format!(" app-v2\n{}", "x".repeat(10_000));
+
+    // Act
+    app.api_client
+        .post(format!("{}/subscriptions", &app.address))
+        .form(&[
+            ("name", "le guin"),
+            ("email", "ursula_le_guin@gmail.com"),
+            ("form_version", form_version.as_str()),
+        ])
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let records = get_consent_records(&app).await;
+    assert_eq!(
+        records[0].form_version,
+        Some(format!("app-v2{}", "x".repeat(94)))
+    );
+}
+
+#[tokio::test]
+async fn confirm_records_the_consent_given_with_the_same_token() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+
+    let mut fields: Vec<(String, String)> = confirmation_links
+        .html
+        .query_pairs()
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect();
+    fields.push(("form_version".into(), CONFIRMATION_FORM_VERSION.into()));
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/subscriptions/confirm", &app.address))
+        .header("User-Agent", USER_AGENT)
+        .form(&fields)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+
+    let records = get_consent_records(&app).await;
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].event, "sign_up");
+    assert_eq!(records[1].event, "confirmation");
+    assert_eq!(records[1].ip_address.as_deref(), Some("127.0.0.1"));
+    assert_eq!(records[1].user_agent.as_deref(), Some(USER_AGENT));
+    assert_eq!(
+        records[1].form_version.as_deref(),
+        Some(CONFIRMATION_FORM_VERSION)
+    );
+    assert!(records[1].subscription_token_hash.is_some());
+    assert_eq!(
+        records[1].subscription_token_hash,
+        records[0].subscription_token_hash
+    );
+}
+
+#[tokio::test]
+async fn the_confirmation_page_submits_its_form_version() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+
+    // Act
+    let html = reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(html.contains(&format!(
+        r#"name="form_version" value="{}""#,
+        CONFIRMATION_FORM_VERSION
+    )));
+}
+
+#[tokio::test]
+async fn forwarded_for_is_ignored_when_no_proxy_is_trusted() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    post_sign_up(&app, Some("203.0.113.7")).await;
+
+    // Assert
+    let records = get_consent_records(&app).await;
+    assert_eq!(records[0].ip_address.as_deref(), Some("127.0.0.1"));
+}
+
+#[tokio::test]
+async fn forwarded_for_is_honoured_behind_a_trusted_proxy() {
+    // Arrange
+    let app = TestApp::spawn_app_with(|config| {
+        config.application.trusted_proxies = vec!["127.0.0.1".parse().unwrap()];
+    })
+    .await;
+
+    // Act
+    post_sign_up(&app, Some("198.51.100.4, 203.0.113.7")).await;
+
+    // Assert
+    let records = get_consent_records(&app).await;
+    assert_eq!(records[0].ip_address.as_deref(), Some("203.0.113.7"));
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_a_subscriber() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!(
+            "{}/admin/subscribers?email=ursula_le_guin@gmail.com",
+            &app.address
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    TestApp::assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn the_admin_subscriber_view_shows_the_consent_trail() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    post_sign_up(&app, None).await;
+    app.test_user.login(&app).await;
+
+    // Act - Part 1 - Look the subscriber up
+    let response = app
+        .api_client
+        .get(format!(
+            "{}/admin/subscribers?email=ursula_le_guin@gmail.com",
+            &app.address
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+    let location = format!("/admin/subscribers/{}", subscriber_id);
+    TestApp::assert_is_redirect_to(&response, &location);
+
+    // Act - Part 2 - Follow the redirect
+    let html = app
+        .api_client
+        .get(format!("{}{}", &app.address, location))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(html.contains("ursula_le_guin@gmail.com"));
+    assert!(html.contains("<td>sign_up</td>"));
+    assert!(html.contains("<td>127.0.0.1</td>"));
+    assert!(html.contains("<td>home-page-v1</td>"));
+    assert!(html.contains(USER_AGENT));
+}
+
+#[tokio::test]
+async fn looking_up_an_unknown_subscriber_says_so() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act - Part 1 - Look up an address nobody signed up with
+    let response = app
+        .api_client
+        .get(format!(
+            "{}/admin/subscribers?email=nobody@example.com",
+            &app.address
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    TestApp::assert_is_redirect_to(&response, "/admin/subscribers");
+
+    // Act - Part 2 - Follow the redirect
+    let html = app
+        .api_client
+        .get(format!("{}/admin/subscribers", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(html.contains("There is no subscriber with the email nobody@example.com."));
+}
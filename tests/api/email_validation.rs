@@ -0,0 +1,291 @@
+//! tests/api/email_validation.rs
+
+use std::sync::Arc;
+
+use reqwest::Response;
+use serde_json::Value;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero_to_prod::email_validation::MxResolver;
+
+use crate::helpers::TestApp;
+
+/// Answers MX lookups from a fixed table instead of the DNS.
+struct FakeMxResolver {
+    domains_accepting_mail: Vec<&'static str>,
+    unreachable: bool,
+}
+
+#[async_trait::async_trait]
+impl MxResolver for FakeMxResolver {
+    async fn accepts_mail(&self, domain: &str) -> Result<bool, anyhow::Error> {
+        anyhow::ensure!(!self.unreachable, "The nameserver is unreachable.");
+        Ok(self.domains_accepting_mail.contains(&domain))
+    }
+}
+
+async fn spawn_app_with_mx_lookups(resolver: FakeMxResolver) -> TestApp {
+    TestApp::spawn_app_with_mx_resolver(
+        |config| config.email_validation.check_mx_records = true,
+        Arc::new(resolver),
+    )
+    .await
+}
+
+async fn sign_up(app: &TestApp, email: &str) -> Response {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let body = serde_urlencoded::to_string([("name", "le guin"), ("email", email)]).unwrap();
+
+    app.post_subscriptions(body).await
+}
+
+async fn post_domain_rule(app: &TestApp, domain: &str, rule: &str) -> Response {
+    app.api_client
+        .post(format!("{}/admin/email-domains", &app.address))
+        .form(&[("domain", domain), ("rule", rule)])
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn get_email_domains_html(app: &TestApp) -> String {
+    app.api_client
+        .get(format!("{}/admin/email-domains", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .text()
+        .await
+        .unwrap()
+}
+
+async fn count_subscribers(app: &TestApp) -> i64 {
+    sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn disposable_addresses_are_rejected() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let response = sign_up(&app, "ursula@mailinator.com").await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+    assert!(response.text().await.unwrap().contains("disposable"));
+    assert_eq!(count_subscribers(&app).await, 0);
+}
+
+#[tokio::test]
+async fn a_likely_typo_is_rejected_with_a_suggestion() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let response = sign_up(&app, "ursula@gmial.com").await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("Did you mean ursula@gmail.com?"));
+    assert_eq!(count_subscribers(&app).await, 0);
+}
+
+#[tokio::test]
+async fn real_domains_close_to_popular_ones_are_not_taken_for_typos() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    for email in ["ursula@ymail.com", "ged@email.com"] {
+        // Act
+        let response = sign_up(&app, email).await;
+
+        // Assert
+        assert_eq!(200, response.status().as_u16(), "{email} was rejected");
+    }
+    assert_eq!(count_subscribers(&app).await, 2);
+}
+
+#[tokio::test]
+async fn the_api_blames_rejected_addresses_on_the_email_field() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let body = serde_json::json!({
+        "name": "le guin",
+        "email": "ursula@gmial.com"
+    });
+
+    // Act
+    let response = app.post_api_subscriptions(&body).await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+    let problem: Value = response.json().await.unwrap();
+    assert_eq!(problem["field"], "email");
+    assert!(problem["detail"]
+        .as_str()
+        .unwrap()
+        .contains("Did you mean ursula@gmail.com?"));
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_email_domains() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let response = post_domain_rule(&app, "example.com", "deny").await;
+
+    // Assert
+    TestApp::assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn a_denied_domain_and_its_subdomains_cannot_sign_up() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act - Part 1 - Deny the domain
+    let response = post_domain_rule(&app, " Example.COM ", "deny").await;
+    TestApp::assert_is_redirect_to(&response, "/admin/email-domains");
+
+    // Act - Part 2 - Follow the redirect
+    let html = get_email_domains_html(&app).await;
+    assert!(html.contains("Sign-ups from example.com are now denied."));
+    assert!(html.contains("<td>example.com</td>"));
+
+    // Act - Part 3 - Sign up from the domain
+    let response = sign_up(&app, "ursula@mail.example.com").await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("We do not accept sign-ups from mail.example.com."));
+    assert_eq!(count_subscribers(&app).await, 0);
+}
+
+#[tokio::test]
+async fn an_allowed_domain_skips_the_automatic_checks() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act - Part 1 - Allow a disposable provider
+    let response = post_domain_rule(&app, "mailinator.com", "allow").await;
+    TestApp::assert_is_redirect_to(&response, "/admin/email-domains");
+
+    // Act - Part 2 - Sign up from it
+    let response = sign_up(&app, "ursula@mailinator.com").await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(count_subscribers(&app).await, 1);
+}
+
+#[tokio::test]
+async fn removing_a_rule_restores_the_automatic_checks() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+    post_domain_rule(&app, "mailinator.com", "allow").await;
+
+    // Act - Part 1 - Remove the rule
+    let response = app
+        .api_client
+        .post(format!("{}/admin/email-domains/remove", &app.address))
+        .form(&[("domain", "mailinator.com")])
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    TestApp::assert_is_redirect_to(&response, "/admin/email-domains");
+
+    // Act - Part 2 - Follow the redirect
+    let html = get_email_domains_html(&app).await;
+    assert!(html.contains("The rule for mailinator.com has been removed."));
+
+    // Act - Part 3 - Sign up from the domain
+    let response = sign_up(&app, "ursula@mailinator.com").await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn an_invalid_domain_rule_is_not_saved() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act - Part 1 - Submit something that is not a domain
+    let response = post_domain_rule(&app, "localhost", "deny").await;
+    TestApp::assert_is_redirect_to(&response, "/admin/email-domains");
+
+    // Act - Part 2 - Follow the redirect
+    let html = get_email_domains_html(&app).await;
+
+    // Assert
+    assert!(html.contains("localhost is not a valid domain."));
+    let n_rules = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM email_domain_rules"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(n_rules, 0);
+}
+
+#[tokio::test]
+async fn domains_without_a_mail_exchanger_are_rejected_when_mx_lookups_are_on() {
+    // Arrange
+    let app = spawn_app_with_mx_lookups(FakeMxResolver {
+        domains_accepting_mail: vec!["gmail.com"],
+        unreachable: false,
+    })
+    .await;
+
+    // Act
+    let rejected = sign_up(&app, "ursula@no-mail.example.com").await;
+    let accepted = sign_up(&app, "ursula_le_guin@gmail.com").await;
+
+    // Assert
+    assert_eq!(400, rejected.status().as_u16());
+    assert!(rejected
+        .text()
+        .await
+        .unwrap()
+        .contains("no-mail.example.com does not accept email."));
+    assert_eq!(200, accepted.status().as_u16());
+    assert_eq!(count_subscribers(&app).await, 1);
+}
+
+#[tokio::test]
+async fn a_failed_mx_lookup_lets_the_address_through() {
+    // Arrange
+    let app = spawn_app_with_mx_lookups(FakeMxResolver {
+        domains_accepting_mail: vec![],
+        unreachable: true,
+    })
+    .await;
+
+    // Act
+    let response = sign_up(&app, "ursula_le_guin@gmail.com").await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+}
@@ -1,19 +1,27 @@
 //! tests/api/helpers.rs
 
+use fake::faker::internet::en::SafeEmail;
+use fake::faker::name::en::Name;
+use fake::Fake;
 use linkify::{LinkFinder, LinkKind};
 use redact::Secret;
 use reqwest::{Client, Response, Url};
 use serde_aux::prelude::bool_true;
 use serde_json::Value;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
-use std::sync::LazyLock;
+use std::sync::{Arc, LazyLock};
 use uuid::Uuid;
-use wiremock::MockServer;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
 use zero_to_prod::{
-    configuration::{Configuration, DatabaseSettings},
-    email_client::EmailClient,
+    configuration::{Configuration, DatabaseSettings, PendingSubscriberSettings, Settings},
+    email_client::{EmailClient, PostmarkWebhookCredentials},
+    email_validation::MxResolver,
     issue_delivery_worker::{try_execute_task, ExecutionOutcome},
-    startup::Application,
+    pending_subscriber_worker::{
+        purge_stale_pending_subscribers, try_send_queued_confirmation, try_send_reminder,
+    },
+    startup::{Application, ApplicationBaseUrl, HmacSecret},
     telemetry::Telemetry,
 };
 
@@ -45,10 +53,34 @@ pub struct TestApp {
     pub test_user: TestUser,
     pub api_client: Client,
     pub email_client: EmailClient,
+    pub base_url: ApplicationBaseUrl,
+    pub hmac_secret: HmacSecret,
+    pub pending_subscribers: PendingSubscriberSettings,
+    pub postmark_webhook: PostmarkWebhookCredentials,
 }
 
 impl TestApp {
     pub async fn spawn_app() -> TestApp {
+        TestApp::spawn_app_with(|_| {}).await
+    }
+
+    /// Spawn the application after letting the test tweak its configuration.
+    pub async fn spawn_app_with(customise: impl FnOnce(&mut Settings)) -> TestApp {
+        TestApp::spawn(customise, None).await
+    }
+
+    /// Spawn the application with MX lookups answered by `mx_resolver`.
+    pub async fn spawn_app_with_mx_resolver(
+        customise: impl FnOnce(&mut Settings),
+        mx_resolver: Arc<dyn MxResolver>,
+    ) -> TestApp {
+        TestApp::spawn(customise, Some(mx_resolver)).await
+    }
+
+    async fn spawn(
+        customise: impl FnOnce(&mut Settings),
+        mx_resolver: Option<Arc<dyn MxResolver>>,
+    ) -> TestApp {
         // The first time `initialize` is invoked the code in `TRACING` is executed.
         // All other invocations will instead skip execution.
         LazyLock::force(&TRACING);
@@ -64,6 +96,11 @@ impl TestApp {
             config.database.database_name = Uuid::new_v4().to_string();
             config.application.port = 0;
             config.email_client.base_url = email_server.uri();
+            // Every test app counts requests from 127.0.0.1, under its own keys
+            config.application.rate_limits.key_prefix = Uuid::new_v4().to_string();
+            // Tests post the form directly rather than loading it first
+            config.application.bot_protection.require_form_token = false;
+            customise(&mut config);
 
             config
         };
@@ -72,14 +109,20 @@ impl TestApp {
         let connection_pool = TestApp::configure_database(&configuration.database).await;
 
         // Launch the application as a background task
-        let application = Application::build(configuration.clone(), connection_pool.clone())
-            .await
-            .expect("Failed to build application");
+        let mx_resolver =
+            mx_resolver.unwrap_or_else(|| Arc::new(configuration.email_validation.mx_resolver()));
+        let application = Application::build_with_mx_resolver(
+            configuration.clone(),
+            connection_pool.clone(),
+            mx_resolver,
+        )
+        .await
+        .expect("Failed to build application");
 
         let application_port = application.port();
 
         let address = format!("http://127.0.0.1:{}", application_port);
-        let _ = tokio::spawn(application.run_until_stopped());
+        tokio::spawn(application.run_until_stopped());
 
         let client = Client::builder()
             .redirect(reqwest::redirect::Policy::none())
@@ -94,7 +137,11 @@ impl TestApp {
             email_server,
             test_user: TestUser::generate(),
             api_client: client,
+            postmark_webhook: configuration.email_client.webhook_credentials(),
             email_client: configuration.email_client.client(),
+            base_url: ApplicationBaseUrl(configuration.application.base_url),
+            hmac_secret: HmacSecret(configuration.application.hmac_secret),
+            pending_subscribers: configuration.pending_subscribers,
         };
 
         test_app.test_user.store(&test_app.db_pool).await;
@@ -137,19 +184,71 @@ impl TestApp {
 
     pub async fn dispatch_all_pending_emails(&self) {
         loop {
-            if let ExecutionOutcome::EmptyQueue =
-                try_execute_task(&self.db_pool, &self.email_client)
-                    .await
-                    .unwrap()
+            if let ExecutionOutcome::EmptyQueue = try_execute_task(
+                &self.db_pool,
+                &self.email_client,
+                &self.base_url,
+                &self.hmac_secret,
+            )
+            .await
+            .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    pub async fn dispatch_queued_confirmations(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_send_queued_confirmation(
+                &self.db_pool,
+                &self.email_client,
+                &self.base_url,
+                &self.hmac_secret,
+            )
+            .await
+            .unwrap()
             {
                 break;
             }
         }
     }
 
+    pub async fn dispatch_due_reminders(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_send_reminder(
+                &self.db_pool,
+                &self.email_client,
+                &self.base_url,
+                &self.hmac_secret,
+                &self.pending_subscribers,
+            )
+            .await
+            .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    pub async fn purge_stale_pending_subscribers(&self) -> u64 {
+        purge_stale_pending_subscribers(&self.db_pool, &self.pending_subscribers)
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_api_subscriptions(&self, body: &Value) -> Response {
+        self.api_client
+            .post(format!("{}/api/v1/subscriptions", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn post_subscriptions(&self, body: String) -> Response {
         self.api_client
-            .post(&format!("{}/subscriptions", &self.address))
+            .post(format!("{}/subscriptions", &self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
@@ -157,14 +256,60 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    pub async fn post_resend_confirmation(&self, body: String) -> Response {
+        self.api_client
+            .post(format!(
+                "{}/subscriptions/resend-confirmation",
+                &self.address
+            ))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Call the Postmark webhook the way Postmark does, with basic auth.
+    pub async fn post_postmark_webhook(&self, body: &str) -> Response {
+        self.api_client
+            .post(format!("{}/webhooks/postmark", &self.address))
+            .basic_auth(
+                &self.postmark_webhook.username,
+                Some(self.postmark_webhook.secret.expose_secret()),
+            )
+            .header("Content-Type", "application/json")
+            .body(body.to_owned())
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Submit the form rendered behind a confirmation link.
+    pub async fn post_confirmation(&self, confirmation_link: &Url) -> Response {
+        // Submit the same fields the confirmation page would.
+        let fields: Vec<(String, String)> = confirmation_link
+            .query_pairs()
+            .map(|(key, value)| (key.into_owned(), value.into_owned()))
+            .collect();
+
+        self.api_client
+            .post(format!("{}/subscriptions/confirm", &self.address))
+            .form(&fields)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
         let body: Value = serde_json::from_slice(&email_request.body).unwrap();
 
-        // Extract the link from one of the request fields.
+        // Extract the link from one of the request fields, skipping the
+        // preference center link in the footer.
         let get_link = |input: &str| {
             let links: Vec<_> = LinkFinder::new()
                 .links(input)
                 .filter(|value| *value.kind() == LinkKind::Url)
+                .filter(|value| !value.as_str().contains("/subscriptions/manage?"))
                 .collect();
 
             assert_eq!(links.len(), 1);
@@ -179,8 +324,8 @@ impl TestApp {
             confirmation_link
         };
 
-        let html = get_link(&body["HtmlBody"].as_str().unwrap());
-        let plain_text = get_link(&body["TextBody"].as_str().unwrap());
+        let html = get_link(body["HtmlBody"].as_str().unwrap());
+        let plain_text = get_link(body["TextBody"].as_str().unwrap());
 
         ConfirmationLinks { html, plain_text }
     }
@@ -190,7 +335,7 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/login", &self.address))
+            .post(format!("{}/login", &self.address))
             .form(body)
             .send()
             .await
@@ -205,7 +350,7 @@ impl TestApp {
     pub async fn get_login_html(&self) -> String {
         let response = self
             .api_client
-            .get(&format!("{}/login", &self.address))
+            .get(format!("{}/login", &self.address))
             .send()
             .await
             .expect("Failed to execute request.");
@@ -224,7 +369,7 @@ impl TestApp {
 
     pub async fn get_admin_dashboard(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/admin/dashboard", &self.address))
+            .get(format!("{}/admin/dashboard", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -232,7 +377,7 @@ impl TestApp {
 
     pub async fn get_change_password(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/admin/password", &self.address))
+            .get(format!("{}/admin/password", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -243,7 +388,7 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/admin/password", &self.address))
+            .post(format!("{}/admin/password", &self.address))
             .form(body)
             .send()
             .await
@@ -256,7 +401,7 @@ impl TestApp {
 
     pub async fn post_logout(&self) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/admin/logout", &self.address))
+            .post(format!("{}/admin/logout", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -267,7 +412,7 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/admin/newsletters", &self.address))
+            .post(format!("{}/admin/newsletters", &self.address))
             .form(body)
             .send()
             .await
@@ -276,7 +421,7 @@ impl TestApp {
 
     pub async fn get_publish_newsletter(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/admin/newsletters", &self.address))
+            .get(format!("{}/admin/newsletters", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -286,3 +431,45 @@ impl TestApp {
         self.get_publish_newsletter().await.text().await.unwrap()
     }
 }
+
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+    let name: String = Name().fake();
+    let email: String = SafeEmail().fake();
+    let body = serde_urlencoded::to_string(serde_json::json!({
+        "name": name,
+        "email": email
+    }))
+    .unwrap();
+
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create unconfirmed subscriber")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+
+    app.get_confirmation_links(email_request)
+}
+
+pub async fn create_confirmed_subscriber(app: &TestApp) -> () {
+    let confirmation_link = create_unconfirmed_subscriber(app).await;
+
+    app.post_confirmation(&confirmation_link.html)
+        .await
+        .error_for_status()
+        .unwrap();
+}
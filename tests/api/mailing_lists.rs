@@ -0,0 +1,229 @@
+//! tests/api/mailing_lists.rs
+
+use uuid::Uuid;
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{create_confirmed_subscriber, TestApp};
+
+async fn create_list(app: &TestApp, slug: &str, name: &str) -> Uuid {
+    let list_id = Uuid::new_v4();
+
+    sqlx::query!(
+        "INSERT INTO lists (list_id, slug, name) VALUES ($1, $2, $3)",
+        list_id,
+        slug,
+        name
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    list_id
+}
+
+fn newsletter_request_body(list: &str) -> serde_json::Value {
+    serde_json::json!({
+        "list": list,
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": Uuid::new_v4().to_string()
+    })
+}
+
+#[tokio::test]
+async fn subscribing_to_an_unknown_list_is_rejected_with_a_400() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com&list=does-not-exist";
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn each_list_is_confirmed_separately() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let weekly_id = create_list(&app, "weekly", "The Weekly Digest").await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    app.post_subscriptions(body.into()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let newsletter_links = app.get_confirmation_links(email_request);
+    app.post_confirmation(&newsletter_links.html)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act - Being confirmed on one list must not skip confirming the next
+    app.post_subscriptions(format!("{}&list=weekly", body))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let membership = sqlx::query!(
+        "SELECT status FROM list_memberships WHERE list_id = $1",
+        weekly_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(membership.status, "pending_confirmation");
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let weekly_links = app.get_confirmation_links(email_request);
+    let response = app.post_confirmation(&weekly_links.html).await;
+    assert_eq!(200, response.status().as_u16());
+
+    let membership = sqlx::query!(
+        "SELECT status FROM list_memberships WHERE list_id = $1",
+        weekly_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(membership.status, "confirmed");
+}
+
+#[tokio::test]
+async fn list_names_are_escaped_in_confirmation_emails_and_pages() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_list(&app, "tips", "Tips & <b>Tricks</b>").await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com&list=tips".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert!(body["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .contains("Tips &amp; &lt;b&gt;Tricks&lt;/b&gt;"));
+
+    let confirmation_link = app.get_confirmation_links(email_request).html;
+    let html_page = reqwest::get(confirmation_link)
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("receive Tips &amp; &lt;b&gt;Tricks&lt;/b&gt;."));
+    assert!(!html_page.contains("<b>Tricks</b>"));
+}
+
+#[tokio::test]
+async fn a_confirmation_link_is_rejected_when_it_names_another_list() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_list(&app, "weekly", "The Weekly Digest").await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let mut confirmation_link = app.get_confirmation_links(email_request).html;
+    let subscription_token = confirmation_link
+        .query_pairs()
+        .find(|(key, _)| key == "subscription_token")
+        .map(|(_, value)| value.into_owned())
+        .unwrap();
+    confirmation_link
+        .query_pairs_mut()
+        .clear()
+        .append_pair("subscription_token", &subscription_token)
+        .append_pair("list", "weekly");
+
+    // Act
+    let response = app.post_confirmation(&confirmation_link).await;
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn issues_are_only_delivered_to_members_of_their_list() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_list(&app, "weekly", "The Weekly Digest").await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        // Our only subscriber is on the default list, not on `weekly`
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_publish_newsletter(&newsletter_request_body("weekly"))
+        .await;
+
+    // Assert
+    TestApp::assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we haven't sent the newsletter email
+}
+
+#[tokio::test]
+async fn publishing_to_an_unknown_list_is_rejected_with_a_400() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_publish_newsletter(&newsletter_request_body("does-not-exist"))
+        .await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn the_publish_form_offers_every_list() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_list(&app, "weekly", "The Weekly Digest").await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let html_page = app.get_publish_newsletter_html().await;
+
+    // Assert
+    assert!(html_page.contains(r#"<option value="newsletter" selected>Newsletter</option>"#));
+    assert!(html_page.contains(r#"<option value="weekly">The Weekly Digest</option>"#));
+}
@@ -1,11 +1,30 @@
 //! tests/api/main.rs
 
 mod admin_dashboard;
+mod api_subscriptions;
+mod bot_protection;
 mod change_password;
+mod confirmation_reminders;
+mod consent_records;
+mod email_validation;
 mod health_check;
 mod helpers;
 mod login;
+mod mailing_lists;
 mod newsletter;
+mod postmark_webhook;
+mod rate_limits;
+mod segments;
+mod sign_up_sources;
+mod subscriber_actions;
+mod subscriber_attributes;
+mod subscriber_browser;
+mod subscriber_data;
+mod subscriber_export;
+mod subscriber_import;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_manage;
+mod subscriptions_unsubscribe;
+mod suppressions;
 mod test_user;
@@ -0,0 +1,229 @@
+//! tests/api/postmark_webhook.rs
+
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::TestApp;
+
+// Postmark's sample payloads for each webhook, all about `john@example.com`.
+const HARD_BOUNCE: &str = include_str!("fixtures/postmark/hard_bounce.json");
+const SOFT_BOUNCE: &str = include_str!("fixtures/postmark/soft_bounce.json");
+const SPAM_COMPLAINT: &str = include_str!("fixtures/postmark/spam_complaint.json");
+const DELIVERY: &str = include_str!("fixtures/postmark/delivery.json");
+const OPEN: &str = include_str!("fixtures/postmark/open.json");
+
+async fn create_confirmed_john(app: &TestApp) {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=john&email=john%40example.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    app.post_confirmation(&confirmation_links.html)
+        .await
+        .error_for_status()
+        .unwrap();
+}
+
+async fn get_john(app: &TestApp) -> (String, Option<String>) {
+    let saved = sqlx::query!(
+        r#"
+        SELECT subscriptions.status, suppressions.reason AS "reason?"
+        FROM subscriptions
+        LEFT JOIN suppressions ON suppressions.email_canonical = subscriptions.email_canonical
+        WHERE subscriptions.email_canonical = 'john@example.com'
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+
+    (saved.status, saved.reason)
+}
+
+#[tokio::test]
+async fn a_hard_bounce_suppresses_the_address() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_john(&app).await;
+
+    // Act
+    let response = app.post_postmark_webhook(HARD_BOUNCE).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        get_john(&app).await,
+        ("bounced".into(), Some("hard_bounce".into()))
+    );
+}
+
+#[tokio::test]
+async fn a_spam_complaint_suppresses_the_address() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_john(&app).await;
+
+    // Act
+    let response = app.post_postmark_webhook(SPAM_COMPLAINT).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        get_john(&app).await,
+        ("complained".into(), Some("complaint".into()))
+    );
+}
+
+#[tokio::test]
+async fn soft_bounces_deliveries_and_other_events_change_nothing() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_john(&app).await;
+
+    for payload in [SOFT_BOUNCE, DELIVERY, OPEN] {
+        // Act
+        let response = app.post_postmark_webhook(payload).await;
+
+        // Assert
+        assert_eq!(200, response.status().as_u16());
+        assert_eq!(get_john(&app).await, ("confirmed".into(), None));
+    }
+}
+
+#[tokio::test]
+async fn events_about_unknown_addresses_still_suppress_them() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let response = app.post_postmark_webhook(HARD_BOUNCE).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT email, reason FROM suppressions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.email, "john@example.com");
+    assert_eq!(saved.reason, "hard_bounce");
+}
+
+#[tokio::test]
+async fn bounced_subscribers_are_not_sent_newsletters() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_john(&app).await;
+    app.post_postmark_webhook(HARD_BOUNCE).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_publish_newsletter(&newsletter_request_body).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Mock verifies on Drop that we haven't sent the newsletter
+}
+
+#[tokio::test]
+async fn the_shared_secret_header_is_accepted_in_place_of_basic_auth() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_john(&app).await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/webhooks/postmark", &app.address))
+        .header("X-Webhook-Secret", "my-webhook-secret")
+        .header("Content-Type", "application/json")
+        .body(SPAM_COMPLAINT)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(get_john(&app).await.0, "complained");
+}
+
+#[tokio::test]
+async fn requests_without_valid_credentials_are_rejected() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_john(&app).await;
+    let url = format!("{}/webhooks/postmark", &app.address);
+    let client = reqwest::Client::new();
+
+    let test_cases = vec![
+        (client.post(&url), "no credentials"),
+        (
+            client
+                .post(&url)
+                .basic_auth("postmark", Some("wrong-secret")),
+            "a wrong password",
+        ),
+        (
+            client
+                .post(&url)
+                .basic_auth("someone", Some("my-webhook-secret")),
+            "a wrong username",
+        ),
+        (
+            client.post(&url).header("X-Webhook-Secret", "wrong-secret"),
+            "a wrong shared secret",
+        ),
+    ];
+
+    for (request, description) in test_cases {
+        // Act
+        let response = request.body(HARD_BOUNCE).send().await.unwrap();
+
+        // Assert
+        assert_eq!(
+            401,
+            response.status().as_u16(),
+            "The webhook did not reject a request with {}.",
+            description
+        );
+        assert_eq!(
+            r#"Basic realm="postmark""#,
+            response.headers()["WWW-Authenticate"]
+        );
+    }
+    assert_eq!(get_john(&app).await, ("confirmed".into(), None));
+}
+
+#[tokio::test]
+async fn a_malformed_payload_is_rejected_with_a_400() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let response = app
+        .post_postmark_webhook(r#"{"RecordType": "Bounce", "Email": "john@example.com"}"#)
+        .await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+}
@@ -0,0 +1,259 @@
+//! tests/api/rate_limits.rs
+
+use std::time::Duration;
+
+use reqwest::Response;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero_to_prod::configuration::{RouteRateLimits, WindowLimit};
+
+use crate::helpers::TestApp;
+
+fn limit(max_requests: u32, window_seconds: u64) -> Option<WindowLimit> {
+    Some(WindowLimit {
+        max_requests,
+        window_seconds,
+    })
+}
+
+fn subscribe_body(email: &str) -> String {
+    serde_urlencoded::to_string([("name", "le guin"), ("email", email)]).unwrap()
+}
+
+async fn accept_all_emails(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+fn assert_is_rate_limited(response: &Response, window_seconds: u64) {
+    assert_eq!(response.status().as_u16(), 429);
+    let retry_after: u64 = response.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!((1..=window_seconds).contains(&retry_after));
+}
+
+#[tokio::test]
+async fn subscribing_is_limited_per_email_address() {
+    // Arrange
+    let app = TestApp::spawn_app_with(|config| {
+        config.application.rate_limits.subscribe = RouteRateLimits {
+            per_ip: None,
+            per_email: limit(2, 3600),
+        };
+    })
+    .await;
+    accept_all_emails(&app).await;
+
+    // Act - Part 1 - Use up the limit
+    for _ in 0..2 {
+        let response = app
+            .post_subscriptions(subscribe_body("ursula_le_guin@gmail.com"))
+            .await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    // Act - Part 2 - The same address, written differently
+    let response = app
+        .post_subscriptions(subscribe_body(" Ursula_Le_Guin@GMAIL.com"))
+        .await;
+
+    // Assert
+    assert_is_rate_limited(&response, 3600);
+    // Other addresses are not held back
+    let response = app
+        .post_subscriptions(subscribe_body("le_guin@gmail.com"))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    // Nor are invalid ones, which are turned down as usual
+    let response = app.post_subscriptions(subscribe_body("ursula")).await;
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn the_form_and_the_api_share_the_subscribe_limits() {
+    // Arrange
+    let app = TestApp::spawn_app_with(|config| {
+        config.application.rate_limits.subscribe = RouteRateLimits {
+            per_ip: limit(1, 3600),
+            per_email: None,
+        };
+    })
+    .await;
+    accept_all_emails(&app).await;
+    app.post_subscriptions(subscribe_body("ursula_le_guin@gmail.com"))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = app
+        .post_api_subscriptions(&serde_json::json!({
+            "name": "le guin",
+            "email": "le_guin@gmail.com"
+        }))
+        .await;
+
+    // Assert
+    assert_is_rate_limited(&response, 3600);
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 1);
+}
+
+#[tokio::test]
+async fn resending_a_confirmation_shares_the_subscribe_limits() {
+    // Arrange
+    let app = TestApp::spawn_app_with(|config| {
+        config.application.rate_limits.subscribe = RouteRateLimits {
+            per_ip: None,
+            per_email: limit(2, 3600),
+        };
+    })
+    .await;
+    accept_all_emails(&app).await;
+    app.post_subscriptions(subscribe_body("ursula_le_guin@gmail.com"))
+        .await
+        .error_for_status()
+        .unwrap();
+    let resend_body = serde_urlencoded::to_string([("email", "ursula_le_guin@gmail.com")]).unwrap();
+    app.post_resend_confirmation(resend_body.clone())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = app.post_resend_confirmation(resend_body).await;
+
+    // Assert
+    assert_is_rate_limited(&response, 3600);
+    let emails = app.email_server.received_requests().await.unwrap();
+    assert_eq!(emails.len(), 2);
+}
+
+#[tokio::test]
+async fn a_request_turned_down_by_one_limit_is_not_counted_against_the_others() {
+    // Arrange
+    let app = TestApp::spawn_app_with(|config| {
+        config.application.rate_limits.subscribe = RouteRateLimits {
+            per_ip: limit(2, 3600),
+            per_email: limit(1, 3600),
+        };
+    })
+    .await;
+    accept_all_emails(&app).await;
+    app.post_subscriptions(subscribe_body("ursula_le_guin@gmail.com"))
+        .await
+        .error_for_status()
+        .unwrap();
+    let response = app
+        .post_subscriptions(subscribe_body("ursula_le_guin@gmail.com"))
+        .await;
+    assert_is_rate_limited(&response, 3600);
+
+    // Act
+    let response = app
+        .post_subscriptions(subscribe_body("le_guin@gmail.com"))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn logging_in_is_limited_per_client_address() {
+    // Arrange
+    let app = TestApp::spawn_app_with(|config| {
+        config.application.rate_limits.login = RouteRateLimits {
+            per_ip: limit(3, 300),
+            per_email: None,
+        };
+    })
+    .await;
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": "wrong-password"
+    });
+    for _ in 0..3 {
+        let response = app.post_login(&login_body).await;
+        TestApp::assert_is_redirect_to(&response, "/login");
+    }
+
+    // Act - Even with the right password
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+
+    // Assert
+    assert_is_rate_limited(&response, 300);
+    // The login page itself can still be loaded
+    let response = app
+        .api_client
+        .get(format!("{}/login", &app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn confirming_is_limited_per_client_address() {
+    // Arrange
+    let app = TestApp::spawn_app_with(|config| {
+        config.application.rate_limits.confirm = RouteRateLimits {
+            per_ip: limit(2, 600),
+            per_email: None,
+        };
+    })
+    .await;
+    let confirm_url = format!(
+        "{}/subscriptions/confirm?subscription_token=guess",
+        &app.address
+    );
+    for _ in 0..2 {
+        app.api_client.get(&confirm_url).send().await.unwrap();
+    }
+
+    // Act
+    let response = app.api_client.get(&confirm_url).send().await.unwrap();
+
+    // Assert
+    assert_is_rate_limited(&response, 600);
+}
+
+#[tokio::test]
+async fn requests_are_let_back_in_as_the_window_slides() {
+    // Arrange
+    let app = TestApp::spawn_app_with(|config| {
+        config.application.rate_limits.login = RouteRateLimits {
+            per_ip: limit(1, 1),
+            per_email: None,
+        };
+    })
+    .await;
+    let login_body = serde_json::json!({
+        "username": "random-username",
+        "password": "random-password"
+    });
+    app.post_login(&login_body).await;
+    assert_is_rate_limited(&app.post_login(&login_body).await, 1);
+
+    // Act
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    let response = app.post_login(&login_body).await;
+
+    // Assert
+    TestApp::assert_is_redirect_to(&response, "/login");
+}
@@ -0,0 +1,382 @@
+//! tests/api/segments.rs
+
+use reqwest::Response;
+use serde_json::{json, Value};
+use uuid::Uuid;
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::TestApp;
+
+async fn create_confirmed_subscriber_on_plan(app: &TestApp, email: &str, plan: &str) {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let body = serde_urlencoded::to_string([
+        ("name", "le guin"),
+        ("email", email),
+        ("attributes[plan]", plan),
+    ])
+    .unwrap();
+    app.post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let confirmation_links = app.get_confirmation_links(email_request);
+    app.post_confirmation(&confirmation_links.html)
+        .await
+        .error_for_status()
+        .unwrap();
+}
+
+async fn post_segment(app: &TestApp, name: &str, expression: &str) -> Response {
+    app.api_client
+        .post(format!("{}/admin/segments", &app.address))
+        .form(&[("name", name), ("expression", expression)])
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn get_html(app: &TestApp, path: &str) -> String {
+    app.api_client
+        .get(format!("{}{}", &app.address, path))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .text()
+        .await
+        .unwrap()
+}
+
+async fn get_segment_id(app: &TestApp, name: &str) -> Uuid {
+    sqlx::query!("SELECT segment_id FROM segments WHERE name = $1", name)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .segment_id
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_segments() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let form = app
+        .api_client
+        .get(format!("{}/admin/segments", &app.address))
+        .send()
+        .await
+        .unwrap();
+    let add = post_segment(&app, "Pro", "attributes.plan = pro").await;
+
+    // Assert
+    TestApp::assert_is_redirect_to(&form, "/login");
+    TestApp::assert_is_redirect_to(&add, "/login");
+    let saved = sqlx::query!("SELECT segment_id FROM segments")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.is_empty());
+}
+
+#[tokio::test]
+async fn a_segment_can_be_saved_and_previewed() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber_on_plan(&app, "pro@example.com", "pro").await;
+    create_confirmed_subscriber_on_plan(&app, "free@example.com", "free").await;
+    app.test_user.login(&app).await;
+
+    // Act - Part 1 - Save the segment
+    let response = post_segment(
+        &app,
+        "Pro",
+        r#"status = confirmed AND subscribed_at > 2024-01-01 AND attributes.plan = "pro""#,
+    )
+    .await;
+    TestApp::assert_is_redirect_to(&response, "/admin/segments");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = get_html(&app, "/admin/segments").await;
+    assert!(html_page.contains("The segment Pro has been saved."));
+
+    // Act - Part 3 - Preview it
+    let segment_id = get_segment_id(&app, "Pro").await;
+    let html_page = get_html(&app, &format!("/admin/segments/{segment_id}")).await;
+
+    // Assert
+    assert!(html_page.contains("1 subscribers match among the confirmed members of"));
+    assert!(html_page.contains("pro@example.com"));
+    assert!(!html_page.contains("free@example.com"));
+}
+
+#[tokio::test]
+async fn an_unsaved_segment_can_be_previewed() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber_on_plan(&app, "pro@example.com", "pro").await;
+    create_confirmed_subscriber_on_plan(&app, "free@example.com", "free").await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let html_page = get_html(
+        &app,
+        "/admin/segments/preview?name=Not+pro&expression=NOT+attributes.plan+%3D+pro",
+    )
+    .await;
+
+    // Assert
+    assert!(html_page.contains("1 subscribers match among the confirmed members of"));
+    assert!(html_page.contains("free@example.com"));
+    assert!(!html_page.contains("pro@example.com"));
+    // The form to save it is filled in
+    assert!(html_page.contains(r#"value="Not pro""#));
+}
+
+#[tokio::test]
+async fn previews_count_who_an_issue_to_the_chosen_list_would_reach() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber_on_plan(&app, "pro@example.com", "pro").await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let body = serde_urlencoded::to_string([
+        ("name", "pending"),
+        ("email", "pending@example.com"),
+        ("attributes[plan]", "pro"),
+    ])
+    .unwrap();
+    app.post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+    sqlx::query!(
+        "INSERT INTO lists (list_id, slug, name) VALUES ($1, 'weekly', 'The Weekly Digest')",
+        Uuid::new_v4()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.test_user.login(&app).await;
+    post_segment(&app, "Pro", "attributes.plan = pro").await;
+    let segment_id = get_segment_id(&app, "Pro").await;
+
+    // Act
+    let newsletter_page = get_html(&app, &format!("/admin/segments/{segment_id}")).await;
+    let weekly_page = get_html(&app, &format!("/admin/segments/{segment_id}?list=weekly")).await;
+
+    // Assert
+    assert!(newsletter_page.contains("1 subscribers match"));
+    assert!(newsletter_page.contains("pro@example.com"));
+    assert!(!newsletter_page.contains("pending@example.com"));
+    assert!(weekly_page
+        .contains("0 subscribers match among the confirmed members of The Weekly Digest."));
+}
+
+#[tokio::test]
+async fn invalid_segments_are_not_saved() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+    post_segment(&app, "Pro", "attributes.plan = pro").await;
+    let test_cases = vec![
+        (
+            "Broken",
+            "attributes.plan =",
+            "The segment ends where a value was expected.",
+        ),
+        ("Typo", "plan = pro", "plan is not a known field."),
+        ("", "attributes.plan = pro", "The segment needs a name."),
+        (
+            "Pro",
+            "attributes.plan = free",
+            "There already is a segment named Pro.",
+        ),
+    ];
+
+    for (name, expression, error_message) in test_cases {
+        // Act
+        let response = post_segment(&app, name, expression).await;
+
+        // Assert
+        TestApp::assert_is_redirect_to(&response, "/admin/segments");
+        let html_page = get_html(&app, "/admin/segments").await;
+        assert!(
+            html_page.contains(error_message),
+            "No error for {}: {}",
+            name,
+            expression
+        );
+    }
+    let saved = sqlx::query!("SELECT name, expression FROM segments")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].expression, "attributes.plan = pro");
+}
+
+#[tokio::test]
+async fn issues_sent_to_a_segment_only_reach_matching_subscribers() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber_on_plan(&app, "pro@example.com", "pro").await;
+    create_confirmed_subscriber_on_plan(&app, "free@example.com", "free").await;
+    app.test_user.login(&app).await;
+    post_segment(&app, "Pro", "attributes.plan = pro").await;
+    let segment_id = get_segment_id(&app, "Pro").await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let newsletter_request_body = json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "segment": segment_id.to_string(),
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    let response = app.post_publish_newsletter(&newsletter_request_body).await;
+    TestApp::assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], "pro@example.com");
+    let issue = sqlx::query!("SELECT segment_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issue.segment_id, Some(segment_id));
+}
+
+#[tokio::test]
+async fn an_empty_segment_sends_to_the_whole_list() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber_on_plan(&app, "pro@example.com", "pro").await;
+    create_confirmed_subscriber_on_plan(&app, "free@example.com", "free").await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let newsletter_request_body = json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "segment": "",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    let response = app.post_publish_newsletter(&newsletter_request_body).await;
+    TestApp::assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    // Mock verifies on Drop that both subscribers received the issue
+}
+
+#[tokio::test]
+async fn publishing_to_an_unknown_segment_is_rejected() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber_on_plan(&app, "pro@example.com", "pro").await;
+    app.test_user.login(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let newsletter_request_body = json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "segment": Uuid::new_v4().to_string(),
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    let response = app.post_publish_newsletter(&newsletter_request_body).await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we haven't sent the newsletter
+}
+
+#[tokio::test]
+async fn deleting_a_segment_keeps_the_issues_sent_to_it() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber_on_plan(&app, "pro@example.com", "pro").await;
+    app.test_user.login(&app).await;
+    post_segment(&app, "Pro", "attributes.plan = pro").await;
+    let segment_id = get_segment_id(&app, "Pro").await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_publish_newsletter(&json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "segment": segment_id.to_string(),
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    }))
+    .await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/admin/segments/remove", &app.address))
+        .form(&[("segment_id", segment_id.to_string())])
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    TestApp::assert_is_redirect_to(&response, "/admin/segments");
+    let html_page = get_html(&app, "/admin/segments").await;
+    assert!(html_page.contains("The segment has been deleted."));
+    let issue = sqlx::query!("SELECT segment_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issue.segment_id, None);
+}
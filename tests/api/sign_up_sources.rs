@@ -0,0 +1,351 @@
+//! tests/api/sign_up_sources.rs
+
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::TestApp;
+
+struct SavedAttribution {
+    utm_source: Option<String>,
+    utm_medium: Option<String>,
+    utm_campaign: Option<String>,
+    referrer: Option<String>,
+    source: Option<String>,
+}
+
+async fn get_attribution(app: &TestApp) -> SavedAttribution {
+    sqlx::query_as!(
+        SavedAttribution,
+        "SELECT utm_source, utm_medium, utm_campaign, referrer, source FROM subscriptions"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+}
+
+async fn get_html(app: &TestApp, path: &str) -> String {
+    app.api_client
+        .get(format!("{}{}", &app.address, path))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .text()
+        .await
+        .unwrap()
+}
+
+async fn accept_all_emails(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+/// The value of a hidden field of the form, if the page has it.
+fn hidden_field(html_page: &str, name: &str) -> Option<String> {
+    let prefix = format!(r#"name="{name}" value=""#);
+    let start = html_page.find(&prefix)? + prefix.len();
+    let end = start + html_page[start..].find('"')?;
+    Some(html_page[start..end].to_owned())
+}
+
+/// Load the home page as a browser would from `referrer`, then send its form
+/// from there.
+async fn sign_up_through_the_home_page(app: &TestApp, referrer: Option<&str>) {
+    let mut request = app.api_client.get(format!("{}/", &app.address));
+    if let Some(referrer) = referrer {
+        request = request.header("Referer", referrer);
+    }
+    let html_page = request.send().await.unwrap().text().await.unwrap();
+
+    let mut fields = vec![
+        ("name".to_string(), "le guin".to_string()),
+        ("email".to_string(), "ursula_le_guin@gmail.com".to_string()),
+    ];
+    for name in ["referrer", "referrer_signature"] {
+        if let Some(value) = hidden_field(&html_page, name) {
+            fields.push((name.to_string(), value));
+        }
+    }
+
+    app.api_client
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("Referer", format!("{}/", &app.address))
+        .body(serde_urlencoded::to_string(fields).unwrap())
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+/// The rows of the breakdown, as `(source, confirmed sign-ups)`.
+fn listed_sources(html_page: &str) -> Vec<(String, String)> {
+    html_page
+        .split("<tr><td>")
+        .skip(1)
+        .map(|row| {
+            let cells: Vec<_> = row.split("</td><td>").collect();
+            (cells[0].to_owned(), cells[1].to_owned())
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn sign_ups_remember_where_they_came_from() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    accept_all_emails(&app).await;
+    let body = serde_urlencoded::to_string([
+        ("name", "le guin"),
+        ("email", "ursula_le_guin@gmail.com"),
+        ("utm_source", "mastodon"),
+        ("utm_medium", " social "),
+        ("utm_campaign", ""),
+        ("source", "footer-form"),
+    ])
+    .unwrap();
+
+    // Act
+    app.api_client
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("Referer", "https://blog.example.com/posts/42")
+        .body(body)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let saved = get_attribution(&app).await;
+    assert_eq!(saved.utm_source.as_deref(), Some("mastodon"));
+    assert_eq!(saved.utm_medium.as_deref(), Some("social"));
+    assert_eq!(saved.utm_campaign, None);
+    assert_eq!(
+        saved.referrer.as_deref(),
+        Some("https://blog.example.com/posts/42")
+    );
+    assert_eq!(saved.source.as_deref(), Some("footer-form"));
+}
+
+#[tokio::test]
+async fn sign_ups_through_the_home_page_remember_the_page_that_linked_to_it() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    accept_all_emails(&app).await;
+
+    // Act
+    sign_up_through_the_home_page(&app, Some("https://blog.example.com/posts/42")).await;
+
+    // Assert
+    assert_eq!(
+        get_attribution(&app).await.referrer.as_deref(),
+        Some("https://blog.example.com/posts/42")
+    );
+}
+
+#[tokio::test]
+async fn the_home_page_is_not_taken_for_the_referrer() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    accept_all_emails(&app).await;
+
+    // Act
+    sign_up_through_the_home_page(&app, None).await;
+
+    // Assert
+    assert_eq!(get_attribution(&app).await.referrer, None);
+}
+
+#[tokio::test]
+async fn a_referrer_the_home_page_did_not_sign_is_ignored() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    accept_all_emails(&app).await;
+    let body = serde_urlencoded::to_string([
+        ("name", "le guin"),
+        ("email", "ursula_le_guin@gmail.com"),
+        ("referrer", "https://forged.example.com"),
+        ("referrer_signature", "forged"),
+    ])
+    .unwrap();
+
+    // Act
+    app.post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    assert_eq!(get_attribution(&app).await.referrer, None);
+}
+
+#[tokio::test]
+async fn api_sign_ups_remember_where_they_came_from() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    accept_all_emails(&app).await;
+
+    // Act
+    app.post_api_subscriptions(&serde_json::json!({
+        "name": "le guin",
+        "email": "ursula_le_guin@gmail.com",
+        "utm_campaign": "spring-launch",
+        "source": "ios-app"
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+
+    // Assert
+    let saved = get_attribution(&app).await;
+    assert_eq!(saved.utm_campaign.as_deref(), Some("spring-launch"));
+    assert_eq!(saved.source.as_deref(), Some("ios-app"));
+    assert_eq!(saved.referrer, None);
+}
+
+#[tokio::test]
+async fn signing_up_again_keeps_the_first_attribution() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    accept_all_emails(&app).await;
+
+    // Act
+    for utm_source in ["mastodon", "newsletter-swap"] {
+        let body = serde_urlencoded::to_string([
+            ("name", "le guin"),
+            ("email", "ursula_le_guin@gmail.com"),
+            ("utm_source", utm_source),
+        ])
+        .unwrap();
+        app.post_subscriptions(body)
+            .await
+            .error_for_status()
+            .unwrap();
+    }
+
+    // Assert
+    assert_eq!(
+        get_attribution(&app).await.utm_source.as_deref(),
+        Some("mastodon")
+    );
+}
+
+#[tokio::test]
+async fn the_home_page_passes_campaign_parameters_on_to_the_form() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let html_page = get_html(
+        &app,
+        "/?utm_source=mastodon&utm_campaign=%22%3E%3Cscript%3E&ref=ignored",
+    )
+    .await;
+
+    // Assert
+    assert!(html_page.contains(r#"<input hidden type="text" name="utm_source" value="mastodon">"#));
+    assert!(html_page.contains(
+        r#"<input hidden type="text" name="utm_campaign" value="&quot;&gt;&lt;script&gt;">"#
+    ));
+    assert!(!html_page.contains(r#"name="utm_medium""#));
+    assert!(!html_page.contains("ignored"));
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_sign_ups_by_source() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/admin/sign-up-sources", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    TestApp::assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn confirmed_sign_ups_are_broken_down_by_source_over_a_date_range() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (
+            id, email, email_canonical, email_display, name, subscribed_at, status,
+            utm_source, referrer, source
+        )
+        SELECT
+            gen_random_uuid(),
+            'subscriber' || i || '@example.com',
+            'subscriber' || i || '@example.com',
+            'subscriber' || i || '@example.com',
+            'Subscriber ' || i,
+            subscribed_at::timestamptz,
+            status,
+            utm_source,
+            referrer,
+            source
+        FROM (VALUES
+            (1, '2024-03-01T12:00:00Z', 'confirmed', 'mastodon', NULL, NULL),
+            (2, '2024-03-02T12:00:00Z', 'confirmed', 'mastodon', NULL, NULL),
+            (3, '2024-03-03T12:00:00Z', 'confirmed', 'mastodon', NULL, 'footer-form'),
+            (4, '2024-03-04T12:00:00Z', 'confirmed', NULL, 'https://Blog.Example.com/a', NULL),
+            (5, '2024-03-31T12:00:00Z', 'confirmed', NULL, NULL, NULL),
+            (6, '2024-03-05T12:00:00Z', 'pending_confirmation', 'mastodon', NULL, NULL),
+            (7, '2024-03-06T12:00:00Z', 'unsubscribed', 'mastodon', NULL, NULL),
+            (8, '2024-04-01T12:00:00Z', 'confirmed', 'mastodon', NULL, NULL),
+            (9, '2024-02-29T12:00:00Z', 'confirmed', 'mastodon', NULL, NULL)
+        ) AS rows (i, subscribed_at, status, utm_source, referrer, source)
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.test_user.login(&app).await;
+
+    // Act
+    let html_page = get_html(&app, "/admin/sign-up-sources?from=2024-03-01&to=2024-03-31").await;
+
+    // Assert
+    assert_eq!(
+        listed_sources(&html_page),
+        vec![
+            ("mastodon".into(), "2".into()),
+            ("(direct)".into(), "1".into()),
+            ("blog.example.com".into(), "1".into()),
+            ("footer-form".into(), "1".into()),
+        ]
+    );
+    assert!(html_page.contains("<tr><th>Total</th><th>5</th>"));
+}
+
+#[tokio::test]
+async fn an_invalid_date_range_is_reported() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/admin/sign-up-sources?from=March", &app.address))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    TestApp::assert_is_redirect_to(&response, "/admin/sign-up-sources");
+    let html_page = get_html(&app, "/admin/sign-up-sources").await;
+    assert!(html_page.contains("March is not a date."));
+}
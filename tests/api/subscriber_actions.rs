@@ -0,0 +1,468 @@
+//! tests/api/subscriber_actions.rs
+
+use reqwest::Response;
+use uuid::Uuid;
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{create_confirmed_subscriber, create_unconfirmed_subscriber, TestApp};
+
+async fn get_subscriber(app: &TestApp) -> (Uuid, String) {
+    let record = sqlx::query!("SELECT id, status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    (record.id, record.status)
+}
+
+async fn get_memberships(app: &TestApp) -> Vec<(String, String)> {
+    sqlx::query!(
+        r#"
+        SELECT lists.slug, list_memberships.status
+        FROM list_memberships
+        JOIN lists ON lists.list_id = list_memberships.list_id
+        ORDER BY lists.slug
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|row| (row.slug, row.status))
+    .collect()
+}
+
+async fn post_action(
+    app: &TestApp,
+    subscriber_id: Uuid,
+    action: &str,
+    form: &[(&str, &str)],
+) -> Response {
+    app.api_client
+        .post(format!(
+            "{}/admin/subscribers/{}/{}",
+            &app.address, subscriber_id, action
+        ))
+        .form(form)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn get_html(app: &TestApp, path: &str) -> String {
+    app.api_client
+        .get(format!("{}{}", &app.address, path))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .text()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_act_on_a_subscriber() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    let (subscriber_id, _) = get_subscriber(&app).await;
+    let key = Uuid::new_v4().to_string();
+
+    for action in ["confirm", "resend", "unsubscribe", "move", "delete"] {
+        // Act
+        let response = post_action(&app, subscriber_id, action, &[("idempotency_key", &key)]).await;
+
+        // Assert
+        TestApp::assert_is_redirect_to(&response, "/login");
+    }
+    assert_eq!(
+        get_subscriber(&app).await,
+        (subscriber_id, "pending_confirmation".into())
+    );
+}
+
+#[tokio::test]
+async fn admins_can_confirm_a_pending_subscriber() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    let (subscriber_id, _) = get_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let key = Uuid::new_v4().to_string();
+
+    // Act - Part 1 - Confirm them
+    let response = post_action(&app, subscriber_id, "confirm", &[("idempotency_key", &key)]).await;
+    let location = format!("/admin/subscribers/{subscriber_id}");
+    TestApp::assert_is_redirect_to(&response, &location);
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = get_html(&app, &location).await;
+    assert!(html_page.contains("has been confirmed."));
+
+    // Assert
+    assert_eq!(get_subscriber(&app).await.1, "confirmed");
+    assert_eq!(
+        get_memberships(&app).await,
+        vec![("newsletter".into(), "confirmed".into())]
+    );
+    // Who confirmed them is part of their consent trail
+    let consent = sqlx::query!(
+        r#"
+        SELECT event, form_version, recorded_by
+        FROM consent_records
+        WHERE subscriber_id = $1
+        ORDER BY recorded_at DESC
+        LIMIT 1
+        "#,
+        subscriber_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(consent.event, "admin_confirmation");
+    assert_eq!(
+        consent.form_version.as_deref(),
+        Some("admin-subscriber-page-v1")
+    );
+    assert_eq!(consent.recorded_by, Some(app.test_user.user_id));
+    assert!(html_page.contains(&format!("<td>{}</td>", app.test_user.username)));
+    // The link they were sent has nothing left to confirm
+    let response = app.post_confirmation(&confirmation_links.html).await;
+    assert_eq!(response.status().as_u16(), 410);
+}
+
+#[tokio::test]
+async fn confirming_a_subscriber_twice_with_the_same_form_acts_once() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    let (subscriber_id, _) = get_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let key = Uuid::new_v4().to_string();
+
+    // Act - Part 1 - Submit the form
+    post_action(&app, subscriber_id, "confirm", &[("idempotency_key", &key)]).await;
+    get_html(&app, "/admin/subscribers").await;
+
+    // Act - Part 2 - Submit it again
+    let response = post_action(&app, subscriber_id, "confirm", &[("idempotency_key", &key)]).await;
+
+    // Assert
+    TestApp::assert_is_redirect_to(&response, &format!("/admin/subscribers/{subscriber_id}"));
+    let html_page = get_html(&app, &format!("/admin/subscribers/{subscriber_id}")).await;
+    assert!(html_page.contains("This action has already been carried out."));
+    assert!(!html_page.contains("is not waiting for confirmation."));
+}
+
+#[tokio::test]
+async fn confirming_a_confirmed_subscriber_says_so() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (subscriber_id, _) = get_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let key = Uuid::new_v4().to_string();
+
+    // Act
+    post_action(&app, subscriber_id, "confirm", &[("idempotency_key", &key)]).await;
+
+    // Assert
+    let html_page = get_html(&app, &format!("/admin/subscribers/{subscriber_id}")).await;
+    assert!(html_page.contains("is not waiting for confirmation."));
+}
+
+#[tokio::test]
+async fn admins_can_resend_the_confirmation_email() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let old_links = create_unconfirmed_subscriber(&app).await;
+    let (subscriber_id, _) = get_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let key = Uuid::new_v4().to_string();
+    post_action(&app, subscriber_id, "resend", &[("idempotency_key", &key)]).await;
+
+    // Assert
+    let html_page = get_html(&app, &format!("/admin/subscribers/{subscriber_id}")).await;
+    assert!(html_page.contains("A new confirmation link has been sent to"));
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let new_links = app.get_confirmation_links(&email_request);
+    assert_ne!(new_links.html, old_links.html);
+    app.post_confirmation(&new_links.html)
+        .await
+        .error_for_status()
+        .unwrap();
+    assert_eq!(get_subscriber(&app).await.1, "confirmed");
+}
+
+#[tokio::test]
+async fn links_already_resent_stay_valid_when_a_later_one_fails() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    let (subscriber_id, _) = get_subscriber(&app).await;
+    let weekly_id = Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO lists (list_id, slug, name) VALUES ($1, 'weekly', 'The Weekly Digest')",
+        weekly_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        r#"
+        INSERT INTO list_memberships (subscriber_id, list_id, status)
+        VALUES ($1, $2, 'pending_confirmation')
+        "#,
+        subscriber_id,
+        weekly_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.test_user.login(&app).await;
+
+    // The newsletter's link goes out first, the weekly digest's fails.
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let key = Uuid::new_v4().to_string();
+    post_action(&app, subscriber_id, "resend", &[("idempotency_key", &key)]).await;
+
+    // Assert
+    let html_page = get_html(&app, &format!("/admin/subscribers/{subscriber_id}")).await;
+    assert!(html_page.contains("The confirmation link for The Weekly Digest could not be sent"));
+    // The first request is the one sent on sign-up.
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let links = app.get_confirmation_links(email_request);
+    app.post_confirmation(&links.html)
+        .await
+        .error_for_status()
+        .unwrap();
+    assert_eq!(
+        get_memberships(&app).await,
+        vec![
+            ("newsletter".into(), "confirmed".into()),
+            ("weekly".into(), "pending_confirmation".into())
+        ]
+    );
+}
+
+#[tokio::test]
+async fn admin_actions_drop_the_subscribers_queued_confirmation_emails() {
+    for action in ["confirm", "unsubscribe"] {
+        // Arrange
+        let app = TestApp::spawn_app().await;
+        create_unconfirmed_subscriber(&app).await;
+        let (subscriber_id, _) = get_subscriber(&app).await;
+        sqlx::query!(
+            r#"
+            INSERT INTO confirmation_email_queue (subscriber_id, list_id, enqueued_at)
+            SELECT subscriber_id, list_id, now() FROM list_memberships
+            "#
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+        app.test_user.login(&app).await;
+
+        // Act
+        let key = Uuid::new_v4().to_string();
+        post_action(&app, subscriber_id, action, &[("idempotency_key", &key)]).await;
+
+        // Assert
+        let queued = sqlx::query!("SELECT subscriber_id FROM confirmation_email_queue")
+            .fetch_all(&app.db_pool)
+            .await
+            .unwrap();
+        assert!(queued.is_empty(), "{}", action);
+    }
+}
+
+#[tokio::test]
+async fn admins_can_unsubscribe_a_subscriber() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (subscriber_id, _) = get_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let key = Uuid::new_v4().to_string();
+    post_action(
+        &app,
+        subscriber_id,
+        "unsubscribe",
+        &[("idempotency_key", &key)],
+    )
+    .await;
+
+    // Assert
+    let html_page = get_html(&app, &format!("/admin/subscribers/{subscriber_id}")).await;
+    assert!(html_page.contains("has been unsubscribed."));
+    assert_eq!(get_subscriber(&app).await.1, "unsubscribed");
+    assert_eq!(
+        get_memberships(&app).await,
+        vec![("newsletter".into(), "unsubscribed".into())]
+    );
+    // No further issues reach them
+    app.post_publish_newsletter(&serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": Uuid::new_v4().to_string()
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn admins_can_move_a_subscriber_to_another_list() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (subscriber_id, _) = get_subscriber(&app).await;
+    sqlx::query!(
+        "INSERT INTO lists (list_id, slug, name) VALUES ($1, 'weekly', 'The Weekly Digest')",
+        Uuid::new_v4()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.test_user.login(&app).await;
+    let location = format!("/admin/subscribers/{subscriber_id}");
+    let test_cases = vec![
+        ("newsletter", "newsletter", "Pick two different lists."),
+        (
+            "newsletter",
+            "monthly",
+            "monthly is not a known mailing list.",
+        ),
+        ("weekly", "newsletter", "is not on The Weekly Digest."),
+        (
+            "newsletter",
+            "weekly",
+            "has been moved from Newsletter to The Weekly Digest.",
+        ),
+        (
+            "weekly",
+            "newsletter",
+            "has been moved from The Weekly Digest to Newsletter.",
+        ),
+    ];
+
+    for (from, to, message) in test_cases {
+        // Act
+        let key = Uuid::new_v4().to_string();
+        let response = post_action(
+            &app,
+            subscriber_id,
+            "move",
+            &[("idempotency_key", &key), ("from", from), ("to", to)],
+        )
+        .await;
+
+        // Assert
+        TestApp::assert_is_redirect_to(&response, &location);
+        let html_page = get_html(&app, &location).await;
+        assert!(html_page.contains(message), "{}", message);
+    }
+    assert_eq!(
+        get_memberships(&app).await,
+        vec![("newsletter".into(), "confirmed".into())]
+    );
+}
+
+#[tokio::test]
+async fn admins_can_delete_a_subscriber() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (subscriber_id, _) = get_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let key = Uuid::new_v4().to_string();
+
+    // Act - Part 1 - Delete them
+    let response = post_action(&app, subscriber_id, "delete", &[("idempotency_key", &key)]).await;
+    TestApp::assert_is_redirect_to(&response, "/admin/subscribers");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = get_html(&app, "/admin/subscribers").await;
+    assert!(html_page.contains("has been deleted."));
+
+    // Assert
+    let remaining = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(remaining.is_empty());
+    assert!(get_memberships(&app).await.is_empty());
+
+    // Act - Part 3 - A fresh form for a subscriber who is gone
+    let key = Uuid::new_v4().to_string();
+    let response = post_action(&app, subscriber_id, "delete", &[("idempotency_key", &key)]).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn the_subscriber_page_has_a_form_per_action() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (subscriber_id, _) = get_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let html_page = get_html(&app, &format!("/admin/subscribers/{subscriber_id}")).await;
+
+    // Assert
+    for action in ["confirm", "resend", "unsubscribe", "move", "delete"] {
+        assert!(html_page.contains(&format!(
+            r#"action="/admin/subscribers/{subscriber_id}/{action}""#
+        )));
+    }
+    // Each form can only be submitted once, not one another's
+    let keys: std::collections::HashSet<_> = html_page
+        .split(r#"name="idempotency_key" value=""#)
+        .skip(1)
+        .map(|rest| rest.split('"').next().unwrap())
+        .collect();
+    assert_eq!(keys.len(), 5);
+}
@@ -0,0 +1,313 @@
+//! tests/api/subscriber_attributes.rs
+
+use reqwest::Response;
+use serde_json::{json, Value};
+use uuid::Uuid;
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{create_confirmed_subscriber, TestApp};
+
+/// Sign up through the HTML form and confirm, as `le guin`.
+async fn create_confirmed_subscriber_with(app: &TestApp, extra_fields: &[(&str, &str)]) {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let mut fields = vec![("name", "le guin"), ("email", "ursula_le_guin@gmail.com")];
+    fields.extend_from_slice(extra_fields);
+    app.post_subscriptions(serde_urlencoded::to_string(fields).unwrap())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let confirmation_links = app.get_confirmation_links(email_request);
+    app.post_confirmation(&confirmation_links.html)
+        .await
+        .error_for_status()
+        .unwrap();
+}
+
+async fn get_subscriber(app: &TestApp) -> (Uuid, Value) {
+    let saved = sqlx::query!("SELECT id, attributes FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    (saved.id, saved.attributes)
+}
+
+async fn post_attributes(app: &TestApp, subscriber_id: Uuid, attributes: &str) -> Response {
+    app.api_client
+        .post(format!(
+            "{}/admin/subscribers/{}/attributes",
+            &app.address, subscriber_id
+        ))
+        .form(&[("attributes", attributes)])
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn get_subscriber_html(app: &TestApp, subscriber_id: Uuid) -> String {
+    app.api_client
+        .get(format!(
+            "{}/admin/subscribers/{}",
+            &app.address, subscriber_id
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .text()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn attributes_sent_with_the_sign_up_form_are_stored() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    create_confirmed_subscriber_with(
+        &app,
+        &[
+            ("attributes[company]", "Earthsea Ltd"),
+            ("attributes[role]", "Wizard"),
+        ],
+    )
+    .await;
+
+    // Assert
+    let (_, attributes) = get_subscriber(&app).await;
+    assert_eq!(
+        attributes,
+        json!({ "company": "Earthsea Ltd", "role": "Wizard" })
+    );
+}
+
+#[tokio::test]
+async fn attributes_sent_to_the_api_are_stored() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_api_subscriptions(&json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com",
+            "attributes": { "company": "Earthsea Ltd", "seats": 12, "is_customer": true }
+        }))
+        .await;
+
+    // Assert
+    assert!(response.status().is_success());
+    let (_, attributes) = get_subscriber(&app).await;
+    assert_eq!(
+        attributes,
+        json!({ "company": "Earthsea Ltd", "seats": 12, "is_customer": true })
+    );
+}
+
+#[tokio::test]
+async fn invalid_attributes_are_rejected_with_a_400() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let test_cases = vec![
+        (
+            json!({ "first name": "Ursula" }),
+            "an invalid attribute name",
+        ),
+        (
+            json!({ "company": { "name": "Earthsea" } }),
+            "a nested value",
+        ),
+        (
+            json!({ "company": "a".repeat(501) }),
+            "a value that is too long",
+        ),
+    ];
+
+    for (attributes, description) in test_cases {
+        // Act
+        let response = app
+            .post_api_subscriptions(&json!({
+                "name": "le guin",
+                "email": "ursula_le_guin@gmail.com",
+                "attributes": attributes
+            }))
+            .await;
+
+        // Assert
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload had {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn merge_tags_are_filled_in_for_each_recipient() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber_with(&app, &[("attributes[company]", "<Tom & Jerry>")]).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let newsletter_request_body = json!({
+        "title": "News for {{ name }}",
+        "text_content": "Dear {{ attributes.company }}, {{ attributes.role | default: \"friend\" }}",
+        "html_content": "<p>Dear {{ attributes.company }}, {{ attributes.role | default: 'friend' }}</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    let response = app.post_publish_newsletter(&newsletter_request_body).await;
+    TestApp::assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["Subject"], "News for le guin");
+    assert!(body["TextBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("Dear <Tom & Jerry>, friend"));
+    assert!(body["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("<p>Dear &lt;Tom &amp; Jerry&gt;, friend</p>"));
+}
+
+#[tokio::test]
+async fn issues_with_malformed_merge_tags_are_not_published() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Submit newsletter form
+    let newsletter_request_body = json!({
+        "title": "Newsletter title",
+        "text_content": "Hi {{ surname }}",
+        "html_content": "<p>Hi {{ name </p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    let response = app.post_publish_newsletter(&newsletter_request_body).await;
+    TestApp::assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(html_page.contains("The text content is invalid:"));
+
+    // Assert
+    let issues = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(issues.is_empty());
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we haven't sent the newsletter
+}
+
+#[tokio::test]
+async fn admins_can_edit_a_subscribers_attributes() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber_with(&app, &[("attributes[company]", "Earthsea Ltd")]).await;
+    app.test_user.login(&app).await;
+    let (subscriber_id, _) = get_subscriber(&app).await;
+
+    // Act - Part 1 - Replace the attributes
+    let response =
+        post_attributes(&app, subscriber_id, r#"{"role": "Archmage", "seats": 3}"#).await;
+    TestApp::assert_is_redirect_to(&response, &format!("/admin/subscribers/{subscriber_id}"));
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = get_subscriber_html(&app, subscriber_id).await;
+    assert!(html_page.contains("The attributes have been saved."));
+    assert!(html_page.contains("<tr><td>role</td><td>&quot;Archmage&quot;</td></tr>"));
+
+    // Assert
+    let (_, attributes) = get_subscriber(&app).await;
+    assert_eq!(attributes, json!({ "role": "Archmage", "seats": 3 }));
+}
+
+#[tokio::test]
+async fn invalid_attributes_are_not_saved_by_admins() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber_with(&app, &[("attributes[company]", "Earthsea Ltd")]).await;
+    app.test_user.login(&app).await;
+    let (subscriber_id, _) = get_subscriber(&app).await;
+    let test_cases = vec![
+        ("not json", "The attributes are not valid JSON"),
+        ("[1, 2]", "The attributes must be a JSON object."),
+        (r#"{"a.b": 1}"#, "a.b is not a valid attribute name."),
+    ];
+
+    for (attributes, error_message) in test_cases {
+        // Act
+        let response = post_attributes(&app, subscriber_id, attributes).await;
+
+        // Assert
+        TestApp::assert_is_redirect_to(&response, &format!("/admin/subscribers/{subscriber_id}"));
+        let html_page = get_subscriber_html(&app, subscriber_id).await;
+        assert!(html_page.contains(error_message));
+    }
+    let (_, attributes) = get_subscriber(&app).await;
+    assert_eq!(attributes, json!({ "company": "Earthsea Ltd" }));
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_edit_attributes() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber_with(&app, &[("attributes[company]", "Earthsea Ltd")]).await;
+    let (subscriber_id, _) = get_subscriber(&app).await;
+
+    // Act
+    let response = post_attributes(&app, subscriber_id, "{}").await;
+
+    // Assert
+    TestApp::assert_is_redirect_to(&response, "/login");
+    let (_, attributes) = get_subscriber(&app).await;
+    assert_eq!(attributes, json!({ "company": "Earthsea Ltd" }));
+}
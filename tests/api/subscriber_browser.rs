@@ -0,0 +1,234 @@
+//! tests/api/subscriber_browser.rs
+
+use std::collections::HashSet;
+
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{create_confirmed_subscriber, TestApp};
+
+async fn get_html(app: &TestApp, path: &str) -> String {
+    app.api_client
+        .get(format!("{}{}", &app.address, path))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .text()
+        .await
+        .unwrap()
+}
+
+/// `count` confirmed subscribers, one second apart: `subscriber1` is the newest.
+async fn insert_subscribers(app: &TestApp, count: i32) {
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (
+            id, email, email_canonical, email_display, name, subscribed_at, status
+        )
+        SELECT
+            gen_random_uuid(),
+            'subscriber' || i || '@example.com',
+            'subscriber' || i || '@example.com',
+            'subscriber' || i || '@example.com',
+            'Subscriber ' || i,
+            now() - i * interval '1 second',
+            'confirmed'
+        FROM generate_series(1, $1) AS i
+        "#,
+        count
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+/// The addresses listed on a page of the browser.
+fn listed_emails(html_page: &str) -> Vec<String> {
+    html_page
+        .split(r#"<tr><td><a href="/admin/subscribers/"#)
+        .skip(1)
+        .map(|row| {
+            let start = row.find('>').unwrap() + 1;
+            let end = row.find("</a>").unwrap();
+            row[start..end].to_owned()
+        })
+        .collect()
+}
+
+fn next_page_link(html_page: &str) -> Option<String> {
+    let end = html_page.find(r#"">Next page"#)?;
+    let start = html_page[..end].rfind(r#"href=""#)? + r#"href=""#.len();
+    Some(html_page[start..end].replace("&amp;", "&"))
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_browse_subscribers() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/admin/subscribers?q=subscriber", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    TestApp::assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn every_subscriber_is_listed_once_across_the_pages() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    insert_subscribers(&app, 120).await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let mut pages = vec![get_html(&app, "/admin/subscribers").await];
+    while let Some(next) = next_page_link(pages.last().unwrap()) {
+        pages.push(get_html(&app, &next).await);
+    }
+
+    // Assert
+    assert_eq!(pages.len(), 3);
+    let emails: Vec<_> = pages.iter().flat_map(|page| listed_emails(page)).collect();
+    assert_eq!(emails.len(), 120);
+    assert_eq!(emails.iter().collect::<HashSet<_>>().len(), 120);
+    // Newest first
+    assert_eq!(emails[0], "subscriber1@example.com");
+    assert_eq!(emails[119], "subscriber120@example.com");
+}
+
+#[tokio::test]
+async fn subscribers_can_be_searched_and_filtered() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    insert_subscribers(&app, 120).await;
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET status = 'unsubscribed', subscribed_at = '2020-06-15T12:00:00Z'
+        WHERE email IN ('subscriber110@example.com', 'subscriber111@example.com')
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.test_user.login(&app).await;
+    let test_cases = vec![
+        (
+            "q=SUBSCRIBER11",
+            vec![
+                "subscriber11@example.com",
+                "subscriber112@example.com",
+                "subscriber113@example.com",
+                "subscriber114@example.com",
+                "subscriber115@example.com",
+                "subscriber116@example.com",
+                "subscriber117@example.com",
+                "subscriber118@example.com",
+                "subscriber119@example.com",
+                "subscriber110@example.com",
+                "subscriber111@example.com",
+            ],
+        ),
+        ("q=Subscriber+99&status=", vec!["subscriber99@example.com"]),
+        (
+            "status=unsubscribed",
+            vec!["subscriber110@example.com", "subscriber111@example.com"],
+        ),
+        (
+            "q=subscriber11&from=2020-06-01&to=2020-06-15",
+            vec!["subscriber110@example.com", "subscriber111@example.com"],
+        ),
+        ("q=50%25", vec![]),
+    ];
+
+    for (query, expected) in test_cases {
+        // Act
+        let html_page = get_html(&app, &format!("/admin/subscribers?{}", query)).await;
+
+        // Assert
+        let mut emails = listed_emails(&html_page);
+        let mut expected: Vec<_> = expected.into_iter().map(String::from).collect();
+        emails.sort();
+        expected.sort();
+        assert_eq!(emails, expected, "Unexpected subscribers for {}", query);
+        assert!(next_page_link(&html_page).is_none());
+    }
+}
+
+#[tokio::test]
+async fn invalid_filters_are_reported() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+    let test_cases = vec![
+        ("status=active", "active is not a known status."),
+        ("to=tomorrow", "tomorrow is not a date."),
+        ("after=123", "123 is not a position in the subscriber list."),
+    ];
+
+    for (query, error_message) in test_cases {
+        // Act
+        let response = app
+            .api_client
+            .get(format!("{}/admin/subscribers?{}", &app.address, query))
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        TestApp::assert_is_redirect_to(&response, "/admin/subscribers");
+        let html_page = get_html(&app, "/admin/subscribers").await;
+        assert!(html_page.contains(error_message), "{}", error_message);
+    }
+}
+
+#[tokio::test]
+async fn the_subscriber_page_shows_lists_confirmation_links_and_issues() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_publish_newsletter(&serde_json::json!({
+        "title": "Our first issue",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+
+    // Act
+    let html_page = get_html(&app, &format!("/admin/subscribers/{subscriber_id}")).await;
+
+    // Assert
+    assert!(html_page.contains("<h2>Lists</h2>"));
+    assert!(html_page.contains("<tr><td>newsletter</td><td>confirmed</td>"));
+    assert!(html_page.contains("<h2>Confirmation links</h2>"));
+    let tokens = html_page
+        .split("<h2>Confirmation links</h2>")
+        .nth(1)
+        .unwrap()
+        .split("<h2>")
+        .next()
+        .unwrap();
+    assert!(tokens.contains("<tr><td>newsletter</td>"));
+    assert!(html_page.contains("<tr><td>Our first issue</td><td>sent</td>"));
+}
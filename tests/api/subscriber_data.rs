@@ -0,0 +1,239 @@
+//! tests/api/subscriber_data.rs
+
+use reqwest::Response;
+use serde_json::Value;
+use uuid::Uuid;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero_to_prod::routes::manage_link;
+
+use crate::helpers::{create_confirmed_subscriber, TestApp};
+
+async fn post_admin_action(app: &TestApp, action: &str, email: &str) -> Response {
+    app.api_client
+        .post(format!("{}/admin/subscriber-data/{}", app.address, action))
+        .form(&[("email", email)])
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+/// A confirmed subscriber with one delivered issue and one still queued.
+async fn create_subscriber_with_history(app: &TestApp) -> (Uuid, String) {
+    create_confirmed_subscriber(app).await;
+    app.test_user.login(app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    for title in ["Delivered issue", "Queued issue"] {
+        let newsletter_request_body = serde_json::json!({
+            "title": title,
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": Uuid::new_v4().to_string()
+        });
+        app.post_publish_newsletter(&newsletter_request_body).await;
+
+        if title == "Delivered issue" {
+            app.dispatch_all_pending_emails().await;
+        }
+    }
+
+    let saved = sqlx::query!("SELECT id, email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    (saved.id, saved.email)
+}
+
+async fn count_rows_held(app: &TestApp, subscriber_id: Uuid, email: &str) -> i64 {
+    sqlx::query_scalar!(
+        r#"
+        SELECT
+            (SELECT COUNT(*) FROM subscriptions WHERE id = $1)
+            + (SELECT COUNT(*) FROM list_memberships WHERE subscriber_id = $1)
+            + (SELECT COUNT(*) FROM subscription_tokens WHERE subscriber_id = $1)
+            + (SELECT COUNT(*) FROM issue_deliveries WHERE subscriber_id = $1)
+            + (SELECT COUNT(*) FROM consent_records WHERE subscriber_id = $1)
+            + (SELECT COUNT(*) FROM issue_delivery_queue WHERE subscriber_email = $2)
+            AS "count!"
+        "#,
+        subscriber_id,
+        email
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_handle_data_requests() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let response = post_admin_action(&app, "export", "ursula_le_guin@gmail.com").await;
+
+    // Assert
+    TestApp::assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn admins_can_export_everything_held_about_an_email() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let (subscriber_id, email) = create_subscriber_with_history(&app).await;
+
+    // Act
+    let response = post_admin_action(&app, "export", &email).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let disposition = response.headers()["Content-Disposition"].to_str().unwrap();
+    assert!(disposition.starts_with("attachment"));
+
+    let export: Value = response.json().await.unwrap();
+    assert_eq!(export["subscriber"]["id"], subscriber_id.to_string());
+    assert_eq!(export["subscriber"]["email"], email);
+    assert_eq!(export["list_memberships"][0]["list"], "newsletter");
+    assert_eq!(export["list_memberships"][0]["status"], "confirmed");
+    assert_eq!(export["subscription_tokens"].as_array().unwrap().len(), 1);
+    assert_eq!(export["deliveries"][0]["title"], "Delivered issue");
+    assert_eq!(export["deliveries"][0]["outcome"], "sent");
+    assert_eq!(export["queued_deliveries"][0]["title"], "Queued issue");
+    assert_eq!(export["consent_records"][0]["event"], "sign_up");
+    assert_eq!(export["consent_records"][1]["event"], "confirmation");
+    assert!(export["consent_records"][0]
+        .get("subscription_token_hash")
+        .is_none());
+}
+
+#[tokio::test]
+async fn exports_say_where_the_subscriber_came_from() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let email = "ursula_le_guin@gmail.com";
+    let body = serde_urlencoded::to_string([
+        ("name", "le guin"),
+        ("email", email),
+        ("utm_source", "mastodon"),
+        ("utm_medium", "social"),
+        ("utm_campaign", "spring-launch"),
+        ("source", "footer-form"),
+    ])
+    .unwrap();
+    app.api_client
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("Referer", "https://blog.example.com/posts/42")
+        .body(body)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = post_admin_action(&app, "export", email).await;
+
+    // Assert
+    let export: Value = response.json().await.unwrap();
+    let subscriber = &export["subscriber"];
+    assert_eq!(subscriber["utm_source"], "mastodon");
+    assert_eq!(subscriber["utm_medium"], "social");
+    assert_eq!(subscriber["utm_campaign"], "spring-launch");
+    assert_eq!(subscriber["referrer"], "https://blog.example.com/posts/42");
+    assert_eq!(subscriber["source"], "footer-form");
+}
+
+#[tokio::test]
+async fn exporting_an_unknown_email_says_nothing_is_held() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act - Part 1 - Request the export
+    let response = post_admin_action(&app, "export", "nobody@example.com").await;
+
+    // Assert - Part 1
+    TestApp::assert_is_redirect_to(&response, "/admin/subscriber-data");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app
+        .api_client
+        .get(format!("{}/admin/subscriber-data", app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    // Assert - Part 2
+    assert!(html_page.contains("No data is held about nobody@example.com."));
+}
+
+#[tokio::test]
+async fn admins_can_erase_everything_held_about_an_email() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let (subscriber_id, email) = create_subscriber_with_history(&app).await;
+    assert!(count_rows_held(&app, subscriber_id, &email).await > 0);
+
+    // Act
+    let response = post_admin_action(&app, "erase", &email).await;
+
+    // Assert
+    TestApp::assert_is_redirect_to(&response, "/admin/subscriber-data");
+    assert_eq!(count_rows_held(&app, subscriber_id, &email).await, 0);
+}
+
+#[tokio::test]
+async fn subscribers_can_export_their_own_data_from_the_preference_center() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let (subscriber_id, email) = create_subscriber_with_history(&app).await;
+    let link = manage_link(&app.address, subscriber_id, &app.hmac_secret)
+        .replace("/subscriptions/manage?", "/subscriptions/manage/export?");
+
+    // Act
+    let response = reqwest::get(link).await.unwrap();
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let export: Value = response.json().await.unwrap();
+    assert_eq!(export["subscriber"]["email"], email);
+}
+
+#[tokio::test]
+async fn subscribers_can_erase_their_own_data_from_the_preference_center() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let (subscriber_id, email) = create_subscriber_with_history(&app).await;
+    let link = manage_link(&app.address, subscriber_id, &app.hmac_secret)
+        .replace("/subscriptions/manage?", "/subscriptions/manage/erase?");
+
+    // Act
+    let response = reqwest::Client::new().post(&link).send().await.unwrap();
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(count_rows_held(&app, subscriber_id, &email).await, 0);
+
+    // The link is useless once the data is gone
+    let response = reqwest::Client::new().post(&link).send().await.unwrap();
+    assert_eq!(410, response.status().as_u16());
+}
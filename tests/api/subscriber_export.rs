@@ -0,0 +1,231 @@
+//! tests/api/subscriber_export.rs
+
+use std::collections::HashSet;
+
+use reqwest::Response;
+use serde_json::{json, Value};
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero_to_prod::csv_file;
+
+use crate::helpers::{create_unconfirmed_subscriber, TestApp};
+
+async fn get_export(app: &TestApp, query: &str) -> Response {
+    app.api_client
+        .get(format!(
+            "{}/admin/subscribers/export?{}",
+            &app.address, query
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn create_confirmed_subscriber_on_plan(app: &TestApp, email: &str, plan: &str) {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let body = serde_urlencoded::to_string([
+        ("name", "le guin"),
+        ("email", email),
+        ("attributes[plan]", plan),
+    ])
+    .unwrap();
+    app.post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let confirmation_links = app.get_confirmation_links(email_request);
+    app.post_confirmation(&confirmation_links.html)
+        .await
+        .error_for_status()
+        .unwrap();
+}
+
+fn ndjson_lines(body: &str) -> Vec<Value> {
+    body.lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_export_subscribers() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let response = get_export(&app, "format=csv").await;
+
+    // Assert
+    TestApp::assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn all_subscribers_are_exported_as_csv_by_default() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber_on_plan(&app, "pro@example.com", "pro").await;
+    create_unconfirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = get_export(&app, "").await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/csv; charset=utf-8"
+    );
+    assert!(response.headers()["Content-Disposition"]
+        .to_str()
+        .unwrap()
+        .starts_with("attachment"));
+    let records = csv_file::parse(&response.bytes().await.unwrap()).unwrap();
+    assert_eq!(records.len(), 3);
+    assert_eq!(
+        records[0].fields,
+        vec![
+            "id",
+            "email",
+            "name",
+            "status",
+            "subscribed_at",
+            "lists",
+            "attributes"
+        ]
+    );
+    let pro = &records[1].fields;
+    assert_eq!(pro[1], "pro@example.com");
+    assert_eq!(pro[3], "confirmed");
+    assert_eq!(pro[5], "newsletter:confirmed");
+    assert_eq!(
+        serde_json::from_str::<Value>(&pro[6]).unwrap(),
+        json!({ "plan": "pro" })
+    );
+    assert_eq!(records[2].fields[3], "pending_confirmation");
+}
+
+#[tokio::test]
+async fn subscribers_can_be_exported_as_ndjson_filtered_by_status_and_date() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber_on_plan(&app, "old@example.com", "pro").await;
+    create_confirmed_subscriber_on_plan(&app, "new@example.com", "free").await;
+    create_unconfirmed_subscriber(&app).await;
+    sqlx::query!(
+        "UPDATE subscriptions SET subscribed_at = '2020-06-15T12:00:00Z' \
+        WHERE email = 'old@example.com'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.test_user.login(&app).await;
+
+    // Act - Part 1 - By status
+    let response = get_export(&app, "format=ndjson&status=confirmed&from=&to=").await;
+    assert_eq!(response.headers()["Content-Type"], "application/x-ndjson");
+    let subscribers = ndjson_lines(&response.text().await.unwrap());
+
+    // Assert - Part 1
+    let emails: Vec<_> = subscribers.iter().map(|s| s["email"].clone()).collect();
+    assert_eq!(
+        emails,
+        vec![json!("old@example.com"), json!("new@example.com")]
+    );
+    assert_eq!(
+        subscribers[0]["lists"],
+        json!([{ "list": "newsletter", "status": "confirmed" }])
+    );
+    assert_eq!(subscribers[0]["attributes"], json!({ "plan": "pro" }));
+
+    // Act - Part 2 - By date, both days included
+    let response = get_export(&app, "format=ndjson&from=2020-06-01&to=2020-06-15").await;
+    let subscribers = ndjson_lines(&response.text().await.unwrap());
+
+    // Assert - Part 2
+    assert_eq!(subscribers.len(), 1);
+    assert_eq!(subscribers[0]["email"], "old@example.com");
+}
+
+#[tokio::test]
+async fn large_lists_are_exported_in_full() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (
+            id, email, email_canonical, email_display, name, subscribed_at, status
+        )
+        SELECT
+            gen_random_uuid(),
+            'subscriber' || i || '@example.com',
+            'subscriber' || i || '@example.com',
+            'subscriber' || i || '@example.com',
+            'Subscriber ' || i,
+            now() - i * interval '1 second',
+            'confirmed'
+        FROM generate_series(1, 1234) AS i
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = get_export(&app, "format=ndjson").await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let subscribers = ndjson_lines(&response.text().await.unwrap());
+    assert_eq!(subscribers.len(), 1234);
+    let ids: HashSet<_> = subscribers.iter().map(|s| s["id"].to_string()).collect();
+    assert_eq!(ids.len(), 1234);
+    // Oldest first
+    assert_eq!(subscribers[0]["email"], "subscriber1234@example.com");
+}
+
+#[tokio::test]
+async fn invalid_export_filters_are_reported() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+    let test_cases = vec![
+        ("format=xml", "xml is not a known export format."),
+        ("status=active", "active is not a known status."),
+        ("from=15/06/2020", "15/06/2020 is not a date."),
+    ];
+
+    for (query, error_message) in test_cases {
+        // Act
+        let response = get_export(&app, query).await;
+
+        // Assert
+        TestApp::assert_is_redirect_to(&response, "/admin/subscribers");
+        let html_page = app
+            .api_client
+            .get(format!("{}/admin/subscribers", &app.address))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert!(html_page.contains(error_message), "{}", error_message);
+    }
+}
@@ -0,0 +1,411 @@
+//! tests/api/subscriber_import.rs
+
+use reqwest::Response;
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{create_confirmed_subscriber, TestApp};
+
+async fn post_subscribers_import(app: &TestApp, csv: &str, mode: &str) -> Response {
+    // As the form orders them: the file comes last.
+    let body = format!(
+        "--boundary\r\n\
+        Content-Disposition: form-data; name=\"list\"\r\n\
+        \r\n\
+        newsletter\r\n\
+        --boundary\r\n\
+        Content-Disposition: form-data; name=\"mode\"\r\n\
+        \r\n\
+        {mode}\r\n\
+        --boundary\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"subscribers.csv\"\r\n\
+        Content-Type: text/csv\r\n\
+        \r\n\
+        {csv}\r\n\
+        --boundary--\r\n"
+    );
+
+    app.api_client
+        .post(format!("{}/admin/subscribers/import", &app.address))
+        .header("Content-Type", "multipart/form-data; boundary=boundary")
+        .body(body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn get(app: &TestApp, path: &str) -> Response {
+    app.api_client
+        .get(format!("{}{}", &app.address, path))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+/// Where the import redirected to, i.e. its results page.
+fn results_page(response: &Response) -> String {
+    assert_eq!(response.status().as_u16(), 303);
+    let location = response.headers()["Location"].to_str().unwrap();
+    assert!(location.starts_with("/admin/subscribers/import/"));
+
+    location.to_owned()
+}
+
+async fn get_existing_email(app: &TestApp) -> String {
+    sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .email
+}
+
+async fn get_subscribers(app: &TestApp) -> Vec<(String, String, String)> {
+    sqlx::query!(
+        r#"
+        SELECT subscriptions.email, subscriptions.status, list_memberships.status AS membership
+        FROM subscriptions
+        JOIN list_memberships ON list_memberships.subscriber_id = subscriptions.id
+        ORDER BY subscriptions.email
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|row| (row.email, row.status, row.membership))
+    .collect()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_import_subscribers() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let form = get(&app, "/admin/subscribers/import").await;
+    let import = post_subscribers_import(&app, "email,name\na@example.com,A", "confirmed").await;
+
+    // Assert
+    TestApp::assert_is_redirect_to(&form, "/login");
+    TestApp::assert_is_redirect_to(&import, "/login");
+    assert!(get_subscribers(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn subscribers_can_be_imported_as_confirmed() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let existing_email = get_existing_email(&app).await;
+    app.test_user.login(&app).await;
+    app.api_client
+        .post(format!("{}/admin/suppressions", &app.address))
+        .form(&[("email", "suppressed@example.com"), ("reason", "complaint")])
+        .send()
+        .await
+        .unwrap();
+    let csv = format!(
+        "email,name,company\r\n\
+        a@example.com,Alice,Earthsea\r\n\
+        b@example.com,Bob,\r\n\
+        not-an-email,Nobody,\r\n\
+        A@example.com,Alice again,\r\n\
+        suppressed@example.com,Sam,\r\n\
+        {existing_email},Ursula,"
+    );
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Upload the file
+    let response = post_subscribers_import(&app, &csv, "confirmed").await;
+    let results = results_page(&response);
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = get(&app, &results).await.text().await.unwrap();
+    assert!(html_page.contains("Imported 2 subscribers to Newsletter"));
+    assert!(html_page.contains("4 rows were rejected."));
+
+    // Assert
+    let subscribers = get_subscribers(&app).await;
+    assert_eq!(subscribers.len(), 3);
+    for email in ["a@example.com", "b@example.com"] {
+        assert!(subscribers.contains(&(email.into(), "confirmed".into(), "confirmed".into())));
+    }
+    let alice = sqlx::query!(
+        r#"
+        SELECT subscriptions.name, subscriptions.attributes, consent_records.event
+        FROM subscriptions
+        JOIN consent_records ON consent_records.subscriber_id = subscriptions.id
+        WHERE email = 'a@example.com'
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(alice.name, "Alice");
+    assert_eq!(
+        alice.attributes,
+        serde_json::json!({ "company": "Earthsea" })
+    );
+    assert_eq!(alice.event, "import");
+    // Mock verifies on Drop that no confirmation email went out
+}
+
+#[tokio::test]
+async fn the_rejected_rows_can_be_downloaded_with_their_reason() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let existing_email = get_existing_email(&app).await;
+    app.test_user.login(&app).await;
+    let csv = format!(
+        "name,email\n\
+        Nobody,not-an-email\n\
+        Alice,a@example.com\n\
+        \"Le Guin, Ursula\",{existing_email}\n"
+    );
+
+    // Act
+    let response = post_subscribers_import(&app, &csv, "confirmed").await;
+    let results = results_page(&response);
+    let report = get(&app, &format!("{}/report", results)).await;
+
+    // Assert
+    assert_eq!(200, report.status().as_u16());
+    assert_eq!(report.headers()["Content-Type"], "text/csv; charset=utf-8");
+    assert!(report.headers()["Content-Disposition"]
+        .to_str()
+        .unwrap()
+        .starts_with("attachment"));
+    assert_eq!(
+        report.text().await.unwrap(),
+        format!(
+            "line,email,name,reason\r\n\
+            2,not-an-email,Nobody,not-an-email is not a valid subscriber email.\r\n\
+            4,{existing_email},\"Le Guin, Ursula\",There already is a subscriber with this address.\r\n"
+        )
+    );
+}
+
+#[tokio::test]
+async fn erasing_a_subscriber_scrubs_them_from_import_reports() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let existing_email = get_existing_email(&app).await;
+    app.test_user.login(&app).await;
+    let csv = format!("name,email\nLe Guin,{}\n", existing_email.to_uppercase());
+    let response = post_subscribers_import(&app, &csv, "confirmed").await;
+    let results = results_page(&response);
+
+    // Act
+    app.api_client
+        .post(format!("{}/admin/subscriber-data/erase", &app.address))
+        .form(&[("email", &existing_email)])
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    let report = get(&app, &format!("{}/report", results))
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(
+        report,
+        "line,email,name,reason\r\n\
+        2,,,There already is a subscriber with this address.\r\n"
+    );
+}
+
+#[tokio::test]
+async fn subscribers_imported_with_double_opt_in_are_sent_a_confirmation_link() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+    let csv = "email,name\na@example.com,Alice\nb@example.com,Bob\n";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Upload the file
+    let response = post_subscribers_import(&app, csv, "double_opt_in").await;
+    results_page(&response);
+
+    // Assert - Part 1
+    assert_eq!(
+        get_subscribers(&app).await,
+        vec![
+            (
+                "a@example.com".into(),
+                "pending_confirmation".into(),
+                "pending_confirmation".into()
+            ),
+            (
+                "b@example.com".into(),
+                "pending_confirmation".into(),
+                "pending_confirmation".into()
+            ),
+        ]
+    );
+
+    // Act - Part 2 - Let the worker send the links
+    assert!(app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .is_empty());
+    app.dispatch_queued_confirmations().await;
+
+    // Act - Part 3 - Confirm one of them
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    app.post_confirmation(&confirmation_links.html)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Assert - Part 3
+    let statuses: Vec<_> = get_subscribers(&app)
+        .await
+        .into_iter()
+        .map(|(_, status, _)| status)
+        .collect();
+    assert!(statuses.contains(&"confirmed".to_string()));
+    assert!(statuses.contains(&"pending_confirmation".to_string()));
+}
+
+#[tokio::test]
+async fn large_files_are_imported_in_full() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+    let mut csv = String::from("email,name\n");
+    for i in 0..1234 {
+        csv.push_str(&format!("subscriber{i}@example.com,Subscriber {i}\n"));
+    }
+
+    // Act
+    let response = post_subscribers_import(&app, &csv, "confirmed").await;
+
+    // Assert
+    let results = results_page(&response);
+    let html_page = get(&app, &results).await.text().await.unwrap();
+    assert!(html_page.contains("Imported 1234 subscribers"));
+    assert_eq!(get_subscribers(&app).await.len(), 1234);
+}
+
+#[tokio::test]
+async fn an_unusable_file_imports_nothing() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+    let test_cases = vec![
+        (
+            "email\na@example.com\n",
+            "confirmed",
+            "The first row of the file has no name column.",
+        ),
+        (
+            "email,name,first name\na@example.com,A,B\n",
+            "confirmed",
+            "The column first name cannot be an attribute.",
+        ),
+        (
+            "email,name\na@example.com,A\n",
+            "everyone",
+            "everyone is not a known import mode.",
+        ),
+        ("", "confirmed", "Choose a CSV file to import."),
+        ("\n\n", "confirmed", "The file is empty."),
+    ];
+
+    for (csv, mode, error_message) in test_cases {
+        // Act
+        let response = post_subscribers_import(&app, csv, mode).await;
+
+        // Assert
+        TestApp::assert_is_redirect_to(&response, "/admin/subscribers/import");
+        let html_page = get(&app, "/admin/subscribers/import")
+            .await
+            .text()
+            .await
+            .unwrap();
+        assert!(html_page.contains(error_message), "{}", error_message);
+    }
+    assert!(get_subscribers(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn imported_subscribers_who_bounce_before_their_link_goes_out_are_not_sent_one() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+    let csv = "email,name\njohn@example.com,John\nb@example.com,Bob\n";
+    post_subscribers_import(&app, csv, "double_opt_in").await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_postmark_webhook(include_str!("fixtures/postmark/hard_bounce.json"))
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_queued_confirmations().await;
+
+    // Assert
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], "b@example.com");
+    // Mock verifies on Drop that John was not sent a link
+}
+
+#[tokio::test]
+async fn queued_confirmations_of_subscribers_no_longer_pending_are_dropped() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+    post_subscribers_import(&app, "email,name\na@example.com,Alice\n", "double_opt_in").await;
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.dispatch_queued_confirmations().await;
+
+    // Assert
+    let queued = sqlx::query!("SELECT subscriber_id FROM confirmation_email_queue")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(queued.is_empty());
+    let tokens = sqlx::query!("SELECT subscriber_id FROM subscription_tokens")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(tokens.is_empty());
+}
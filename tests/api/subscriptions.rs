@@ -5,6 +5,8 @@ use wiremock::{
     Mock, ResponseTemplate,
 };
 
+use zero_to_prod::configuration::RouteRateLimits;
+
 use crate::helpers::TestApp;
 
 #[tokio::test]
@@ -113,7 +115,7 @@ async fn subscribe_sends_a_confirmation_email_with_a_link() {
 
     // Assert
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = app.get_confirmation_links(&email_request);
+    let confirmation_links = app.get_confirmation_links(email_request);
 
     assert_eq!(confirmation_links.html, confirmation_links.plain_text);
 }
@@ -189,3 +191,226 @@ async fn subscribe_returns_a_400_when_fields_are_present_but_invalid() {
         );
     }
 }
+
+#[tokio::test]
+async fn subscribing_twice_while_pending_sends_a_fresh_confirmation_email() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let first_response = app.post_subscriptions(body.into()).await;
+    let second_response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(200, first_response.status().as_u16());
+    assert_eq!(200, second_response.status().as_u16());
+
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let first_link = app.get_confirmation_links(&email_requests[0]).html;
+    let second_link = app.get_confirmation_links(&email_requests[1]).html;
+    assert_ne!(first_link, second_link);
+
+    // Only the most recent link confirms the subscription.
+    let response = app.post_confirmation(&first_link).await;
+    assert_eq!(401, response.status().as_u16());
+    let response = app.post_confirmation(&second_link).await;
+    assert_eq!(200, response.status().as_u16());
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriptions.");
+    assert_eq!(saved.len(), 1);
+}
+
+#[tokio::test]
+async fn subscribing_again_once_confirmed_returns_a_200_without_side_effects() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    let mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    app.post_confirmation(&confirmation_links.html)
+        .await
+        .error_for_status()
+        .unwrap();
+    drop(mock_guard);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriptions.");
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].status, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribing_again_after_unsubscribing_restarts_double_opt_in() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (
+            id, email, email_canonical, email_display, name, subscribed_at, status
+        )
+        VALUES (
+            $1,
+            'ursula_le_guin@gmail.com',
+            'ursula_le_guin@gmail.com',
+            'ursula_le_guin@gmail.com',
+            'le guin',
+            now(),
+            'unsubscribed'
+        )
+        "#,
+        uuid::Uuid::new_v4()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn concurrent_first_sign_ups_for_the_same_address_all_succeed() {
+    // Arrange
+    let app = TestApp::spawn_app_with(|config| {
+        config.application.rate_limits.subscribe = RouteRateLimits::default();
+    })
+    .await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(10)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let responses =
+        futures::future::join_all((0..10).map(|_| app.post_subscriptions(body.into()))).await;
+
+    // Assert
+    for response in responses {
+        assert_eq!(200, response.status().as_u16());
+    }
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriptions.");
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn variants_of_the_same_address_are_the_same_subscriber() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    for email in [
+        "Bob@Example.com%20",
+        "bob@example.com",
+        "%20BOB@EXAMPLE.COM",
+    ] {
+        let response = app
+            .post_subscriptions(format!("name=bob&email={}", email))
+            .await;
+        assert_eq!(200, response.status().as_u16());
+    }
+
+    // Assert
+    let saved = sqlx::query!("SELECT email, email_canonical, email_display FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriptions.");
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].email, "Bob@example.com");
+    assert_eq!(saved[0].email_canonical, "bob@example.com");
+    assert_eq!(saved[0].email_display, "Bob@Example.com");
+}
+
+#[tokio::test]
+async fn internationalized_domains_are_stored_as_punycode() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let body = "name=le%20guin&email=ursula%40b%C3%BCcher.de";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+
+    let saved = sqlx::query!("SELECT email, email_display FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.email, "ursula@xn--bcher-kva.de");
+    assert_eq!(saved.email_display, "ursula@bücher.de");
+}
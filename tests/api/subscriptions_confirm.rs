@@ -3,7 +3,7 @@ use wiremock::{
     Mock, ResponseTemplate,
 };
 
-use crate::helpers::TestApp;
+use crate::helpers::{create_unconfirmed_subscriber, TestApp};
 
 #[tokio::test]
 async fn confirmations_without_token_are_rejected_with_a_400() {
@@ -44,7 +44,28 @@ async fn the_link_returned_by_subscribe_returns_a_200_if_called() {
 }
 
 #[tokio::test]
-async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
+async fn following_the_confirmation_link_does_not_confirm_a_subscriber() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+
+    // Act - This is what a link scanner does
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains(r#"<form action="/subscriptions/confirm" method="post">"#));
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn submitting_the_confirmation_form_confirms_a_subscriber() {
     // Arrange
     let app = TestApp::spawn_app().await;
     let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
@@ -57,16 +78,19 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
 
     app.post_subscriptions(body.into()).await;
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = app.get_confirmation_links(&email_request);
+    let confirmation_links = app.get_confirmation_links(email_request);
 
     // Act
-    reqwest::get(confirmation_links.html)
+    let response = app.post_confirmation(&confirmation_links.html).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert!(response
+        .text()
         .await
         .unwrap()
-        .error_for_status()
-        .unwrap();
+        .contains("Your subscription has been confirmed."));
 
-    // Assert
     let saved = sqlx::query!("SELECT email, name, status FROM subscriptions",)
         .fetch_one(&app.db_pool)
         .await
@@ -76,3 +100,186 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
     assert_eq!(saved.name, "le guin");
     assert_eq!(saved.status, "confirmed");
 }
+
+#[tokio::test]
+async fn a_confirmation_link_cannot_be_used_twice() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+
+    app.post_confirmation(&confirmation_links.html)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = app.post_confirmation(&confirmation_links.html).await;
+
+    // Assert
+    assert_eq!(410, response.status().as_u16());
+    assert!(response.text().await.unwrap().contains("has expired"));
+
+    // Following the link again explains what happened too
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+    assert_eq!(410, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn an_expired_confirmation_link_is_rejected_with_a_410() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+
+    sqlx::query!("UPDATE subscription_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = app.post_confirmation(&confirmation_links.html).await;
+
+    // Assert
+    assert_eq!(410, response.status().as_u16());
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn resending_the_confirmation_issues_a_new_working_link() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    sqlx::query!("UPDATE subscription_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = app
+        .post_resend_confirmation("email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let confirmation_links = app.get_confirmation_links(&email_requests[1]);
+
+    let response = app.post_confirmation(&confirmation_links.html).await;
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn resending_for_an_unknown_address_returns_a_200_without_sending_an_email() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_resend_confirmation("email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn a_malformed_token_is_rejected_with_a_400() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let response = reqwest::get(&format!(
+        "{}/subscriptions/confirm?subscription_token=not-a-token'--",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn subscription_tokens_are_not_stored_in_plaintext() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+
+    // Assert
+    let token = confirmation_links
+        .html
+        .query_pairs()
+        .find(|(key, _)| key == "subscription_token")
+        .map(|(_, value)| value.into_owned())
+        .unwrap();
+
+    let saved = sqlx::query!("SELECT subscription_token_hash FROM subscription_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved token.");
+
+    assert_ne!(saved.subscription_token_hash, token);
+    assert_eq!(saved.subscription_token_hash.len(), 64);
+}
+
+#[tokio::test]
+async fn an_unknown_token_renders_an_explanation() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let response = reqwest::get(format!(
+        "{}/subscriptions/confirm?subscription_token={}",
+        app.address,
+        "a".repeat(25)
+    ))
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("We could not find a subscription for this link."));
+}
+
+#[tokio::test]
+async fn confirming_redirects_to_the_configured_thank_you_page() {
+    // Arrange
+    let app = TestApp::spawn_app_with(|config| {
+        config.application.confirmation_redirect_url = Some("https://example.com/thank-you".into());
+    })
+    .await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+
+    // Act
+    let response = app.post_confirmation(&confirmation_links.html).await;
+
+    // Assert
+    TestApp::assert_is_redirect_to(&response, "https://example.com/thank-you");
+}
@@ -0,0 +1,535 @@
+//! tests/api/subscriptions_manage.rs
+
+use chrono::{TimeDelta, Utc};
+use linkify::{LinkFinder, LinkKind};
+use reqwest::{Response, Url};
+use uuid::Uuid;
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+use zero_to_prod::domain::ManageToken;
+use zero_to_prod::routes::manage_link;
+
+use crate::helpers::{create_confirmed_subscriber, TestApp};
+
+async fn get_subscriber_id(app: &TestApp) -> Uuid {
+    sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id
+}
+
+/// The preference center link in an email's footer, pointed at the test app.
+fn find_manage_link(app: &TestApp, email_request: &wiremock::Request) -> Url {
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let text_body = body["TextBody"].as_str().unwrap();
+    let raw_link = LinkFinder::new()
+        .links(text_body)
+        .filter(|link| *link.kind() == LinkKind::Url)
+        .map(|link| link.as_str().to_owned())
+        .find(|link| link.contains("/subscriptions/manage"))
+        .unwrap();
+    assert!(body["HtmlBody"].as_str().unwrap().contains(&raw_link));
+
+    let mut link = Url::parse(&raw_link).unwrap();
+    link.set_port(Some(app.port)).unwrap();
+    link
+}
+
+async fn post_preferences(app: &TestApp, link: &str, fields: &[(&str, &str)]) -> Response {
+    app.api_client
+        .post(link)
+        .form(fields)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn newsletter_issues_link_to_the_preference_center() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": Uuid::new_v4().to_string()
+    });
+    app.post_publish_newsletter(&newsletter_request_body).await;
+
+    // Act
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let response = reqwest::get(find_manage_link(&app, &email_request))
+        .await
+        .unwrap();
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn confirmation_emails_link_to_the_preference_center() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let response = reqwest::get(find_manage_link(&app, email_request))
+        .await
+        .unwrap();
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn the_preference_center_rejects_forged_and_expired_links() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let subscriber_id = get_subscriber_id(&app).await;
+    let expired = ManageToken::generate(
+        subscriber_id,
+        Utc::now() - TimeDelta::minutes(1),
+        &app.hmac_secret,
+    );
+    let test_cases = vec![
+        (format!("{}.1.not-a-signature", subscriber_id), 400),
+        (expired.to_string(), 410),
+    ];
+
+    for (token, status) in test_cases {
+        // Act
+        let response = app
+            .api_client
+            .get(format!(
+                "{}/subscriptions/manage?token={}",
+                app.address, token
+            ))
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(status, response.status().as_u16());
+    }
+}
+
+#[tokio::test]
+async fn subscribers_can_update_their_name_and_lists() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    sqlx::query!(
+        "INSERT INTO lists (list_id, slug, name) VALUES ($1, 'weekly', 'The Weekly Digest')",
+        Uuid::new_v4()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let subscriber_id = get_subscriber_id(&app).await;
+    let email = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .email;
+    let link = manage_link(&app.address, subscriber_id, &app.hmac_secret);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Swap the default list for `weekly`
+    let response = post_preferences(
+        &app,
+        &link,
+        &[
+            ("name", "Ursula Le Guin"),
+            ("email", &email),
+            ("list", "weekly"),
+            ("status", "subscribed"),
+        ],
+    )
+    .await;
+
+    // Assert - Part 1
+    assert_eq!(303, response.status().as_u16());
+
+    let memberships = sqlx::query!(
+        r#"
+        SELECT lists.slug, list_memberships.status
+        FROM list_memberships
+        JOIN lists ON lists.list_id = list_memberships.list_id
+        ORDER BY lists.slug
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    let memberships: Vec<_> = memberships
+        .into_iter()
+        .map(|row| (row.slug, row.status))
+        .collect();
+    assert_eq!(
+        memberships,
+        vec![
+            ("newsletter".to_string(), "unsubscribed".to_string()),
+            ("weekly".to_string(), "pending_confirmation".to_string()),
+        ]
+    );
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app
+        .api_client
+        .get(&link)
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    // Assert - Part 2
+    assert!(html_page.contains("Your preferences have been saved."));
+    assert!(html_page.contains("to confirm the lists you joined."));
+    assert!(html_page.contains(r#"value="Ursula Le Guin""#));
+
+    // Act - Part 3 - Confirm the new list
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let confirmation_links = app.get_confirmation_links(&email_request);
+    let response = app.post_confirmation(&confirmation_links.html).await;
+
+    // Assert - Part 3
+    assert_eq!(200, response.status().as_u16());
+    let membership = sqlx::query!(
+        r#"
+        SELECT list_memberships.status
+        FROM list_memberships
+        JOIN lists ON lists.list_id = list_memberships.list_id
+        WHERE lists.slug = 'weekly'
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(membership.status, "confirmed");
+}
+
+#[tokio::test]
+async fn opting_back_in_after_leaving_needs_a_new_confirmation() {
+    for status in ["unsubscribed", "bounced", "complained"] {
+        // Arrange
+        let app = TestApp::spawn_app().await;
+        create_confirmed_subscriber(&app).await;
+        let saved = sqlx::query!("SELECT id, name, email FROM subscriptions")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+        sqlx::query!(
+            "UPDATE subscriptions SET status = $1 WHERE id = $2",
+            status,
+            saved.id
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+        let link = manage_link(&app.address, saved.id, &app.hmac_secret);
+
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&app.email_server)
+            .await;
+
+        // Act
+        let response = post_preferences(
+            &app,
+            &link,
+            &[
+                ("name", &saved.name),
+                ("email", &saved.email),
+                ("list", "newsletter"),
+                ("status", "subscribed"),
+            ],
+        )
+        .await;
+
+        // Assert
+        assert_eq!(303, response.status().as_u16());
+
+        let subscriber = sqlx::query!("SELECT status FROM subscriptions")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+        assert_eq!(subscriber.status, "pending_confirmation", "{}", status);
+
+        let membership = sqlx::query!("SELECT status FROM list_memberships")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+        assert_eq!(membership.status, "pending_confirmation", "{}", status);
+    }
+}
+
+#[tokio::test]
+async fn suppressed_addresses_cannot_join_lists_through_the_preference_center() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let saved = sqlx::query!("SELECT id, name, email, email_canonical FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    sqlx::query!(
+        "UPDATE subscriptions SET status = 'bounced' WHERE id = $1",
+        saved.id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        r#"
+        INSERT INTO suppressions (email_canonical, email, reason, created_at)
+        VALUES ($1, $2, 'hard_bounce', now())
+        "#,
+        saved.email_canonical,
+        saved.email
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let link = manage_link(&app.address, saved.id, &app.hmac_secret);
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = post_preferences(
+        &app,
+        &link,
+        &[
+            ("name", &saved.name),
+            ("email", &saved.email),
+            ("list", "newsletter"),
+            ("status", "subscribed"),
+        ],
+    )
+    .await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+
+    let subscriber = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscriber.status, "bounced");
+}
+
+#[tokio::test]
+async fn stopping_all_emails_unsubscribes_the_subscriber() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let saved = sqlx::query!("SELECT id, name, email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    let link = manage_link(&app.address, saved.id, &app.hmac_secret);
+
+    // Act
+    post_preferences(
+        &app,
+        &link,
+        &[
+            ("name", &saved.name),
+            ("email", &saved.email),
+            ("list", "newsletter"),
+            ("status", "unsubscribed"),
+        ],
+    )
+    .await;
+
+    // Assert
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+
+    let membership = sqlx::query!("SELECT status FROM list_memberships")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(membership.status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn a_new_email_address_is_only_used_once_confirmed() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let saved = sqlx::query!("SELECT id, name, email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    let link = manage_link(&app.address, saved.id, &app.hmac_secret);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Ask for a new address
+    post_preferences(
+        &app,
+        &link,
+        &[
+            ("name", &saved.name),
+            ("email", "ursula_le_guin@example.com"),
+            ("list", "newsletter"),
+            ("status", "subscribed"),
+        ],
+    )
+    .await;
+
+    // Assert - Part 1
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], "ursula_le_guin@example.com");
+
+    let current = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(current.email, saved.email);
+
+    // Act - Part 2 - Confirm it
+    let confirmation_link = app.get_confirmation_links(&email_request).html;
+    let response = reqwest::get(confirmation_link.clone()).await.unwrap();
+    assert_eq!(200, response.status().as_u16());
+
+    let token = confirmation_link
+        .query_pairs()
+        .find(|(key, _)| key == "token")
+        .map(|(_, value)| value.into_owned())
+        .unwrap();
+    let response = app
+        .api_client
+        .post(format!(
+            "{}/subscriptions/manage/confirm-email",
+            app.address
+        ))
+        .form(&[("token", &token)])
+        .send()
+        .await
+        .unwrap();
+
+    // Assert - Part 2
+    assert_eq!(200, response.status().as_u16());
+
+    let current = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(current.email, "ursula_le_guin@example.com");
+}
+
+#[tokio::test]
+async fn invalid_preferences_are_rejected_with_a_400() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let saved = sqlx::query!("SELECT id, name, email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    let link = manage_link(&app.address, saved.id, &app.hmac_secret);
+    let test_cases = vec![
+        (
+            vec![("name", ""), ("email", saved.email.as_str())],
+            "empty name",
+        ),
+        (
+            vec![("name", saved.name.as_str()), ("email", "not-an-email")],
+            "invalid email",
+        ),
+        (
+            vec![
+                ("name", saved.name.as_str()),
+                ("email", "ursula@mailinator.com"),
+            ],
+            "address at a disposable provider",
+        ),
+    ];
+
+    for (fields, description) in test_cases {
+        // Act
+        let response = post_preferences(&app, &link, &fields).await;
+
+        // Assert
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The preference center did not reject an {}.",
+            description
+        );
+    }
+}
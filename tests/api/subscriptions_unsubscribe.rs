@@ -0,0 +1,212 @@
+//! tests/api/subscriptions_unsubscribe.rs
+
+use reqwest::Url;
+use serde_json::Value;
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{create_confirmed_subscriber, TestApp};
+
+async fn publish_and_deliver_a_newsletter(app: &TestApp) {
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+
+    let response = app.post_publish_newsletter(&newsletter_request_body).await;
+    TestApp::assert_is_redirect_to(&response, "/admin/newsletters");
+
+    app.dispatch_all_pending_emails().await;
+}
+
+fn get_list_unsubscribe_link(app: &TestApp, email_request: &wiremock::Request) -> Url {
+    let body: Value = serde_json::from_slice(&email_request.body).unwrap();
+    let headers = body["Headers"].as_array().unwrap();
+
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|header| header["Name"] == name)
+            .and_then(|header| header["Value"].as_str())
+            .unwrap()
+            .to_owned()
+    };
+
+    assert_eq!(
+        header("List-Unsubscribe-Post"),
+        "List-Unsubscribe=One-Click"
+    );
+
+    let raw_link = header("List-Unsubscribe");
+    let raw_link = raw_link.trim_start_matches('<').trim_end_matches('>');
+
+    // The same link must be offered in the footer of both bodies.
+    assert!(body["HtmlBody"].as_str().unwrap().contains(raw_link));
+    assert!(body["TextBody"].as_str().unwrap().contains(raw_link));
+
+    let mut link = Url::parse(raw_link).unwrap();
+    assert_eq!(link.host_str().unwrap(), "127.0.0.1");
+    link.set_port(Some(app.port)).unwrap();
+
+    link
+}
+
+#[tokio::test]
+async fn unsubscribing_without_a_token_is_rejected_with_a_400() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions/unsubscribe", app.address))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn unsubscribing_with_a_forged_token_is_rejected_with_a_400() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let token = format!("{}.bm90LWEtc2lnbmF0dXJl", uuid::Uuid::new_v4());
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!(
+            "{}/subscriptions/unsubscribe?token={}",
+            app.address, token
+        ))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn newsletter_issues_carry_one_click_unsubscribe_headers() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    publish_and_deliver_a_newsletter(&app).await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let link = get_list_unsubscribe_link(&app, &email_request);
+
+    let response = reqwest::get(link).await.unwrap();
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn one_click_unsubscribe_marks_the_subscriber_as_unsubscribed() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    publish_and_deliver_a_newsletter(&app).await;
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let link = get_list_unsubscribe_link(&app, &email_request);
+
+    // Act - Mail clients POST `List-Unsubscribe=One-Click` to the header's URL
+    let response = reqwest::Client::new()
+        .post(link)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("List-Unsubscribe=One-Click")
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+
+    assert_eq!(saved.status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn newsletters_are_not_delivered_to_unsubscribed_subscribers() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    let mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    publish_and_deliver_a_newsletter(&app).await;
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let link = get_list_unsubscribe_link(&app, &email_request);
+
+    reqwest::Client::new()
+        .post(link)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    drop(mock_guard);
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        // We assert that no request is fired at Postmark!
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    publish_and_deliver_a_newsletter(&app).await;
+
+    // Mock verifies on Drop that we haven't sent the second issue
+}
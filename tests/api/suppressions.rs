@@ -0,0 +1,352 @@
+//! tests/api/suppressions.rs
+
+use reqwest::Response;
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+use zero_to_prod::routes::manage_link;
+
+use crate::helpers::{create_confirmed_subscriber, TestApp};
+
+async fn post_suppression(app: &TestApp, email: &str, reason: &str) -> Response {
+    app.api_client
+        .post(format!("{}/admin/suppressions", &app.address))
+        .form(&[("email", email), ("reason", reason)])
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn post_suppressions_import(app: &TestApp, csv: impl AsRef<[u8]>, reason: &str) -> Response {
+    let body = [
+        b"--boundary\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"suppressions.csv\"\r\n\
+        Content-Type: text/csv\r\n\
+        \r\n",
+        csv.as_ref(),
+        format!(
+            "\r\n\
+            --boundary\r\n\
+            Content-Disposition: form-data; name=\"reason\"\r\n\
+            \r\n\
+            {reason}\r\n\
+            --boundary--\r\n"
+        )
+        .as_bytes(),
+    ]
+    .concat();
+
+    app.api_client
+        .post(format!("{}/admin/suppressions/import", &app.address))
+        .header("Content-Type", "multipart/form-data; boundary=boundary")
+        .body(body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn get_suppressions_html(app: &TestApp) -> String {
+    app.api_client
+        .get(format!("{}/admin/suppressions", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .text()
+        .await
+        .unwrap()
+}
+
+async fn get_suppression_reasons(app: &TestApp) -> Vec<(String, String)> {
+    sqlx::query!("SELECT email, reason FROM suppressions ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|row| (row.email, row.reason))
+        .collect()
+}
+
+async fn get_subscriber_email(app: &TestApp) -> String {
+    sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .email
+}
+
+async fn publish_newsletter(app: &TestApp) {
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+
+    let response = app.post_publish_newsletter(&newsletter_request_body).await;
+    TestApp::assert_is_redirect_to(&response, "/admin/newsletters");
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_the_suppression_list() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let form = app
+        .api_client
+        .get(format!("{}/admin/suppressions", &app.address))
+        .send()
+        .await
+        .unwrap();
+    let add = post_suppression(&app, "ursula@example.com", "manual").await;
+    let import = post_suppressions_import(&app, "ursula@example.com", "manual").await;
+
+    // Assert
+    TestApp::assert_is_redirect_to(&form, "/login");
+    TestApp::assert_is_redirect_to(&add, "/login");
+    TestApp::assert_is_redirect_to(&import, "/login");
+    assert!(get_suppression_reasons(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn suppressed_subscribers_are_not_sent_newsletters() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let email = get_subscriber_email(&app).await;
+    // Suppressions match however the local part is written.
+    let (local_part, domain) = email.split_once('@').unwrap();
+    let email = format!("{}@{}", local_part.to_uppercase(), domain);
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Suppress the address
+    let response = post_suppression(&app, &email, "complaint").await;
+    TestApp::assert_is_redirect_to(&response, "/admin/suppressions");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = get_suppressions_html(&app).await;
+    assert!(html_page.contains(&format!("{} is now suppressed.", email)));
+
+    // Act - Part 3 - Publish
+    publish_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(
+        get_suppression_reasons(&app).await,
+        vec![(email, "complaint".to_string())]
+    );
+    // Mock verifies on Drop that we haven't sent the newsletter
+}
+
+#[tokio::test]
+async fn issues_already_queued_are_not_delivered_to_newly_suppressed_addresses() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let email = get_subscriber_email(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    publish_newsletter(&app).await;
+    post_suppression(&app, &email, "hard_bounce").await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let queued = sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(queued.is_empty());
+    // Mock verifies on Drop that we haven't sent the newsletter
+}
+
+#[tokio::test]
+async fn signing_up_with_a_suppressed_address_sends_no_confirmation() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+    post_suppression(&app, "ursula_le_guin@gmail.com", "hard_bounce").await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert - The response does not give the suppression away
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_optional(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.is_none());
+}
+
+#[tokio::test]
+async fn unsubscribing_suppresses_the_address_until_the_next_confirmation() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let saved = sqlx::query!("SELECT id, name, email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    let link = manage_link(&app.address, saved.id, &app.hmac_secret);
+
+    // Act - Part 1 - Stop all emails
+    app.api_client
+        .post(&link)
+        .form(&[
+            ("name", saved.name.as_str()),
+            ("email", &saved.email),
+            ("list", "newsletter"),
+            ("status", "unsubscribed"),
+        ])
+        .send()
+        .await
+        .unwrap();
+
+    // Assert - Part 1
+    assert_eq!(
+        get_suppression_reasons(&app).await,
+        vec![(saved.email.clone(), "unsubscribe".to_string())]
+    );
+
+    // Act - Part 2 - Sign up again and confirm
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let body = serde_urlencoded::to_string([("name", "le guin"), ("email", &saved.email)]).unwrap();
+    app.post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let confirmation_links = app.get_confirmation_links(email_request);
+    app.post_confirmation(&confirmation_links.html)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Assert - Part 2
+    assert!(get_suppression_reasons(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn a_removed_suppression_no_longer_applies() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+    post_suppression(&app, "ursula_le_guin@gmail.com", "manual").await;
+    let html_page = get_suppressions_html(&app).await;
+    assert!(html_page.contains("ursula_le_guin@gmail.com"));
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/admin/suppressions/remove", &app.address))
+        .form(&[("email", "ursula_le_guin@gmail.com")])
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    TestApp::assert_is_redirect_to(&response, "/admin/suppressions");
+    let html_page = get_suppressions_html(&app).await;
+    assert!(html_page.contains("ursula_le_guin@gmail.com is no longer suppressed."));
+    assert!(get_suppression_reasons(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn an_invalid_address_cannot_be_suppressed() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = post_suppression(&app, "definitely-not-an-email", "manual").await;
+
+    // Assert
+    TestApp::assert_is_redirect_to(&response, "/admin/suppressions");
+    let html_page = get_suppressions_html(&app).await;
+    assert!(html_page.contains("definitely-not-an-email is not a valid subscriber email."));
+    assert!(get_suppression_reasons(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn a_csv_file_of_addresses_can_be_imported() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+    post_suppression(&app, "already@example.com", "manual").await;
+    let csv = "email,reason\r\n\
+        a@example.com,complaint\r\n\
+        \"b@example.com\"\r\n\
+        ALREADY@example.com\r\n\
+        not-an-email,manual\r\n\
+        c@example.com,soft bounce";
+
+    // Act
+    let response = post_suppressions_import(&app, csv, "hard_bounce").await;
+
+    // Assert
+    TestApp::assert_is_redirect_to(&response, "/admin/suppressions");
+    let html_page = get_suppressions_html(&app).await;
+    assert!(html_page.contains("Suppressed 2 new addresses; 1 were already on the list."));
+    assert!(html_page.contains("2 rows were rejected:"));
+    assert!(html_page.contains("Line 5: not-an-email is not a valid subscriber email."));
+    assert!(html_page.contains("Line 6: soft bounce is not a known suppression reason."));
+    assert_eq!(
+        get_suppression_reasons(&app).await,
+        vec![
+            ("a@example.com".to_string(), "complaint".to_string()),
+            ("already@example.com".to_string(), "manual".to_string()),
+            ("b@example.com".to_string(), "hard_bounce".to_string()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn an_unreadable_csv_file_imports_nothing() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response =
+        post_suppressions_import(&app, b"a@example.com\n\xE9@example.com\n", "manual").await;
+
+    // Assert
+    TestApp::assert_is_redirect_to(&response, "/admin/suppressions");
+    let html_page = get_suppressions_html(&app).await;
+    assert!(html_page.contains("Line 2: the file is not UTF-8 encoded."));
+    assert!(get_suppression_reasons(&app).await.is_empty());
+}